@@ -1,6 +1,6 @@
 use alloy_primitives::hex::FromHex;
 use alloy_primitives::U256;
-use alloy_sol_types::{sol, SolCall};
+use alloy_sol_types::{sol, SolCall, SolInterface};
 use revm::primitives::{Address, Bytes};
 use rust_embed::Embed;
 
@@ -20,6 +20,17 @@ sol! {
     function balanceOf(bytes, address) returns (uint256);
 }
 
+sol! {
+    interface IBRC20_ControllerErrors {
+        error BRC20InsufficientBalance(bytes ticker, address sender, uint256 balance, uint256 needed);
+        error BRC20InvalidSender(bytes ticker, address sender);
+        error BRC20InvalidReceiver(bytes ticker, address receiver);
+        error BRC20InsufficientAllowance(bytes ticker, address spender, uint256 allowance, uint256 needed);
+        error BRC20InvalidApprover(bytes ticker, address approver);
+        error BRC20InvalidSpender(bytes ticker, address spender);
+    }
+}
+
 pub fn load_brc20_mint_tx(ticker: String, address: Address, amount: U256) -> TxInfo {
     TxInfo {
         from: INDEXER_ADDRESS.parse().unwrap(),
@@ -61,6 +72,54 @@ pub fn decode_brc20_balance_result(data: Option<&Bytes>) -> U256 {
     result.unwrap()._0
 }
 
+/// Decodes a BRC20_Controller custom error into a human readable message
+pub fn decode_brc20_controller_error(data: &[u8]) -> Option<String> {
+    use IBRC20_ControllerErrors::IBRC20_ControllerErrorsErrors as Errors;
+
+    let error = Errors::abi_decode(data, true).ok()?;
+    Some(match error {
+        Errors::BRC20InsufficientBalance(e) => format!(
+            "BRC20InsufficientBalance(ticker: {}, sender: {}, balance: {}, needed: {})",
+            ticker_to_string(&e.ticker),
+            e.sender,
+            e.balance,
+            e.needed
+        ),
+        Errors::BRC20InvalidSender(e) => format!(
+            "BRC20InvalidSender(ticker: {}, sender: {})",
+            ticker_to_string(&e.ticker),
+            e.sender
+        ),
+        Errors::BRC20InvalidReceiver(e) => format!(
+            "BRC20InvalidReceiver(ticker: {}, receiver: {})",
+            ticker_to_string(&e.ticker),
+            e.receiver
+        ),
+        Errors::BRC20InsufficientAllowance(e) => format!(
+            "BRC20InsufficientAllowance(ticker: {}, spender: {}, allowance: {}, needed: {})",
+            ticker_to_string(&e.ticker),
+            e.spender,
+            e.allowance,
+            e.needed
+        ),
+        Errors::BRC20InvalidApprover(e) => format!(
+            "BRC20InvalidApprover(ticker: {}, approver: {})",
+            ticker_to_string(&e.ticker),
+            e.approver
+        ),
+        Errors::BRC20InvalidSpender(e) => format!(
+            "BRC20InvalidSpender(ticker: {}, spender: {})",
+            ticker_to_string(&e.ticker),
+            e.spender
+        ),
+    })
+}
+
+/// Tickers are shown as text if they are valid UTF-8, otherwise as hex
+fn ticker_to_string(ticker: &Bytes) -> String {
+    String::from_utf8(ticker.to_vec()).unwrap_or_else(|_| ticker.to_string())
+}
+
 pub fn load_brc20_deploy_tx() -> TxInfo {
     let file_content = ContractAssets::get(&format!("{}.bin", BRC20_CONTROLLER_PATH));
     let file_content = file_content.unwrap();
@@ -0,0 +1,139 @@
+use std::error::Error;
+use std::sync::Arc;
+
+use rocksdb::{ColumnFamily, DB};
+
+use crate::db::types::BitcoinTxED;
+
+/// Persistent cache of the Bitcoin data fetched from the data provider, keyed by txid and block hash
+///
+/// Only confirmed transactions and block heights are cached, they are written directly instead
+/// of with the block changes and are never reverted. A Bitcoin reorg can move a confirmed
+/// transaction to another block, so its block should be checked before the transaction is used.
+#[derive(Clone)]
+pub struct BitcoinCache {
+    db: Arc<DB>,
+    transactions_name: String,
+    block_heights_name: String,
+}
+
+impl BitcoinCache {
+    /// Create a new BitcoinCache
+    ///
+    /// The column families returned by column_families should exist in the database
+    ///
+    /// db: &Arc<DB> - the shared database
+    /// name: &str - the name of the cache
+    ///
+    /// Returns: BitcoinCache - the created BitcoinCache
+    pub fn new(db: &Arc<DB>, name: &str) -> Self {
+        let column_families = Self::column_families(name);
+        Self {
+            db: db.clone(),
+            transactions_name: column_families[0].clone(),
+            block_heights_name: column_families[1].clone(),
+        }
+    }
+
+    /// Get the column families used by a cache with the given name
+    pub fn column_families(name: &str) -> Vec<String> {
+        vec![format!("{}_tx", name), format!("{}_block_height", name)]
+    }
+
+    fn cf(&self, name: &str) -> &ColumnFamily {
+        self.db.cf_handle(name).unwrap()
+    }
+
+    pub fn get_transaction(&self, txid: &str) -> Result<Option<BitcoinTxED>, Box<dyn Error>> {
+        let Some(bytes) = self
+            .db
+            .get_cf(self.cf(&self.transactions_name), txid.to_lowercase())?
+        else {
+            return Ok(None);
+        };
+        Ok(Some(serde_json::from_slice(&bytes)?))
+    }
+
+    /// Add a transaction, it should be confirmed
+    pub fn set_transaction(
+        &self,
+        txid: &str,
+        transaction: &BitcoinTxED,
+    ) -> Result<(), Box<dyn Error>> {
+        self.db.put_cf(
+            self.cf(&self.transactions_name),
+            txid.to_lowercase(),
+            serde_json::to_vec(transaction)?,
+        )?;
+        Ok(())
+    }
+
+    /// Remove a transaction, e.g. when it's not confirmed anymore
+    pub fn delete_transaction(&self, txid: &str) -> Result<(), Box<dyn Error>> {
+        self.db
+            .delete_cf(self.cf(&self.transactions_name), txid.to_lowercase())?;
+        Ok(())
+    }
+
+    pub fn get_block_height(&self, block_hash: &str) -> Result<Option<u64>, Box<dyn Error>> {
+        let Some(bytes) = self
+            .db
+            .get_cf(self.cf(&self.block_heights_name), block_hash.to_lowercase())?
+        else {
+            return Ok(None);
+        };
+        Ok(Some(u64::from_be_bytes(bytes.as_slice().try_into()?)))
+    }
+
+    pub fn set_block_height(&self, block_hash: &str, height: u64) -> Result<(), Box<dyn Error>> {
+        self.db.put_cf(
+            self.cf(&self.block_heights_name),
+            block_hash.to_lowercase(),
+            height.to_be_bytes(),
+        )?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::db::cached_database::open_database;
+
+    #[test]
+    fn test_bitcoin_cache() {
+        let tempdir = TempDir::new().unwrap();
+        let database = Arc::new(
+            open_database(tempdir.path(), &BitcoinCache::column_families("test")).unwrap(),
+        );
+        let cache = BitcoinCache::new(&database, "test");
+
+        let transaction = BitcoinTxED {
+            txid: "ab".to_string(),
+            block_hash: Some("cd".to_string()),
+            vin: vec![],
+            vout: vec![],
+        };
+        assert_eq!(cache.get_transaction("ab").unwrap(), None);
+        cache.set_transaction("AB", &transaction).unwrap();
+        assert_eq!(
+            cache.get_transaction("ab").unwrap(),
+            Some(transaction.clone())
+        );
+        cache.delete_transaction("Ab").unwrap();
+        assert_eq!(cache.get_transaction("ab").unwrap(), None);
+        cache.set_transaction("ab", &transaction).unwrap();
+
+        assert_eq!(cache.get_block_height("cd").unwrap(), None);
+        cache.set_block_height("cd", 240960).unwrap();
+        assert_eq!(cache.get_block_height("CD").unwrap(), Some(240960));
+
+        // Data is shared by the clones, as they use the same database
+        let cache_clone = cache.clone();
+        assert_eq!(cache_clone.get_block_height("cd").unwrap(), Some(240960));
+    }
+}
@@ -1,8 +1,8 @@
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap};
 use std::hash::Hash;
-use std::path::Path;
+use std::sync::Arc;
 
-use rocksdb::{Error, IteratorMode, Options, DB};
+use rocksdb::{ColumnFamily, Error, IteratorMode, WriteBatch, DB};
 
 use crate::db::cached_database::BlockHistoryCache;
 use crate::db::types::{Decode, Encode};
@@ -12,6 +12,17 @@ use crate::db::types::{Decode, Encode};
 // It uses a cache to store the data in memory and only writes to the database when commit is called
 // It also supports reorg by reverting back the state to the latest valid block
 //
+// Values, journals and archived values are stored in separate column families of a shared database,
+// so changes of all databases can be written at once
+//
+// Journal keeps the value before each change keyed by block number and key, so committed blocks can be reverted
+// Entries older than the max reorg depth are removed on commit
+// Journal index has the same entries keyed by key and block number, so the first change of a key after a block is found with one seek
+//
+// In archive mode, every change is also kept in an archive column family keyed by key and block number,
+// so values can be read at any block since archive mode was enabled, not only the ones that can still be reorged
+// The value a key had when archive mode was enabled is archived at that block with its first archived change
+//
 // K: the type of the key
 // V: the type of the value to store
 // C: the type of the cache
@@ -20,11 +31,21 @@ pub struct BlockCachedDatabase<K, V, C>
 where
     K: Encode + Decode + Eq + Hash + Clone,
     V: Encode + Decode + Clone + Eq,
-    C: BlockHistoryCache<V> + Encode + Decode + Clone,
+    C: BlockHistoryCache<V> + Clone,
 {
-    db: DB,
-    cache_db: DB,
+    db: Arc<DB>,
+    name: String,
+    journal_name: String,
+    journal_index_name: String,
     cache: HashMap<K, C>,
+    /// Number of blocks that can be reorged, None to keep the journal for all blocks
+    max_reorg_depth: Option<u64>,
+    /// Name of the archive column family, only set in archive mode
+    archive_name: Option<String>,
+    /// First block that's archived, only set in archive mode
+    archive_start_block: Option<u64>,
+    /// Changes that are not written to the archive database yet, by key and block number
+    archive_cache: HashMap<K, BTreeMap<u64, V>>,
 
     _phantom: std::marker::PhantomData<V>,
 }
@@ -33,31 +54,72 @@ impl<K, V, C> BlockCachedDatabase<K, V, C>
 where
     K: Encode + Decode + Eq + Hash + Clone,
     V: Encode + Decode + Eq + Clone,
-    C: BlockHistoryCache<V> + Encode + Decode + Clone,
+    C: BlockHistoryCache<V> + Clone,
 {
-    /// Create a new BlockCachedDatabase
+    /// Create a new BlockCachedDatabase, that also archives all values if archive_start_block is set
+    ///
+    /// The column families returned by column_families should exist in the database
     ///
-    /// It creates a new database if it does not exist
+    /// Archive keys are the encoded key followed by the block number,
+    /// so keys should have a fixed length encoding
     ///
-    /// path: &Path - the path to store the database
+    /// db: &Arc<DB> - the shared database
     /// name: &str - the name of the database
+    /// archive_start_block: Option<u64> - block archive mode was enabled at, None to not keep the history of all values
+    /// max_reorg_depth: Option<u64> - number of blocks that can be reorged, None for no limit
     ///
     /// Returns: BlockCachedDatabase<K, V, C> - the created BlockCachedDatabase
-    pub fn new(path: &Path, name: &str) -> Self {
-        let mut opts = Options::default();
-        opts.create_if_missing(true);
-        opts.set_max_open_files(256);
-        let db = DB::open(&opts, &path.join(Path::new(name))).unwrap();
-        let cache_db = DB::open(&opts, &path.join(Path::new(&format!("{}_cache", name)))).unwrap();
-        let cache = HashMap::new();
+    pub fn new(
+        db: &Arc<DB>,
+        name: &str,
+        archive_start_block: Option<u64>,
+        max_reorg_depth: Option<u64>,
+    ) -> Self {
+        let [name, journal_name, journal_index_name, archive_name] = get_column_family_names(name);
         Self {
-            db,
-            cache_db,
-            cache,
+            db: db.clone(),
+            name,
+            journal_name,
+            journal_index_name,
+            cache: HashMap::new(),
+            max_reorg_depth,
+            archive_name: archive_start_block.map(|_| archive_name),
+            archive_start_block,
+            archive_cache: HashMap::new(),
             _phantom: std::marker::PhantomData,
         }
     }
 
+    /// Get the column families used by a database with the given name
+    ///
+    /// Archive column family is always included, so the database can be opened with or without archive mode
+    pub fn column_families(name: &str) -> Vec<String> {
+        get_column_family_names(name).to_vec()
+    }
+
+    fn cf(&self) -> &ColumnFamily {
+        self.db.cf_handle(&self.name).unwrap()
+    }
+
+    fn journal_cf(&self) -> &ColumnFamily {
+        self.db.cf_handle(&self.journal_name).unwrap()
+    }
+
+    fn journal_index_cf(&self) -> &ColumnFamily {
+        self.db.cf_handle(&self.journal_index_name).unwrap()
+    }
+
+    fn archive_cf(&self) -> Option<&ColumnFamily> {
+        self.archive_name
+            .as_ref()
+            .map(|name| self.db.cf_handle(name).unwrap())
+    }
+
+    /// Check if the history of all values is kept
+    pub fn is_archive(&self) -> bool {
+        self.archive_name.is_some()
+    }
+
     /// Get the value for a key
     ///
     /// It first checks the cache and then the database
@@ -71,7 +133,12 @@ where
             let cache = self.cache.get(key).unwrap();
             return Ok(cache.latest());
         }
-        let result = self.db.get(key.encode().unwrap())?;
+        self.committed_latest(key)
+    }
+
+    /// Get the value for a key in the database, ignoring the changes that are not committed yet
+    fn committed_latest(&self, key: &K) -> Result<Option<V>, Error> {
+        let result = self.db.get_cf(self.cf(), key.encode().unwrap())?;
         if result.is_none() {
             return Ok(None);
         }
@@ -79,6 +146,51 @@ where
         Ok(Some(value))
     }
 
+    /// Get the value for a key at the end of a block
+    ///
+    /// It uses the history cache for the changes that are not committed yet,
+    /// and the journal to revert the committed changes after the block
+    ///
+    /// Only accurate for the blocks that can still be reorged, unless archive mode was enabled before the block
+    /// Keys should have a fixed length encoding, so the journal index of a key isn't mixed up with other keys
+    ///
+    /// key: &K - the key to get the value for
+    /// block_number: u64 - the block number
+    /// Returns: Option<V> - the value for the key at the end of the block
+    pub fn at(&self, key: &K, block_number: u64) -> Result<Option<V>, Error> {
+        if self
+            .archive_start_block
+            .is_some_and(|archive_start_block| archive_start_block <= block_number)
+        {
+            return self.archive_at(key, block_number);
+        }
+        if let Some(cache) = self.cache.get(key) {
+            if cache
+                .first_change()
+                .is_some_and(|first_change| first_change <= block_number)
+            {
+                return Ok(cache.at(block_number));
+            }
+        }
+        // First committed change after the block has the value at the end of the block
+        let key_bytes = key.encode().unwrap();
+        let index_key = get_journal_index_key(&key_bytes, block_number + 1);
+        if let Some(kv_pair) = self
+            .db
+            .iterator_cf(
+                self.journal_index_cf(),
+                IteratorMode::From(&index_key, rocksdb::Direction::Forward),
+            )
+            .next()
+        {
+            let (entry_key, previous_value) = kv_pair?;
+            if entry_key.len() == index_key.len() && entry_key.starts_with(&key_bytes) {
+                return Ok(decode_journal_value::<V>(previous_value.to_vec()));
+            }
+        }
+        self.committed_latest(key)
+    }
+
     /// Get the range of values between start_key and end_key
     ///
     /// It returns a list of key-value pairs between start_key and end_key
@@ -89,39 +201,46 @@ where
     /// end_key: &K - the end key, exclusive
     /// Returns: Vec<(K, V)> - the list of key-value pairs
     pub fn get_range(&self, start_key: &K, end_key: &K) -> Result<Vec<(K, V)>, Error> {
-        let mut result = Vec::new();
+        // Encoded keys are used for ordering, cached values override the ones in the database
+        let mut result = BTreeMap::new();
         let start_key_bytes = start_key.encode().unwrap();
         let end_key_bytes = end_key.encode().unwrap();
 
-        for key in self.cache.keys() {
-            let key_bytes = key.encode().unwrap();
-            if *key_bytes < *start_key_bytes {
-                continue;
-            }
-            if *key_bytes >= *end_key_bytes {
-                break;
-            }
-            let cache = self.cache.get(key).unwrap();
-            if cache.latest().is_none() {
-                continue;
-            }
-            result.push((key.clone(), cache.latest().unwrap()));
-        }
-
-        for kv_pair in self.db.iterator(IteratorMode::From(
-            &start_key_bytes,
-            rocksdb::Direction::Forward,
-        )) {
+        for kv_pair in self.db.iterator_cf(
+            self.cf(),
+            IteratorMode::From(&start_key_bytes, rocksdb::Direction::Forward),
+        ) {
             let (key, value) = kv_pair?;
             if *key >= *end_key_bytes {
                 break;
             }
-            let key = K::decode(key.to_vec()).unwrap();
             let value = V::decode(value.to_vec()).unwrap();
-            result.push((key, value));
+            result.insert(key.to_vec(), (K::decode(key.to_vec()).unwrap(), value));
+        }
+
+        for (key, cache) in self.cache.iter() {
+            let key_bytes = key.encode().unwrap();
+            if *key_bytes < *start_key_bytes || *key_bytes >= *end_key_bytes {
+                continue;
+            }
+            match cache.latest() {
+                Some(value) => result.insert(key_bytes, (key.clone(), value)),
+                None => result.remove(&key_bytes),
+            };
         }
 
-        Ok(result)
+        Ok(result.into_values().collect())
+    }
+
+    /// Check if there are no values stored in the cache or the database
+    pub fn is_empty(&self) -> bool {
+        if self.cache.values().any(|cache| cache.latest().is_some()) {
+            return false;
+        }
+        self.db
+            .iterator_cf(self.cf(), IteratorMode::Start)
+            .next()
+            .is_none()
     }
 
     /// Set the value for a key
@@ -132,6 +251,12 @@ where
     /// key: K - the key to set the value for
     /// value: V - the value to set
     pub fn set(&mut self, block_number: u64, key: K, value: V) -> Result<(), Error> {
+        if self.is_archive() {
+            self.archive_cache
+                .entry(key.clone())
+                .or_default()
+                .insert(block_number, value.clone());
+        }
         if self.cache.contains_key(&key) {
             let cache = self.cache.get_mut(&key).unwrap();
             cache.set(block_number, value);
@@ -145,66 +270,144 @@ where
 
     /// Commit the cache to the database
     ///
-    /// It writes all the values in the cache to the database
+    /// It adds all the values in the cache and their previous values in the journal to the batch,
+    /// they are written when the batch is written
+    /// Journal entries that are too old for a reorg at the block number are removed
     /// It does not clear the cache
     ///
+    /// batch: &mut WriteBatch - the batch to add the changes to
     /// block_number: U256 - the block number to commit at
-    pub fn commit(&mut self, block_number: u64) -> Result<(), Error> {
+    pub fn commit(&self, batch: &mut WriteBatch, block_number: u64) -> Result<(), Error> {
         for (key, cache) in self.cache.iter() {
+            let previous_values = cache.previous_values();
+            if previous_values.is_empty() {
+                continue;
+            }
             let key_bytes = K::encode(key).unwrap();
-            let cache_bytes = C::encode(cache).unwrap();
-            if cache.is_old(block_number) {
-                self.cache_db.delete(&key_bytes)?;
-            } else {
-                self.cache_db.put(&key_bytes, &cache_bytes)?;
+            for (change_block_number, previous_value) in previous_values {
+                let journal_key = get_journal_key(change_block_number, &key_bytes);
+                // Block might be committed before, keep the value before its first change
+                if self.db.get_cf(self.journal_cf(), &journal_key)?.is_none() {
+                    let journal_value = encode_journal_value(previous_value.as_ref());
+                    batch.put_cf(
+                        self.journal_index_cf(),
+                        get_journal_index_key(&key_bytes, change_block_number),
+                        &journal_value,
+                    );
+                    batch.put_cf(self.journal_cf(), journal_key, journal_value);
+                }
             }
 
             if cache.latest().is_none() {
-                self.db.delete(&key_bytes)?;
+                batch.delete_cf(self.cf(), &key_bytes);
             } else {
-                self.db
-                    .put(&key_bytes, &cache.latest().unwrap().encode().unwrap())?;
+                batch.put_cf(
+                    self.cf(),
+                    &key_bytes,
+                    cache.latest().unwrap().encode().unwrap(),
+                );
             }
         }
 
-        let keys_to_remove: Vec<K> = self
-            .cache
-            .iter()
-            .filter(|(_, cache)| cache.is_old(block_number))
-            .map(|(key, _)| key.clone())
-            .collect();
-        for key in keys_to_remove {
-            self.cache.remove(&key);
+        if let Some(max_reorg_depth) = self.max_reorg_depth {
+            // A reorg can revert the blocks after block_number - max_reorg_depth
+            let oldest_block_number = block_number.saturating_sub(max_reorg_depth);
+            for kv_pair in self.db.iterator_cf(self.journal_cf(), IteratorMode::Start) {
+                let (journal_key, _) = kv_pair?;
+                if get_journal_block_number(&journal_key) > oldest_block_number {
+                    break;
+                }
+                batch.delete_cf(
+                    self.journal_index_cf(),
+                    get_journal_index_key_of(&journal_key),
+                );
+                batch.delete_cf(self.journal_cf(), journal_key);
+            }
         }
 
-        self.clear_cache();
+        if let Some(archive_cf) = self.archive_cf() {
+            let archive_start_block = self.archive_start_block.unwrap();
+            for (key, history) in self.archive_cache.iter() {
+                // Keep the value the key had when archive mode was enabled, changes at that block replace it
+                if !history.is_empty() && self.get_archive_entry(key, u64::MAX)?.is_none() {
+                    if let Some(value) = self.committed_latest(key)? {
+                        batch.put_cf(
+                            archive_cf,
+                            Self::get_archive_key(key, archive_start_block),
+                            value.encode().unwrap(),
+                        );
+                    }
+                }
+                for (history_block_number, value) in history.iter() {
+                    batch.put_cf(
+                        archive_cf,
+                        Self::get_archive_key(key, *history_block_number),
+                        value.encode().unwrap(),
+                    );
+                }
+            }
+        }
         Ok(())
     }
 
     /// Revert the state to the latest valid block
     ///
-    /// It reverts the state of all the caches to the latest valid block
-    /// It does not clear the cache
+    /// It reverts the changes in the cache, and the committed changes using the journal
+    /// Restored values, journal and archive deletions are added to the batch
+    /// Make sure to commit and write the batch to apply the changes to the database
     ///
+    /// batch: &mut WriteBatch - the batch to add the changes to
     /// latest_valid_block_number: U256 - the latest valid block number
-    pub fn reorg(&mut self, latest_valid_block_number: u64) -> Result<(), Error> {
-        let mut keys = HashSet::new();
-        {
-            for kv_pair in self.cache_db.full_iterator(IteratorMode::Start) {
-                keys.insert(K::decode(kv_pair.unwrap().0.to_vec()).unwrap());
-            }
-            for key in self.cache.keys() {
-                keys.insert(key.clone());
+    pub fn reorg(
+        &mut self,
+        batch: &mut WriteBatch,
+        latest_valid_block_number: u64,
+    ) -> Result<(), Error> {
+        self.discard_changes_after(latest_valid_block_number);
+
+        // Journal is ordered by block number, so the first entry of a key has the value at the latest valid block
+        let mut restored_values = HashMap::new();
+        let start_key = (latest_valid_block_number + 1).to_be_bytes();
+        for kv_pair in self.db.iterator_cf(
+            self.journal_cf(),
+            IteratorMode::From(&start_key, rocksdb::Direction::Forward),
+        ) {
+            let (journal_key, previous_value) = kv_pair?;
+            let key = K::decode(journal_key[8..].to_vec()).unwrap();
+            restored_values
+                .entry(key)
+                .or_insert_with(|| decode_journal_value::<V>(previous_value.to_vec()));
+            batch.delete_cf(
+                self.journal_index_cf(),
+                get_journal_index_key_of(&journal_key),
+            );
+            batch.delete_cf(self.journal_cf(), journal_key);
+        }
+
+        for (key, value) in restored_values {
+            self.remove_archive_entries_after(batch, &key, latest_valid_block_number)?;
+            let key_bytes = key.encode().unwrap();
+            match &value {
+                Some(value) => batch.put_cf(self.cf(), &key_bytes, value.encode().unwrap()),
+                None => batch.delete_cf(self.cf(), &key_bytes),
             }
+            self.cache.insert(key, C::new(value));
         }
-        for key in keys {
-            self.load_cache_if_needed(&key)?;
-            let cache = self.cache.get_mut(&key).unwrap();
+        Ok(())
+    }
+
+    /// Discard the changes after the latest valid block number from the cache
+    ///
+    /// Unlike reorg, it doesn't write to the database, so it only reverts the changes that are not committed yet
+    ///
+    /// latest_valid_block_number: u64 - the latest valid block number
+    pub fn discard_changes_after(&mut self, latest_valid_block_number: u64) {
+        for history in self.archive_cache.values_mut() {
+            history.retain(|block_number, _| *block_number <= latest_valid_block_number);
+        }
+        for cache in self.cache.values_mut() {
             cache.reorg(latest_valid_block_number);
         }
-        self.commit(latest_valid_block_number)?;
-        self.clear_cache();
-        Ok(())
     }
 
     /// Clear the cache
@@ -213,34 +416,325 @@ where
     /// Otherwise the data will be lost
     pub fn clear_cache(&mut self) {
         self.cache.clear();
+        self.archive_cache.clear();
     }
 
-    fn load_cache_if_needed(&mut self, key: &K) -> Result<(), Error> {
-        if self.cache.contains_key(key) {
+    fn get_archive_key(key: &K, block_number: u64) -> Vec<u8> {
+        let mut bytes = key.encode().unwrap();
+        bytes.extend_from_slice(&block_number.to_be_bytes());
+        bytes
+    }
+
+    /// Returns the archived changes of the key at or before the block, latest first, with their block numbers
+    fn get_archive_entries(&self, key: &K, block_number: u64) -> Result<Vec<(u64, V)>, Error> {
+        let Some(archive_cf) = self.archive_cf() else {
+            return Ok(Vec::new());
+        };
+        let key_bytes = key.encode().unwrap();
+        let archive_key = Self::get_archive_key(key, block_number);
+        let mut entries = Vec::new();
+        for kv_pair in self.db.iterator_cf(
+            archive_cf,
+            IteratorMode::From(&archive_key, rocksdb::Direction::Reverse),
+        ) {
+            let (entry_key, value) = kv_pair?;
+            if entry_key.len() != archive_key.len() || !entry_key.starts_with(&key_bytes) {
+                break;
+            }
+            let entry_block_number =
+                u64::from_be_bytes(entry_key[key_bytes.len()..].try_into().unwrap());
+            entries.push((entry_block_number, V::decode(value.to_vec()).unwrap()));
+        }
+        Ok(entries)
+    }
+
+    /// Returns the last archived change of the key at or before the block, with its block number
+    ///
+    /// Changes archived before archive mode was last enabled are ignored, as later changes might be missing
+    fn get_archive_entry(&self, key: &K, block_number: u64) -> Result<Option<(u64, V)>, Error> {
+        let Some(archive_cf) = self.archive_cf() else {
+            return Ok(None);
+        };
+        let key_bytes = key.encode().unwrap();
+        let archive_key = Self::get_archive_key(key, block_number);
+        let Some(kv_pair) = self
+            .db
+            .iterator_cf(
+                archive_cf,
+                IteratorMode::From(&archive_key, rocksdb::Direction::Reverse),
+            )
+            .next()
+        else {
+            return Ok(None);
+        };
+        let (entry_key, value) = kv_pair?;
+        if entry_key.len() != archive_key.len() || !entry_key.starts_with(&key_bytes) {
+            return Ok(None);
+        }
+        let entry_block_number =
+            u64::from_be_bytes(entry_key[key_bytes.len()..].try_into().unwrap());
+        if self
+            .archive_start_block
+            .is_some_and(|archive_start_block| entry_block_number < archive_start_block)
+        {
+            return Ok(None);
+        }
+        Ok(Some((
+            entry_block_number,
+            V::decode(value.to_vec()).unwrap(),
+        )))
+    }
+
+    /// Get the value for a key at the end of a block after archive mode was enabled
+    fn archive_at(&self, key: &K, block_number: u64) -> Result<Option<V>, Error> {
+        let history = self.archive_cache.get(key);
+        if let Some((_, value)) = history.and_then(|x| x.range(..=block_number).next_back()) {
+            return Ok(Some(value.clone()));
+        }
+        if let Some((_, value)) = self.get_archive_entry(key, block_number)? {
+            return Ok(Some(value));
+        }
+        // Value was first set after the block, otherwise the value before it would be archived
+        if history.is_some_and(|x| !x.is_empty())
+            || self.get_archive_entry(key, u64::MAX)?.is_some()
+        {
+            return Ok(None);
+        }
+        // Value hasn't changed since archive mode was enabled
+        self.latest(key)
+    }
+
+    fn remove_archive_entries_after(
+        &self,
+        batch: &mut WriteBatch,
+        key: &K,
+        latest_valid_block_number: u64,
+    ) -> Result<(), Error> {
+        let Some(archive_cf) = self.archive_cf() else {
             return Ok(());
+        };
+        for (block_number, _) in self.get_archive_entries(key, u64::MAX)? {
+            if block_number <= latest_valid_block_number {
+                break;
+            }
+            batch.delete_cf(archive_cf, Self::get_archive_key(key, block_number));
+        }
+        Ok(())
+    }
+
+    /// Returns the block number of the latest change in the journal
+    #[cfg(test)]
+    fn get_last_journal_block_number(&self) -> Result<Option<u64>, Error> {
+        match self
+            .db
+            .iterator_cf(self.journal_cf(), IteratorMode::End)
+            .next()
+        {
+            Some(kv_pair) => Ok(Some(get_journal_block_number(&kv_pair?.0))),
+            None => Ok(None),
         }
+    }
 
-        let cache_bytes = self.cache_db.get(key.encode().unwrap())?;
-        if cache_bytes.is_none() {
-            self.cache.insert(key.clone(), C::new(None));
+    fn load_cache_if_needed(&mut self, key: &K) -> Result<(), Error> {
+        if self.cache.contains_key(key) {
             return Ok(());
         }
-        let cache = C::decode(cache_bytes.unwrap().to_vec()).unwrap();
-        self.cache.insert(key.clone(), cache);
+
+        // Start the history with the value in the database, so it can be written to the journal
+        let initial_value = self.committed_latest(key)?;
+        self.cache.insert(key.clone(), C::new(initial_value));
         Ok(())
     }
 }
 
+fn get_column_family_names(name: &str) -> [String; 4] {
+    [
+        name.to_string(),
+        format!("{}_journal", name),
+        format!("{}_journal_index", name),
+        format!("{}_archive", name),
+    ]
+}
+
+/// Journal keys are the block number followed by the encoded key, so entries are ordered by block number
+fn get_journal_key(block_number: u64, key_bytes: &[u8]) -> Vec<u8> {
+    let mut bytes = block_number.to_be_bytes().to_vec();
+    bytes.extend_from_slice(key_bytes);
+    bytes
+}
+
+fn get_journal_block_number(journal_key: &[u8]) -> u64 {
+    u64::from_be_bytes(journal_key[..8].try_into().unwrap())
+}
+
+/// Journal index keys are the encoded key followed by the block number, so entries are ordered by key
+fn get_journal_index_key(key_bytes: &[u8], block_number: u64) -> Vec<u8> {
+    let mut bytes = key_bytes.to_vec();
+    bytes.extend_from_slice(&block_number.to_be_bytes());
+    bytes
+}
+
+/// Returns the journal index key of the same entry as the journal key
+fn get_journal_index_key_of(journal_key: &[u8]) -> Vec<u8> {
+    get_journal_index_key(&journal_key[8..], get_journal_block_number(journal_key))
+}
+
+/// Journal values are prefixed with 1 if the key had a value, 0 otherwise
+fn encode_journal_value<V: Encode>(value: Option<&V>) -> Vec<u8> {
+    match value {
+        Some(value) => {
+            let mut bytes = vec![1];
+            bytes.extend_from_slice(&value.encode().unwrap());
+            bytes
+        }
+        None => vec![0],
+    }
+}
+
+fn decode_journal_value<V: Decode>(bytes: Vec<u8>) -> Option<V> {
+    if bytes[0] == 0 {
+        return None;
+    }
+    Some(V::decode(bytes[1..].to_vec()).unwrap())
+}
+
+/// Get the column family that kept the history caches of a database, before the journal was used
+pub fn get_history_cache_column_family(name: &str) -> String {
+    format!("{}_cache", name)
+}
+
+/// Moves the history caches of a database into its journal, and drops the history cache column family
+///
+/// History caches have the values of a key at each block it was changed,
+/// so the value before each change except the oldest one is added to the journal
+///
+/// db: &mut DB - the shared database, journal column family should exist
+/// name: &str - the name of the database
+///
+/// Returns: bool - true if the history caches existed and were moved
+pub fn migrate_history_cache(db: &mut DB, name: &str) -> Result<bool, Error> {
+    let cache_name = get_history_cache_column_family(name);
+    let [_, journal_name, _, _] = get_column_family_names(name);
+    let Some(cache_cf) = db.cf_handle(&cache_name) else {
+        return Ok(false);
+    };
+    let journal_cf = db.cf_handle(&journal_name).unwrap();
+
+    let mut batch = WriteBatch::default();
+    for kv_pair in db.iterator_cf(cache_cf, IteratorMode::Start) {
+        let (key, cache_bytes) = kv_pair?;
+        // History caches are encoded as block number, value size and value for each change
+        let mut previous_value = None;
+        let mut i = 0;
+        while i < cache_bytes.len() {
+            let block_number = u64::from_be_bytes(cache_bytes[i..i + 8].try_into().unwrap());
+            let size = u32::from_be_bytes(cache_bytes[i + 8..i + 12].try_into().unwrap()) as usize;
+            i += 12;
+            let value = (size != 0).then(|| cache_bytes[i..i + size].to_vec());
+            i += size;
+            if let Some(previous_value) = previous_value {
+                let journal_value = match previous_value {
+                    Some(previous_value) => [vec![1], previous_value].concat(),
+                    None => vec![0],
+                };
+                batch.put_cf(
+                    journal_cf,
+                    get_journal_key(block_number, &key),
+                    journal_value,
+                );
+            }
+            previous_value = Some(value);
+        }
+    }
+    db.write(batch)?;
+    db.drop_cf(&cache_name)?;
+    Ok(true)
+}
+
+/// Builds the journal index of a database from its journal, for databases created before the index existed
+///
+/// Index is written in one batch, so it's either empty or complete
+///
+/// db: &DB - the shared database, journal and journal index column families should exist
+/// name: &str - the name of the database
+///
+/// Returns: bool - true if the journal index was built
+pub fn migrate_journal_index(db: &DB, name: &str) -> Result<bool, Error> {
+    let [_, journal_name, journal_index_name, _] = get_column_family_names(name);
+    let journal_cf = db.cf_handle(&journal_name).unwrap();
+    let journal_index_cf = db.cf_handle(&journal_index_name).unwrap();
+    if db
+        .iterator_cf(journal_index_cf, IteratorMode::Start)
+        .next()
+        .is_some()
+    {
+        return Ok(false);
+    }
+
+    let mut batch = WriteBatch::default();
+    for kv_pair in db.iterator_cf(journal_cf, IteratorMode::Start) {
+        let (journal_key, journal_value) = kv_pair?;
+        batch.put_cf(
+            journal_index_cf,
+            get_journal_index_key_of(&journal_key),
+            journal_value,
+        );
+    }
+    if batch.is_empty() {
+        return Ok(false);
+    }
+    db.write(batch)?;
+    Ok(true)
+}
+
 #[cfg(test)]
 mod tests {
+    use std::path::Path;
+    use std::sync::Arc;
+
     use revm::primitives::{Address, B256, U256};
     use revm_state::AccountInfo;
+    use rocksdb::{WriteBatch, DB};
     use tempfile::TempDir;
 
-    use crate::db::cached_database::{
-        BlockCachedDatabase, BlockHistoryCache, BlockHistoryCacheData,
-    };
-    use crate::db::types::{AccountInfoED, AddressED, Decode, Encode};
+    use super::*;
+    use crate::db::cached_database::{open_database, BlockHistoryCacheData};
+    use crate::db::types::{AccountInfoED, AddressED, Decode, Encode, U64ED};
+    use crate::db::DEFAULT_MAX_REORG_DEPTH;
+
+    fn open(path: &Path) -> Arc<DB> {
+        let column_families = BlockCachedDatabase::<
+            AddressED,
+            AccountInfoED,
+            BlockHistoryCacheData<AccountInfoED>,
+        >::column_families("test_db");
+        Arc::new(open_database(path, &column_families).unwrap())
+    }
+
+    fn commit<K, V, C>(db: &mut BlockCachedDatabase<K, V, C>, block_number: u64)
+    where
+        K: Encode + Decode + Eq + std::hash::Hash + Clone,
+        V: Encode + Decode + Eq + Clone,
+        C: BlockHistoryCache<V> + Clone,
+    {
+        let mut batch = WriteBatch::default();
+        db.commit(&mut batch, block_number).unwrap();
+        db.db.write(batch).unwrap();
+        db.clear_cache();
+    }
+
+    fn reorg<K, V, C>(db: &mut BlockCachedDatabase<K, V, C>, latest_valid_block_number: u64)
+    where
+        K: Encode + Decode + Eq + std::hash::Hash + Clone,
+        V: Encode + Decode + Eq + Clone,
+        C: BlockHistoryCache<V> + Clone,
+    {
+        let mut batch = WriteBatch::default();
+        db.reorg(&mut batch, latest_valid_block_number).unwrap();
+        db.commit(&mut batch, latest_valid_block_number).unwrap();
+        db.db.write(batch).unwrap();
+        db.clear_cache();
+    }
 
     #[test]
     fn test_cache_only() {
@@ -249,7 +743,12 @@ mod tests {
             AddressED,
             AccountInfoED,
             BlockHistoryCacheData<AccountInfoED>,
-        >::new(path.path(), "test_db");
+        >::new(
+            &open(path.path()),
+            "test_db",
+            None,
+            Some(DEFAULT_MAX_REORG_DEPTH),
+        );
 
         let address: Address = "0x1234567890123456789012345678901234567890"
             .parse()
@@ -282,7 +781,12 @@ mod tests {
             AddressED,
             AccountInfoED,
             BlockHistoryCacheData<AccountInfoED>,
-        >::new(path.path(), "test_db");
+        >::new(
+            &open(path.path()),
+            "test_db",
+            None,
+            Some(DEFAULT_MAX_REORG_DEPTH),
+        );
 
         let address: Address = "0x1234567890123456789012345678901234567890"
             .parse()
@@ -301,26 +805,24 @@ mod tests {
         assert_eq!(account_info.0.nonce, 1);
         assert_eq!(account_info.0.code_hash, B256::from([1; 32]));
 
-        db.commit(1).unwrap();
-
-        let real_db = db.db;
+        commit(&mut db, 1);
 
-        let account_info = real_db.get(address_ed.encode().unwrap()).unwrap();
+        let account_info = db.db.get_cf(db.cf(), address_ed.encode().unwrap()).unwrap();
 
         let account_info = AccountInfoED::decode(account_info.unwrap().to_vec()).unwrap();
         assert_eq!(account_info.0.balance, U256::from(100));
         assert_eq!(account_info.0.nonce, 1);
         assert_eq!(account_info.0.code_hash, B256::from([1; 32]));
 
-        let cache_db = db.cache_db;
-
-        let cache = cache_db.get(address_ed.encode().unwrap()).unwrap();
-
-        let cache =
-            BlockHistoryCacheData::<AccountInfoED>::decode(cache.unwrap().to_vec()).unwrap();
-        assert_eq!(cache.latest().unwrap().0.balance, U256::from(100));
-        assert_eq!(cache.latest().unwrap().0.nonce, 1);
-        assert_eq!(cache.latest().unwrap().0.code_hash, B256::from([1; 32]));
+        // Journal has the value before the change
+        let journal_value = db
+            .db
+            .get_cf(
+                db.journal_cf(),
+                get_journal_key(1, &address_ed.encode().unwrap()),
+            )
+            .unwrap();
+        assert!(decode_journal_value::<AccountInfoED>(journal_value.unwrap()).is_none());
     }
 
     #[test]
@@ -330,7 +832,12 @@ mod tests {
             AddressED,
             AccountInfoED,
             BlockHistoryCacheData<AccountInfoED>,
-        >::new(path.path(), "test_db");
+        >::new(
+            &open(path.path()),
+            "test_db",
+            None,
+            Some(DEFAULT_MAX_REORG_DEPTH),
+        );
 
         let address: Address = "0x1234567890123456789012345678901234567890"
             .parse()
@@ -349,8 +856,8 @@ mod tests {
         assert_eq!(account_info.0.nonce, 1);
         assert_eq!(account_info.0.code_hash, B256::from([1; 32]));
 
-        db.commit(1).unwrap();
-        db.reorg(0).unwrap();
+        commit(&mut db, 1);
+        reorg(&mut db, 0);
         db.clear_cache();
 
         let account_info = db.latest(&address_ed);
@@ -364,7 +871,12 @@ mod tests {
             AddressED,
             AccountInfoED,
             BlockHistoryCacheData<AccountInfoED>,
-        >::new(path.path(), "test_db");
+        >::new(
+            &open(path.path()),
+            "test_db",
+            None,
+            Some(DEFAULT_MAX_REORG_DEPTH),
+        );
 
         let address: Address = "0x1234567890123456789012345678901234567890"
             .parse()
@@ -383,15 +895,322 @@ mod tests {
                 }),
             );
         }
-        db.commit(10).unwrap();
+        commit(&mut db, 10);
 
-        db.reorg(5).unwrap();
+        reorg(&mut db, 5);
 
-        db.commit(5).unwrap();
+        commit(&mut db, 5);
 
         let account_info = db.latest(&address_ed).unwrap().unwrap();
         assert_eq!(account_info.0.balance, U256::from(100 + 5));
         assert_eq!(account_info.0.nonce, 1 + 5);
         assert_eq!(account_info.0.code_hash, B256::from([1; 32]));
     }
+
+    #[test]
+    fn test_get_range() {
+        let path = TempDir::new().unwrap();
+        let mut db = BlockCachedDatabase::<U64ED, U64ED, BlockHistoryCacheData<U64ED>>::new(
+            &open(path.path()),
+            "test_db",
+            None,
+            Some(DEFAULT_MAX_REORG_DEPTH),
+        );
+        assert!(db.is_empty());
+
+        for i in 0..10 {
+            db.set(1, U64ED::from_u64(i), U64ED::from_u64(i * 10))
+                .unwrap();
+        }
+        commit(&mut db, 1);
+
+        // Values in the cache override the database, and reorged values are removed
+        db.set(2, U64ED::from_u64(3), U64ED::from_u64(300)).unwrap();
+        for i in 10..20 {
+            db.set(2, U64ED::from_u64(i), U64ED::from_u64(i * 10))
+                .unwrap();
+        }
+        assert!(!db.is_empty());
+
+        let range = db
+            .get_range(&U64ED::from_u64(2), &U64ED::from_u64(12))
+            .unwrap();
+        let keys: Vec<u64> = range.iter().map(|(key, _)| key.to_u64()).collect();
+        assert_eq!(keys, (2..12).collect::<Vec<u64>>());
+        assert_eq!(range[1].1.to_u64(), 300);
+
+        commit(&mut db, 2);
+        reorg(&mut db, 1);
+
+        let range = db
+            .get_range(&U64ED::from_u64(0), &U64ED::from_u64(20))
+            .unwrap();
+        let keys: Vec<u64> = range.iter().map(|(key, _)| key.to_u64()).collect();
+        assert_eq!(keys, (0..10).collect::<Vec<u64>>());
+        assert_eq!(range[3].1.to_u64(), 30);
+    }
+
+    #[test]
+    fn test_history_after_cache_expires() {
+        let path = TempDir::new().unwrap();
+        let mut db = BlockCachedDatabase::<U64ED, U64ED, BlockHistoryCacheData<U64ED>>::new(
+            &open(path.path()),
+            "test_db",
+            None,
+            Some(DEFAULT_MAX_REORG_DEPTH),
+        );
+        let key = U64ED::from_u64(1);
+
+        db.set(1, key.clone(), U64ED::from_u64(10)).unwrap();
+        commit(&mut db, 1);
+        assert!(db.at(&key, 0).unwrap().is_none());
+        assert_eq!(db.at(&key, 1).unwrap().unwrap().to_u64(), 10);
+
+        // Journal entries are removed when they are too old for a reorg
+        reorg(&mut db, 20);
+        assert!(db.get_last_journal_block_number().unwrap().is_none());
+        assert!(db
+            .db
+            .iterator_cf(db.journal_index_cf(), IteratorMode::Start)
+            .next()
+            .is_none());
+        assert_eq!(db.at(&key, 20).unwrap().unwrap().to_u64(), 10);
+
+        db.set(21, key.clone(), U64ED::from_u64(20)).unwrap();
+        commit(&mut db, 21);
+        assert_eq!(db.at(&key, 20).unwrap().unwrap().to_u64(), 10);
+        assert_eq!(db.at(&key, 21).unwrap().unwrap().to_u64(), 20);
+
+        // Reorg restores the value from before the history was removed
+        reorg(&mut db, 20);
+        assert_eq!(db.latest(&key).unwrap().unwrap().to_u64(), 10);
+    }
+
+    #[test]
+    fn test_archive_mode() {
+        let path = TempDir::new().unwrap();
+        let mut db = BlockCachedDatabase::<U64ED, U64ED, BlockHistoryCacheData<U64ED>>::new(
+            &open(path.path()),
+            "test_db",
+            Some(0),
+            Some(DEFAULT_MAX_REORG_DEPTH),
+        );
+        let key = U64ED::from_u64(1);
+
+        // Changes are available before and after they are committed
+        db.set(5, key.clone(), U64ED::from_u64(10)).unwrap();
+        assert_eq!(db.at(&key, 5).unwrap().unwrap().to_u64(), 10);
+        commit(&mut db, 5);
+        db.set(50, key.clone(), U64ED::from_u64(20)).unwrap();
+        db.set(50, key.clone(), U64ED::from_u64(30)).unwrap();
+        commit(&mut db, 50);
+        db.set(100, key.clone(), U64ED::from_u64(40)).unwrap();
+        commit(&mut db, 100);
+
+        assert!(db.at(&key, 4).unwrap().is_none());
+        assert_eq!(db.at(&key, 5).unwrap().unwrap().to_u64(), 10);
+        assert_eq!(db.at(&key, 49).unwrap().unwrap().to_u64(), 10);
+        assert_eq!(db.at(&key, 50).unwrap().unwrap().to_u64(), 30);
+        assert_eq!(db.at(&key, 100).unwrap().unwrap().to_u64(), 40);
+        assert_eq!(db.at(&key, 1000).unwrap().unwrap().to_u64(), 40);
+
+        // Other keys with the same prefix are not mixed up
+        let other_key = U64ED::from_u64(2);
+        assert!(db.at(&other_key, 100).unwrap().is_none());
+
+        // Reorg removes the archived changes after the latest valid block
+        reorg(&mut db, 99);
+        assert_eq!(db.at(&key, 100).unwrap().unwrap().to_u64(), 30);
+        assert_eq!(db.latest(&key).unwrap().unwrap().to_u64(), 30);
+
+        // Cleared changes are not archived
+        db.set(101, key.clone(), U64ED::from_u64(50)).unwrap();
+        db.clear_cache();
+        assert_eq!(db.at(&key, 101).unwrap().unwrap().to_u64(), 30);
+    }
+
+    #[test]
+    fn test_archive_mode_enabled_later() {
+        let path = TempDir::new().unwrap();
+        let database = open(path.path());
+        let open_db = |archive_start_block| {
+            BlockCachedDatabase::<U64ED, U64ED, BlockHistoryCacheData<U64ED>>::new(
+                &database,
+                "test_db",
+                archive_start_block,
+                Some(DEFAULT_MAX_REORG_DEPTH),
+            )
+        };
+        let key = U64ED::from_u64(1);
+        let new_key = U64ED::from_u64(2);
+
+        let mut db = open_db(None);
+        db.set(5, key.clone(), U64ED::from_u64(10)).unwrap();
+        commit(&mut db, 5);
+
+        // Value before archive mode is archived with the first archived change
+        let mut db = open_db(Some(20));
+        db.set(30, key.clone(), U64ED::from_u64(20)).unwrap();
+        db.set(30, new_key.clone(), U64ED::from_u64(7)).unwrap();
+        commit(&mut db, 30);
+        assert_eq!(db.at(&key, 20).unwrap().unwrap().to_u64(), 10);
+        assert_eq!(db.at(&key, 29).unwrap().unwrap().to_u64(), 10);
+        assert_eq!(db.at(&key, 30).unwrap().unwrap().to_u64(), 20);
+        assert!(db.at(&new_key, 29).unwrap().is_none());
+        assert_eq!(db.at(&new_key, 30).unwrap().unwrap().to_u64(), 7);
+
+        // Changes archived before archive mode was disabled are ignored once it's enabled again
+        let mut db = open_db(None);
+        db.set(40, key.clone(), U64ED::from_u64(40)).unwrap();
+        commit(&mut db, 40);
+        let mut db = open_db(Some(50));
+        assert_eq!(db.at(&key, 50).unwrap().unwrap().to_u64(), 40);
+        db.set(60, key.clone(), U64ED::from_u64(60)).unwrap();
+        commit(&mut db, 60);
+        assert_eq!(db.at(&key, 55).unwrap().unwrap().to_u64(), 40);
+        assert_eq!(db.at(&key, 60).unwrap().unwrap().to_u64(), 60);
+    }
+
+    #[test]
+    fn test_discard_changes_after() {
+        let path = TempDir::new().unwrap();
+        let mut db = BlockCachedDatabase::<U64ED, U64ED, BlockHistoryCacheData<U64ED>>::new(
+            &open(path.path()),
+            "test_db",
+            None,
+            Some(DEFAULT_MAX_REORG_DEPTH),
+        );
+        let committed_key = U64ED::from_u64(1);
+        let new_key = U64ED::from_u64(2);
+
+        db.set(1, committed_key.clone(), U64ED::from_u64(10))
+            .unwrap();
+        commit(&mut db, 1);
+        db.clear_cache();
+        db.set(2, committed_key.clone(), U64ED::from_u64(20))
+            .unwrap();
+        db.set(3, committed_key.clone(), U64ED::from_u64(30))
+            .unwrap();
+        db.set(3, new_key.clone(), U64ED::from_u64(40)).unwrap();
+
+        db.discard_changes_after(2);
+        assert_eq!(db.latest(&committed_key).unwrap().unwrap().to_u64(), 20);
+        assert!(db.latest(&new_key).unwrap().is_none());
+
+        // Discarded changes are not written to the database
+        commit(&mut db, 3);
+        db.clear_cache();
+        assert_eq!(db.latest(&committed_key).unwrap().unwrap().to_u64(), 20);
+        assert!(db.latest(&new_key).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_commit_written_with_batch() {
+        let path = TempDir::new().unwrap();
+        let database = open(path.path());
+        let mut db = BlockCachedDatabase::<U64ED, U64ED, BlockHistoryCacheData<U64ED>>::new(
+            &database,
+            "test_db",
+            None,
+            Some(DEFAULT_MAX_REORG_DEPTH),
+        );
+        let key = U64ED::from_u64(1);
+        db.set(1, key.clone(), U64ED::from_u64(100)).unwrap();
+
+        let mut batch = WriteBatch::default();
+        db.commit(&mut batch, 1).unwrap();
+        assert!(database
+            .get_cf(db.cf(), key.encode().unwrap())
+            .unwrap()
+            .is_none());
+
+        database.write(batch).unwrap();
+        db.clear_cache();
+        assert_eq!(db.latest(&key).unwrap().unwrap(), U64ED::from_u64(100));
+    }
+
+    #[test]
+    fn test_unbounded_reorg_depth() {
+        let path = TempDir::new().unwrap();
+        let mut db = BlockCachedDatabase::<U64ED, U64ED, BlockHistoryCacheData<U64ED>>::new(
+            &open(path.path()),
+            "test_db",
+            None,
+            None,
+        );
+        let key = U64ED::from_u64(1);
+        for block_number in 1..=200 {
+            db.set(
+                block_number,
+                key.clone(),
+                U64ED::from_u64(block_number * 10),
+            )
+            .unwrap();
+            commit(&mut db, block_number);
+        }
+        assert_eq!(db.at(&key, 50).unwrap().unwrap().to_u64(), 500);
+
+        reorg(&mut db, 50);
+        assert_eq!(db.latest(&key).unwrap().unwrap().to_u64(), 500);
+        assert_eq!(db.get_last_journal_block_number().unwrap(), Some(50));
+
+        reorg(&mut db, 0);
+        assert!(db.latest(&key).unwrap().is_none());
+        assert!(db.is_empty());
+    }
+
+    #[test]
+    fn test_migrate_history_cache() {
+        let path = TempDir::new().unwrap();
+        let mut column_families =
+            BlockCachedDatabase::<U64ED, U64ED, BlockHistoryCacheData<U64ED>>::column_families(
+                "test_db",
+            );
+        column_families.push(get_history_cache_column_family("test_db"));
+        let mut database = open_database(path.path(), &column_families).unwrap();
+
+        // History cache with the values at blocks 1 and 2
+        let key = U64ED::from_u64(1);
+        let mut cache_bytes = Vec::new();
+        for (block_number, value) in [(1u64, 10u64), (2, 20)] {
+            let value_bytes = U64ED::from_u64(value).encode().unwrap();
+            cache_bytes.extend_from_slice(&block_number.to_be_bytes());
+            cache_bytes.extend_from_slice(&(value_bytes.len() as u32).to_be_bytes());
+            cache_bytes.extend_from_slice(&value_bytes);
+        }
+        let cache_cf = database
+            .cf_handle(&get_history_cache_column_family("test_db"))
+            .unwrap();
+        database
+            .put_cf(cache_cf, key.encode().unwrap(), cache_bytes)
+            .unwrap();
+        let cf = database.cf_handle("test_db").unwrap();
+        database
+            .put_cf(
+                cf,
+                key.encode().unwrap(),
+                U64ED::from_u64(20).encode().unwrap(),
+            )
+            .unwrap();
+
+        assert!(migrate_history_cache(&mut database, "test_db").unwrap());
+        assert!(database
+            .cf_handle(&get_history_cache_column_family("test_db"))
+            .is_none());
+        assert!(!migrate_history_cache(&mut database, "test_db").unwrap());
+
+        // Journal index is built for the migrated journal
+        assert!(migrate_journal_index(&database, "test_db").unwrap());
+        assert!(!migrate_journal_index(&database, "test_db").unwrap());
+
+        let mut db = BlockCachedDatabase::<U64ED, U64ED, BlockHistoryCacheData<U64ED>>::new(
+            &Arc::new(database),
+            "test_db",
+            None,
+            None,
+        );
+        assert_eq!(db.at(&key, 1).unwrap().unwrap().to_u64(), 10);
+        reorg(&mut db, 1);
+        assert_eq!(db.latest(&key).unwrap().unwrap().to_u64(), 10);
+    }
 }
@@ -1,7 +1,7 @@
 use std::collections::BTreeMap;
-use std::path::Path;
+use std::sync::Arc;
 
-use rocksdb::{Error, IteratorMode, Options, DB};
+use rocksdb::{ColumnFamily, Error, IteratorMode, WriteBatch, DB};
 
 use crate::db::types::{Decode, Encode, U64ED};
 
@@ -9,6 +9,7 @@ use crate::db::types::{Decode, Encode, U64ED};
 ///
 /// It uses a cache to store the data in memory and only writes to the database when commit is called
 /// It also supports reorg by deleting data that is not valid anymore
+/// Data is stored in a column family of a shared database, so changes of all databases can be written at once
 /// It uses U256 as the key (block_number) and V as the value
 ///
 /// V: the type of the value to store
@@ -17,7 +18,8 @@ pub struct BlockDatabase<V>
 where
     V: Encode + Decode + Clone,
 {
-    db: DB,
+    db: Arc<DB>,
+    name: String,
     cache: BTreeMap<u64, V>,
 }
 
@@ -27,23 +29,29 @@ where
 {
     /// Create a new BlockDatabase
     ///
-    /// It creates a new database if it does not exist
+    /// The column families returned by column_families should exist in the database
     ///
-    /// path: &Path - the path to the database
+    /// db: &Arc<DB> - the shared database
     /// name: &str - the name of the database
     ///
     /// Returns: BlockDatabase<V> - the created BlockDatabase
-    pub fn new(path: &Path, name: &str) -> Self {
-        let mut opts = Options::default();
-        opts.create_if_missing(true);
-        opts.set_max_open_files(256);
-        let db = DB::open(&opts, &path.join(Path::new(name))).unwrap();
+    pub fn new(db: &Arc<DB>, name: &str) -> Self {
         Self {
-            db,
+            db: db.clone(),
+            name: name.to_string(),
             cache: BTreeMap::new(),
         }
     }
 
+    /// Get the column families used by a database with the given name
+    pub fn column_families(name: &str) -> Vec<String> {
+        vec![name.to_string()]
+    }
+
+    fn cf(&self) -> &ColumnFamily {
+        self.db.cf_handle(&self.name).unwrap()
+    }
+
     /// Get the value for a block number
     //
     /// It first checks the cache and then the database
@@ -52,17 +60,18 @@ where
     //
     /// block_number: u64 - the block number to get the value for
     /// Returns: Option<V> - the value for the block number
-    pub fn get(&mut self, key: u64) -> Result<Option<V>, Error> {
+    pub fn get(&self, key: u64) -> Result<Option<V>, Error> {
         if let Some(value) = self.cache.get(&key) {
             return Ok(Some(value.clone()));
         }
 
-        let value_bytes = self.db.get(U64ED::from_u64(key).encode().unwrap())?;
+        let value_bytes = self
+            .db
+            .get_cf(self.cf(), U64ED::from_u64(key).encode().unwrap())?;
         if value_bytes.is_none() {
             return Ok(None);
         }
         let value = V::decode(value_bytes.unwrap().to_vec()).unwrap();
-        self.cache.insert(key.clone(), value.clone());
         Ok(Some(value))
     }
 
@@ -78,15 +87,17 @@ where
 
     /// Commit the cache to the database
     //
-    /// It writes all the values in the cache to the database
+    /// It adds all the values in the cache to the batch, they are written when the batch is written
     /// It does not clear the cache
-    pub fn commit(&mut self) -> Result<(), Error> {
+    pub fn commit(&self, batch: &mut WriteBatch) -> Result<(), Error> {
         for (key, value) in self.cache.iter() {
             let value_bytes = value.encode().unwrap();
-            self.db
-                .put(U64ED::from_u64(*key).encode().unwrap(), &value_bytes)?;
+            batch.put_cf(
+                self.cf(),
+                U64ED::from_u64(*key).encode().unwrap(),
+                &value_bytes,
+            );
         }
-        self.db.flush()?;
         Ok(())
     }
 
@@ -100,6 +111,16 @@ where
         self.cache.clear();
     }
 
+    /// Discard the values after the latest valid block number from the cache
+    //
+    /// It doesn't delete the data from the database, so it only reverts the values that are not committed yet
+    //
+    /// latest_valid_block_number: u64 - the latest valid block number
+    pub fn discard_changes_after(&mut self, latest_valid_block_number: u64) {
+        self.cache
+            .retain(|block_number, _| *block_number <= latest_valid_block_number);
+    }
+
     /// Get the last key in the database
     //
     /// It returns the last key in the database
@@ -109,7 +130,7 @@ where
     pub fn last_key(&self) -> Result<Option<u64>, Error> {
         let result = self
             .db
-            .full_iterator(IteratorMode::End)
+            .full_iterator_cf(self.cf(), IteratorMode::End)
             .take(1)
             .map(|res| {
                 let (key, _) = res.unwrap();
@@ -132,10 +153,14 @@ where
     /// Reorg the database
     //
     /// It deletes all the data that is not valid anymore, i.e. the data with block number greater than latest_valid_block_number
-    /// Make sure to call commit after calling this function to write the changes to the database
+    /// Deletions are added to the batch, make sure to write the batch to apply the changes to the database
     //
     /// latest_valid_block_number: u64 - the latest valid block number
-    pub fn reorg(&mut self, latest_valid_block_number: u64) -> Result<(), Error> {
+    pub fn reorg(
+        &mut self,
+        batch: &mut WriteBatch,
+        latest_valid_block_number: u64,
+    ) -> Result<(), Error> {
         let mut current = latest_valid_block_number + 1;
         let end = self.last_key().unwrap();
         if end.is_none() {
@@ -143,9 +168,7 @@ where
         }
         let end = end.unwrap();
         while end >= current {
-            self.db
-                .delete(U64ED::from_u64(current).encode().unwrap())
-                .unwrap();
+            batch.delete_cf(self.cf(), U64ED::from_u64(current).encode().unwrap());
             self.cache.remove(&current);
             current += 1;
         }
@@ -157,15 +180,25 @@ where
 #[cfg(test)]
 mod tests {
     use revm::primitives::U256;
+    use rocksdb::WriteBatch;
+    use std::sync::Arc;
+
     use tempfile::TempDir;
 
-    use crate::db::cached_database::BlockDatabase;
+    use crate::db::cached_database::{open_database, BlockDatabase};
     use crate::db::types::U256ED;
 
     #[test]
     fn test_block_database() {
         let tempdir = TempDir::new().unwrap();
-        let mut db = BlockDatabase::<U256ED>::new(tempdir.path(), "test");
+        let database = Arc::new(
+            open_database(
+                tempdir.path(),
+                &BlockDatabase::<U256ED>::column_families("test"),
+            )
+            .unwrap(),
+        );
+        let mut db = BlockDatabase::<U256ED>::new(&database, "test");
 
         let block_number = 1;
         let value = U256ED::from_u256(U256::from(100));
@@ -181,7 +214,9 @@ mod tests {
 
         assert_eq!(db.last_key().unwrap().unwrap(), 3);
 
-        db.commit().unwrap();
+        let mut batch = WriteBatch::default();
+        db.commit(&mut batch).unwrap();
+        database.write(batch).unwrap();
         db.clear_cache();
 
         assert_eq!(
@@ -198,7 +233,9 @@ mod tests {
         );
         assert_eq!(db.last_key().unwrap().unwrap(), 3);
 
-        db.reorg(2).unwrap();
+        let mut batch = WriteBatch::default();
+        db.reorg(&mut batch, 2).unwrap();
+        database.write(batch).unwrap();
 
         assert_eq!(
             db.get(1).unwrap().unwrap(),
@@ -1,15 +1,18 @@
 use std::collections::BTreeMap;
-use std::error::Error;
 
 use crate::db::types::{Decode, Encode};
-use crate::db::MAX_HISTORY_SIZE;
 
 /// Cache to store the history of a value at different block numbers
+///
+/// It only keeps the changes that are not written to the database yet,
+/// committed history is kept in the journal of the database
 #[derive(Clone)]
 pub struct BlockHistoryCacheData<V>
 where
     V: Encode + Decode + Clone + Eq,
 {
+    /// Value before the first change in the cache
+    initial_value: Option<V>,
     cache: BTreeMap<u64, Option<V>>,
 }
 
@@ -19,9 +22,11 @@ where
 {
     fn new(initial_value: Option<V>) -> Self;
     fn latest(&self) -> Option<V>;
+    fn at(&self, block_number: u64) -> Option<V>;
     fn set(&mut self, block_number: u64, value: V);
     fn reorg(&mut self, latest_valid_block_number: u64);
-    fn is_old(&self, block_number: u64) -> bool;
+    fn first_change(&self) -> Option<u64>;
+    fn previous_values(&self) -> Vec<(u64, Option<V>)>;
 }
 
 impl<V> BlockHistoryCache<V> for BlockHistoryCacheData<V>
@@ -30,22 +35,34 @@ where
 {
     /// Create a new BlockHistoryCache
     ///
-    /// initial_value: Option<V> - the initial value to store
+    /// initial_value: Option<V> - the value before any change in the cache
     ///
     /// Returns: BlockHistoryCacheData<V> - the created BlockHistoryCache
     fn new(initial_value: Option<V>) -> Self {
-        let mut cache = BTreeMap::new();
-        if let Some(value) = initial_value {
-            cache.insert(0, Some(value));
-        } else {
-            cache.insert(0, None);
+        Self {
+            initial_value,
+            cache: BTreeMap::new(),
         }
-        Self { cache }
     }
 
     /// Get the latest value
     fn latest(&self) -> Option<V> {
-        self.cache.values().last().cloned().unwrap_or(None)
+        match self.cache.values().last() {
+            Some(value) => value.clone(),
+            None => self.initial_value.clone(),
+        }
+    }
+
+    /// Get the value at the end of a block
+    ///
+    /// Initial value is returned for the blocks before the first change
+    ///
+    /// block_number: u64 - the block number
+    fn at(&self, block_number: u64) -> Option<V> {
+        match self.cache.range(..=block_number).next_back() {
+            Some((_, value)) => value.clone(),
+            None => self.initial_value.clone(),
+        }
     }
 
     /// Set the value for a block number
@@ -66,101 +83,31 @@ where
             }
         }
         self.cache.insert(block_number, Some(value));
-
-        // Remove the old values
-        // Any key that is less than the set block number - MAX_HISTORY_SIZE is too old
-        let keys_to_remove: Vec<u64> = self
-            .cache
-            .keys()
-            .filter(|&&key| key + MAX_HISTORY_SIZE <= block_number)
-            .cloned()
-            .collect();
-
-        // Remove all except the last one to keep at least one value in the cache
-        if keys_to_remove.len() != 0 {
-            for key in keys_to_remove.iter().take(keys_to_remove.len() - 1) {
-                self.cache.remove(key);
-            }
-        }
     }
 
     /// Reorganize the cache, removing all values with block number greater than the latest valid block number
     ///
     /// latest_valid_block_number: U256 - the latest valid block number
     fn reorg(&mut self, latest_valid_block_number: u64) {
-        let keys_to_remove: Vec<u64> = self
-            .cache
-            .keys()
-            .filter(|&&key| key > latest_valid_block_number)
-            .cloned()
-            .collect();
-        for key in keys_to_remove {
-            self.cache.remove(&key);
-        }
+        self.cache.split_off(&(latest_valid_block_number + 1));
     }
 
-    /// Check if the cache is too old for a reorg at the latest block number
-    ///
-    /// A cache is considered too old for a reorg if the latest stored value is older than MAX_HISTORY_SIZE
-    ///
-    /// Caches too old for a reorg should be removed from the database, as they are not useful anymore
-    ///
-    /// block_number: u64 - current block number
-    /// Returns: bool - true if the cache is empty, false otherwise
-    fn is_old(&self, latest_block_number: u64) -> bool {
-        if self.cache.is_empty() {
-            return true;
-        }
-        let latest_stored_block_number = self.cache.keys().last().unwrap();
-        if *latest_stored_block_number + MAX_HISTORY_SIZE < latest_block_number {
-            return true;
-        }
-        false
+    /// Get the block number of the first change in the cache, None if the value hasn't changed
+    fn first_change(&self) -> Option<u64> {
+        self.cache.keys().next().cloned()
     }
-}
 
-impl<V> Encode for BlockHistoryCacheData<V>
-where
-    V: Encode + Decode + Clone + Eq,
-{
-    fn encode(&self) -> Result<Vec<u8>, Box<dyn Error>> {
-        let mut bytes = Vec::new();
+    /// Get the value before each change in the cache, with the block number of the change
+    ///
+    /// These are the values to restore if the block of the change is reorged
+    fn previous_values(&self) -> Vec<(u64, Option<V>)> {
+        let mut previous_value = self.initial_value.clone();
+        let mut previous_values = Vec::new();
         for (block_number, value) in self.cache.iter() {
-            bytes.extend_from_slice(&block_number.to_be_bytes());
-            if value.is_none() {
-                bytes.extend_from_slice(&0u32.to_be_bytes());
-                continue;
-            }
-            let value_bytes = value.as_ref().unwrap().encode().unwrap();
-            let size: u32 = value_bytes.len().try_into().unwrap();
-            bytes.extend_from_slice(&size.to_be_bytes());
-            bytes.extend_from_slice(&value_bytes);
-        }
-        Ok(bytes)
-    }
-}
-
-impl<V> Decode for BlockHistoryCacheData<V>
-where
-    V: Encode + Decode + Clone + Eq,
-{
-    fn decode(bytes: Vec<u8>) -> Result<Self, Box<dyn Error>> {
-        let mut cache: BTreeMap<u64, Option<V>> = BTreeMap::new();
-        let mut i = 0;
-        while i < bytes.len() {
-            let block_number = u64::from_be_bytes(bytes[i..i + 8].try_into().unwrap());
-            i += 8;
-            let size = u32::from_be_bytes(bytes[i..i + 4].try_into().unwrap()) as usize;
-            i += 4;
-            if size == 0 {
-                cache.insert(block_number, None);
-                continue;
-            }
-            let value = V::decode((&bytes[i..i + size]).to_vec()).unwrap();
-            cache.insert(block_number, Some(value));
-            i += size;
+            previous_values.push((*block_number, previous_value));
+            previous_value = value.clone();
         }
-        Ok(Self { cache })
+        previous_values
     }
 }
 
@@ -200,16 +147,23 @@ mod tests {
     }
 
     #[test]
-    fn test_history_size() {
-        let mut cache = BlockHistoryCacheData::<U256ED>::new(None);
-
-        for i in 0..MAX_HISTORY_SIZE + 2 {
-            let value = U256::from(100 * i);
-            let value_ed = U256ED::from_u256(value);
-            cache.set(i, value_ed.clone());
+    fn test_previous_values() {
+        let mut cache =
+            BlockHistoryCacheData::<U256ED>::new(Some(U256ED::from_u256(U256::from(1))));
+        assert!(cache.first_change().is_none());
+        assert!(cache.previous_values().is_empty());
+
+        for i in 2..5 {
+            cache.set(i, U256ED::from_u256(U256::from(100 * i)));
         }
 
-        assert_eq!(cache.cache.len(), (MAX_HISTORY_SIZE + 1) as usize);
+        assert_eq!(cache.first_change(), Some(2));
+        let previous_values = cache.previous_values();
+        assert_eq!(previous_values.len(), 3);
+        assert_eq!(previous_values[0].0, 2);
+        assert_eq!(previous_values[0].1.as_ref().unwrap().0, U256::from(1));
+        assert_eq!(previous_values[2].0, 4);
+        assert_eq!(previous_values[2].1.as_ref().unwrap().0, U256::from(300));
     }
 
     #[test]
@@ -220,17 +174,16 @@ mod tests {
     }
 
     #[test]
-    fn test_encode_decode() {
+    fn test_at() {
         let mut cache = BlockHistoryCacheData::<U256ED>::new(None);
-        let block_number = 1;
-        let value = U256::from(100);
-        let value_ed = U256ED::from_u256(value);
-
-        cache.set(block_number, value_ed.clone());
-        let encoded = BlockHistoryCacheData::<U256ED>::encode(&cache).unwrap();
-        let decoded = BlockHistoryCacheData::<U256ED>::decode(encoded).unwrap();
-
-        assert_eq!(decoded.latest().unwrap().0, value_ed.0);
+        cache.set(2, U256ED::from_u256(U256::from(100)));
+        cache.set(5, U256ED::from_u256(U256::from(200)));
+
+        assert!(cache.at(1).is_none());
+        assert_eq!(cache.at(2).unwrap().0, U256::from(100));
+        assert_eq!(cache.at(4).unwrap().0, U256::from(100));
+        assert_eq!(cache.at(5).unwrap().0, U256::from(200));
+        assert_eq!(cache.at(10).unwrap().0, U256::from(200));
     }
 
     #[test]
@@ -301,5 +254,6 @@ mod tests {
         cache.set(2, value_ed.clone());
 
         assert_eq!(cache.cache.len(), 1);
+        assert_eq!(cache.first_change(), Some(0));
     }
 }
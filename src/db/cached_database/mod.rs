@@ -1,8 +1,145 @@
+use std::path::Path;
+
+use rocksdb::{Direction, Error, IteratorMode, Options, WriteBatch, DB};
+
 mod block_history_cache;
 pub use block_history_cache::{BlockHistoryCache, BlockHistoryCacheData};
 
 mod block_database;
 pub use block_database::BlockDatabase;
 
+mod node_database;
+pub use node_database::NodeDatabase;
+
+mod bitcoin_cache;
+pub use bitcoin_cache::BitcoinCache;
+
 mod block_cached_database;
-pub use block_cached_database::BlockCachedDatabase;
+pub use block_cached_database::{
+    get_history_cache_column_family, migrate_history_cache, migrate_journal_index,
+    BlockCachedDatabase,
+};
+
+/// Opens the database shared by all the block databases, missing column families are created
+///
+/// path: &Path - the path to the database
+/// column_families: &[String] - all the column families in the database
+///
+/// Returns: DB - the opened database
+pub fn open_database(path: &Path, column_families: &[String]) -> Result<DB, Error> {
+    let mut opts = Options::default();
+    opts.create_if_missing(true);
+    opts.create_missing_column_families(true);
+    opts.set_max_open_files(256);
+    DB::open_cf(&opts, path, column_families)
+}
+
+/// Number of values written at once while moving a database into its column family
+const MIGRATION_BATCH_SIZE: usize = 10_000;
+
+/// Moves a database created before column families were used into its column family
+///
+/// Values are written in batches along with the last moved key, so an interrupted migration
+/// continues after it. The old database is destroyed after all of its values are written
+///
+/// db: &DB - the shared database
+/// path: &Path - the path to the old database
+/// column_family: &str - the column family to move the values to
+/// progress_column_family: &str - the column family to keep the last moved key in
+///
+/// Returns: bool - true if the old database existed and was moved
+pub fn migrate_database(
+    db: &DB,
+    path: &Path,
+    column_family: &str,
+    progress_column_family: &str,
+) -> Result<bool, Error> {
+    if !path.exists() {
+        return Ok(false);
+    }
+    let cf = db.cf_handle(column_family).unwrap();
+    let progress_cf = db.cf_handle(progress_column_family).unwrap();
+    let progress_key = get_migration_progress_key(column_family);
+    let opts = Options::default();
+    {
+        let old_db = DB::open(&opts, path)?;
+        let last_key = db.get_cf(progress_cf, &progress_key)?;
+        let iterator = match &last_key {
+            Some(last_key) => old_db.iterator(IteratorMode::From(last_key, Direction::Forward)),
+            None => old_db.iterator(IteratorMode::Start),
+        };
+        let mut batch = WriteBatch::default();
+        for kv_pair in iterator {
+            let (key, value) = kv_pair?;
+            if last_key.as_deref() == Some(&*key) {
+                continue;
+            }
+            batch.put_cf(cf, &key, value);
+            if batch.len() >= MIGRATION_BATCH_SIZE {
+                batch.put_cf(progress_cf, &progress_key, &key);
+                db.write(std::mem::take(&mut batch))?;
+            }
+        }
+        db.write(batch)?;
+    }
+    // Moving the values again is harmless if the old database isn't destroyed yet
+    db.delete_cf(progress_cf, &progress_key)?;
+    DB::destroy(&opts, path)?;
+    Ok(true)
+}
+
+/// Key of the last value moved by migrate_database, in the progress column family
+fn get_migration_progress_key(column_family: &str) -> Vec<u8> {
+    format!("migration_progress_{}", column_family).into_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use rocksdb::{Options, DB};
+    use tempfile::TempDir;
+
+    use crate::db::cached_database::{
+        get_migration_progress_key, migrate_database, open_database, MIGRATION_BATCH_SIZE,
+    };
+
+    #[test]
+    fn test_resume_migrate_database() {
+        let tempdir = TempDir::new().unwrap();
+        let old_path = tempdir.path().join("values");
+        let key_count = MIGRATION_BATCH_SIZE as u64 * 2 + 5;
+        {
+            let mut opts = Options::default();
+            opts.create_if_missing(true);
+            let old_db = DB::open(&opts, &old_path).unwrap();
+            for key in 0..key_count {
+                old_db.put(key.to_be_bytes(), [1]).unwrap();
+            }
+        }
+        let db = open_database(
+            &tempdir.path().join("db"),
+            &["values".to_string(), "metadata".to_string()],
+        )
+        .unwrap();
+        let cf = db.cf_handle("values").unwrap();
+        let progress_cf = db.cf_handle("metadata").unwrap();
+
+        // Interrupted migration continues after the last moved key
+        let last_key = (MIGRATION_BATCH_SIZE as u64 - 1).to_be_bytes();
+        db.put_cf(progress_cf, get_migration_progress_key("values"), last_key)
+            .unwrap();
+        assert!(migrate_database(&db, &old_path, "values", "metadata").unwrap());
+        assert!(!old_path.exists());
+        assert!(db.get_cf(cf, 0u64.to_be_bytes()).unwrap().is_none());
+        assert!(db.get_cf(cf, last_key).unwrap().is_none());
+        for key in [MIGRATION_BATCH_SIZE as u64, key_count - 1] {
+            assert_eq!(db.get_cf(cf, key.to_be_bytes()).unwrap(), Some(vec![1]));
+        }
+        assert!(db
+            .get_cf(progress_cf, get_migration_progress_key("values"))
+            .unwrap()
+            .is_none());
+
+        // Nothing to migrate once the old database is gone
+        assert!(!migrate_database(&db, &old_path, "values", "metadata").unwrap());
+    }
+}
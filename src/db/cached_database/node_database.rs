@@ -0,0 +1,129 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::sync::Arc;
+
+use revm::primitives::B256;
+use rocksdb::{ColumnFamily, WriteBatch, DB};
+
+use crate::db::merkle_trie::{NodeReader, NodeStore};
+
+/// Database to store the nodes of the state tries, keyed by their hash
+///
+/// Nodes are never changed or deleted, so it doesn't need a history, reorgs only change the roots
+/// New nodes are kept in the cache and only added to the database when commit is called
+pub struct NodeDatabase {
+    db: Arc<DB>,
+    name: String,
+    cache: HashMap<B256, Vec<u8>>,
+}
+
+impl NodeDatabase {
+    /// Create a new NodeDatabase
+    ///
+    /// The column families returned by column_families should exist in the database
+    ///
+    /// db: &Arc<DB> - the shared database
+    /// name: &str - the name of the database
+    ///
+    /// Returns: NodeDatabase - the created NodeDatabase
+    pub fn new(db: &Arc<DB>, name: &str) -> Self {
+        Self {
+            db: db.clone(),
+            name: name.to_string(),
+            cache: HashMap::new(),
+        }
+    }
+
+    /// Get the column families used by a database with the given name
+    pub fn column_families(name: &str) -> Vec<String> {
+        vec![name.to_string()]
+    }
+
+    fn cf(&self) -> &ColumnFamily {
+        self.db.cf_handle(&self.name).unwrap()
+    }
+
+    /// Commit the cache to the database
+    //
+    /// It adds all the nodes in the cache to the batch, they are written when the batch is written
+    /// It does not clear the cache
+    pub fn commit(&self, batch: &mut WriteBatch) {
+        for (hash, node) in self.cache.iter() {
+            batch.put_cf(self.cf(), hash.as_slice(), node);
+        }
+    }
+
+    /// Clear the cache
+    //
+    /// Nodes that are not committed are lost, they can be computed again from the tables
+    pub fn clear_cache(&mut self) {
+        self.cache.clear();
+    }
+}
+
+impl NodeReader for NodeDatabase {
+    fn get_node(&self, hash: B256) -> Result<Option<Vec<u8>>, Box<dyn Error>> {
+        if let Some(node) = self.cache.get(&hash) {
+            return Ok(Some(node.clone()));
+        }
+        Ok(self.db.get_cf(self.cf(), hash.as_slice())?)
+    }
+}
+
+impl NodeStore for NodeDatabase {
+    fn set_node(&mut self, hash: B256, node: Vec<u8>) {
+        self.cache.insert(hash, node);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    use revm::primitives::keccak256;
+    use rocksdb::WriteBatch;
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::db::cached_database::open_database;
+    use crate::db::merkle_trie::{MerkleTrie, EMPTY_ROOT};
+
+    #[test]
+    fn test_node_database() {
+        let tempdir = TempDir::new().unwrap();
+        let database = Arc::new(
+            open_database(tempdir.path(), &NodeDatabase::column_families("test")).unwrap(),
+        );
+        let mut db = NodeDatabase::new(&database, "test");
+        let mut expected_store = HashMap::new();
+
+        let mut trie = MerkleTrie::new(&mut db, EMPTY_ROOT);
+        let mut expected_trie = MerkleTrie::new(&mut expected_store, EMPTY_ROOT);
+        for i in 0u64..20 {
+            let key = keccak256(i.to_be_bytes());
+            trie.insert(key.as_slice(), vec![i as u8 + 1]).unwrap();
+            expected_trie
+                .insert(key.as_slice(), vec![i as u8 + 1])
+                .unwrap();
+        }
+        let root = trie.root();
+
+        let mut batch = WriteBatch::default();
+        db.commit(&mut batch);
+        database.write(batch).unwrap();
+        db.clear_cache();
+        assert!(db.get_node(root).unwrap().is_some());
+
+        // Existing nodes are read from the database
+        let mut trie = MerkleTrie::new(&mut db, root);
+        for i in 10u64..30 {
+            let key = keccak256(i.to_be_bytes());
+            trie.insert(key.as_slice(), vec![i as u8 + 2]).unwrap();
+            expected_trie
+                .insert(key.as_slice(), vec![i as u8 + 2])
+                .unwrap();
+        }
+        assert_eq!(trie.root(), expected_trie.root());
+    }
+}
@@ -0,0 +1,670 @@
+use std::collections::HashMap;
+use std::error::Error;
+
+use alloy_primitives::b256;
+use alloy_rlp::{Decodable, Encodable, Header, EMPTY_STRING_CODE};
+use revm::primitives::{keccak256, Log, B256, U256};
+
+/// Root hash of a trie without any values, keccak256 of the RLP encoding of an empty string
+pub const EMPTY_ROOT: B256 =
+    b256!("56e81f171bcc55a6ff8345e692c0f86e5b48e01b996cadc001622fb5e363b421");
+
+/// Read access to trie nodes, keyed by the keccak256 hash of their RLP encoding
+pub trait NodeReader {
+    fn get_node(&self, hash: B256) -> Result<Option<Vec<u8>>, Box<dyn Error>>;
+}
+
+/// Storage for trie nodes, keyed by the keccak256 hash of their RLP encoding
+pub trait NodeStore: NodeReader {
+    fn set_node(&mut self, hash: B256, node: Vec<u8>);
+}
+
+impl<T: NodeReader> NodeReader for &T {
+    fn get_node(&self, hash: B256) -> Result<Option<Vec<u8>>, Box<dyn Error>> {
+        (**self).get_node(hash)
+    }
+}
+
+impl<T: NodeReader> NodeReader for &mut T {
+    fn get_node(&self, hash: B256) -> Result<Option<Vec<u8>>, Box<dyn Error>> {
+        (**self).get_node(hash)
+    }
+}
+
+impl<T: NodeStore> NodeStore for &mut T {
+    fn set_node(&mut self, hash: B256, node: Vec<u8>) {
+        (**self).set_node(hash, node)
+    }
+}
+
+impl NodeReader for HashMap<B256, Vec<u8>> {
+    fn get_node(&self, hash: B256) -> Result<Option<Vec<u8>>, Box<dyn Error>> {
+        Ok(self.get(&hash).cloned())
+    }
+}
+
+impl NodeStore for HashMap<B256, Vec<u8>> {
+    fn set_node(&mut self, hash: B256, node: Vec<u8>) {
+        self.insert(hash, node);
+    }
+}
+
+/// Node of a Merkle-Patricia trie
+///
+/// Children are kept as they are embedded in the parent, either RLP encoded hash of the child,
+/// the RLP encoding of the child itself if it's shorter than 32 bytes, or an empty string
+enum Node {
+    Empty,
+    Leaf(Vec<u8>, Vec<u8>),
+    Extension(Vec<u8>, Vec<u8>),
+    Branch(Vec<Vec<u8>>, Option<Vec<u8>>),
+}
+
+/// Merkle-Patricia trie as used in Ethereum, nodes are read from and written to the store
+///
+/// Nodes are never removed from the store, so the values at any previous root can still be read
+/// Keys are used as they are, state tries should use the keccak256 hash of the key
+///
+/// The store is usually a reference, a shared reference to a NodeReader is enough to read
+/// values and proofs, updates need a mutable reference to a NodeStore
+pub struct MerkleTrie<S> {
+    store: S,
+    root: B256,
+}
+
+impl<S> MerkleTrie<S> {
+    /// Opens the trie with the given root, EMPTY_ROOT for a new trie
+    pub fn new(store: S, root: B256) -> Self {
+        Self { store, root }
+    }
+
+    pub fn root(&self) -> B256 {
+        self.root
+    }
+}
+
+impl<S: NodeReader> MerkleTrie<S> {
+    /// Get the value for a key, None if the key is not in the trie
+    pub fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, Box<dyn Error>> {
+        let mut node = self.load_root()?;
+        let mut path = &to_nibbles(key)[..];
+        loop {
+            let child = match node {
+                Node::Empty => return Ok(None),
+                Node::Leaf(leaf_path, value) => {
+                    return Ok((leaf_path == path).then_some(value));
+                }
+                Node::Extension(extension_path, child) => {
+                    if !path.starts_with(&extension_path) {
+                        return Ok(None);
+                    }
+                    path = &path[extension_path.len()..];
+                    child
+                }
+                Node::Branch(mut children, value) => {
+                    if path.is_empty() {
+                        return Ok(value);
+                    }
+                    let child = children.swap_remove(path[0] as usize);
+                    path = &path[1..];
+                    child
+                }
+            };
+            node = self.load(&child)?;
+        }
+    }
+
+    /// Get the nodes on the path to a key, starting from the root
+    ///
+    /// Nodes that are embedded in their parent are not included, as in eth_getProof
+    /// Proof of a missing key ends with the node that shows the key is not in the trie
+    pub fn get_proof(&self, key: &[u8]) -> Result<Vec<Vec<u8>>, Box<dyn Error>> {
+        if self.root == EMPTY_ROOT {
+            return Ok(Vec::new());
+        }
+        let root_node = self.get_stored_node(self.root)?;
+        let mut node = decode_node(&root_node)?;
+        let mut proof = vec![root_node];
+        let mut path = &to_nibbles(key)[..];
+        loop {
+            let child = match node {
+                Node::Empty | Node::Leaf(_, _) => break,
+                Node::Extension(extension_path, child) => {
+                    if !path.starts_with(&extension_path) {
+                        break;
+                    }
+                    path = &path[extension_path.len()..];
+                    child
+                }
+                Node::Branch(mut children, _) => {
+                    if path.is_empty() {
+                        break;
+                    }
+                    let child = children.swap_remove(path[0] as usize);
+                    path = &path[1..];
+                    child
+                }
+            };
+            node = match get_child_hash(&child) {
+                Some(hash) => {
+                    let child_node = self.get_stored_node(hash)?;
+                    let node = decode_node(&child_node)?;
+                    proof.push(child_node);
+                    node
+                }
+                None => decode_node(&child)?,
+            };
+        }
+        Ok(proof)
+    }
+
+    fn load(&self, reference: &[u8]) -> Result<Node, Box<dyn Error>> {
+        match get_child_hash(reference) {
+            Some(hash) => decode_node(&self.get_stored_node(hash)?),
+            None => decode_node(reference),
+        }
+    }
+
+    fn load_root(&self) -> Result<Node, Box<dyn Error>> {
+        if self.root == EMPTY_ROOT {
+            return Ok(Node::Empty);
+        }
+        decode_node(&self.get_stored_node(self.root)?)
+    }
+
+    fn get_stored_node(&self, hash: B256) -> Result<Vec<u8>, Box<dyn Error>> {
+        self.store
+            .get_node(hash)?
+            .ok_or_else(|| format!("Missing trie node {}", hash).into())
+    }
+}
+
+impl<S: NodeStore> MerkleTrie<S> {
+    /// Set the value for a key, empty values should be removed instead
+    pub fn insert(&mut self, key: &[u8], value: Vec<u8>) -> Result<(), Box<dyn Error>> {
+        let root = self.load_root()?;
+        let root = self.insert_at(root, &to_nibbles(key), value)?;
+        self.set_root(&root);
+        Ok(())
+    }
+
+    /// Remove the value for a key, does nothing if the key is not in the trie
+    pub fn remove(&mut self, key: &[u8]) -> Result<(), Box<dyn Error>> {
+        let root = self.load_root()?;
+        let root = self.remove_at(root, &to_nibbles(key))?;
+        self.set_root(&root);
+        Ok(())
+    }
+
+    fn insert_at(
+        &mut self,
+        node: Node,
+        path: &[u8],
+        value: Vec<u8>,
+    ) -> Result<Node, Box<dyn Error>> {
+        match node {
+            Node::Empty => Ok(Node::Leaf(path.to_vec(), value)),
+            Node::Leaf(leaf_path, leaf_value) => {
+                if leaf_path == path {
+                    return Ok(Node::Leaf(leaf_path, value));
+                }
+                let common = common_prefix_length(&leaf_path, path);
+                let mut children = empty_children();
+                let mut branch_value = None;
+                for (remaining_path, value) in
+                    [(&leaf_path[common..], leaf_value), (&path[common..], value)]
+                {
+                    if remaining_path.is_empty() {
+                        branch_value = Some(value);
+                    } else {
+                        children[remaining_path[0] as usize] =
+                            self.reference(&Node::Leaf(remaining_path[1..].to_vec(), value));
+                    }
+                }
+                Ok(self.wrap_in_extension(&path[..common], Node::Branch(children, branch_value)))
+            }
+            Node::Extension(extension_path, child) => {
+                let common = common_prefix_length(&extension_path, path);
+                if common == extension_path.len() {
+                    let child_node = self.load(&child)?;
+                    let child_node = self.insert_at(child_node, &path[common..], value)?;
+                    return Ok(Node::Extension(extension_path, self.reference(&child_node)));
+                }
+                let mut children = empty_children();
+                children[extension_path[common] as usize] = if extension_path.len() == common + 1 {
+                    child
+                } else {
+                    self.reference(&Node::Extension(
+                        extension_path[common + 1..].to_vec(),
+                        child,
+                    ))
+                };
+                let mut branch_value = None;
+                if path.len() == common {
+                    branch_value = Some(value);
+                } else {
+                    children[path[common] as usize] =
+                        self.reference(&Node::Leaf(path[common + 1..].to_vec(), value));
+                }
+                Ok(self.wrap_in_extension(&path[..common], Node::Branch(children, branch_value)))
+            }
+            Node::Branch(mut children, branch_value) => {
+                if path.is_empty() {
+                    return Ok(Node::Branch(children, Some(value)));
+                }
+                let index = path[0] as usize;
+                let child_node = self.load(&children[index])?;
+                let child_node = self.insert_at(child_node, &path[1..], value)?;
+                children[index] = self.reference(&child_node);
+                Ok(Node::Branch(children, branch_value))
+            }
+        }
+    }
+
+    fn remove_at(&mut self, node: Node, path: &[u8]) -> Result<Node, Box<dyn Error>> {
+        match node {
+            Node::Empty => Ok(Node::Empty),
+            Node::Leaf(leaf_path, leaf_value) => {
+                if leaf_path == path {
+                    Ok(Node::Empty)
+                } else {
+                    Ok(Node::Leaf(leaf_path, leaf_value))
+                }
+            }
+            Node::Extension(extension_path, child) => {
+                if !path.starts_with(&extension_path) {
+                    return Ok(Node::Extension(extension_path, child));
+                }
+                let child_node = self.load(&child)?;
+                let child_node = self.remove_at(child_node, &path[extension_path.len()..])?;
+                Ok(self.join_paths(extension_path, child_node))
+            }
+            Node::Branch(mut children, mut branch_value) => {
+                if path.is_empty() {
+                    branch_value = None;
+                } else {
+                    let index = path[0] as usize;
+                    let child_node = self.load(&children[index])?;
+                    let child_node = self.remove_at(child_node, &path[1..])?;
+                    children[index] = self.reference(&child_node);
+                }
+
+                // Branches with a single child or only a value are collapsed
+                let used_children = children
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, child)| child.as_slice() != [EMPTY_STRING_CODE])
+                    .map(|(index, _)| index)
+                    .collect::<Vec<usize>>();
+                match (used_children.as_slice(), branch_value) {
+                    ([], Some(value)) => Ok(Node::Leaf(Vec::new(), value)),
+                    ([], None) => Ok(Node::Empty),
+                    ([index], None) => {
+                        let child_node = self.load(&children[*index])?;
+                        Ok(self.join_paths(vec![*index as u8], child_node))
+                    }
+                    (_, branch_value) => Ok(Node::Branch(children, branch_value)),
+                }
+            }
+        }
+    }
+
+    /// Returns the node that has the path followed by the given node
+    fn join_paths(&mut self, path: Vec<u8>, node: Node) -> Node {
+        match node {
+            Node::Empty => Node::Empty,
+            Node::Leaf(leaf_path, value) => Node::Leaf([path, leaf_path].concat(), value),
+            Node::Extension(extension_path, child) => {
+                Node::Extension([path, extension_path].concat(), child)
+            }
+            Node::Branch(_, _) => Node::Extension(path, self.reference(&node)),
+        }
+    }
+
+    fn wrap_in_extension(&mut self, path: &[u8], node: Node) -> Node {
+        if path.is_empty() {
+            return node;
+        }
+        Node::Extension(path.to_vec(), self.reference(&node))
+    }
+
+    /// Returns the reference to embed the node in its parent, nodes of 32 bytes or longer are stored by hash
+    fn reference(&mut self, node: &Node) -> Vec<u8> {
+        let encoded = encode_node(node);
+        if encoded.len() < 32 {
+            return encoded;
+        }
+        let hash = keccak256(&encoded);
+        self.store.set_node(hash, encoded);
+        let mut reference = Vec::new();
+        hash.encode(&mut reference);
+        reference
+    }
+
+    fn set_root(&mut self, node: &Node) {
+        let encoded = encode_node(node);
+        if let Node::Empty = node {
+            self.root = EMPTY_ROOT;
+            return;
+        }
+        // Root is always stored by hash, even if it's shorter than 32 bytes
+        self.root = keccak256(&encoded);
+        self.store.set_node(self.root, encoded);
+    }
+}
+
+/// Returns the RLP encoding of an account in the state trie
+pub fn encode_account(nonce: u64, balance: U256, storage_root: B256, code_hash: B256) -> Vec<u8> {
+    let mut payload = Vec::new();
+    nonce.encode(&mut payload);
+    balance.encode(&mut payload);
+    storage_root.encode(&mut payload);
+    code_hash.encode(&mut payload);
+    encode_list(payload)
+}
+
+/// Decodes an account in the state trie
+///
+/// Returns: (u64, U256, B256, B256) - nonce, balance, storage root and code hash
+pub fn decode_account(encoded: &[u8]) -> Result<(u64, U256, B256, B256), Box<dyn Error>> {
+    let mut buf = encoded;
+    let mut payload = Header::decode_bytes(&mut buf, true)?;
+    Ok((
+        u64::decode(&mut payload)?,
+        U256::decode(&mut payload)?,
+        B256::decode(&mut payload)?,
+        B256::decode(&mut payload)?,
+    ))
+}
+
+/// Returns the RLP encoding of a receipt in the receipts trie, same as the legacy receipts in Ethereum
+pub fn encode_receipt(
+    status: u8,
+    cumulative_gas_used: u64,
+    logs_bloom: &[u8],
+    logs: &[Log],
+) -> Vec<u8> {
+    let mut payload = Vec::new();
+    status.encode(&mut payload);
+    cumulative_gas_used.encode(&mut payload);
+    logs_bloom.encode(&mut payload);
+    alloy_rlp::encode_list::<Log, Log>(logs, &mut payload);
+    encode_list(payload)
+}
+
+/// Returns the root of a trie with the values keyed by the RLP encoding of their index,
+/// as used for the transactions and receipts of a block
+pub fn get_ordered_trie_root(values: Vec<Vec<u8>>) -> Result<B256, Box<dyn Error>> {
+    let mut store = HashMap::new();
+    let mut trie = MerkleTrie::new(&mut store, EMPTY_ROOT);
+    for (index, value) in values.into_iter().enumerate() {
+        let mut key = Vec::new();
+        index.encode(&mut key);
+        trie.insert(&key, value)?;
+    }
+    Ok(trie.root())
+}
+
+fn encode_list(payload: Vec<u8>) -> Vec<u8> {
+    let mut encoded = Vec::new();
+    Header {
+        list: true,
+        payload_length: payload.len(),
+    }
+    .encode(&mut encoded);
+    encoded.extend_from_slice(&payload);
+    encoded
+}
+
+fn encode_node(node: &Node) -> Vec<u8> {
+    let mut payload = Vec::new();
+    match node {
+        Node::Empty => return vec![EMPTY_STRING_CODE],
+        Node::Leaf(path, value) => {
+            encode_path(path, true).as_slice().encode(&mut payload);
+            value.as_slice().encode(&mut payload);
+        }
+        Node::Extension(path, child) => {
+            encode_path(path, false).as_slice().encode(&mut payload);
+            payload.extend_from_slice(child);
+        }
+        Node::Branch(children, value) => {
+            for child in children {
+                payload.extend_from_slice(child);
+            }
+            value.as_deref().unwrap_or_default().encode(&mut payload);
+        }
+    }
+    encode_list(payload)
+}
+
+fn decode_node(encoded: &[u8]) -> Result<Node, Box<dyn Error>> {
+    if encoded == [EMPTY_STRING_CODE] {
+        return Ok(Node::Empty);
+    }
+    let mut buf = encoded;
+    let mut payload = Header::decode_bytes(&mut buf, true)?;
+    let mut items = Vec::new();
+    while !payload.is_empty() {
+        items.push(split_item(&mut payload)?);
+    }
+    match items.len() {
+        2 => {
+            let (path, is_leaf) = decode_path(get_item_payload(items[0])?);
+            if is_leaf {
+                Ok(Node::Leaf(path, get_item_payload(items[1])?.to_vec()))
+            } else {
+                Ok(Node::Extension(path, items[1].to_vec()))
+            }
+        }
+        17 => {
+            let value = get_item_payload(items[16])?;
+            Ok(Node::Branch(
+                items[..16].iter().map(|item| item.to_vec()).collect(),
+                (!value.is_empty()).then(|| value.to_vec()),
+            ))
+        }
+        _ => Err("Invalid trie node".into()),
+    }
+}
+
+/// Splits the next RLP item from the buffer, including its header
+fn split_item<'b>(buf: &mut &'b [u8]) -> Result<&'b [u8], Box<dyn Error>> {
+    let start = *buf;
+    let header = Header::decode(buf)?;
+    let length = start.len() - buf.len() + header.payload_length;
+    if length > start.len() {
+        return Err("Invalid trie node".into());
+    }
+    *buf = &start[length..];
+    Ok(&start[..length])
+}
+
+fn get_item_payload(item: &[u8]) -> Result<&[u8], Box<dyn Error>> {
+    let mut buf = item;
+    Ok(Header::decode_bytes(&mut buf, false)?)
+}
+
+fn get_child_hash(reference: &[u8]) -> Option<B256> {
+    (reference.len() == 33 && reference[0] == EMPTY_STRING_CODE + 32)
+        .then(|| B256::from_slice(&reference[1..]))
+}
+
+fn empty_children() -> Vec<Vec<u8>> {
+    vec![vec![EMPTY_STRING_CODE]; 16]
+}
+
+fn to_nibbles(key: &[u8]) -> Vec<u8> {
+    key.iter()
+        .flat_map(|byte| [byte >> 4, byte & 0x0f])
+        .collect()
+}
+
+fn common_prefix_length(a: &[u8], b: &[u8]) -> usize {
+    a.iter().zip(b).take_while(|(a, b)| a == b).count()
+}
+
+/// Hex prefix encoding of a path, the first nibble has the node type and whether the path has odd length
+fn encode_path(nibbles: &[u8], is_leaf: bool) -> Vec<u8> {
+    let flag = if is_leaf { 2 } else { 0 };
+    let mut bytes = Vec::new();
+    let rest = if nibbles.len() % 2 == 1 {
+        bytes.push(((flag + 1) << 4) | nibbles[0]);
+        &nibbles[1..]
+    } else {
+        bytes.push(flag << 4);
+        nibbles
+    };
+    for pair in rest.chunks(2) {
+        bytes.push((pair[0] << 4) | pair[1]);
+    }
+    bytes
+}
+
+fn decode_path(bytes: &[u8]) -> (Vec<u8>, bool) {
+    let flag = bytes[0] >> 4;
+    let mut nibbles = Vec::new();
+    if flag & 1 == 1 {
+        nibbles.push(bytes[0] & 0x0f);
+    }
+    nibbles.extend(to_nibbles(&bytes[1..]));
+    (nibbles, flag >= 2)
+}
+
+#[cfg(test)]
+mod tests {
+    use revm::primitives::{Address, KECCAK_EMPTY};
+
+    use super::*;
+
+    #[test]
+    fn test_empty_trie() {
+        let mut store = HashMap::new();
+        let mut trie = MerkleTrie::new(&mut store, EMPTY_ROOT);
+        assert_eq!(trie.root(), keccak256([EMPTY_STRING_CODE]));
+
+        trie.insert(b"key", b"value".to_vec()).unwrap();
+        assert_ne!(trie.root(), EMPTY_ROOT);
+        trie.remove(b"key").unwrap();
+        assert_eq!(trie.root(), EMPTY_ROOT);
+        assert!(trie.get(b"key").unwrap().is_none());
+        assert!(trie.get_proof(b"key").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_known_roots() {
+        // Roots from the Ethereum trie tests
+        let mut store = HashMap::new();
+        let mut trie = MerkleTrie::new(&mut store, EMPTY_ROOT);
+        trie.insert(b"doe", b"reindeer".to_vec()).unwrap();
+        trie.insert(b"dog", b"puppy".to_vec()).unwrap();
+        trie.insert(b"dogglesworth", b"cat".to_vec()).unwrap();
+        assert_eq!(
+            trie.root(),
+            b256!("8aad789dff2f538bca5d8ea56e8abe10f4c7ba3a5dea95fea4cd6e7c3a1168d3")
+        );
+
+        let mut trie = MerkleTrie::new(&mut store, EMPTY_ROOT);
+        for (key, value) in [
+            ("do", "verb"),
+            ("horse", "stallion"),
+            ("doge", "coin"),
+            ("dog", "puppy"),
+        ] {
+            trie.insert(key.as_bytes(), value.as_bytes().to_vec())
+                .unwrap();
+        }
+        assert_eq!(
+            trie.root(),
+            b256!("5991bb8c6514148a29db676a14ac506cd2cd5775ace63c30a4fe457715e9ac84")
+        );
+        assert_eq!(trie.get(b"doge").unwrap().unwrap(), b"coin".to_vec());
+        assert_eq!(trie.get(b"do").unwrap().unwrap(), b"verb".to_vec());
+        assert!(trie.get(b"dogs").unwrap().is_none());
+    }
+
+    #[test]
+    fn test_root_is_independent_of_history() {
+        let mut store = HashMap::new();
+        let mut trie = MerkleTrie::new(&mut store, EMPTY_ROOT);
+        for i in 0u64..100 {
+            trie.insert(keccak256(i.to_be_bytes()).as_slice(), vec![i as u8 + 1])
+                .unwrap();
+        }
+        let root = trie.root();
+
+        // Removing the added keys restores the root
+        for i in 100u64..150 {
+            trie.insert(keccak256(i.to_be_bytes()).as_slice(), vec![i as u8 + 1])
+                .unwrap();
+        }
+        for i in 100u64..150 {
+            trie.remove(keccak256(i.to_be_bytes()).as_slice()).unwrap();
+        }
+        assert_eq!(trie.root(), root);
+
+        // Insertion order doesn't change the root
+        let mut trie = MerkleTrie::new(&mut store, EMPTY_ROOT);
+        for i in (0u64..100).rev() {
+            trie.insert(keccak256(i.to_be_bytes()).as_slice(), vec![0xff])
+                .unwrap();
+            trie.insert(keccak256(i.to_be_bytes()).as_slice(), vec![i as u8 + 1])
+                .unwrap();
+        }
+        assert_eq!(trie.root(), root);
+    }
+
+    #[test]
+    fn test_proof() {
+        let mut store = HashMap::new();
+        let mut trie = MerkleTrie::new(&mut store, EMPTY_ROOT);
+        let addresses = (1u8..20)
+            .map(Address::repeat_byte)
+            .collect::<Vec<Address>>();
+        for (i, address) in addresses.iter().enumerate() {
+            trie.insert(
+                keccak256(address).as_slice(),
+                encode_account(i as u64, U256::from(10), EMPTY_ROOT, KECCAK_EMPTY),
+            )
+            .unwrap();
+        }
+        let root = trie.root();
+
+        for (i, address) in addresses.iter().enumerate() {
+            let key = keccak256(address);
+            let proof = trie.get_proof(key.as_slice()).unwrap();
+            assert_eq!(keccak256(&proof[0]), root);
+
+            // Proof has all the nodes needed to read the value from the root
+            let mut proof_store = proof
+                .into_iter()
+                .map(|node| (keccak256(&node), node))
+                .collect::<HashMap<B256, Vec<u8>>>();
+            let proof_trie = MerkleTrie::new(&mut proof_store, root);
+            let account = proof_trie.get(key.as_slice()).unwrap().unwrap();
+            assert_eq!(
+                decode_account(&account).unwrap(),
+                (i as u64, U256::from(10), EMPTY_ROOT, KECCAK_EMPTY)
+            );
+        }
+
+        // Proof of a missing key shows that it's not in the trie
+        let key = keccak256(Address::ZERO);
+        let mut proof_store = trie
+            .get_proof(key.as_slice())
+            .unwrap()
+            .into_iter()
+            .map(|node| (keccak256(&node), node))
+            .collect::<HashMap<B256, Vec<u8>>>();
+        let proof_trie = MerkleTrie::new(&mut proof_store, root);
+        assert!(proof_trie.get(key.as_slice()).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_ordered_trie_root() {
+        assert_eq!(get_ordered_trie_root(Vec::new()).unwrap(), EMPTY_ROOT);
+        let root = get_ordered_trie_root(vec![vec![1], vec![2]]).unwrap();
+        assert_ne!(root, EMPTY_ROOT);
+        assert_eq!(root, get_ordered_trie_root(vec![vec![1], vec![2]]).unwrap());
+    }
+}
@@ -1,32 +1,97 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::error::Error;
 use std::fmt::Display;
 use std::path::Path;
+use std::sync::Arc;
 
 use cached_database::BlockDatabase;
 use revm::context::result::ExecutionResult;
 use revm::context::DBErrorMarker;
 use revm::primitives::alloy_primitives::{Bloom, U128, U64};
-use revm::primitives::ruint::aliases::U256;
-use revm::primitives::{Address, Bytes, FixedBytes, B256};
-use revm::{Database as DatabaseTrait, DatabaseCommit};
+use revm::primitives::ruint::aliases::{U256, U512};
+use revm::primitives::{keccak256, Address, Bytes, FixedBytes, Log, B256, KECCAK_EMPTY};
+use revm::{Database as DatabaseTrait, DatabaseCommit, DatabaseRef};
 use revm_state::{Account, AccountInfo, Bytecode};
+use rocksdb::WriteBatch;
 
 mod cached_database;
-use cached_database::{BlockCachedDatabase, BlockHistoryCacheData};
+pub use cached_database::BitcoinCache;
+use cached_database::{
+    get_history_cache_column_family, migrate_database, migrate_history_cache,
+    migrate_journal_index, open_database, BlockCachedDatabase, BlockHistoryCacheData, NodeDatabase,
+};
 
 pub mod types;
 
+mod merkle_trie;
+use merkle_trie::{
+    decode_account, encode_account, encode_receipt, get_ordered_trie_root, MerkleTrie, EMPTY_ROOT,
+};
+
+mod state_view;
+pub use state_view::StateView;
+
 use rs_merkle::algorithms::Sha256;
 use rs_merkle::MerkleTree;
 pub use types::{
-    AccountInfoED, AddressED, BEncodeDecode, BlockResponseED, BytecodeED, LogResponseED, TxED,
-    TxReceiptED, UintEncodeDecode, B256ED, U128ED, U256ED, U512ED, U64ED,
+    AccountInfoED, AccountProofED, AddressED, BEncodeDecode, BitcoinDataED, BlockResponseED,
+    BytecodeED, LogIndexKeyED, LogResponseED, StorageProofED, TxED, TxReceiptED, UintEncodeDecode,
+    B256ED, U128ED, U256ED, U512ED, U64ED,
 };
 
-pub const MAX_HISTORY_SIZE: u64 = 10;
+/// Number of blocks that can be reorged if it's not configured
+pub const DEFAULT_MAX_REORG_DEPTH: u64 = 10;
+
+/// Name of the database that holds all the tables as column families
+const DATABASE_NAME: &str = "brc20_prog_db";
+
+/// Names of the tables stored in BlockCachedDatabase
+const BLOCK_CACHED_DATABASES: [&str; 12] = [
+    "account_memory",
+    "code",
+    "account",
+    "number_and_index_to_tx_hash",
+    "tx_receipt",
+    "inscription_id_to_tx_hash",
+    "tx",
+    "block_hash_to_number",
+    "log_address_index",
+    "log_topic_index",
+    "account_storage_root",
+    "tx_bitcoin_data",
+];
+
+/// Names of the tables stored in BlockDatabase
+const BLOCK_DATABASES: [&str; 8] = [
+    "block_number_to_block",
+    "block_number_to_hash",
+    "block_number_to_timestamp",
+    "block_number_to_gas_used",
+    "block_number_to_mine_tm",
+    "block_number_to_state_root",
+    "block_number_to_receipts_root",
+    "block_number_to_state_hash",
+];
+
+/// Name of the table that holds the nodes of the account and storage tries
+const TRIE_NODE_DATABASE: &str = "trie_nodes";
+
+/// Name of the cache of the data fetched from bitcoind
+const BITCOIN_CACHE_NAME: &str = "bitcoin_cache";
+
+/// Name of the table that records the state of the database itself, e.g. finished migrations
+const METADATA_DATABASE: &str = "metadata";
+
+/// Set in the metadata once the log indexes contain the logs of all the stored receipts
+const LOG_INDEX_BUILT_KEY: &[u8] = b"log_index_built";
+
+/// First block whose state changes are archived, set while archive mode is enabled
+const ARCHIVE_START_BLOCK_KEY: &[u8] = b"archive_start_block";
 
 pub struct DB {
+    /// Database that holds all the tables, changes of all tables are written to it at once
+    db: Option<Arc<rocksdb::DB>>,
+
     /// Account address to memory location
     /// TODO: If the value is zero, consider deleting it from the database to save space
     db_account_memory: Option<BlockCachedDatabase<U512ED, U256ED, BlockHistoryCacheData<U256ED>>>,
@@ -53,6 +118,28 @@ pub struct DB {
     db_inscription_id_to_tx_hash:
         Option<BlockCachedDatabase<String, B256ED, BlockHistoryCacheData<B256ED>>>,
 
+    /// Log address, block number and tx index to tx hash
+    db_log_address_index:
+        Option<BlockCachedDatabase<LogIndexKeyED, B256ED, BlockHistoryCacheData<B256ED>>>,
+
+    /// Log topic position, topic, block number and tx index to tx hash
+    db_log_topic_index:
+        Option<BlockCachedDatabase<LogIndexKeyED, B256ED, BlockHistoryCacheData<B256ED>>>,
+
+    /// Account address to the root of its storage trie, missing if the account has no storage
+    db_account_storage_root:
+        Option<BlockCachedDatabase<AddressED, B256ED, BlockHistoryCacheData<B256ED>>>,
+
+    /// Hash to node of the account and storage tries
+    db_trie_nodes: Option<NodeDatabase>,
+
+    /// Tx hash to the Bitcoin data read by the precompiles while executing it
+    db_tx_bitcoin_data:
+        Option<BlockCachedDatabase<B256ED, BitcoinDataED, BlockHistoryCacheData<BitcoinDataED>>>,
+
+    /// Transactions and block heights fetched from bitcoind
+    bitcoin_cache: Option<BitcoinCache>,
+
     /// Block hash to block number
     db_block_hash_to_number:
         Option<BlockCachedDatabase<B256ED, U64ED, BlockHistoryCacheData<U64ED>>>,
@@ -72,13 +159,35 @@ pub struct DB {
     /// Block number to mine timestamp
     db_block_number_to_mine_tm: Option<BlockDatabase<U128ED>>,
 
+    /// Block number to state root
+    db_block_number_to_state_root: Option<BlockDatabase<B256ED>>,
+
+    /// Block number to receipts root
+    db_block_number_to_receipts_root: Option<BlockDatabase<B256ED>>,
+
+    /// Block number to state hash, the hash of the block hash, receipts root and state root
+    db_block_number_to_state_hash: Option<BlockDatabase<B256ED>>,
+
+    /// Accounts and storage slots changed since the last state root, they are updated in the tries when the block is finalised
+    changed_state: BTreeMap<Address, BTreeSet<U256>>,
+
     /// Cache for latest block number and block hash
     latest_block_number: Option<(u64, B256)>,
+
+    /// Number of the block that's being built, state changes are recorded at this block
+    pending_block_number: Option<u64>,
+
+    /// Number of blocks that can be reorged, None if there is no limit
+    max_reorg_depth: Option<u64>,
+
+    /// First block whose state is archived, None if archive mode is disabled
+    archive_start_block: Option<u64>,
 }
 
 impl Default for DB {
     fn default() -> Self {
         Self {
+            db: None,
             db_account_memory: None,
             db_code: None,
             db_account: None,
@@ -86,55 +195,239 @@ impl Default for DB {
             db_tx_receipt: None,
             db_tx: None,
             db_inscription_id_to_tx_hash: None,
+            db_log_address_index: None,
+            db_log_topic_index: None,
             db_block_number_to_block: None,
             db_block_number_to_hash: None,
             db_block_hash_to_number: None,
             db_block_number_to_timestamp: None,
             db_block_number_to_gas_used: None,
             db_block_number_to_mine_tm: None,
+            db_block_number_to_state_root: None,
+            db_block_number_to_receipts_root: None,
+            db_block_number_to_state_hash: None,
+            db_account_storage_root: None,
+            db_trie_nodes: None,
+            db_tx_bitcoin_data: None,
+            bitcoin_cache: None,
+            changed_state: BTreeMap::new(),
             latest_block_number: None,
+            pending_block_number: None,
+            max_reorg_depth: Some(DEFAULT_MAX_REORG_DEPTH),
+            archive_start_block: None,
         }
     }
 }
 
 impl DB {
     pub fn new(base_path: &Path) -> Result<Self, Box<dyn Error>> {
-        rlimit::Resource::NOFILE.set(4096, 8192)?;
+        Self::new_with_archive_mode(base_path, false)
+    }
+
+    /// Opens the database, in archive mode the history of account info, storage and code
+    /// is kept for all blocks instead of only the ones that can be reorged
+    pub fn new_with_archive_mode(
+        base_path: &Path,
+        archive_mode: bool,
+    ) -> Result<Self, Box<dyn Error>> {
+        Self::new_with_options(base_path, archive_mode, Some(DEFAULT_MAX_REORG_DEPTH))
+    }
+
+    /// Opens the database, changes are kept in the journal for max_reorg_depth blocks,
+    /// or for all blocks if max_reorg_depth is None
+    pub fn new_with_options(
+        base_path: &Path,
+        archive_mode: bool,
+        max_reorg_depth: Option<u64>,
+    ) -> Result<Self, Box<dyn Error>> {
+        let database_path = base_path.join(DATABASE_NAME);
+        let mut column_families = Vec::new();
+        for name in BLOCK_CACHED_DATABASES {
+            column_families.extend(BlockCachedDatabase::<
+                U64ED,
+                U64ED,
+                BlockHistoryCacheData<U64ED>,
+            >::column_families(name));
+        }
+        for name in BLOCK_DATABASES {
+            column_families.extend(BlockDatabase::<U64ED>::column_families(name));
+        }
+        column_families.extend(NodeDatabase::column_families(TRIE_NODE_DATABASE));
+        column_families.extend(BitcoinCache::column_families(BITCOIN_CACHE_NAME));
+        column_families.push(METADATA_DATABASE.to_string());
+        // History caches of the older versions are opened to move them into the journals
+        let existing_column_families =
+            rocksdb::DB::list_cf(&rocksdb::Options::default(), &database_path).unwrap_or_default();
+        for name in BLOCK_CACHED_DATABASES {
+            let history_cache = get_history_cache_column_family(name);
+            if base_path.join(&history_cache).exists()
+                || existing_column_families.contains(&history_cache)
+            {
+                column_families.push(history_cache);
+            }
+        }
 
-        Ok(Self {
-            db_account_memory: Some(BlockCachedDatabase::new(&base_path, "account_memory")),
-            db_code: Some(BlockCachedDatabase::new(&base_path, "code")),
-            db_account: Some(BlockCachedDatabase::new(&base_path, "account")),
+        let mut database = open_database(&database_path, &column_families)?;
+        for column_family in column_families.iter() {
+            if migrate_database(
+                &database,
+                &base_path.join(column_family),
+                column_family,
+                METADATA_DATABASE,
+            )? {
+                println!("Migrated {} to {}", column_family, DATABASE_NAME);
+            }
+        }
+        for name in BLOCK_CACHED_DATABASES {
+            if migrate_history_cache(&mut database, name)? {
+                println!("Migrated history cache of {} to journal", name);
+            }
+            if migrate_journal_index(&database, name)? {
+                println!("Built journal index of {}", name);
+            }
+        }
+        let database = Arc::new(database);
+        let archive_start_block = Self::load_archive_start_block(&database, archive_mode)?;
+
+        let mut db = Self {
+            db_account_memory: Some(BlockCachedDatabase::new(
+                &database,
+                "account_memory",
+                archive_start_block,
+                max_reorg_depth,
+            )),
+            db_code: Some(BlockCachedDatabase::new(
+                &database,
+                "code",
+                archive_start_block,
+                max_reorg_depth,
+            )),
+            db_account: Some(BlockCachedDatabase::new(
+                &database,
+                "account",
+                archive_start_block,
+                max_reorg_depth,
+            )),
             db_number_and_index_to_tx_hash: Some(BlockCachedDatabase::new(
-                &base_path,
+                &database,
                 "number_and_index_to_tx_hash",
+                None,
+                max_reorg_depth,
+            )),
+            db_tx_receipt: Some(BlockCachedDatabase::new(
+                &database,
+                "tx_receipt",
+                None,
+                max_reorg_depth,
             )),
-            db_tx_receipt: Some(BlockCachedDatabase::new(&base_path, "tx_receipt")),
             db_inscription_id_to_tx_hash: Some(BlockCachedDatabase::new(
-                &base_path,
+                &database,
                 "inscription_id_to_tx_hash",
+                None,
+                max_reorg_depth,
+            )),
+            db_tx: Some(BlockCachedDatabase::new(
+                &database,
+                "tx",
+                None,
+                max_reorg_depth,
             )),
-            db_tx: Some(BlockCachedDatabase::new(&base_path, "tx")),
             db_block_hash_to_number: Some(BlockCachedDatabase::new(
-                &base_path,
+                &database,
                 "block_hash_to_number",
+                None,
+                max_reorg_depth,
             )),
-            db_block_number_to_block: Some(BlockDatabase::new(&base_path, "block_number_to_block")),
-            db_block_number_to_hash: Some(BlockDatabase::new(&base_path, "block_number_to_hash")),
+            db_block_number_to_block: Some(BlockDatabase::new(&database, "block_number_to_block")),
+            db_block_number_to_hash: Some(BlockDatabase::new(&database, "block_number_to_hash")),
             db_block_number_to_timestamp: Some(BlockDatabase::new(
-                &base_path,
+                &database,
                 "block_number_to_timestamp",
             )),
             db_block_number_to_gas_used: Some(BlockDatabase::new(
-                &base_path,
+                &database,
                 "block_number_to_gas_used",
             )),
             db_block_number_to_mine_tm: Some(BlockDatabase::new(
-                &base_path,
+                &database,
                 "block_number_to_mine_tm",
             )),
+            db_log_address_index: Some(BlockCachedDatabase::new(
+                &database,
+                "log_address_index",
+                None,
+                max_reorg_depth,
+            )),
+            db_log_topic_index: Some(BlockCachedDatabase::new(
+                &database,
+                "log_topic_index",
+                None,
+                max_reorg_depth,
+            )),
+            db_block_number_to_state_root: Some(BlockDatabase::new(
+                &database,
+                "block_number_to_state_root",
+            )),
+            db_block_number_to_receipts_root: Some(BlockDatabase::new(
+                &database,
+                "block_number_to_receipts_root",
+            )),
+            db_block_number_to_state_hash: Some(BlockDatabase::new(
+                &database,
+                "block_number_to_state_hash",
+            )),
+            db_account_storage_root: Some(BlockCachedDatabase::new(
+                &database,
+                "account_storage_root",
+                archive_start_block,
+                max_reorg_depth,
+            )),
+            db_trie_nodes: Some(NodeDatabase::new(&database, TRIE_NODE_DATABASE)),
+            db_tx_bitcoin_data: Some(BlockCachedDatabase::new(
+                &database,
+                "tx_bitcoin_data",
+                None,
+                max_reorg_depth,
+            )),
+            bitcoin_cache: Some(BitcoinCache::new(&database, BITCOIN_CACHE_NAME)),
+            changed_state: BTreeMap::new(),
+            db: Some(database),
+            max_reorg_depth,
+            archive_start_block,
             latest_block_number: None,
-        })
+            pending_block_number: None,
+        };
+        db.build_log_index_if_needed()?;
+        db.build_state_trie_if_needed()?;
+        Ok(db)
+    }
+
+    /// Returns the first block whose state is archived, None if archive mode is disabled
+    ///
+    /// It's stored when archive mode is enabled, and removed when the database is opened without it,
+    /// as changes in between are not archived
+    fn load_archive_start_block(
+        database: &Arc<rocksdb::DB>,
+        archive_mode: bool,
+    ) -> Result<Option<u64>, Box<dyn Error>> {
+        let metadata_cf = database.cf_handle(METADATA_DATABASE).unwrap();
+        if !archive_mode {
+            database.delete_cf(metadata_cf, ARCHIVE_START_BLOCK_KEY)?;
+            return Ok(None);
+        }
+        if let Some(bytes) = database.get_cf(metadata_cf, ARCHIVE_START_BLOCK_KEY)? {
+            return Ok(Some(u64::from_be_bytes(bytes.as_slice().try_into()?)));
+        }
+        // Changes of the next block are the first ones to be archived
+        let archive_start_block = BlockDatabase::<B256ED>::new(database, "block_number_to_hash")
+            .last_key()?
+            .map_or(0, |x| x + 1);
+        database.put_cf(
+            metadata_cf,
+            ARCHIVE_START_BLOCK_KEY,
+            archive_start_block.to_be_bytes(),
+        )?;
+        Ok(Some(archive_start_block))
     }
 
     pub fn get_latest_block_height(&self) -> Result<u64, Box<dyn Error>> {
@@ -149,8 +442,32 @@ impl DB {
             .unwrap_or(0))
     }
 
+    /// Number of blocks that can be reorged, None if there is no limit
+    pub fn get_max_reorg_depth(&self) -> Option<u64> {
+        self.max_reorg_depth
+    }
+
+    /// Check if the history of the state is kept for all blocks
+    pub fn is_archive(&self) -> bool {
+        self.db_account.as_ref().is_some_and(|x| x.is_archive())
+    }
+
+    /// Sets the number of the block that's being built, state changes are recorded at this block
+    /// until the block hash is set
+    pub fn set_pending_block_number(&mut self, block_number: u64) {
+        self.pending_block_number = Some(block_number);
+    }
+
+    /// Returns the block number to record state changes at, latest block if no block is being built
+    fn get_pending_block_height(&self) -> Result<u64, Box<dyn Error>> {
+        match self.pending_block_number {
+            Some(block_number) => Ok(block_number),
+            None => self.get_latest_block_height(),
+        }
+    }
+
     pub fn get_account_memory(
-        &mut self,
+        &self,
         account: Address,
         mem_loc: U256,
     ) -> Result<Option<U256ED>, Box<dyn Error>> {
@@ -163,13 +480,35 @@ impl DB {
         Ok(ret)
     }
 
+    /// Returns the storage value at the end of the given block
+    ///
+    /// Only accurate for the blocks that can still be reorged, unless archive mode was enabled before the block
+    pub fn get_account_memory_at(
+        &self,
+        account: Address,
+        mem_loc: U256,
+        block_number: u64,
+    ) -> Result<Option<U256ED>, Box<dyn Error>> {
+        let ret = self
+            .db_account_memory
+            .as_ref()
+            .unwrap()
+            .at(&U512ED::from_addr_u256(account, mem_loc), block_number)?;
+
+        Ok(ret)
+    }
+
     pub fn set_account_memory(
         &mut self,
         account: Address,
         mem_loc: U256,
         value: U256,
     ) -> Result<(), Box<dyn Error>> {
-        let block_number = self.get_latest_block_height()?;
+        let block_number = self.get_pending_block_height()?;
+        self.changed_state
+            .entry(account)
+            .or_default()
+            .insert(mem_loc);
         self.db_account_memory.as_mut().unwrap().set(
             block_number,
             U512ED::from_addr_u256(account, mem_loc),
@@ -179,7 +518,7 @@ impl DB {
         Ok(())
     }
 
-    pub fn get_code(&mut self, code_hash: B256) -> Result<Option<BytecodeED>, Box<dyn Error>> {
+    pub fn get_code(&self, code_hash: B256) -> Result<Option<BytecodeED>, Box<dyn Error>> {
         let ret = self
             .db_code
             .as_ref()
@@ -190,7 +529,7 @@ impl DB {
     }
 
     pub fn set_code(&mut self, code_hash: B256, bytecode: Bytecode) -> Result<(), Box<dyn Error>> {
-        let block_number = self.get_latest_block_height()?;
+        let block_number = self.get_pending_block_height()?;
         Ok(self.db_code.as_mut().unwrap().set(
             block_number,
             B256ED::from_b256(code_hash),
@@ -202,74 +541,41 @@ impl DB {
         ((block_number as u128) << 64) | tx_idx as u128
     }
 
+    /// Returns the logs matching the filter in the given block range
+    ///
+    /// Empty addresses match any address. Each topic position matches any of the given topics,
+    /// an empty position matches any topic, and logs must have at least as many topics as the filter.
+    /// Stops after max_results + 1 logs, so callers can detect that the result is too large
     pub fn get_logs(
-        &mut self,
-        mut block_number_from: Option<u64>,
-        mut block_number_to: Option<u64>,
-        contract_address: Option<Address>,
-        topics: Vec<B256>,
+        &self,
+        block_number_from: Option<u64>,
+        block_number_to: Option<u64>,
+        addresses: Vec<Address>,
+        topics: Vec<Vec<B256>>,
+        max_results: usize,
     ) -> Result<Vec<LogResponseED>, Box<dyn Error>> {
-        if block_number_from.is_none() {
-            block_number_from = self.latest_block_number.map(|x| x.0);
-        }
-        if block_number_to.is_none() {
-            block_number_to = self.latest_block_number.map(|x| x.0);
-        }
-
-        // Limit the number of blocks to be fetched
-        // DB is under a lock here and if this takes too long, it will block other threads
-        //
-        // TODO: This is a temporary solution, we can potentially avoid using a mutex for reads
-        // TODO: Also, test this, maybe it's not that slow?
-        if block_number_to.unwrap() - block_number_from.unwrap() > 5 {
-            return Ok(Vec::new());
-        }
+        let latest_block_number = self.get_latest_block_height()?;
+        let block_number_from = block_number_from.unwrap_or(latest_block_number);
+        let block_number_to = block_number_to.unwrap_or(latest_block_number);
 
         let mut logs = Vec::new();
+        if block_number_from > block_number_to {
+            return Ok(logs);
+        }
 
-        let tx_ids = self
-            .db_number_and_index_to_tx_hash
-            .as_ref()
-            .unwrap()
-            .get_range(
-                &U128ED::from_u128(Self::get_number_and_index_key(
-                    block_number_from.unwrap(),
-                    0,
-                )),
-                &&U128ED::from_u128(Self::get_number_and_index_key(
-                    block_number_to.unwrap() + 1,
-                    0,
-                )),
-            )?;
-
-        for tx_pair in tx_ids {
-            let tx_id = tx_pair.1;
-            let tx_receipt = self.get_tx_receipt(tx_id.0).unwrap().unwrap();
-            if tx_receipt.contract_address.is_none() {
-                continue;
-            }
-            if contract_address.is_some()
-                && tx_receipt.contract_address.unwrap().0 != contract_address.unwrap()
-            {
-                continue;
-            }
-
-            let mut current_index = tx_receipt.logs.log_index;
-            for log in tx_receipt.logs.logs {
-                let mut matched = true;
-                if topics.len() != 0 && log.topics().len() != topics.len() {
-                    current_index += 1;
-                    continue;
-                }
-
-                for (i, topic) in topics.iter().enumerate() {
-                    if log.topics()[i] != *topic {
-                        matched = false;
-                        break;
-                    }
-                }
-
-                if matched {
+        for tx_hash in self.get_log_candidate_tx_hashes(
+            block_number_from,
+            block_number_to,
+            &addresses,
+            &topics,
+        )? {
+            let tx_receipt = self
+                .get_tx_receipt(tx_hash.0)?
+                .ok_or("Missing tx receipt")?;
+            for (log_index, log) in (tx_receipt.logs.log_index..).zip(tx_receipt.logs.logs) {
+                if (addresses.is_empty() || addresses.contains(&log.address))
+                    && Self::log_matches_topics(&log, &topics)
+                {
                     logs.push(LogResponseED {
                         address: AddressED(log.address),
                         topics: log
@@ -282,19 +588,191 @@ impl DB {
                         transaction_hash: tx_receipt.transaction_hash.clone(),
                         block_hash: tx_receipt.hash.clone(),
                         block_number: U64ED::from_u64(tx_receipt.block_number),
-                        log_index: U64ED::from_u64(current_index),
+                        log_index: U64ED::from_u64(log_index),
+                        removed: false,
                     });
+                    if logs.len() > max_results {
+                        return Ok(logs);
+                    }
                 }
-
-                current_index += 1;
             }
         }
 
         Ok(logs)
     }
 
-    pub fn get_tx_count(
+    fn log_matches_topics(log: &Log, topics: &[Vec<B256>]) -> bool {
+        if log.topics().len() < topics.len() {
+            return false;
+        }
+        topics
+            .iter()
+            .zip(log.topics())
+            .all(|(options, topic)| options.is_empty() || options.contains(topic))
+    }
+
+    /// Returns the hashes of the txes that can contain logs matching the filter, in block and tx order
+    ///
+    /// Address index is used if there are addresses in the filter, otherwise the topic index is used
+    /// for the first topic position that isn't a wildcard. All txes in the range are returned if neither exists
+    fn get_log_candidate_tx_hashes(
+        &self,
+        block_number_from: u64,
+        block_number_to: u64,
+        addresses: &[Address],
+        topics: &[Vec<B256>],
+    ) -> Result<Vec<B256ED>, Box<dyn Error>> {
+        let block_number_end = block_number_to.saturating_add(1);
+        let mut tx_ids = Vec::new();
+        if !addresses.is_empty() {
+            for address in addresses {
+                tx_ids.extend(self.db_log_address_index.as_ref().unwrap().get_range(
+                    &LogIndexKeyED::from_address(*address, block_number_from, 0),
+                    &LogIndexKeyED::from_address(*address, block_number_end, 0),
+                )?);
+            }
+        } else if let Some((position, options)) = topics
+            .iter()
+            .enumerate()
+            .find(|(_, options)| !options.is_empty())
+        {
+            for topic in options {
+                tx_ids.extend(self.db_log_topic_index.as_ref().unwrap().get_range(
+                    &LogIndexKeyED::from_topic(position as u8, *topic, block_number_from, 0),
+                    &LogIndexKeyED::from_topic(position as u8, *topic, block_number_end, 0),
+                )?);
+            }
+        } else {
+            return Ok(self
+                .db_number_and_index_to_tx_hash
+                .as_ref()
+                .unwrap()
+                .get_range(
+                    &U128ED::from_u128(Self::get_number_and_index_key(block_number_from, 0)),
+                    &U128ED::from_u128(Self::get_number_and_index_key(block_number_end, 0)),
+                )?
+                .into_iter()
+                .map(|(_, tx_hash)| tx_hash)
+                .collect());
+        };
+
+        // Results of different addresses or topics are merged in block and tx order
+        tx_ids.sort_by_key(|(key, _)| (key.block_number, key.tx_idx));
+        tx_ids.dedup_by_key(|(key, _)| (key.block_number, key.tx_idx));
+        Ok(tx_ids.into_iter().map(|(_, tx_hash)| tx_hash).collect())
+    }
+
+    /// Adds the logs of a tx to the address and topic indexes
+    fn index_logs(
         &mut self,
+        block_number: u64,
+        tx_idx: u64,
+        tx_hash: B256,
+        logs: &[Log],
+    ) -> Result<(), Box<dyn Error>> {
+        for log in logs {
+            self.db_log_address_index.as_mut().unwrap().set(
+                block_number,
+                LogIndexKeyED::from_address(log.address, block_number, tx_idx),
+                B256ED::from_b256(tx_hash),
+            )?;
+            for (position, topic) in log.topics().iter().enumerate() {
+                self.db_log_topic_index.as_mut().unwrap().set(
+                    block_number,
+                    LogIndexKeyED::from_topic(position as u8, *topic, block_number, tx_idx),
+                    B256ED::from_b256(tx_hash),
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    fn get_metadata(&self, key: &[u8]) -> Result<Option<Vec<u8>>, Box<dyn Error>> {
+        let db = self.db.as_ref().unwrap();
+        Ok(db.get_cf(db.cf_handle(METADATA_DATABASE).unwrap(), key)?)
+    }
+
+    /// Writes a metadata value directly, it's not part of the block changes
+    fn set_metadata(&self, key: &[u8], value: &[u8]) -> Result<(), Box<dyn Error>> {
+        let db = self.db.as_ref().unwrap();
+        Ok(db.put_cf(db.cf_handle(METADATA_DATABASE).unwrap(), key, value)?)
+    }
+
+    /// Builds the log indexes from the stored receipts, for databases created before the indexes existed
+    ///
+    /// A marker is stored once it's done, as databases without logs have empty indexes
+    fn build_log_index_if_needed(&mut self) -> Result<(), Box<dyn Error>> {
+        if self.get_metadata(LOG_INDEX_BUILT_KEY)?.is_some() {
+            return Ok(());
+        }
+        if self
+            .db_number_and_index_to_tx_hash
+            .as_ref()
+            .unwrap()
+            .is_empty()
+        {
+            return self.set_metadata(LOG_INDEX_BUILT_KEY, &[1]);
+        }
+
+        let latest_block_number = self.get_latest_block_height()?;
+        let mut block_number = 0;
+        while block_number <= latest_block_number {
+            let block_number_end = (block_number + 1000).min(latest_block_number + 1);
+            let tx_ids = self
+                .db_number_and_index_to_tx_hash
+                .as_ref()
+                .unwrap()
+                .get_range(
+                    &U128ED::from_u128(Self::get_number_and_index_key(block_number, 0)),
+                    &U128ED::from_u128(Self::get_number_and_index_key(block_number_end, 0)),
+                )?;
+            for (_, tx_hash) in tx_ids {
+                let Some(tx_receipt) = self.get_tx_receipt(tx_hash.0)? else {
+                    continue;
+                };
+                self.index_logs(
+                    tx_receipt.block_number,
+                    tx_receipt.transaction_index,
+                    tx_hash.0,
+                    &tx_receipt.logs.logs,
+                )?;
+            }
+            let mut batch = WriteBatch::default();
+            self.db_log_address_index
+                .as_ref()
+                .unwrap()
+                .commit(&mut batch, latest_block_number)?;
+            self.db_log_topic_index
+                .as_ref()
+                .unwrap()
+                .commit(&mut batch, latest_block_number)?;
+            self.db.as_ref().unwrap().write(batch)?;
+            self.db_log_address_index.as_mut().unwrap().clear_cache();
+            self.db_log_topic_index.as_mut().unwrap().clear_cache();
+            block_number = block_number_end;
+        }
+        self.set_metadata(LOG_INDEX_BUILT_KEY, &[1])
+    }
+
+    /// Computes the roots and the state hash of the latest block, for databases created before the state trie existed
+    ///
+    /// Stored block is rewritten with the roots, as older versions stored it without them
+    fn build_state_trie_if_needed(&mut self) -> Result<(), Box<dyn Error>> {
+        let latest_block_number = self.get_latest_block_height()?;
+        if self.get_block_hash(latest_block_number)?.is_none()
+            || self.get_state_root(latest_block_number)?.is_some()
+        {
+            return Ok(());
+        }
+
+        let state_root = self.build_state_trie(latest_block_number)?;
+        let receipts_root = self.compute_receipts_root(latest_block_number)?;
+        self.set_roots(latest_block_number, state_root, receipts_root)?;
+        self.commit_changes()
+    }
+
+    pub fn get_tx_count(
+        &self,
         account: Option<Address>,
         block_number: u64,
     ) -> Result<u64, Box<dyn Error>> {
@@ -319,7 +797,7 @@ impl DB {
     }
 
     pub fn get_tx_hash_by_inscription_id(
-        &mut self,
+        &self,
         inscription_id: String,
     ) -> Result<Option<B256ED>, Box<dyn Error>> {
         let ret = self
@@ -336,7 +814,7 @@ impl DB {
         inscription_id: String,
         tx_hash: B256,
     ) -> Result<(), Box<dyn Error>> {
-        let block_number = self.get_latest_block_height()?;
+        let block_number = self.get_pending_block_height()?;
         Ok(self.db_inscription_id_to_tx_hash.as_mut().unwrap().set(
             block_number,
             inscription_id,
@@ -345,7 +823,7 @@ impl DB {
     }
 
     pub fn get_tx_hash_by_block_number_and_index(
-        &mut self,
+        &self,
         block_number: u64,
         tx_idx: u64,
     ) -> Result<Option<B256ED>, Box<dyn Error>> {
@@ -360,7 +838,7 @@ impl DB {
     }
 
     pub fn get_tx_hash_by_block_hash_and_index(
-        &mut self,
+        &self,
         block_hash: B256,
         tx_idx: u64,
     ) -> Result<Option<B256ED>, Box<dyn Error>> {
@@ -372,7 +850,7 @@ impl DB {
         self.get_tx_hash_by_block_number_and_index(block_number.unwrap().to_u64(), tx_idx)
     }
 
-    pub fn get_tx_by_hash(&mut self, tx_hash: B256) -> Result<Option<TxED>, Box<dyn Error>> {
+    pub fn get_tx_by_hash(&self, tx_hash: B256) -> Result<Option<TxED>, Box<dyn Error>> {
         let ret = self
             .db_tx
             .as_ref()
@@ -382,7 +860,7 @@ impl DB {
         Ok(ret)
     }
 
-    pub fn get_tx_receipt(&mut self, tx_hash: B256) -> Result<Option<TxReceiptED>, Box<dyn Error>> {
+    pub fn get_tx_receipt(&self, tx_hash: B256) -> Result<Option<TxReceiptED>, Box<dyn Error>> {
         let ret = self
             .db_tx_receipt
             .as_ref()
@@ -392,6 +870,44 @@ impl DB {
         Ok(ret)
     }
 
+    /// Returns the Bitcoin data read by the precompiles while executing the transaction,
+    /// None if it didn't read any
+    pub fn get_tx_bitcoin_data(
+        &self,
+        tx_hash: B256,
+    ) -> Result<Option<BitcoinDataED>, Box<dyn Error>> {
+        let ret = self
+            .db_tx_bitcoin_data
+            .as_ref()
+            .unwrap()
+            .latest(&B256ED::from_b256(tx_hash))?;
+
+        Ok(ret)
+    }
+
+    /// Records the Bitcoin data read by the precompiles while executing the transaction
+    pub fn set_tx_bitcoin_data(
+        &mut self,
+        block_number: u64,
+        tx_hash: B256,
+        bitcoin_data: BitcoinDataED,
+    ) -> Result<(), Box<dyn Error>> {
+        if bitcoin_data.is_empty() {
+            return Ok(());
+        }
+        self.db_tx_bitcoin_data.as_mut().unwrap().set(
+            block_number,
+            B256ED::from_b256(tx_hash),
+            bitcoin_data,
+        )?;
+        Ok(())
+    }
+
+    /// Returns the persistent cache of the data fetched from bitcoind
+    pub fn get_bitcoin_cache(&self) -> Option<BitcoinCache> {
+        self.bitcoin_cache.clone()
+    }
+
     pub fn set_tx_receipt(
         &mut self,
         result_type: &str,
@@ -404,6 +920,7 @@ impl DB {
         from: Address,
         to: Option<Address>,
         data: &Bytes,
+        gas_limit: u64,
         tx_hash: B256,
         tx_idx: u64,
         output: &ExecutionResult,
@@ -439,7 +956,7 @@ impl DB {
             from: AddressED(from),
             to: to.map(AddressED),
             value: 0,
-            gas: 0,
+            gas: gas_limit,
             gas_price: 0,
             input: data.clone(),
         };
@@ -459,6 +976,8 @@ impl DB {
             self.set_tx_hash_by_inscription_id(inscription_id.unwrap(), tx_hash)?;
         }
 
+        self.index_logs(block_number, tx_idx, tx_hash, output.logs())?;
+
         Ok(self.db_tx_receipt.as_mut().unwrap().set(
             block_number,
             B256ED::from_b256(tx_hash),
@@ -467,7 +986,7 @@ impl DB {
     }
 
     pub fn get_account_info(
-        &mut self,
+        &self,
         account: Address,
     ) -> Result<Option<AccountInfoED>, Box<dyn Error>> {
         let ret = self
@@ -479,12 +998,52 @@ impl DB {
         Ok(ret)
     }
 
+    /// Returns the account info at the end of the given block
+    ///
+    /// Only accurate for the blocks that can still be reorged, unless archive mode was enabled before the block
+    pub fn get_account_info_at(
+        &self,
+        account: Address,
+        block_number: u64,
+    ) -> Result<Option<AccountInfoED>, Box<dyn Error>> {
+        let ret = self
+            .db_account
+            .as_ref()
+            .unwrap()
+            .at(&AddressED(account), block_number)?;
+
+        Ok(ret)
+    }
+
+    /// Returns a read only view of the state at the end of the given block,
+    /// or before the first block if block_number is None
+    ///
+    /// Only the blocks that can still be reorged are available, unless archive mode was enabled before the block
+    pub fn get_state_view(
+        &self,
+        block_number: Option<u64>,
+    ) -> Result<StateView<'_>, Box<dyn Error>> {
+        let latest_block_number = self.get_latest_block_height()?;
+        let archived = self
+            .archive_start_block
+            .is_some_and(|archive_start_block| block_number.unwrap_or(0) >= archive_start_block);
+        if !archived
+            && self.max_reorg_depth.is_some_and(|max_reorg_depth| {
+                block_number.unwrap_or(0) + max_reorg_depth < latest_block_number
+            })
+        {
+            return Err("State is not available for this block".into());
+        }
+        Ok(StateView::new(self, block_number))
+    }
+
     pub fn set_account_info(
         &mut self,
         account: Address,
         value: AccountInfo,
     ) -> Result<(), Box<dyn Error>> {
-        let block_number = self.get_latest_block_height()?;
+        let block_number = self.get_pending_block_height()?;
+        self.changed_state.entry(account).or_default();
         Ok(self.db_account.as_mut().unwrap().set(
             block_number,
             AddressED(account),
@@ -492,10 +1051,7 @@ impl DB {
         )?)
     }
 
-    pub fn get_block(
-        &mut self,
-        block_number: u64,
-    ) -> Result<Option<BlockResponseED>, Box<dyn Error>> {
+    pub fn get_block(&self, block_number: u64) -> Result<Option<BlockResponseED>, Box<dyn Error>> {
         let block_hash = self.get_block_hash(block_number)?;
         if block_hash.is_none() {
             return Ok(None);
@@ -503,14 +1059,21 @@ impl DB {
 
         let stored_block = self
             .db_block_number_to_block
-            .as_mut()
+            .as_ref()
             .unwrap()
             .get(block_number)?;
         if stored_block.is_some() {
             return Ok(Some(stored_block.unwrap()));
         }
+        self.build_block(block_number, block_hash.unwrap())
+    }
 
-        let block_hash = block_hash.unwrap();
+    /// Builds the block response from the stored block data, blocks are stored once their roots are set
+    fn build_block(
+        &self,
+        block_number: u64,
+        block_hash: B256,
+    ) -> Result<Option<BlockResponseED>, Box<dyn Error>> {
         let block_timestamp = self.get_block_timestamp(block_number)?;
         let gas_used = self.get_gas_used(block_number)?;
         let mine_timestamp = self.get_mine_timestamp(block_number)?;
@@ -535,6 +1098,9 @@ impl DB {
 
         let tx_merkle = MerkleTree::<Sha256>::from_leaves(leaves.as_slice());
 
+        let state_root = self.get_state_root(block_number)?.unwrap_or(B256::ZERO);
+        let receipts_root = self.get_receipts_root(block_number)?.unwrap_or(B256::ZERO);
+
         let mut transactions = Vec::new();
         let mut bloom = Bloom::new([0u8; 256]);
         for tx_pair in tx_ids {
@@ -548,7 +1114,7 @@ impl DB {
             transactions.push(tx_id);
         }
 
-        let block_response = BlockResponseED::new(
+        let mut block_response = BlockResponseED::new(
             0,
             36000000,
             gas_used.unwrap_or(U64::ZERO).as_limbs()[0],
@@ -562,19 +1128,15 @@ impl DB {
             BEncodeDecode(FixedBytes(tx_merkle.root().unwrap_or([0; 32]))),
             0,
             BEncodeDecode(parent_hash),
-            BEncodeDecode(FixedBytes([0; 32])),
+            BEncodeDecode(receipts_root),
             0,
         );
-
-        self.db_block_number_to_block
-            .as_mut()
-            .unwrap()
-            .set(block_number, block_response.clone());
+        block_response.state_root = BEncodeDecode(state_root);
 
         Ok(Some(block_response))
     }
 
-    pub fn get_block_number(&mut self, block_hash: B256) -> Result<Option<U64ED>, Box<dyn Error>> {
+    pub fn get_block_number(&self, block_hash: B256) -> Result<Option<U64ED>, Box<dyn Error>> {
         let ret = self
             .db_block_hash_to_number
             .as_ref()
@@ -584,10 +1146,10 @@ impl DB {
         Ok(ret)
     }
 
-    pub fn get_block_hash(&mut self, block_number: u64) -> Result<Option<B256>, Box<dyn Error>> {
+    pub fn get_block_hash(&self, block_number: u64) -> Result<Option<B256>, Box<dyn Error>> {
         let ret = self
             .db_block_number_to_hash
-            .as_mut()
+            .as_ref()
             .unwrap()
             .get(block_number)?;
 
@@ -604,6 +1166,12 @@ impl DB {
         {
             self.latest_block_number = Some((block_number, block_hash));
         }
+        if self
+            .pending_block_number
+            .is_some_and(|pending_block_number| pending_block_number <= block_number)
+        {
+            self.pending_block_number = None;
+        }
 
         if block_hash == B256::ZERO {
             // just hash the number
@@ -628,10 +1196,10 @@ impl DB {
         )?)
     }
 
-    pub fn get_block_timestamp(&mut self, number: u64) -> Result<Option<U64>, Box<dyn Error>> {
+    pub fn get_block_timestamp(&self, number: u64) -> Result<Option<U64>, Box<dyn Error>> {
         let ret = self
             .db_block_number_to_timestamp
-            .as_mut()
+            .as_ref()
             .unwrap()
             .get(number)?;
 
@@ -650,10 +1218,10 @@ impl DB {
             .set(block_number, U64ED::from_u64(block_timestamp)))
     }
 
-    pub fn get_gas_used(&mut self, block_number: u64) -> Result<Option<U64>, Box<dyn Error>> {
+    pub fn get_gas_used(&self, block_number: u64) -> Result<Option<U64>, Box<dyn Error>> {
         let ret = self
             .db_block_number_to_gas_used
-            .as_mut()
+            .as_ref()
             .unwrap()
             .get(block_number)?;
 
@@ -668,13 +1236,10 @@ impl DB {
             .set(block_number, U64ED::from_u64(gas_used)))
     }
 
-    pub fn get_mine_timestamp(
-        &mut self,
-        block_number: u64,
-    ) -> Result<Option<U128>, Box<dyn Error>> {
+    pub fn get_mine_timestamp(&self, block_number: u64) -> Result<Option<U128>, Box<dyn Error>> {
         let ret = self
             .db_block_number_to_mine_tm
-            .as_mut()
+            .as_ref()
             .unwrap()
             .get(block_number)?;
 
@@ -693,48 +1258,403 @@ impl DB {
             .set(block_number, U128ED::from_u128(mine_timestamp)))
     }
 
-    pub fn commit_changes(&mut self) -> Result<(), Box<dyn Error>> {
-        let latest_block_number = self.get_latest_block_height()?;
-
-        self.db_block_number_to_hash.as_mut().unwrap().commit()?;
-        self.db_block_number_to_timestamp
-            .as_mut()
-            .unwrap()
-            .commit()?;
-        self.db_block_number_to_gas_used
-            .as_mut()
+    /// Returns the root of the account trie at the end of the block, None if it's not computed
+    pub fn get_state_root(&self, block_number: u64) -> Result<Option<B256>, Box<dyn Error>> {
+        let ret = self
+            .db_block_number_to_state_root
+            .as_ref()
             .unwrap()
-            .commit()?;
-        self.db_block_number_to_mine_tm.as_mut().unwrap().commit()?;
-        self.db_block_number_to_block.as_mut().unwrap().commit()?;
+            .get(block_number)?;
 
-        self.db_number_and_index_to_tx_hash
-            .as_mut()
-            .unwrap()
-            .commit(latest_block_number)?;
-        self.db_inscription_id_to_tx_hash
-            .as_mut()
-            .unwrap()
-            .commit(latest_block_number)?;
-        self.db_tx.as_mut().unwrap().commit(latest_block_number)?;
-        self.db_tx_receipt
-            .as_mut()
-            .unwrap()
-            .commit(latest_block_number)?;
-        self.db_account_memory
-            .as_mut()
-            .unwrap()
-            .commit(latest_block_number)?;
-        self.db_code.as_mut().unwrap().commit(latest_block_number)?;
-        self.db_account
-            .as_mut()
+        Ok(ret.map(|x| x.0))
+    }
+
+    pub fn get_receipts_root(&self, block_number: u64) -> Result<Option<B256>, Box<dyn Error>> {
+        let ret = self
+            .db_block_number_to_receipts_root
+            .as_ref()
             .unwrap()
-            .commit(latest_block_number)?;
-        self.db_block_hash_to_number
+            .get(block_number)?;
+
+        Ok(ret.map(|x| x.0))
+    }
+
+    /// Returns the account and storage proofs at the end of the block, in EIP-1186 format
+    ///
+    /// Proofs are read from the tries, so they are available for every block with a state root
+    /// Returns None if the block doesn't have a state root
+    pub fn get_proof(
+        &self,
+        account: Address,
+        storage_keys: &[U256],
+        block_number: u64,
+    ) -> Result<Option<AccountProofED>, Box<dyn Error>> {
+        let Some(state_root) = self.get_state_root(block_number)? else {
+            return Ok(None);
+        };
+
+        let key = keccak256(account);
+        let state_trie = MerkleTrie::new(self.db_trie_nodes.as_ref().unwrap(), state_root);
+        let account_proof = state_trie.get_proof(key.as_slice())?;
+        let (nonce, balance, storage_root, code_hash) = match state_trie.get(key.as_slice())? {
+            Some(encoded) => decode_account(&encoded)?,
+            None => (0, U256::ZERO, EMPTY_ROOT, KECCAK_EMPTY),
+        };
+
+        let storage_trie = MerkleTrie::new(self.db_trie_nodes.as_ref().unwrap(), storage_root);
+        let mut storage_proof = Vec::new();
+        for storage_key in storage_keys {
+            let key = keccak256(storage_key.to_be_bytes::<32>());
+            let value = match storage_trie.get(key.as_slice())? {
+                Some(encoded) => alloy_rlp::decode_exact::<U256>(encoded)?,
+                None => U256::ZERO,
+            };
+            storage_proof.push(StorageProofED {
+                key: U256ED::from_u256(*storage_key),
+                value: U256ED::from_u256(value),
+                proof: storage_trie
+                    .get_proof(key.as_slice())?
+                    .into_iter()
+                    .map(Bytes::from)
+                    .collect(),
+            });
+        }
+
+        Ok(Some(AccountProofED {
+            address: AddressED(account),
+            account_proof: account_proof.into_iter().map(Bytes::from).collect(),
+            balance: U256ED::from_u256(balance),
+            code_hash: B256ED::from_b256(code_hash),
+            nonce: U64ED::from_u64(nonce),
+            storage_hash: B256ED::from_b256(storage_root),
+            storage_proof,
+        }))
+    }
+
+    /// Returns the state hash of the block, None if it's not computed
+    pub fn get_block_state_hash(&self, block_number: u64) -> Result<Option<B256>, Box<dyn Error>> {
+        let ret = self
+            .db_block_number_to_state_hash
+            .as_ref()
+            .unwrap()
+            .get(block_number)?;
+
+        Ok(ret.map(|x| x.0))
+    }
+
+    /// Computes the state root, the receipts root and the state hash of a block, should be called
+    /// once all transactions of the block are added and the block hash is set
+    ///
+    /// Only the accounts changed since the previous block are updated in the tries, the whole state
+    /// is added again if the previous block doesn't have a state root
+    ///
+    /// Returns: B256 - the state hash of the block
+    pub fn set_block_roots(&mut self, block_number: u64) -> Result<B256, Box<dyn Error>> {
+        let previous_state_root = match block_number.checked_sub(1) {
+            Some(previous_block_number) => match self.get_state_root(previous_block_number)? {
+                Some(state_root) => Some(state_root),
+                None if self.get_block_hash(previous_block_number)?.is_none() => Some(EMPTY_ROOT),
+                None => None,
+            },
+            None => Some(EMPTY_ROOT),
+        };
+
+        let state_root = match previous_state_root {
+            Some(mut state_root) => {
+                for (account, slots) in std::mem::take(&mut self.changed_state) {
+                    let storage_root = self
+                        .db_account_storage_root
+                        .as_ref()
+                        .unwrap()
+                        .latest(&AddressED(account))?
+                        .map(|x| x.0)
+                        .unwrap_or(EMPTY_ROOT);
+                    state_root = self.update_account_in_trie(
+                        state_root,
+                        storage_root,
+                        account,
+                        slots,
+                        block_number,
+                    )?;
+                }
+                state_root
+            }
+            None => self.build_state_trie(block_number)?,
+        };
+        let receipts_root = self.compute_receipts_root(block_number)?;
+        self.set_roots(block_number, state_root, receipts_root)
+    }
+
+    /// Stores the roots of the block and the state hash computed from them
+    ///
+    /// State hash is keccak256 of the block hash, the receipts root and the state root, so indexers
+    /// with the same state hash agree on the block, the results of its transactions and the whole state
+    ///
+    /// Returns: B256 - the state hash of the block
+    fn set_roots(
+        &mut self,
+        block_number: u64,
+        state_root: B256,
+        receipts_root: B256,
+    ) -> Result<B256, Box<dyn Error>> {
+        let block_hash = self
+            .get_block_hash(block_number)?
+            .ok_or("Block hash is not set")?;
+        let state_hash = keccak256(
+            [
+                block_hash.as_slice(),
+                receipts_root.as_slice(),
+                state_root.as_slice(),
+            ]
+            .concat(),
+        );
+
+        self.db_block_number_to_state_root
+            .as_mut()
+            .unwrap()
+            .set(block_number, B256ED::from_b256(state_root));
+        self.db_block_number_to_receipts_root
+            .as_mut()
+            .unwrap()
+            .set(block_number, B256ED::from_b256(receipts_root));
+        self.db_block_number_to_state_hash
             .as_mut()
             .unwrap()
-            .commit(latest_block_number)?;
+            .set(block_number, B256ED::from_b256(state_hash));
+
+        // Block is complete with the roots, so it's stored instead of being built for every read
+        if let Some(block) = self.build_block(block_number, block_hash)? {
+            self.db_block_number_to_block
+                .as_mut()
+                .unwrap()
+                .set(block_number, block);
+        }
+        Ok(state_hash)
+    }
+
+    /// Updates the storage trie of the account with the latest values of the slots,
+    /// then updates the account in the account trie
+    ///
+    /// Returns: B256 - the new root of the account trie
+    fn update_account_in_trie(
+        &mut self,
+        state_root: B256,
+        storage_root: B256,
+        account: Address,
+        slots: BTreeSet<U256>,
+        block_number: u64,
+    ) -> Result<B256, Box<dyn Error>> {
+        let mut values = Vec::new();
+        for slot in slots {
+            let value = self
+                .get_account_memory(account, slot)?
+                .map(|x| x.0)
+                .unwrap_or(U256::ZERO);
+            values.push((slot, value));
+        }
+        let mut storage_trie = MerkleTrie::new(self.db_trie_nodes.as_mut().unwrap(), storage_root);
+        for (slot, value) in values {
+            let key = keccak256(slot.to_be_bytes::<32>());
+            if value.is_zero() {
+                storage_trie.remove(key.as_slice())?;
+            } else {
+                storage_trie.insert(key.as_slice(), alloy_rlp::encode(value))?;
+            }
+        }
+        let new_storage_root = storage_trie.root();
+        if new_storage_root != storage_root {
+            self.db_account_storage_root.as_mut().unwrap().set(
+                block_number,
+                AddressED(account),
+                B256ED::from_b256(new_storage_root),
+            )?;
+        }
+
+        let account_info = self.get_account_info(account)?.map(|x| x.0);
+        let mut state_trie = MerkleTrie::new(self.db_trie_nodes.as_mut().unwrap(), state_root);
+        let key = keccak256(account);
+        match account_info {
+            Some(account_info) if !account_info.is_empty() || new_storage_root != EMPTY_ROOT => {
+                let code_hash = if account_info.code_hash == B256::ZERO {
+                    KECCAK_EMPTY
+                } else {
+                    account_info.code_hash
+                };
+                state_trie.insert(
+                    key.as_slice(),
+                    encode_account(
+                        account_info.nonce,
+                        account_info.balance,
+                        new_storage_root,
+                        code_hash,
+                    ),
+                )?;
+            }
+            // Empty accounts are not in the trie, as in Ethereum
+            _ => state_trie.remove(key.as_slice())?,
+        }
+        Ok(state_trie.root())
+    }
+
+    /// Adds all accounts and storage to new tries, used when the previous state root is not known
+    ///
+    /// Returns: B256 - the root of the account trie
+    fn build_state_trie(&mut self, block_number: u64) -> Result<B256, Box<dyn Error>> {
+        let mut accounts = self
+            .db_account
+            .as_ref()
+            .unwrap()
+            .get_range(
+                &AddressED(Address::ZERO),
+                &AddressED(Address::repeat_byte(0xff)),
+            )?
+            .into_iter()
+            .map(|(account, _)| account.0)
+            .collect::<Vec<Address>>();
+        if self.get_account_info(Address::repeat_byte(0xff))?.is_some() {
+            accounts.push(Address::repeat_byte(0xff));
+        }
+
+        let mut state_root = EMPTY_ROOT;
+        for account in accounts {
+            // Storage keys of an account start with its address, slot is in the last 32 bytes
+            let start_key = U512ED::from_addr_u256(account, U256::ZERO);
+            let end_key = UintEncodeDecode(start_key.0 + (U512::from(1) << 256));
+            let slots = self
+                .db_account_memory
+                .as_ref()
+                .unwrap()
+                .get_range(&start_key, &end_key)?
+                .into_iter()
+                .map(|(key, _)| U256::from_be_slice(&key.0.to_be_bytes::<64>()[32..]))
+                .collect::<BTreeSet<U256>>();
+            state_root =
+                self.update_account_in_trie(state_root, EMPTY_ROOT, account, slots, block_number)?;
+        }
+        self.changed_state.clear();
+        Ok(state_root)
+    }
+
+    /// Computes the root of the trie of the receipts in the block, keyed by transaction index
+    fn compute_receipts_root(&mut self, block_number: u64) -> Result<B256, Box<dyn Error>> {
+        let tx_ids = self
+            .db_number_and_index_to_tx_hash
+            .as_ref()
+            .unwrap()
+            .get_range(
+                &U128ED::from_u128(Self::get_number_and_index_key(block_number, 0)),
+                &U128ED::from_u128(Self::get_number_and_index_key(block_number + 1, 0)),
+            )?;
+        let mut receipts = Vec::new();
+        for (_, tx_hash) in tx_ids {
+            let Some(tx_receipt) = self.get_tx_receipt(tx_hash.0)? else {
+                continue;
+            };
+            receipts.push(encode_receipt(
+                tx_receipt.status,
+                tx_receipt.cumulative_gas_used,
+                tx_receipt.logs_bloom.0.as_slice(),
+                &tx_receipt.logs.logs,
+            ));
+        }
+        get_ordered_trie_root(receipts)
+    }
+
+    pub fn commit_changes(&mut self) -> Result<(), Box<dyn Error>> {
+        let latest_block_number = self.get_latest_block_height()?;
+        self.write_changes(WriteBatch::default(), latest_block_number)
+    }
+
+    /// Adds the changes of all tables to the batch and writes it, so either all changes are written or none
+    fn write_changes(
+        &mut self,
+        mut batch: WriteBatch,
+        latest_block_number: u64,
+    ) -> Result<(), Box<dyn Error>> {
+        self.db_block_number_to_hash
+            .as_ref()
+            .unwrap()
+            .commit(&mut batch)?;
+        self.db_block_number_to_timestamp
+            .as_ref()
+            .unwrap()
+            .commit(&mut batch)?;
+        self.db_block_number_to_gas_used
+            .as_ref()
+            .unwrap()
+            .commit(&mut batch)?;
+        self.db_block_number_to_mine_tm
+            .as_ref()
+            .unwrap()
+            .commit(&mut batch)?;
+        self.db_block_number_to_block
+            .as_ref()
+            .unwrap()
+            .commit(&mut batch)?;
+        self.db_block_number_to_state_root
+            .as_ref()
+            .unwrap()
+            .commit(&mut batch)?;
+        self.db_block_number_to_receipts_root
+            .as_ref()
+            .unwrap()
+            .commit(&mut batch)?;
+        self.db_block_number_to_state_hash
+            .as_ref()
+            .unwrap()
+            .commit(&mut batch)?;
+        self.db_trie_nodes.as_ref().unwrap().commit(&mut batch);
+
+        self.db_number_and_index_to_tx_hash
+            .as_ref()
+            .unwrap()
+            .commit(&mut batch, latest_block_number)?;
+        self.db_inscription_id_to_tx_hash
+            .as_ref()
+            .unwrap()
+            .commit(&mut batch, latest_block_number)?;
+        self.db_tx
+            .as_ref()
+            .unwrap()
+            .commit(&mut batch, latest_block_number)?;
+        self.db_tx_receipt
+            .as_ref()
+            .unwrap()
+            .commit(&mut batch, latest_block_number)?;
+        self.db_account_memory
+            .as_ref()
+            .unwrap()
+            .commit(&mut batch, latest_block_number)?;
+        self.db_code
+            .as_ref()
+            .unwrap()
+            .commit(&mut batch, latest_block_number)?;
+        self.db_account
+            .as_ref()
+            .unwrap()
+            .commit(&mut batch, latest_block_number)?;
+        self.db_block_hash_to_number
+            .as_ref()
+            .unwrap()
+            .commit(&mut batch, latest_block_number)?;
+        self.db_log_address_index
+            .as_ref()
+            .unwrap()
+            .commit(&mut batch, latest_block_number)?;
+        self.db_log_topic_index
+            .as_ref()
+            .unwrap()
+            .commit(&mut batch, latest_block_number)?;
+        self.db_account_storage_root
+            .as_ref()
+            .unwrap()
+            .commit(&mut batch, latest_block_number)?;
+        self.db_tx_bitcoin_data
+            .as_ref()
+            .unwrap()
+            .commit(&mut batch, latest_block_number)?;
 
+        self.db.as_ref().unwrap().write(batch)?;
         self.clear_caches();
         Ok(())
     }
@@ -751,6 +1671,8 @@ impl DB {
             .clear_cache();
         self.db_tx.as_mut().unwrap().clear_cache();
         self.db_tx_receipt.as_mut().unwrap().clear_cache();
+        self.db_log_address_index.as_mut().unwrap().clear_cache();
+        self.db_log_topic_index.as_mut().unwrap().clear_cache();
         self.db_number_and_index_to_tx_hash
             .as_mut()
             .unwrap()
@@ -771,66 +1693,202 @@ impl DB {
             .as_mut()
             .unwrap()
             .clear_cache();
+        self.db_block_number_to_state_root
+            .as_mut()
+            .unwrap()
+            .clear_cache();
+        self.db_block_number_to_receipts_root
+            .as_mut()
+            .unwrap()
+            .clear_cache();
+        self.db_block_number_to_state_hash
+            .as_mut()
+            .unwrap()
+            .clear_cache();
+        self.db_account_storage_root.as_mut().unwrap().clear_cache();
+        self.db_tx_bitcoin_data.as_mut().unwrap().clear_cache();
+        self.db_trie_nodes.as_mut().unwrap().clear_cache();
+        self.changed_state.clear();
+
+        self.latest_block_number = None;
+        self.pending_block_number = None;
+    }
+
+    /// Discards the changes after the latest valid block that are not committed yet,
+    /// used to revert a block that failed while it was being built
+    pub fn discard_changes_after(&mut self, latest_valid_block_number: u64) {
+        self.db_account_memory
+            .as_mut()
+            .unwrap()
+            .discard_changes_after(latest_valid_block_number);
+        self.db_code
+            .as_mut()
+            .unwrap()
+            .discard_changes_after(latest_valid_block_number);
+        self.db_account
+            .as_mut()
+            .unwrap()
+            .discard_changes_after(latest_valid_block_number);
+        self.db_block_hash_to_number
+            .as_mut()
+            .unwrap()
+            .discard_changes_after(latest_valid_block_number);
+        self.db_number_and_index_to_tx_hash
+            .as_mut()
+            .unwrap()
+            .discard_changes_after(latest_valid_block_number);
+        self.db_tx_receipt
+            .as_mut()
+            .unwrap()
+            .discard_changes_after(latest_valid_block_number);
+        self.db_inscription_id_to_tx_hash
+            .as_mut()
+            .unwrap()
+            .discard_changes_after(latest_valid_block_number);
+        self.db_tx
+            .as_mut()
+            .unwrap()
+            .discard_changes_after(latest_valid_block_number);
+        self.db_log_address_index
+            .as_mut()
+            .unwrap()
+            .discard_changes_after(latest_valid_block_number);
+        self.db_log_topic_index
+            .as_mut()
+            .unwrap()
+            .discard_changes_after(latest_valid_block_number);
+        self.db_block_number_to_hash
+            .as_mut()
+            .unwrap()
+            .discard_changes_after(latest_valid_block_number);
+        self.db_block_number_to_timestamp
+            .as_mut()
+            .unwrap()
+            .discard_changes_after(latest_valid_block_number);
+        self.db_block_number_to_gas_used
+            .as_mut()
+            .unwrap()
+            .discard_changes_after(latest_valid_block_number);
+        self.db_block_number_to_mine_tm
+            .as_mut()
+            .unwrap()
+            .discard_changes_after(latest_valid_block_number);
+        self.db_block_number_to_block
+            .as_mut()
+            .unwrap()
+            .discard_changes_after(latest_valid_block_number);
+        self.db_block_number_to_state_root
+            .as_mut()
+            .unwrap()
+            .discard_changes_after(latest_valid_block_number);
+        self.db_block_number_to_receipts_root
+            .as_mut()
+            .unwrap()
+            .discard_changes_after(latest_valid_block_number);
+        self.db_block_number_to_state_hash
+            .as_mut()
+            .unwrap()
+            .discard_changes_after(latest_valid_block_number);
+        self.db_account_storage_root
+            .as_mut()
+            .unwrap()
+            .discard_changes_after(latest_valid_block_number);
+        self.db_tx_bitcoin_data
+            .as_mut()
+            .unwrap()
+            .discard_changes_after(latest_valid_block_number);
+        self.changed_state.clear();
 
         self.latest_block_number = None;
+        self.pending_block_number = None;
     }
 
+    /// Reverts all tables to the latest valid block, the reverted state is written at once
     pub fn reorg(&mut self, latest_valid_block_number: u64) -> Result<(), Box<dyn Error>> {
+        let mut batch = WriteBatch::default();
         self.db_account_memory
             .as_mut()
             .unwrap()
-            .reorg(latest_valid_block_number)?;
+            .reorg(&mut batch, latest_valid_block_number)?;
         self.db_code
             .as_mut()
             .unwrap()
-            .reorg(latest_valid_block_number)?;
+            .reorg(&mut batch, latest_valid_block_number)?;
         self.db_account
             .as_mut()
             .unwrap()
-            .reorg(latest_valid_block_number)?;
+            .reorg(&mut batch, latest_valid_block_number)?;
         self.db_block_hash_to_number
             .as_mut()
             .unwrap()
-            .reorg(latest_valid_block_number)?;
+            .reorg(&mut batch, latest_valid_block_number)?;
         self.db_number_and_index_to_tx_hash
             .as_mut()
             .unwrap()
-            .reorg(latest_valid_block_number)?;
+            .reorg(&mut batch, latest_valid_block_number)?;
         self.db_tx_receipt
             .as_mut()
             .unwrap()
-            .reorg(latest_valid_block_number)?;
+            .reorg(&mut batch, latest_valid_block_number)?;
         self.db_inscription_id_to_tx_hash
             .as_mut()
             .unwrap()
-            .reorg(latest_valid_block_number)?;
+            .reorg(&mut batch, latest_valid_block_number)?;
         self.db_tx
             .as_mut()
             .unwrap()
-            .reorg(latest_valid_block_number)?;
+            .reorg(&mut batch, latest_valid_block_number)?;
+        self.db_log_address_index
+            .as_mut()
+            .unwrap()
+            .reorg(&mut batch, latest_valid_block_number)?;
+        self.db_log_topic_index
+            .as_mut()
+            .unwrap()
+            .reorg(&mut batch, latest_valid_block_number)?;
 
         self.db_block_number_to_hash
             .as_mut()
             .unwrap()
-            .reorg(latest_valid_block_number)?;
+            .reorg(&mut batch, latest_valid_block_number)?;
         self.db_block_number_to_timestamp
             .as_mut()
             .unwrap()
-            .reorg(latest_valid_block_number)?;
+            .reorg(&mut batch, latest_valid_block_number)?;
         self.db_block_number_to_gas_used
             .as_mut()
             .unwrap()
-            .reorg(latest_valid_block_number)?;
+            .reorg(&mut batch, latest_valid_block_number)?;
         self.db_block_number_to_mine_tm
             .as_mut()
             .unwrap()
-            .reorg(latest_valid_block_number)?;
+            .reorg(&mut batch, latest_valid_block_number)?;
         self.db_block_number_to_block
             .as_mut()
             .unwrap()
-            .reorg(latest_valid_block_number)?;
+            .reorg(&mut batch, latest_valid_block_number)?;
+        self.db_block_number_to_state_root
+            .as_mut()
+            .unwrap()
+            .reorg(&mut batch, latest_valid_block_number)?;
+        self.db_block_number_to_receipts_root
+            .as_mut()
+            .unwrap()
+            .reorg(&mut batch, latest_valid_block_number)?;
+        self.db_block_number_to_state_hash
+            .as_mut()
+            .unwrap()
+            .reorg(&mut batch, latest_valid_block_number)?;
+        self.db_account_storage_root
+            .as_mut()
+            .unwrap()
+            .reorg(&mut batch, latest_valid_block_number)?;
+        self.db_tx_bitcoin_data
+            .as_mut()
+            .unwrap()
+            .reorg(&mut batch, latest_valid_block_number)?;
 
-        Ok(self.commit_changes()?)
+        self.write_changes(batch, latest_valid_block_number)
     }
 }
 
@@ -851,41 +1909,62 @@ impl Error for DBError {
     }
 }
 
-impl DatabaseTrait for DB {
+/// Reads the latest state, including the changes of the block that is being built
+///
+/// Shared references are enough, so transactions can be executed on a CacheDB while other
+/// readers use the database, changes are committed afterwards with a mutable reference
+impl DatabaseRef for DB {
     type Error = DBError;
 
     /// Get basic account information.
-    fn basic(&mut self, address: Address) -> Result<Option<AccountInfo>, Self::Error> {
-        let res = self.get_account_info(address);
-        res.map(|x| {
-            x.map(|x| {
-                let mut account_info = x.0;
-                account_info.code = Some(self.code_by_hash(account_info.code_hash).unwrap());
-                account_info
-            })
-        })
-        .map_err(|x| DBError(x))
+    fn basic_ref(&self, address: Address) -> Result<Option<AccountInfo>, Self::Error> {
+        let Some(account_info) = self.get_account_info(address).map_err(DBError)? else {
+            return Ok(None);
+        };
+        let mut account_info = account_info.0;
+        account_info.code = Some(self.code_by_hash_ref(account_info.code_hash)?);
+        Ok(Some(account_info))
     }
 
     /// Get account code by its hash.
-    fn code_by_hash(&mut self, code_hash: B256) -> Result<Bytecode, Self::Error> {
+    fn code_by_hash_ref(&self, code_hash: B256) -> Result<Bytecode, Self::Error> {
         self.get_code(code_hash)
             .map(|x| x.unwrap_or(BytecodeED(Bytecode::new())).0)
-            .map_err(|x| DBError(x))
+            .map_err(DBError)
     }
 
     /// Get storage value of address at index.
-    fn storage(&mut self, address: Address, index: U256) -> Result<U256, Self::Error> {
+    fn storage_ref(&self, address: Address, index: U256) -> Result<U256, Self::Error> {
         self.get_account_memory(address, index)
             .map(|x| x.unwrap_or(U256ED::from_u256(U256::ZERO)).0)
-            .map_err(|x| DBError(x))
+            .map_err(DBError)
     }
 
     /// Get block hash by block number.
-    fn block_hash(&mut self, number: u64) -> Result<B256, Self::Error> {
+    fn block_hash_ref(&self, number: u64) -> Result<B256, Self::Error> {
         self.get_block_hash(number)
             .map(|x| x.unwrap_or(B256::ZERO))
-            .map_err(|x| DBError(x))
+            .map_err(DBError)
+    }
+}
+
+impl DatabaseTrait for DB {
+    type Error = DBError;
+
+    fn basic(&mut self, address: Address) -> Result<Option<AccountInfo>, Self::Error> {
+        self.basic_ref(address)
+    }
+
+    fn code_by_hash(&mut self, code_hash: B256) -> Result<Bytecode, Self::Error> {
+        self.code_by_hash_ref(code_hash)
+    }
+
+    fn storage(&mut self, address: Address, index: U256) -> Result<U256, Self::Error> {
+        self.storage_ref(address, index)
+    }
+
+    fn block_hash(&mut self, number: u64) -> Result<B256, Self::Error> {
+        self.block_hash_ref(number)
     }
 }
 
@@ -929,21 +2008,64 @@ impl DatabaseCommit for DB {
 mod tests {
     use revm::context::result::{Output, SuccessReason};
     use revm::primitives::{Address, Bytes};
+    use revm::DatabaseRef;
+    use rocksdb::IteratorMode;
     use tempfile::TempDir;
 
     use super::*;
 
-    #[test]
-    fn test_db() {
-        let path = TempDir::new().unwrap().into_path();
-
-        let address = Address::from([1u8; 20]);
-        let code_hash = B256::from([2u8; 32]);
-        let bytecode = Bytecode::new_raw(Bytes::from(vec![3u8; 32]));
-        let account_info = AccountInfo {
-            balance: U256::from(100),
-            nonce: 4,
-            code_hash,
+    /// Stores a successful transaction with the given logs as the only transaction of the block
+    fn set_test_receipt(
+        db: &mut DB,
+        block_number: u64,
+        contract_address: Option<Address>,
+        to: Address,
+        logs: Vec<Log>,
+        start_log_index: u64,
+    ) {
+        let block_hash = B256::from(U256::from(block_number));
+        let output = ExecutionResult::Success {
+            reason: SuccessReason::Return,
+            gas_used: 10,
+            gas_refunded: 0,
+            logs,
+            output: Output::Call(Bytes::new()),
+        };
+        db.set_block_hash(block_number, block_hash).unwrap();
+        db.set_tx_receipt(
+            "type",
+            "reason",
+            None,
+            block_hash,
+            block_number,
+            0,
+            contract_address,
+            Address::ZERO,
+            Some(to),
+            &Bytes::new(),
+            1000,
+            B256::from(U256::from(block_number + 100)),
+            0,
+            &output,
+            10,
+            0,
+            start_log_index,
+            None,
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn test_db() {
+        let path = TempDir::new().unwrap().into_path();
+
+        let address = Address::from([1u8; 20]);
+        let code_hash = B256::from([2u8; 32]);
+        let bytecode = Bytecode::new_raw(Bytes::from(vec![3u8; 32]));
+        let account_info = AccountInfo {
+            balance: U256::from(100),
+            nonce: 4,
+            code_hash,
             code: Some(bytecode.clone()),
         };
         let mine_timestamp = 5;
@@ -1001,7 +2123,7 @@ mod tests {
             db.commit_changes().unwrap();
         }
 
-        let mut db = DB::new(&path).unwrap();
+        let db = DB::new(&path).unwrap();
 
         assert_eq!(
             db.get_account_info(address).unwrap().unwrap().0,
@@ -1056,6 +2178,7 @@ mod tests {
         let cumulative_gas_used = 8;
         let nonce = 9;
         let start_log_index = 10;
+        let gas_limit = 12;
 
         {
             let mut db = DB::new(&path).unwrap();
@@ -1072,6 +2195,7 @@ mod tests {
                 from,
                 Some(to),
                 &Bytes::from(data),
+                gas_limit,
                 tx_hash,
                 tx_idx,
                 &output,
@@ -1085,7 +2209,7 @@ mod tests {
             db.commit_changes().unwrap();
         }
 
-        let mut db = DB::new(&path).unwrap();
+        let db = DB::new(&path).unwrap();
 
         assert_eq!(
             db.get_tx_hash_by_inscription_id("inscription_id".to_string())
@@ -1128,5 +2252,683 @@ mod tests {
                 Some(&Bytes::from(vec![11u8; 32])),
             )
         );
+        assert_eq!(db.get_tx_by_hash(tx_hash).unwrap().unwrap().gas, gas_limit);
+    }
+
+    #[test]
+    fn test_get_logs() {
+        let path = TempDir::new().unwrap().into_path();
+
+        let address_even = Address::from([1u8; 20]);
+        let address_odd = Address::from([2u8; 20]);
+        let topic_even = B256::from([3u8; 32]);
+        let topic_odd = B256::from([4u8; 32]);
+
+        let mut db = DB::new(&path).unwrap();
+        for block_number in 1..=20u64 {
+            let (address, topic) = if block_number % 2 == 0 {
+                (address_even, topic_even)
+            } else {
+                (address_odd, topic_odd)
+            };
+            set_test_receipt(
+                &mut db,
+                block_number,
+                Some(address),
+                address,
+                vec![Log::new_unchecked(address, vec![topic], Bytes::new())],
+                0,
+            );
+        }
+        db.commit_changes().unwrap();
+
+        // Ranges wider than the reorg window are allowed
+        let logs = db
+            .get_logs(Some(1), Some(20), Vec::new(), Vec::new(), 100)
+            .unwrap();
+        assert_eq!(logs.len(), 20);
+
+        let logs = db
+            .get_logs(Some(1), Some(20), vec![address_even], Vec::new(), 100)
+            .unwrap();
+        assert_eq!(logs.len(), 10);
+        assert!(logs.iter().all(|log| log.address.0 == address_even));
+        assert_eq!(logs[0].block_number, U64ED::from_u64(2));
+
+        let logs = db
+            .get_logs(Some(3), Some(8), Vec::new(), vec![vec![topic_odd]], 100)
+            .unwrap();
+        assert_eq!(logs.len(), 3);
+        assert!(logs.iter().all(|log| log.topics[0].0 == topic_odd));
+
+        // One more log than max_results is returned so callers can detect the limit
+        let logs = db
+            .get_logs(Some(1), Some(20), Vec::new(), Vec::new(), 5)
+            .unwrap();
+        assert_eq!(logs.len(), 6);
+
+        // Defaults to the latest block
+        let logs = db
+            .get_logs(None, None, Vec::new(), Vec::new(), 100)
+            .unwrap();
+        assert_eq!(logs.len(), 1);
+        assert_eq!(logs[0].block_number, U64ED::from_u64(20));
+
+        db.reorg(15).unwrap();
+        let logs = db
+            .get_logs(Some(1), Some(20), vec![address_even], Vec::new(), 100)
+            .unwrap();
+        assert_eq!(logs.len(), 7);
+        let logs = db
+            .get_logs(Some(1), Some(20), Vec::new(), vec![vec![topic_odd]], 100)
+            .unwrap();
+        assert_eq!(logs.len(), 8);
+    }
+
+    #[test]
+    fn test_get_logs_topic_filters() {
+        let path = TempDir::new().unwrap().into_path();
+
+        let address = Address::from([1u8; 20]);
+        let other_address = Address::from([2u8; 20]);
+        let topic_a = B256::from([3u8; 32]);
+        let topic_b = B256::from([4u8; 32]);
+        let topic_x = B256::from([5u8; 32]);
+        let topic_y = B256::from([6u8; 32]);
+
+        let mut db = DB::new(&path).unwrap();
+        set_test_receipt(
+            &mut db,
+            1,
+            Some(address),
+            address,
+            vec![
+                Log::new_unchecked(address, vec![topic_a, topic_x], Bytes::new()),
+                Log::new_unchecked(address, vec![topic_a, topic_y], Bytes::new()),
+                Log::new_unchecked(address, vec![topic_b, topic_x], Bytes::new()),
+                Log::new_unchecked(address, vec![topic_a], Bytes::new()),
+            ],
+            0,
+        );
+        db.commit_changes().unwrap();
+
+        let get_log_count = |db: &mut DB, addresses: Vec<Address>, topics: Vec<Vec<B256>>| {
+            db.get_logs(Some(1), Some(1), addresses, topics, 100)
+                .unwrap()
+                .len()
+        };
+
+        assert_eq!(get_log_count(&mut db, Vec::new(), vec![vec![topic_a]]), 3);
+        // Wildcard in the first position
+        assert_eq!(
+            get_log_count(&mut db, Vec::new(), vec![Vec::new(), vec![topic_x]]),
+            2
+        );
+        // OR in each position
+        assert_eq!(
+            get_log_count(
+                &mut db,
+                Vec::new(),
+                vec![vec![topic_a, topic_b], vec![topic_x, topic_y]]
+            ),
+            3
+        );
+        // Trailing wildcard requires the log to have a topic in that position
+        assert_eq!(
+            get_log_count(&mut db, Vec::new(), vec![vec![topic_a], Vec::new()]),
+            2
+        );
+        assert_eq!(
+            get_log_count(&mut db, vec![other_address, address], Vec::new()),
+            4
+        );
+        assert_eq!(get_log_count(&mut db, vec![other_address], Vec::new()), 0);
+    }
+
+    #[test]
+    fn test_get_logs_of_calls() {
+        let path = TempDir::new().unwrap().into_path();
+
+        let contract = Address::from([1u8; 20]);
+        let nested_contract = Address::from([2u8; 20]);
+        let topic = B256::from([3u8; 32]);
+
+        let mut db = DB::new(&path).unwrap();
+        // Calls to existing contracts have no contract address in the receipt
+        set_test_receipt(
+            &mut db,
+            1,
+            None,
+            contract,
+            vec![
+                Log::new_unchecked(nested_contract, vec![topic], Bytes::new()),
+                Log::new_unchecked(contract, vec![topic], Bytes::new()),
+            ],
+            3,
+        );
+        db.commit_changes().unwrap();
+
+        let logs = db
+            .get_logs(Some(1), Some(1), Vec::new(), Vec::new(), 100)
+            .unwrap();
+        assert_eq!(logs.len(), 2);
+
+        let logs = db
+            .get_logs(Some(1), Some(1), vec![nested_contract], Vec::new(), 100)
+            .unwrap();
+        assert_eq!(logs.len(), 1);
+        assert_eq!(logs[0].address.0, nested_contract);
+        assert_eq!(logs[0].log_index, U64ED::from_u64(3));
+
+        let logs = db
+            .get_logs(Some(1), Some(1), vec![contract], vec![vec![topic]], 100)
+            .unwrap();
+        assert_eq!(logs.len(), 1);
+        assert_eq!(logs[0].address.0, contract);
+        assert_eq!(logs[0].log_index, U64ED::from_u64(4));
+    }
+
+    #[test]
+    fn test_build_log_index() {
+        let path = TempDir::new().unwrap().into_path();
+        let address = Address::from([1u8; 20]);
+
+        {
+            let mut db = DB::new(&path).unwrap();
+            // Empty databases are marked as built, their logs are indexed as they are added
+            assert!(db.get_metadata(LOG_INDEX_BUILT_KEY).unwrap().is_some());
+            set_test_receipt(
+                &mut db,
+                1,
+                None,
+                address,
+                vec![Log::new_unchecked(address, Vec::new(), Bytes::new())],
+                0,
+            );
+            db.commit_changes().unwrap();
+
+            // Same as a database created before the log indexes
+            let database = db.db.as_ref().unwrap();
+            for name in ["log_address_index", "log_topic_index"] {
+                let keys = database
+                    .iterator_cf(database.cf_handle(name).unwrap(), IteratorMode::Start)
+                    .map(|kv_pair| kv_pair.unwrap().0)
+                    .collect::<Vec<_>>();
+                for key in keys {
+                    database
+                        .delete_cf(database.cf_handle(name).unwrap(), key)
+                        .unwrap();
+                }
+            }
+            database
+                .delete_cf(
+                    database.cf_handle(METADATA_DATABASE).unwrap(),
+                    LOG_INDEX_BUILT_KEY,
+                )
+                .unwrap();
+        }
+
+        let db = DB::new(&path).unwrap();
+        assert!(db.get_metadata(LOG_INDEX_BUILT_KEY).unwrap().is_some());
+        let logs = db
+            .get_logs(Some(1), Some(1), vec![address], Vec::new(), 100)
+            .unwrap();
+        assert_eq!(logs.len(), 1);
+    }
+
+    #[test]
+    fn test_state_history() {
+        let path = TempDir::new().unwrap().into_path();
+
+        let address = Address::from([1u8; 20]);
+        let mem_loc = U256::from(2);
+        let get_account_info = |nonce| AccountInfo {
+            nonce,
+            ..Default::default()
+        };
+
+        let mut db = DB::new(&path).unwrap();
+        db.set_block_hash(0, B256::from([3u8; 32])).unwrap();
+        for block_number in 1..=2 {
+            db.set_pending_block_number(block_number);
+            db.set_account_info(address, get_account_info(block_number))
+                .unwrap();
+            db.set_account_memory(address, mem_loc, U256::from(block_number * 10))
+                .unwrap();
+            db.set_block_hash(block_number, B256::from(U256::from(block_number)))
+                .unwrap();
+        }
+        db.commit_changes().unwrap();
+
+        // Changes are recorded at the block they are made in
+        assert!(db.get_account_info_at(address, 0).unwrap().is_none());
+        assert_eq!(
+            db.get_account_info_at(address, 1).unwrap().unwrap().0.nonce,
+            1
+        );
+        assert_eq!(
+            db.get_account_memory_at(address, mem_loc, 2)
+                .unwrap()
+                .unwrap()
+                .0,
+            U256::from(20)
+        );
+
+        {
+            let state_view = db.get_state_view(Some(1)).unwrap();
+            assert_eq!(state_view.basic_ref(address).unwrap().unwrap().nonce, 1);
+            assert_eq!(
+                state_view.storage_ref(address, mem_loc).unwrap(),
+                U256::from(10)
+            );
+            let state_view = db.get_state_view(None).unwrap();
+            assert!(state_view.basic_ref(address).unwrap().is_none());
+        }
+
+        db.reorg(1).unwrap();
+        assert_eq!(db.get_account_info(address).unwrap().unwrap().0.nonce, 1);
+        assert_eq!(
+            db.get_account_memory(address, mem_loc).unwrap().unwrap().0,
+            U256::from(10)
+        );
+    }
+
+    #[test]
+    fn test_archive_state_history() {
+        let path = TempDir::new().unwrap().into_path();
+        let archive_path = TempDir::new().unwrap().into_path();
+
+        let address = Address::from([1u8; 20]);
+        let mem_loc = U256::from(2);
+
+        let mut db = DB::new(&path).unwrap();
+        let mut archive_db = DB::new_with_archive_mode(&archive_path, true).unwrap();
+        assert!(!db.is_archive());
+        assert!(archive_db.is_archive());
+        for db in [&mut db, &mut archive_db] {
+            db.set_block_hash(0, B256::from([3u8; 32])).unwrap();
+            for block_number in 1..=DEFAULT_MAX_REORG_DEPTH * 2 {
+                db.set_pending_block_number(block_number);
+                db.set_account_memory(address, mem_loc, U256::from(block_number * 10))
+                    .unwrap();
+                db.set_block_hash(block_number, B256::from(U256::from(block_number)))
+                    .unwrap();
+                db.commit_changes().unwrap();
+            }
+        }
+
+        // Old blocks are only available in archive mode
+        assert!(db.get_state_view(Some(1)).is_err());
+        let state_view = archive_db.get_state_view(Some(1)).unwrap();
+        assert_eq!(
+            state_view.storage_ref(address, mem_loc).unwrap(),
+            U256::from(10)
+        );
+        let state_view = archive_db.get_state_view(None).unwrap();
+        assert_eq!(
+            state_view.storage_ref(address, mem_loc).unwrap(),
+            U256::ZERO
+        );
+        assert_eq!(
+            archive_db
+                .get_account_memory_at(address, mem_loc, DEFAULT_MAX_REORG_DEPTH * 2)
+                .unwrap()
+                .unwrap()
+                .0,
+            U256::from(DEFAULT_MAX_REORG_DEPTH * 20)
+        );
+
+        archive_db.reorg(DEFAULT_MAX_REORG_DEPTH * 2 - 1).unwrap();
+        assert_eq!(
+            archive_db
+                .get_account_memory_at(address, mem_loc, DEFAULT_MAX_REORG_DEPTH * 2)
+                .unwrap()
+                .unwrap()
+                .0,
+            U256::from(DEFAULT_MAX_REORG_DEPTH * 20 - 10)
+        );
+    }
+
+    #[test]
+    fn test_archive_mode_enabled_later() {
+        let path = TempDir::new().unwrap().into_path();
+        let address = Address::from([1u8; 20]);
+        let mem_loc = U256::from(2);
+        let latest_block_number = DEFAULT_MAX_REORG_DEPTH * 2;
+
+        {
+            let mut db = DB::new(&path).unwrap();
+            db.set_block_hash(0, B256::from([3u8; 32])).unwrap();
+            db.set_account_memory(address, mem_loc, U256::from(10))
+                .unwrap();
+            db.commit_changes().unwrap();
+            for block_number in 1..=latest_block_number {
+                db.set_block_hash(block_number, B256::from(U256::from(block_number)))
+                    .unwrap();
+                db.commit_changes().unwrap();
+            }
+        }
+
+        // Archive mode starts at the next block, value is changed later
+        let mut db = DB::new_with_archive_mode(&path, true).unwrap();
+        let archive_start_block = latest_block_number + 1;
+        let change_block_number = archive_start_block + 5;
+        for block_number in archive_start_block..=change_block_number + DEFAULT_MAX_REORG_DEPTH * 2
+        {
+            db.set_pending_block_number(block_number);
+            if block_number == change_block_number {
+                db.set_account_memory(address, mem_loc, U256::from(20))
+                    .unwrap();
+            }
+            db.set_block_hash(block_number, B256::from(U256::from(block_number)))
+                .unwrap();
+            db.commit_changes().unwrap();
+        }
+
+        // Value set before archive mode is available until it's changed
+        for (block_number, value) in [
+            (archive_start_block, 10),
+            (change_block_number - 1, 10),
+            (change_block_number, 20),
+        ] {
+            let state_view = db.get_state_view(Some(block_number)).unwrap();
+            assert_eq!(
+                state_view.storage_ref(address, mem_loc).unwrap(),
+                U256::from(value)
+            );
+        }
+        // Blocks before archive mode was enabled are not available once they can't be reorged
+        assert!(db.get_state_view(Some(latest_block_number)).is_err());
+    }
+
+    #[test]
+    fn test_migrate_separate_databases() {
+        use types::Encode;
+
+        let path = TempDir::new().unwrap().into_path();
+        let block_hash = B256::from([1; 32]);
+        {
+            let mut opts = rocksdb::Options::default();
+            opts.create_if_missing(true);
+            let old_db = rocksdb::DB::open(&opts, path.join("block_number_to_hash")).unwrap();
+            old_db
+                .put(
+                    U64ED::from_u64(5).encode().unwrap(),
+                    B256ED::from_b256(block_hash).encode().unwrap(),
+                )
+                .unwrap();
+        }
+
+        let db = DB::new(&path).unwrap();
+        assert!(!path.join("block_number_to_hash").exists());
+        assert_eq!(db.get_block_hash(5).unwrap(), Some(block_hash));
+        assert_eq!(db.get_latest_block_height().unwrap(), 5);
+    }
+
+    #[test]
+    fn test_configured_reorg_depth() {
+        let path = TempDir::new().unwrap().into_path();
+        let address = Address::from([1u8; 20]);
+        let mem_loc = U256::from(2);
+
+        let mut db = DB::new_with_options(&path, false, Some(100)).unwrap();
+        assert_eq!(db.get_max_reorg_depth(), Some(100));
+        db.set_block_hash(0, B256::from([3u8; 32])).unwrap();
+        for block_number in 1..=150 {
+            db.set_pending_block_number(block_number);
+            db.set_account_memory(address, mem_loc, U256::from(block_number))
+                .unwrap();
+            db.set_block_hash(block_number, B256::from(U256::from(block_number)))
+                .unwrap();
+            db.commit_changes().unwrap();
+        }
+
+        // Blocks older than the max reorg depth are not available
+        assert!(db.get_state_view(Some(49)).is_err());
+        assert_eq!(
+            db.get_account_memory_at(address, mem_loc, 60)
+                .unwrap()
+                .unwrap()
+                .0,
+            U256::from(60)
+        );
+
+        db.reorg(60).unwrap();
+        assert_eq!(db.get_latest_block_height().unwrap(), 60);
+        assert_eq!(
+            db.get_account_memory(address, mem_loc).unwrap().unwrap().0,
+            U256::from(60)
+        );
+    }
+
+    #[test]
+    fn test_block_roots() {
+        let path = TempDir::new().unwrap().into_path();
+        let addresses = [Address::from([1u8; 20]), Address::from([2u8; 20])];
+        let mem_loc = U256::from(2);
+
+        let mut db = DB::new(&path).unwrap();
+        db.set_block_hash(0, B256::from([3u8; 32])).unwrap();
+        db.set_block_roots(0).unwrap();
+        let add_block = |db: &mut DB, block_number: u64| {
+            db.set_pending_block_number(block_number);
+            for address in addresses {
+                db.set_account_info(
+                    address,
+                    AccountInfo {
+                        nonce: block_number,
+                        ..Default::default()
+                    },
+                )
+                .unwrap();
+                db.set_account_memory(address, mem_loc, U256::from(block_number % 2))
+                    .unwrap();
+            }
+            db.set_block_hash(block_number, B256::from(U256::from(block_number)))
+                .unwrap();
+            db.set_block_roots(block_number).unwrap();
+            db.commit_changes().unwrap();
+            db.get_state_root(block_number).unwrap().unwrap()
+        };
+        let roots = (1..=3)
+            .map(|block_number| add_block(&mut db, block_number))
+            .collect::<Vec<B256>>();
+
+        assert_eq!(db.get_state_root(0).unwrap().unwrap(), EMPTY_ROOT);
+        assert_ne!(roots[0], roots[1]);
+        assert_ne!(roots[1], roots[2]);
+        let block = db.get_block(3).unwrap().unwrap();
+        assert_eq!(block.state_root.0, roots[2]);
+        assert_eq!(block.receipts_root.0, EMPTY_ROOT);
+
+        // State hash commits to the block hash and both roots
+        let state_hash = db.get_block_state_hash(3).unwrap().unwrap();
+        assert_eq!(
+            state_hash,
+            keccak256(
+                [
+                    block.hash.0.as_slice(),
+                    EMPTY_ROOT.as_slice(),
+                    roots[2].as_slice()
+                ]
+                .concat()
+            )
+        );
+
+        // Building the trie from the whole state gives the same root
+        assert_eq!(db.build_state_trie(3).unwrap(), roots[2]);
+        db.clear_caches();
+
+        // Reorged blocks get the same roots when they are added again
+        db.reorg(1).unwrap();
+        assert!(db.get_state_root(2).unwrap().is_none());
+        assert_eq!(add_block(&mut db, 2), roots[1]);
+        assert_eq!(add_block(&mut db, 3), roots[2]);
+        assert_eq!(db.get_block_state_hash(3).unwrap().unwrap(), state_hash);
+
+        // Same state with a different block hash has a different state hash
+        db.reorg(2).unwrap();
+        db.set_pending_block_number(3);
+        db.set_block_hash(3, B256::from([4u8; 32])).unwrap();
+        db.set_block_roots(3).unwrap();
+        assert_ne!(db.get_block_state_hash(3).unwrap().unwrap(), state_hash);
+    }
+
+    #[test]
+    fn test_build_state_trie_of_old_database() {
+        let path = TempDir::new().unwrap().into_path();
+        let address = Address::from([1u8; 20]);
+
+        // Older versions stored blocks without roots
+        {
+            let mut db = DB::new(&path).unwrap();
+            db.set_block_hash(0, B256::from([3u8; 32])).unwrap();
+            db.set_account_info(
+                address,
+                AccountInfo {
+                    nonce: 1,
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+            db.set_account_memory(address, U256::from(2), U256::from(10))
+                .unwrap();
+            let block = db.build_block(0, B256::from([3u8; 32])).unwrap().unwrap();
+            db.db_block_number_to_block.as_mut().unwrap().set(0, block);
+            db.commit_changes().unwrap();
+            assert!(db.get_state_root(0).unwrap().is_none());
+        }
+
+        // Roots of the latest block are rebuilt and the stored block is rewritten with them
+        let db = DB::new(&path).unwrap();
+        let state_root = db.get_state_root(0).unwrap().unwrap();
+        assert_ne!(state_root, EMPTY_ROOT);
+        let block = db.get_block(0).unwrap().unwrap();
+        assert_eq!(block.state_root.0, state_root);
+        assert_eq!(block.receipts_root.0, EMPTY_ROOT);
+    }
+
+    #[test]
+    fn test_get_proof() {
+        let path = TempDir::new().unwrap().into_path();
+        let address = Address::from([1u8; 20]);
+        let mem_loc = U256::from(2);
+
+        let mut db = DB::new(&path).unwrap();
+        db.set_block_hash(0, B256::from([3u8; 32])).unwrap();
+        db.set_block_roots(0).unwrap();
+        for block_number in 1..=2 {
+            db.set_pending_block_number(block_number);
+            db.set_account_info(
+                address,
+                AccountInfo {
+                    nonce: block_number,
+                    balance: U256::from(100),
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+            db.set_account_memory(address, mem_loc, U256::from(block_number * 10))
+                .unwrap();
+            db.set_block_hash(block_number, B256::from(U256::from(block_number)))
+                .unwrap();
+            db.set_block_roots(block_number).unwrap();
+            db.commit_changes().unwrap();
+        }
+
+        // Proofs of earlier blocks are available without archive mode
+        let proof = db
+            .get_proof(address, &[mem_loc, U256::from(3)], 1)
+            .unwrap()
+            .unwrap();
+        assert_eq!(proof.nonce, U64ED::from_u64(1));
+        assert_eq!(proof.balance, U256ED::from_u256(U256::from(100)));
+        assert_eq!(proof.code_hash.0, KECCAK_EMPTY);
+        assert_eq!(
+            proof.storage_proof[0].value,
+            U256ED::from_u256(U256::from(10))
+        );
+        assert_eq!(proof.storage_proof[1].value, U256ED::from_u256(U256::ZERO));
+
+        // Proofs start with the root nodes
+        let state_root = db.get_state_root(1).unwrap().unwrap();
+        assert_eq!(keccak256(&proof.account_proof[0]), state_root);
+        assert_eq!(
+            keccak256(&proof.storage_proof[0].proof[0]),
+            proof.storage_hash.0
+        );
+
+        let proof = db.get_proof(address, &[mem_loc], 2).unwrap().unwrap();
+        assert_eq!(proof.nonce, U64ED::from_u64(2));
+        assert_eq!(
+            proof.storage_proof[0].value,
+            U256ED::from_u256(U256::from(20))
+        );
+
+        // Missing accounts have empty values
+        let proof = db
+            .get_proof(Address::from([2u8; 20]), &[], 2)
+            .unwrap()
+            .unwrap();
+        assert_eq!(proof.nonce, U64ED::from_u64(0));
+        assert_eq!(proof.storage_hash.0, EMPTY_ROOT);
+        assert!(!proof.account_proof.is_empty());
+
+        assert!(db.get_proof(address, &[], 3).unwrap().is_none());
+    }
+    #[test]
+    fn test_tx_bitcoin_data() {
+        let path = TempDir::new().unwrap().into_path();
+        let mut bitcoin_data = BitcoinDataED::default();
+        bitcoin_data
+            .block_heights
+            .insert("aa".to_string(), Some(240960));
+
+        let mut db = DB::new(&path).unwrap();
+        db.set_block_hash(0, B256::from([3u8; 32])).unwrap();
+        for block_number in 1..=2 {
+            db.set_pending_block_number(block_number);
+            db.set_tx_bitcoin_data(
+                block_number,
+                B256::from(U256::from(block_number)),
+                bitcoin_data.clone(),
+            )
+            .unwrap();
+            // Transactions that didn't read any data are not recorded
+            db.set_tx_bitcoin_data(
+                block_number,
+                B256::from(U256::from(block_number + 10)),
+                BitcoinDataED::default(),
+            )
+            .unwrap();
+            db.set_block_hash(block_number, B256::from(U256::from(block_number)))
+                .unwrap();
+            db.commit_changes().unwrap();
+        }
+
+        assert_eq!(
+            db.get_tx_bitcoin_data(B256::from(U256::from(2))).unwrap(),
+            Some(bitcoin_data.clone())
+        );
+        assert_eq!(
+            db.get_tx_bitcoin_data(B256::from(U256::from(12))).unwrap(),
+            None
+        );
+
+        // Recorded data is reverted with the transactions
+        db.reorg(1).unwrap();
+        assert_eq!(
+            db.get_tx_bitcoin_data(B256::from(U256::from(1))).unwrap(),
+            Some(bitcoin_data)
+        );
+        assert_eq!(
+            db.get_tx_bitcoin_data(B256::from(U256::from(2))).unwrap(),
+            None
+        );
+
+        // Cache is stored in the same database
+        let cache = db.get_bitcoin_cache().unwrap();
+        cache.set_block_height("bb", 10).unwrap();
+        assert_eq!(cache.get_block_height("bb").unwrap(), Some(10));
     }
 }
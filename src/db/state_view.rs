@@ -0,0 +1,87 @@
+use revm::primitives::{Address, B256, U256};
+use revm::DatabaseRef;
+use revm_state::{AccountInfo, Bytecode};
+
+use crate::db::{DBError, DB};
+
+/// Read only view of the state at the end of a block, used to re-execute transactions
+///
+/// Changes made by the EVM should be kept in a CacheDB on top of this view
+pub struct StateView<'a> {
+    db: &'a DB,
+    block: ViewBlock,
+}
+
+#[derive(Clone, Copy)]
+enum ViewBlock {
+    /// Latest state, including the changes of the block that's being built
+    Latest,
+    /// End of the block, None means the state before the first block, which is empty
+    At(Option<u64>),
+}
+
+impl<'a> StateView<'a> {
+    pub fn new(db: &'a DB, block_number: Option<u64>) -> Self {
+        Self {
+            db,
+            block: ViewBlock::At(block_number),
+        }
+    }
+
+    /// View of the latest state, so calls on the pending state and on a block run the same way
+    pub fn latest(db: &'a DB) -> Self {
+        Self {
+            db,
+            block: ViewBlock::Latest,
+        }
+    }
+}
+
+impl DatabaseRef for StateView<'_> {
+    type Error = DBError;
+
+    fn basic_ref(&self, address: Address) -> Result<Option<AccountInfo>, Self::Error> {
+        let block_number = match self.block {
+            ViewBlock::Latest => return self.db.basic_ref(address),
+            ViewBlock::At(None) => return Ok(None),
+            ViewBlock::At(Some(block_number)) => block_number,
+        };
+        let account_info = self
+            .db
+            .get_account_info_at(address, block_number)
+            .map_err(DBError)?;
+        let Some(account_info) = account_info else {
+            return Ok(None);
+        };
+        let mut account_info = account_info.0;
+        account_info.code = Some(self.code_by_hash_ref(account_info.code_hash)?);
+        Ok(Some(account_info))
+    }
+
+    fn code_by_hash_ref(&self, code_hash: B256) -> Result<Bytecode, Self::Error> {
+        // Code is stored by its hash, so the latest value is valid for all blocks
+        self.db
+            .get_code(code_hash)
+            .map(|x| x.map(|x| x.0).unwrap_or_default())
+            .map_err(DBError)
+    }
+
+    fn storage_ref(&self, address: Address, index: U256) -> Result<U256, Self::Error> {
+        let block_number = match self.block {
+            ViewBlock::Latest => return self.db.storage_ref(address, index),
+            ViewBlock::At(None) => return Ok(U256::ZERO),
+            ViewBlock::At(Some(block_number)) => block_number,
+        };
+        self.db
+            .get_account_memory_at(address, index, block_number)
+            .map(|x| x.map(|x| x.0).unwrap_or(U256::ZERO))
+            .map_err(DBError)
+    }
+
+    fn block_hash_ref(&self, number: u64) -> Result<B256, Self::Error> {
+        self.db
+            .get_block_hash(number)
+            .map(|x| x.unwrap_or(B256::ZERO))
+            .map_err(DBError)
+    }
+}
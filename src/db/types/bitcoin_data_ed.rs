@@ -0,0 +1,119 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+
+use serde::{Deserialize, Serialize};
+
+use crate::db::types::{Decode, Encode};
+
+/// Bitcoin transaction with the fields used by the precompiles
+///
+/// Hashes are in the hex format used by bitcoind and block explorers, without the 0x prefix
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct BitcoinTxED {
+    pub txid: String,
+    /// Hash of the block that includes the transaction, None if it's not confirmed
+    #[serde(rename = "blockHash")]
+    pub block_hash: Option<String>,
+    pub vin: Vec<BitcoinTxInED>,
+    pub vout: Vec<BitcoinTxOutED>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct BitcoinTxInED {
+    /// Txid of the spent output, None for coinbase inputs
+    pub txid: Option<String>,
+    pub vout: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct BitcoinTxOutED {
+    /// Value in satoshis
+    pub value: u64,
+    #[serde(rename = "scriptPubKey")]
+    pub script_pubkey: String,
+}
+
+impl BitcoinTxED {
+    pub fn is_coinbase(&self) -> bool {
+        self.vin.first().is_some_and(|vin| vin.txid.is_none())
+    }
+}
+
+/// Bitcoin block header with its height
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct BitcoinBlockHeaderED {
+    pub hash: String,
+    pub height: u64,
+    pub time: u64,
+    #[serde(rename = "merkleRoot")]
+    pub merkle_root: String,
+    /// None for the genesis block
+    #[serde(rename = "previousBlockHash")]
+    pub previous_block_hash: Option<String>,
+}
+
+/// Bitcoin data read by the precompiles while executing a transaction
+///
+/// Missing transactions and blocks are kept as null, so replaying the transaction
+/// with this data gives the same result without asking the provider again
+#[derive(Debug, Default, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct BitcoinDataED {
+    /// Txid to transaction
+    pub transactions: BTreeMap<String, Option<BitcoinTxED>>,
+    /// Block hash to block height
+    #[serde(rename = "blockHeights")]
+    pub block_heights: BTreeMap<String, Option<u64>>,
+}
+
+impl BitcoinDataED {
+    pub fn is_empty(&self) -> bool {
+        self.transactions.is_empty() && self.block_heights.is_empty()
+    }
+}
+
+impl Encode for BitcoinDataED {
+    fn encode(&self) -> Result<Vec<u8>, Box<dyn Error>> {
+        Ok(serde_json::to_vec(self)?)
+    }
+}
+
+impl Decode for BitcoinDataED {
+    fn decode(bytes: Vec<u8>) -> Result<Self, Box<dyn Error>> {
+        Ok(serde_json::from_slice(&bytes)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bitcoin_data_ed() {
+        let mut data = BitcoinDataED::default();
+        assert!(data.is_empty());
+
+        let transaction = BitcoinTxED {
+            txid: "aa".to_string(),
+            block_hash: Some("bb".to_string()),
+            vin: vec![BitcoinTxInED {
+                txid: Some("ee".to_string()),
+                vout: 1,
+            }],
+            vout: vec![BitcoinTxOutED {
+                value: 10000000,
+                script_pubkey: "0014f477952f33561c1b89a1fe9f28682f623263e159".to_string(),
+            }],
+        };
+        assert!(!transaction.is_coinbase());
+        data.transactions
+            .insert("aa".to_string(), Some(transaction));
+        data.transactions.insert("cc".to_string(), None);
+        data.block_heights.insert("bb".to_string(), Some(240960));
+        data.block_heights.insert("dd".to_string(), None);
+        assert!(!data.is_empty());
+
+        let bytes = data.encode().unwrap();
+        let decoded = BitcoinDataED::decode(bytes).unwrap();
+        assert_eq!(data, decoded);
+    }
+}
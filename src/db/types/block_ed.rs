@@ -157,6 +157,7 @@ impl Encode for BlockResponseED {
         bytes.extend_from_slice(&self.parent_hash.encode()?);
         bytes.extend_from_slice(&self.receipts_root.encode()?);
         bytes.extend_from_slice(&self.size.to_be_bytes());
+        bytes.extend_from_slice(&self.state_root.encode()?);
         Ok(bytes)
     }
 }
@@ -202,8 +203,15 @@ impl Decode for BlockResponseED {
         let receipts_root = B256ED::decode(bytes[i..i + 32].to_vec())?;
         i += 32;
         let size = u64::from_be_bytes(bytes[i..i + 8].try_into()?);
+        i += 8;
+        // Blocks stored before the state root was added end here
+        let state_root = if bytes.len() >= i + 32 {
+            B256ED::decode(bytes[i..i + 32].to_vec())?
+        } else {
+            BEncodeDecode(FixedBytes([0u8; 32]))
+        };
 
-        Ok(BlockResponseED::new(
+        let mut block = BlockResponseED::new(
             difficulty,
             gas_limit,
             gas_used,
@@ -219,7 +227,9 @@ impl Decode for BlockResponseED {
             parent_hash,
             receipts_root,
             size,
-        ))
+        );
+        block.state_root = state_root;
+        Ok(block)
     }
 }
 
@@ -229,7 +239,7 @@ mod tests {
 
     #[test]
     fn test_block_response_encode_decode() {
-        let block = BlockResponseED::new(
+        let mut block = BlockResponseED::new(
             1,
             2,
             3,
@@ -249,10 +259,16 @@ mod tests {
             BEncodeDecode(FixedBytes([15u8; 32])),
             16,
         );
+        block.state_root = BEncodeDecode(FixedBytes([17u8; 32]));
 
         let encoded = block.encode().unwrap();
-        let decoded = BlockResponseED::decode(encoded).unwrap();
+        let decoded = BlockResponseED::decode(encoded.clone()).unwrap();
 
         assert_eq!(block, decoded);
+
+        // Blocks encoded without the state root are decoded with a zero state root
+        let decoded = BlockResponseED::decode(encoded[..encoded.len() - 32].to_vec()).unwrap();
+        assert_eq!(decoded.state_root, BEncodeDecode(FixedBytes([0u8; 32])));
+        assert_eq!(decoded.receipts_root, block.receipts_root);
     }
 }
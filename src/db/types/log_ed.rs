@@ -28,6 +28,8 @@ pub struct LogResponseED {
     pub block_number: U64ED,
     #[serde(rename = "logIndex")]
     pub log_index: U64ED,
+    /// True if the log was removed by a reorg, only used in subscription notifications
+    pub removed: bool,
 }
 
 fn bytes_hex<S>(bytes: &Bytes, serializer: S) -> Result<S::Ok, S::Error>
@@ -0,0 +1,97 @@
+use std::error::Error;
+
+use revm::primitives::{Address, B256};
+
+use crate::db::types::{Decode, Encode};
+
+/// Key for the log indexes
+///
+/// Prefix is either the log address, or the topic position followed by the topic.
+/// Block number and tx index come after the prefix in big endian,
+/// so keys with the same prefix are sorted by block number and tx index in their encoded form
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct LogIndexKeyED {
+    pub prefix: Vec<u8>,
+    pub block_number: u64,
+    pub tx_idx: u64,
+}
+
+impl LogIndexKeyED {
+    pub fn from_address(address: Address, block_number: u64, tx_idx: u64) -> Self {
+        Self {
+            prefix: address.to_vec(),
+            block_number,
+            tx_idx,
+        }
+    }
+
+    pub fn from_topic(position: u8, topic: B256, block_number: u64, tx_idx: u64) -> Self {
+        let mut prefix = Vec::new();
+        prefix.push(position);
+        prefix.extend_from_slice(topic.as_slice());
+        Self {
+            prefix,
+            block_number,
+            tx_idx,
+        }
+    }
+}
+
+impl Encode for LogIndexKeyED {
+    fn encode(&self) -> Result<Vec<u8>, Box<dyn Error>> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(&self.prefix);
+        bytes.extend_from_slice(&self.block_number.to_be_bytes());
+        bytes.extend_from_slice(&self.tx_idx.to_be_bytes());
+        Ok(bytes)
+    }
+}
+
+impl Decode for LogIndexKeyED {
+    fn decode(bytes: Vec<u8>) -> Result<Self, Box<dyn Error>>
+    where
+        Self: Sized,
+    {
+        if bytes.len() < 16 {
+            return Err("Log index key is too short".into());
+        }
+        let prefix_len = bytes.len() - 16;
+        Ok(LogIndexKeyED {
+            prefix: bytes[..prefix_len].to_vec(),
+            block_number: u64::from_be_bytes(bytes[prefix_len..prefix_len + 8].try_into()?),
+            tx_idx: u64::from_be_bytes(bytes[prefix_len + 8..].try_into()?),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_log_index_key_ed() {
+        let key = LogIndexKeyED::from_topic(2, B256::from([1u8; 32]), 3, 4);
+        let bytes = LogIndexKeyED::encode(&key).unwrap();
+        assert_eq!(bytes.len(), 1 + 32 + 8 + 8);
+        let decoded = LogIndexKeyED::decode(bytes).unwrap();
+        assert_eq!(key, decoded);
+
+        let key = LogIndexKeyED::from_address(Address::from([5u8; 20]), 6, 7);
+        let bytes = LogIndexKeyED::encode(&key).unwrap();
+        let decoded = LogIndexKeyED::decode(bytes).unwrap();
+        assert_eq!(key, decoded);
+    }
+
+    #[test]
+    fn test_log_index_key_ed_order() {
+        let address = Address::from([5u8; 20]);
+        let keys = [
+            LogIndexKeyED::from_address(address, 1, 300),
+            LogIndexKeyED::from_address(address, 2, 0),
+            LogIndexKeyED::from_address(address, 256, 1),
+        ];
+        for pair in keys.windows(2) {
+            assert!(pair[0].encode().unwrap() < pair[1].encode().unwrap());
+        }
+    }
+}
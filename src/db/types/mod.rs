@@ -16,6 +16,9 @@ pub use b256_ed::*;
 mod log_ed;
 pub use log_ed::*;
 
+mod log_index_key_ed;
+pub use log_index_key_ed::*;
+
 mod tx_ed;
 pub use tx_ed::*;
 
@@ -25,5 +28,11 @@ pub use tx_receipt_ed::*;
 mod block_ed;
 pub use block_ed::*;
 
+mod proof_ed;
+pub use proof_ed::*;
+
+mod bitcoin_data_ed;
+pub use bitcoin_data_ed::*;
+
 mod encode_decode;
 pub use encode_decode::*;
@@ -0,0 +1,39 @@
+use revm::primitives::{hex, Bytes};
+use serde::Serialize;
+
+use crate::db::types::{AddressED, B256ED, U256ED, U64ED};
+
+/// Account and storage proofs as returned by eth_getProof, in EIP-1186 format
+#[derive(Debug, Serialize, Clone, PartialEq, Eq)]
+pub struct AccountProofED {
+    pub address: AddressED,
+    #[serde(rename = "accountProof", serialize_with = "bytes_list_to_hex")]
+    pub account_proof: Vec<Bytes>,
+    pub balance: U256ED,
+    #[serde(rename = "codeHash")]
+    pub code_hash: B256ED,
+    pub nonce: U64ED,
+    #[serde(rename = "storageHash")]
+    pub storage_hash: B256ED,
+    #[serde(rename = "storageProof")]
+    pub storage_proof: Vec<StorageProofED>,
+}
+
+#[derive(Debug, Serialize, Clone, PartialEq, Eq)]
+pub struct StorageProofED {
+    pub key: U256ED,
+    pub value: U256ED,
+    #[serde(serialize_with = "bytes_list_to_hex")]
+    pub proof: Vec<Bytes>,
+}
+
+fn bytes_list_to_hex<S>(bytes_list: &[Bytes], serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+{
+    serializer.collect_seq(
+        bytes_list
+            .iter()
+            .map(|bytes| format!("0x{}", hex::encode(bytes))),
+    )
+}
@@ -1,5 +1,6 @@
 use revm::context::{BlockEnv, CfgEnv, ContextSetters, ContextTr, Evm, EvmData, TxEnv};
 use revm::context_interface::block::BlobExcessGasAndPrice;
+use revm::database::CacheDB;
 use revm::handler::instructions::{EthInstructions, InstructionProvider};
 use revm::handler::EvmTr;
 use revm::inspector::{inspect_instructions, InspectorEvmTr, JournalExt, NoOpInspector};
@@ -7,19 +8,36 @@ use revm::interpreter::interpreter::EthInterpreter;
 use revm::interpreter::{Interpreter, InterpreterTypes};
 use revm::primitives::hardfork::SpecId;
 use revm::primitives::{B256, U256};
-use revm::{Context, Inspector, Journal, JournalEntry};
+use revm::{Context, Database, Inspector, Journal, JournalEntry};
 
 use crate::db::DB;
-use crate::evm::precompiles::BRC20Precompiles;
+use crate::evm::precompiles::{BRC20Precompiles, BitcoinData};
 
 const CURRENT_SPEC: SpecId = SpecId::CANCUN;
 
-pub fn get_evm(
+/// EVM on the latest state of the database, changes are kept in a CacheDB until they are committed
+pub type LatestStateEvm<'a> =
+    BRC20Evm<Context<BlockEnv, TxEnv, CfgEnv, CacheDB<&'a DB>>, NoOpInspector>;
+
+/// Returns an EVM that reads the latest state through a shared reference to the database,
+/// so other readers can use the database while it runs
+///
+/// Changes are returned by transact and should be committed to the database afterwards
+pub fn get_evm(block_info: BlockEnv, db: &DB, gas_limit: Option<u64>) -> LatestStateEvm<'_> {
+    let bitcoin_cache = db.get_bitcoin_cache();
+    let mut evm = get_evm_with_inspector(block_info, CacheDB::new(db), gas_limit, NoOpInspector);
+    *evm.bitcoin_data() = BitcoinData::new(bitcoin_cache);
+    evm
+}
+
+/// Same as get_evm, but runs on any database and with the given inspector, e.g. for tracing
+pub fn get_evm_with_inspector<D: Database, INSP>(
     block_info: BlockEnv,
-    db: DB,
+    db: D,
     gas_limit: Option<u64>,
-) -> BRC20Evm<Context<BlockEnv, TxEnv, CfgEnv, DB>, NoOpInspector> {
-    let mut ctx: Context<BlockEnv, TxEnv, CfgEnv, DB, Journal<DB, JournalEntry>> =
+    inspector: INSP,
+) -> BRC20Evm<Context<BlockEnv, TxEnv, CfgEnv, D>, INSP> {
+    let mut ctx: Context<BlockEnv, TxEnv, CfgEnv, D, Journal<D, JournalEntry>> =
         Context::new(db, CURRENT_SPEC);
 
     ctx.cfg.chain_id = 331337;
@@ -37,7 +55,7 @@ pub fn get_evm(
     ctx.tx.gas_price = 0;
     ctx.tx.value = U256::ZERO;
 
-    BRC20Evm::new(ctx, NoOpInspector)
+    BRC20Evm::new(ctx, inspector)
 }
 
 /// MyEvm variant of the EVM.
@@ -53,6 +71,16 @@ impl<CTX: ContextTr, INSP> BRC20Evm<CTX, INSP> {
             precompiles: BRC20Precompiles::default(),
         })
     }
+
+    /// Bitcoin data used by the precompiles, it records the data read by each transaction
+    pub fn bitcoin_data(&mut self) -> &mut BitcoinData {
+        &mut self.0.precompiles.bitcoin_data
+    }
+
+    /// Sets the hash of the executing block, used to get BRC20 balances at this block
+    pub fn set_block_hash(&mut self, block_hash: Option<B256>) {
+        self.0.precompiles.block_hash = block_hash;
+    }
 }
 
 impl<CTX: ContextTr, INSP> EvmTr for BRC20Evm<CTX, INSP>
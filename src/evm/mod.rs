@@ -7,7 +7,11 @@ mod evm;
 pub use evm::*;
 
 mod precompiles;
-pub use precompiles::{check_bitcoin_rpc_status, get_brc20_balance};
+pub use precompiles::{
+    check_bitcoin_data_provider_status, BitcoinData, BRC20_BALANCE_PROVIDER, BRC20_BALANCE_SNAPSHOT,
+};
 
 mod utils;
 pub use utils::*;
+
+pub mod tracers;
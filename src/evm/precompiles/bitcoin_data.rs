@@ -0,0 +1,321 @@
+use std::sync::Arc;
+
+use crate::db::types::{BitcoinDataED, BitcoinTxED};
+use crate::db::BitcoinCache;
+use crate::evm::precompiles::{BitcoinDataProvider, BITCOIN_DATA_PROVIDER};
+
+/// Source of the Bitcoin data used by the precompiles
+///
+/// Data is read from the recorded data of the transaction being replayed, then from the
+/// persistent cache and finally from the provider. Everything that is read is recorded,
+/// so it can be stored with the transaction and used again when it's replayed.
+///
+/// Errors mean the provider couldn't answer, they should fail the whole transaction instead of
+/// only the precompile call, so the result doesn't depend on the availability of the provider
+pub struct BitcoinData {
+    provider: Arc<dyn BitcoinDataProvider>,
+    cache: Option<BitcoinCache>,
+    replay: BitcoinDataED,
+    recorded: BitcoinDataED,
+}
+
+impl Default for BitcoinData {
+    fn default() -> Self {
+        Self::new(None)
+    }
+}
+
+impl BitcoinData {
+    /// Uses the provider configured in the environment
+    pub fn new(cache: Option<BitcoinCache>) -> Self {
+        Self::with_provider(BITCOIN_DATA_PROVIDER.clone(), cache)
+    }
+
+    pub fn with_provider(
+        provider: Arc<dyn BitcoinDataProvider>,
+        cache: Option<BitcoinCache>,
+    ) -> Self {
+        Self {
+            provider,
+            cache,
+            replay: BitcoinDataED::default(),
+            recorded: BitcoinDataED::default(),
+        }
+    }
+
+    /// Sets the data to use for the next transaction, it's recorded again when it's read
+    pub fn set_replay_data(&mut self, replay: BitcoinDataED) {
+        self.replay = replay;
+    }
+
+    /// Returns the data read since the last call, and clears it for the next transaction
+    pub fn take_recorded_data(&mut self) -> BitcoinDataED {
+        self.replay = BitcoinDataED::default();
+        std::mem::take(&mut self.recorded)
+    }
+
+    /// Returns the transaction, None if it doesn't exist
+    pub fn get_transaction(&mut self, txid: &str) -> Result<Option<BitcoinTxED>, String> {
+        let txid = txid.to_lowercase();
+        if let Some(transaction) = self.recorded.transactions.get(&txid) {
+            return Ok(transaction.clone());
+        }
+        let transaction = match self.replay.transactions.get(&txid) {
+            Some(transaction) => transaction.clone(),
+            None => self.fetch_transaction(&txid)?,
+        };
+        self.recorded.transactions.insert(txid, transaction.clone());
+        Ok(transaction)
+    }
+
+    /// Returns the height of the block, None if it doesn't exist
+    pub fn get_block_height(&mut self, block_hash: &str) -> Result<Option<u64>, String> {
+        let block_hash = block_hash.to_lowercase();
+        if let Some(height) = self.recorded.block_heights.get(&block_hash) {
+            return Ok(*height);
+        }
+        let height = match self.replay.block_heights.get(&block_hash) {
+            Some(height) => *height,
+            None => self.fetch_block_height(&block_hash)?,
+        };
+        self.recorded.block_heights.insert(block_hash, height);
+        Ok(height)
+    }
+
+    fn fetch_transaction(&self, txid: &str) -> Result<Option<BitcoinTxED>, String> {
+        if !is_hash(txid) {
+            return Ok(None);
+        }
+        let mut cached = false;
+        if let Some(cache) = &self.cache {
+            if let Some(transaction) = cache.get_transaction(txid).map_err(|e| e.to_string())? {
+                // Transactions of blocks that were reorged out are fetched again, so their block
+                // is the same as on a node that didn't cache them
+                let block_hash = transaction.block_hash.as_deref().unwrap_or_default();
+                if self.is_in_main_chain(block_hash)? {
+                    return Ok(Some(transaction));
+                }
+                cached = true;
+            }
+        }
+        let transaction = self.provider.get_transaction(txid).inspect_err(|error| {
+            tracing::error!(
+                "Error while getting transaction {} from {}: {}",
+                txid,
+                self.provider.name(),
+                error
+            );
+        })?;
+        // Transactions in the mempool can still change their block, so they are not cached
+        if let Some(cache) = &self.cache {
+            match &transaction {
+                Some(transaction) if transaction.block_hash.is_some() => cache
+                    .set_transaction(txid, transaction)
+                    .map_err(|e| e.to_string())?,
+                _ if cached => cache.delete_transaction(txid).map_err(|e| e.to_string())?,
+                _ => {}
+            }
+        }
+        Ok(transaction)
+    }
+
+    fn is_in_main_chain(&self, block_hash: &str) -> Result<bool, String> {
+        if !is_hash(block_hash) {
+            return Ok(false);
+        }
+        self.provider
+            .is_in_main_chain(block_hash)
+            .inspect_err(|error| {
+                tracing::error!(
+                    "Error while checking block {} in {}: {}",
+                    block_hash,
+                    self.provider.name(),
+                    error
+                );
+            })
+    }
+
+    fn fetch_block_height(&self, block_hash: &str) -> Result<Option<u64>, String> {
+        if !is_hash(block_hash) {
+            return Ok(None);
+        }
+        if let Some(cache) = &self.cache {
+            if let Some(height) = cache
+                .get_block_height(block_hash)
+                .map_err(|e| e.to_string())?
+            {
+                return Ok(Some(height));
+            }
+        }
+        let height = self
+            .provider
+            .get_block_height(block_hash)
+            .inspect_err(|error| {
+                tracing::error!(
+                    "Error while getting block {} from {}: {}",
+                    block_hash,
+                    self.provider.name(),
+                    error
+                );
+            })?;
+        if let (Some(cache), Some(height)) = (&self.cache, height) {
+            cache
+                .set_block_height(block_hash, height)
+                .map_err(|e| e.to_string())?;
+        }
+        Ok(height)
+    }
+}
+
+/// Only hex strings are sent to the provider, anything else can't be a txid or block hash
+fn is_hash(hash: &str) -> bool {
+    !hash.is_empty() && hash.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::db::types::BitcoinBlockHeaderED;
+    use crate::db::DB;
+    use crate::evm::precompiles::get_test_fixture_provider;
+
+    /// Has a single transaction in the given block, which is the only block in the main chain
+    struct ReorgProvider {
+        block_hash: Mutex<Option<String>>,
+    }
+
+    impl BitcoinDataProvider for ReorgProvider {
+        fn name(&self) -> &'static str {
+            "reorg"
+        }
+
+        fn get_transaction(&self, txid: &str) -> Result<Option<BitcoinTxED>, String> {
+            Ok(Some(BitcoinTxED {
+                txid: txid.to_string(),
+                block_hash: self.block_hash.lock().unwrap().clone(),
+                vin: vec![],
+                vout: vec![],
+            }))
+        }
+
+        fn get_block_header(&self, _: &str) -> Result<Option<BitcoinBlockHeaderED>, String> {
+            Ok(None)
+        }
+
+        fn is_in_main_chain(&self, block_hash: &str) -> Result<bool, String> {
+            Ok(self.block_hash.lock().unwrap().as_deref() == Some(block_hash))
+        }
+
+        fn check_status(&self) -> bool {
+            true
+        }
+    }
+
+    #[test]
+    fn test_bitcoin_data_replay() {
+        let mut replay = BitcoinDataED::default();
+        let transaction = BitcoinTxED {
+            txid: "ab".to_string(),
+            block_hash: Some("cd".to_string()),
+            vin: vec![],
+            vout: vec![],
+        };
+        replay
+            .transactions
+            .insert("ab".to_string(), Some(transaction.clone()));
+        replay.transactions.insert("ef".to_string(), None);
+        replay.block_heights.insert("cd".to_string(), Some(10));
+
+        // Replayed data is used without asking the provider
+        let mut bitcoin_data = BitcoinData::with_provider(get_test_fixture_provider(), None);
+        bitcoin_data.set_replay_data(replay);
+        assert_eq!(
+            bitcoin_data.get_transaction("AB").unwrap(),
+            Some(transaction.clone())
+        );
+        assert_eq!(bitcoin_data.get_transaction("ef").unwrap(), None);
+        assert_eq!(bitcoin_data.get_block_height("cd").unwrap(), Some(10));
+
+        // Only the data that is read is recorded
+        let recorded = bitcoin_data.take_recorded_data();
+        assert_eq!(recorded.transactions.len(), 2);
+        assert_eq!(recorded.transactions["ab"], Some(transaction));
+        assert_eq!(recorded.transactions["ef"], None);
+        assert_eq!(recorded.block_heights["cd"], Some(10));
+
+        // Replay data is cleared for the next transaction
+        assert!(bitcoin_data.take_recorded_data().is_empty());
+        assert!(bitcoin_data.replay.is_empty());
+    }
+
+    #[test]
+    fn test_bitcoin_data_cache() {
+        let path = TempDir::new().unwrap().into_path();
+        let db = DB::new(&path).unwrap();
+        let cache = db.get_bitcoin_cache().unwrap();
+        let confirmed_txid = "4b9228370196e80cfc329c73e9d109487cdc67abb1a0cac00662824fe7242cf3";
+        let unconfirmed_txid = "514009a91179fc847e1bf4f0c8f8189d458d5ec4d771d5711498efac658a9500";
+        let block_hash = "fb665657d185149a8adefbda5693369ac67ef0ebe48dc258c90610803b358356";
+
+        let mut bitcoin_data =
+            BitcoinData::with_provider(get_test_fixture_provider(), Some(cache.clone()));
+        let transaction = bitcoin_data
+            .get_transaction(confirmed_txid)
+            .unwrap()
+            .unwrap();
+        assert_eq!(transaction.block_hash.as_deref(), Some(block_hash));
+        assert!(bitcoin_data
+            .get_transaction(unconfirmed_txid)
+            .unwrap()
+            .is_some());
+        assert_eq!(
+            bitcoin_data.get_block_height(block_hash).unwrap(),
+            Some(240960)
+        );
+        assert_eq!(bitcoin_data.get_transaction("not a txid").unwrap(), None);
+
+        // Only confirmed transactions are cached
+        assert_eq!(
+            cache.get_transaction(confirmed_txid).unwrap(),
+            Some(transaction)
+        );
+        assert_eq!(cache.get_transaction(unconfirmed_txid).unwrap(), None);
+        assert_eq!(cache.get_block_height(block_hash).unwrap(), Some(240960));
+    }
+
+    #[test]
+    fn test_bitcoin_data_cache_reorg() {
+        let path = TempDir::new().unwrap().into_path();
+        let db = DB::new(&path).unwrap();
+        let cache = db.get_bitcoin_cache().unwrap();
+        let provider = Arc::new(ReorgProvider {
+            block_hash: Mutex::new(Some("aa".to_string())),
+        });
+        let get_block_hash = || {
+            BitcoinData::with_provider(provider.clone(), Some(cache.clone()))
+                .get_transaction("ab")
+                .unwrap()
+                .unwrap()
+                .block_hash
+        };
+        assert_eq!(get_block_hash().as_deref(), Some("aa"));
+        assert!(cache.get_transaction("ab").unwrap().is_some());
+
+        // Cached transaction is fetched again when its block is reorged out
+        *provider.block_hash.lock().unwrap() = Some("bb".to_string());
+        assert_eq!(get_block_hash().as_deref(), Some("bb"));
+        assert_eq!(
+            cache.get_transaction("ab").unwrap().unwrap().block_hash,
+            Some("bb".to_string())
+        );
+
+        // Transaction back in the mempool is removed from the cache
+        *provider.block_hash.lock().unwrap() = None;
+        assert_eq!(get_block_hash(), None);
+        assert_eq!(cache.get_transaction("ab").unwrap(), None);
+    }
+}
@@ -0,0 +1,191 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use bitcoin::consensus::encode::deserialize_hex;
+use bitcoin::Transaction;
+
+use crate::db::types::{BitcoinBlockHeaderED, BitcoinTxED, BitcoinTxInED, BitcoinTxOutED};
+use crate::evm::precompiles::{BitcoindProvider, EsploraProvider, FixtureProvider};
+
+lazy_static::lazy_static! {
+    static ref BITCOIN_DATA_PROVIDER_TYPE: String = std::env::var("BITCOIN_DATA_PROVIDER")
+            .unwrap_or("bitcoind".to_string());
+    static ref BITCOIN_ESPLORA_URL: String = std::env::var("BITCOIN_ESPLORA_URL")
+            .unwrap_or("http://localhost:3000".to_string());
+    static ref BITCOIN_FIXTURE_DIR: String = std::env::var("BITCOIN_FIXTURE_DIR")
+            .unwrap_or("bitcoin_fixtures".to_string());
+    /// Provider used by the precompiles, selected by the BITCOIN_DATA_PROVIDER environment variable
+    pub static ref BITCOIN_DATA_PROVIDER: Arc<dyn BitcoinDataProvider> =
+        match BITCOIN_DATA_PROVIDER_TYPE.as_str() {
+            "esplora" => Arc::new(EsploraProvider::new(&BITCOIN_ESPLORA_URL)),
+            "fixture" => Arc::new(FixtureProvider::new(PathBuf::from(&*BITCOIN_FIXTURE_DIR))),
+            _ => Arc::new(BitcoindProvider::from_env()),
+        };
+}
+
+/// Source of the Bitcoin transactions and blocks used by the precompiles
+///
+/// Hashes are given in the hex format used by bitcoind and block explorers.
+/// Methods return Ok(None) if the transaction or block doesn't exist, and an error if the
+/// provider can't be reached, so a missing object is never confused with an unavailable provider
+pub trait BitcoinDataProvider: Send + Sync {
+    /// Name of the provider, used in logs
+    fn name(&self) -> &'static str;
+
+    fn get_transaction(&self, txid: &str) -> Result<Option<BitcoinTxED>, String>;
+
+    fn get_block_header(&self, block_hash: &str) -> Result<Option<BitcoinBlockHeaderED>, String>;
+
+    fn get_block_height(&self, block_hash: &str) -> Result<Option<u64>, String> {
+        Ok(self
+            .get_block_header(block_hash)?
+            .map(|block_header| block_header.height))
+    }
+
+    /// Returns false if the block was reorged out of the main chain or doesn't exist
+    fn is_in_main_chain(&self, block_hash: &str) -> Result<bool, String>;
+
+    /// Returns true if the provider can be reached
+    fn check_status(&self) -> bool;
+}
+
+/// Parses a transaction in the raw hex format
+pub fn parse_raw_transaction(
+    raw_transaction: &str,
+    block_hash: Option<String>,
+) -> Result<BitcoinTxED, String> {
+    let transaction: Transaction = deserialize_hex(raw_transaction.trim())
+        .map_err(|error| format!("Invalid raw transaction: {}", error))?;
+    Ok(BitcoinTxED {
+        txid: transaction.compute_txid().to_string(),
+        block_hash,
+        vin: transaction
+            .input
+            .iter()
+            .map(|input| BitcoinTxInED {
+                txid: (!input.previous_output.is_null())
+                    .then(|| input.previous_output.txid.to_string()),
+                vout: if input.previous_output.is_null() {
+                    0
+                } else {
+                    input.previous_output.vout as u64
+                },
+            })
+            .collect(),
+        vout: transaction
+            .output
+            .iter()
+            .map(|output| BitcoinTxOutED {
+                value: output.value.to_sat(),
+                script_pubkey: hex::encode(output.script_pubkey.as_bytes()),
+            })
+            .collect(),
+    })
+}
+
+/// Parses a transaction in the verbose format of bitcoind getrawtransaction
+pub fn parse_verbose_transaction(transaction: &serde_json::Value) -> Result<BitcoinTxED, String> {
+    let invalid = || format!("Invalid transaction: {}", transaction);
+    let mut vin = Vec::new();
+    for input in transaction["vin"].as_array().ok_or_else(invalid)? {
+        if input["coinbase"].is_string() {
+            vin.push(BitcoinTxInED {
+                txid: None,
+                vout: 0,
+            });
+            continue;
+        }
+        vin.push(BitcoinTxInED {
+            txid: Some(input["txid"].as_str().ok_or_else(invalid)?.to_lowercase()),
+            vout: input["vout"].as_u64().ok_or_else(invalid)?,
+        });
+    }
+    let mut vout = Vec::new();
+    for output in transaction["vout"].as_array().ok_or_else(invalid)? {
+        vout.push(BitcoinTxOutED {
+            value: btc_to_sats(output["value"].as_f64().ok_or_else(invalid)?),
+            script_pubkey: output["scriptPubKey"]["hex"]
+                .as_str()
+                .ok_or_else(invalid)?
+                .to_lowercase(),
+        });
+    }
+    Ok(BitcoinTxED {
+        txid: transaction["txid"]
+            .as_str()
+            .ok_or_else(invalid)?
+            .to_lowercase(),
+        block_hash: transaction["blockhash"].as_str().map(|x| x.to_lowercase()),
+        vin,
+        vout,
+    })
+}
+
+/// Parses a block header in the verbose format of bitcoind getblockheader
+pub fn parse_verbose_block_header(
+    block_header: &serde_json::Value,
+) -> Result<BitcoinBlockHeaderED, String> {
+    let invalid = || format!("Invalid block header: {}", block_header);
+    Ok(BitcoinBlockHeaderED {
+        hash: block_header["hash"]
+            .as_str()
+            .ok_or_else(invalid)?
+            .to_lowercase(),
+        height: block_header["height"].as_u64().ok_or_else(invalid)?,
+        time: block_header["time"].as_u64().ok_or_else(invalid)?,
+        merkle_root: block_header["merkleroot"]
+            .as_str()
+            .ok_or_else(invalid)?
+            .to_lowercase(),
+        previous_block_hash: block_header["previousblockhash"]
+            .as_str()
+            .map(|x| x.to_lowercase()),
+    })
+}
+
+/// Checks a block header in the verbose format of bitcoind getblockheader,
+/// blocks that aren't in the main chain have -1 confirmations
+pub fn is_verbose_block_header_in_main_chain(block_header: &serde_json::Value) -> bool {
+    block_header["confirmations"]
+        .as_i64()
+        .is_none_or(|confirmations| confirmations >= 0)
+}
+
+/// Converts a BTC amount in the JSON format of bitcoind to satoshis
+///
+/// Amounts have 8 decimals, so rounding gives the exact value that the float approximates
+fn btc_to_sats(btc_value: f64) -> u64 {
+    (btc_value * 1e8).round() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_btc_to_sats() {
+        assert_eq!(btc_to_sats(0.29), 29000000);
+        assert_eq!(btc_to_sats(0.09658), 9658000);
+        assert_eq!(btc_to_sats(20999999.9769), 2099999997690000);
+    }
+
+    #[test]
+    fn test_parse_verbose_transaction() {
+        let transaction = parse_verbose_transaction(&serde_json::json!({
+            "txid": "AA",
+            "blockhash": "bb",
+            "vin": [{"coinbase": "03"}, {"txid": "cc", "vout": 3}],
+            "vout": [{"value": 0.29, "scriptPubKey": {"hex": "0014FF"}}],
+        }))
+        .unwrap();
+        assert_eq!(transaction.txid, "aa");
+        assert_eq!(transaction.block_hash, Some("bb".to_string()));
+        assert!(transaction.is_coinbase());
+        assert_eq!(transaction.vin[1].txid, Some("cc".to_string()));
+        assert_eq!(transaction.vin[1].vout, 3);
+        assert_eq!(transaction.vout[0].value, 29000000);
+        assert_eq!(transaction.vout[0].script_pubkey, "0014ff");
+
+        assert!(parse_verbose_transaction(&serde_json::json!({"txid": "aa"})).is_err());
+    }
+}
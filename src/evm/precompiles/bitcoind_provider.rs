@@ -0,0 +1,224 @@
+use std::path::PathBuf;
+
+use base64::prelude::BASE64_URL_SAFE;
+use base64::Engine;
+
+use crate::db::types::{BitcoinBlockHeaderED, BitcoinTxED};
+use crate::evm::precompiles::btc_utils::BTC_CLIENT;
+use crate::evm::precompiles::{
+    is_verbose_block_header_in_main_chain, parse_verbose_block_header, parse_verbose_transaction,
+    BitcoinDataProvider,
+};
+
+lazy_static::lazy_static! {
+    static ref BITCOIN_RPC_URL: String = std::env::var("BITCOIN_RPC_URL")
+            .unwrap_or("http://localhost:38332".to_string());
+    static ref BITCOIN_RPC_USER: String = std::env::var("BITCOIN_RPC_USER")
+            .unwrap_or("user".to_string());
+    static ref BITCOIN_RPC_PASSWORD: String = std::env::var("BITCOIN_RPC_PASSWORD")
+            .unwrap_or("password".to_string());
+    static ref BITCOIN_RPC_COOKIE_FILE: Option<PathBuf> = std::env::var("BITCOIN_RPC_COOKIE_FILE")
+            .ok()
+            .filter(|x| !x.is_empty())
+            .map(PathBuf::from);
+}
+
+/// Number of times a request is retried if bitcoind can't be reached
+const RPC_RETRIES: u32 = 5;
+
+/// How bitcoind requests are authenticated
+pub enum BitcoindAuth {
+    UserPassword(String, String),
+    /// Path of the cookie file written by bitcoind, it's read for every request
+    /// as bitcoind writes a new one each time it starts
+    CookieFile(PathBuf),
+}
+
+/// Gets Bitcoin data from the JSON-RPC interface of bitcoind, txindex should be enabled
+pub struct BitcoindProvider {
+    url: String,
+    auth: BitcoindAuth,
+}
+
+impl BitcoindProvider {
+    pub fn new(url: &str, auth: BitcoindAuth) -> Self {
+        Self {
+            url: url.to_string(),
+            auth,
+        }
+    }
+
+    /// Uses BITCOIN_RPC_URL, and BITCOIN_RPC_COOKIE_FILE if it's set,
+    /// otherwise BITCOIN_RPC_USER and BITCOIN_RPC_PASSWORD
+    pub fn from_env() -> Self {
+        let auth = match BITCOIN_RPC_COOKIE_FILE.as_ref() {
+            Some(cookie_file) => BitcoindAuth::CookieFile(cookie_file.clone()),
+            None => BitcoindAuth::UserPassword(
+                BITCOIN_RPC_USER.to_string(),
+                BITCOIN_RPC_PASSWORD.to_string(),
+            ),
+        };
+        Self::new(&BITCOIN_RPC_URL, auth)
+    }
+
+    fn get_auth_header(&self) -> Result<String, String> {
+        let credentials = match &self.auth {
+            BitcoindAuth::UserPassword(user, password) => format!("{}:{}", user, password),
+            // Cookie file has the user and password in the same format
+            BitcoindAuth::CookieFile(path) => std::fs::read_to_string(path)
+                .map_err(|error| format!("Failed to read {}: {}", path.display(), error))?
+                .trim()
+                .to_string(),
+        };
+        Ok(String::from("Basic ") + &BASE64_URL_SAFE.encode(credentials.as_bytes()))
+    }
+
+    /// Sends a request to bitcoind and returns its result, retrying if bitcoind can't be reached
+    ///
+    /// Returns Ok(None) for the errors that mean the requested object doesn't exist
+    fn send_rpc_request(
+        &self,
+        method: &str,
+        params: serde_json::Value,
+    ) -> Result<Option<serde_json::Value>, String> {
+        let request = serde_json::json!({
+            "jsonrpc": "1.0",
+            "id": "brc20prog",
+            "method": method,
+            "params": params,
+        })
+        .to_string();
+
+        let mut retries_left = RPC_RETRIES;
+        let mut response = loop {
+            let response = BTC_CLIENT
+                .post(&self.url)
+                .header("Authorization", self.get_auth_header()?)
+                .content_type("application/json")
+                .config()
+                .http_status_as_error(false)
+                .build()
+                .send(&request);
+            match response {
+                Ok(response) => break response,
+                Err(error) if retries_left == 0 => {
+                    return Err(format!("Failed to call {}: {}", method, error));
+                }
+                Err(_) => {
+                    // wait and retry
+                    retries_left -= 1;
+                    std::thread::sleep(std::time::Duration::from_secs(1));
+                }
+            }
+        };
+
+        let status = response.status();
+        let body = response
+            .body_mut()
+            .read_to_string()
+            .map_err(|error| format!("Failed to read {} response: {}", method, error))?;
+        // bitcoind returns errors with a non 200 status, but the body still has the error code
+        let Ok(body) = body.parse::<serde_json::Value>() else {
+            return Err(format!(
+                "Invalid {} response with status {}: {}",
+                method, status, body
+            ));
+        };
+
+        if body["error"].is_object() {
+            return match body["error"]["code"].as_i64() {
+                // Invalid parameter and object not found
+                Some(-8) | Some(-5) => Ok(None),
+                _ => Err(format!("Failed to call {}: {}", method, body["error"])),
+            };
+        }
+
+        match &body["result"] {
+            serde_json::Value::Null => Ok(None),
+            result => Ok(Some(result.clone())),
+        }
+    }
+}
+
+impl BitcoinDataProvider for BitcoindProvider {
+    fn name(&self) -> &'static str {
+        "bitcoind"
+    }
+
+    fn get_transaction(&self, txid: &str) -> Result<Option<BitcoinTxED>, String> {
+        self.send_rpc_request(
+            "getrawtransaction",
+            serde_json::json!({"txid": txid, "verbose": true}),
+        )?
+        .map(|transaction| parse_verbose_transaction(&transaction))
+        .transpose()
+    }
+
+    fn get_block_header(&self, block_hash: &str) -> Result<Option<BitcoinBlockHeaderED>, String> {
+        self.send_rpc_request(
+            "getblockheader",
+            serde_json::json!({"blockhash": block_hash, "verbose": true}),
+        )?
+        .map(|block_header| parse_verbose_block_header(&block_header))
+        .transpose()
+    }
+
+    fn is_in_main_chain(&self, block_hash: &str) -> Result<bool, String> {
+        Ok(self
+            .send_rpc_request(
+                "getblockheader",
+                serde_json::json!({"blockhash": block_hash, "verbose": true}),
+            )?
+            .is_some_and(|block_header| is_verbose_block_header_in_main_chain(&block_header)))
+    }
+
+    fn check_status(&self) -> bool {
+        self.send_rpc_request("getblockchaininfo", serde_json::json!([]))
+            .is_ok_and(|x| x.is_some())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::evm::precompiles::btc_utils::skip_btc_tests;
+
+    #[test]
+    fn test_cookie_file_auth() {
+        let tempdir = tempfile::TempDir::new().unwrap();
+        let cookie_file = tempdir.path().join(".cookie");
+        std::fs::write(&cookie_file, "__cookie__:secret\n").unwrap();
+
+        let provider = BitcoindProvider::new(
+            "http://localhost:38332",
+            BitcoindAuth::CookieFile(cookie_file.clone()),
+        );
+        let expected = BitcoindProvider::new(
+            "http://localhost:38332",
+            BitcoindAuth::UserPassword("__cookie__".to_string(), "secret".to_string()),
+        );
+        assert_eq!(
+            provider.get_auth_header().unwrap(),
+            expected.get_auth_header().unwrap()
+        );
+
+        // Missing cookie file is an error, not a missing object
+        std::fs::remove_file(&cookie_file).unwrap();
+        assert!(provider.get_auth_header().is_err());
+        assert!(provider.get_transaction("aa").is_err());
+    }
+
+    #[test]
+    fn test_get_raw_transaction() {
+        if skip_btc_tests() {
+            return;
+        }
+
+        let txid = "4183fb733b9553ca8b93208c91dda18bee3d0b8510720b15d76d979af7fd9926";
+        let response = BitcoindProvider::from_env()
+            .get_transaction(txid)
+            .unwrap()
+            .unwrap();
+        assert_eq!(response.txid, txid);
+    }
+}
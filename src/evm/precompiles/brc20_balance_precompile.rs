@@ -1,33 +1,35 @@
-use alloy_primitives::U256;
 use alloy_sol_types::{sol, SolCall};
 use revm::interpreter::{Gas, InstructionResult, InterpreterResult};
-use revm::primitives::Bytes;
-use ureq::Agent;
+use revm::primitives::{Bytes, B256};
 
-use crate::evm::precompiles::{precompile_error, precompile_output, use_gas};
-
-lazy_static::lazy_static! {
-    static ref BRC20_CLIENT: Agent = Agent::new_with_defaults();
-    static ref BRC20_PROG_BALANCE_SERVER_URL: String = std::env::var("BRC20_PROG_BALANCE_SERVER_URL")
-            .unwrap_or("http://localhost:18546".to_string());
-}
+use crate::evm::precompiles::{precompile_error, precompile_output, use_gas, Brc20BalanceProvider};
 
 sol! {
     function balanceOf(bytes ticker, bytes pkscript) returns (uint256);
 }
 
-pub fn brc20_balance_precompile(bytes: &Bytes, gas_limit: u64) -> InterpreterResult {
+/// Returns the balance at the executing block, so the result doesn't depend on how far
+/// the BRC20 indexer has progressed
+///
+/// Returns an error only if the balance can't be fetched, which should fail the transaction
+pub fn brc20_balance_precompile(
+    bytes: &Bytes,
+    gas_limit: u64,
+    block_height: u64,
+    block_hash: Option<B256>,
+    balance_provider: &dyn Brc20BalanceProvider,
+) -> Result<InterpreterResult, String> {
     let mut interpreter_result =
         InterpreterResult::new(InstructionResult::Stop, Bytes::new(), Gas::new(gas_limit));
 
     if !use_gas(&mut interpreter_result, 100000) {
-        return interpreter_result;
+        return Ok(interpreter_result);
     }
 
     let result = balanceOfCall::abi_decode(bytes, false);
 
     if result.is_err() {
-        return precompile_error(interpreter_result);
+        return Ok(precompile_error(interpreter_result));
     }
 
     let returns = result.unwrap();
@@ -35,40 +37,57 @@ pub fn brc20_balance_precompile(bytes: &Bytes, gas_limit: u64) -> InterpreterRes
     let ticker = returns.ticker;
     let pkscript = returns.pkscript;
 
-    let balance = get_brc20_balance(&ticker, &pkscript);
-
-    if balance.is_err() {
-        return precompile_error(interpreter_result);
-    }
+    let balance = balance_provider
+        .get_balance(&ticker, &pkscript, block_height, block_hash)
+        .inspect_err(|error| {
+            tracing::error!(
+                "Error while getting BRC20 balance from {}: {}",
+                balance_provider.name(),
+                error
+            )
+        })?;
 
-    let balance = U256::from(balance.unwrap());
     let bytes = balanceOfCall::abi_encode_returns(&(balance,));
 
-    return precompile_output(interpreter_result, bytes);
+    return Ok(precompile_output(interpreter_result, bytes));
 }
 
-pub fn get_brc20_balance(ticker: &Bytes, pkscript: &Bytes) -> Result<u64, String> {
-    let response = BRC20_CLIENT
-        .get(BRC20_PROG_BALANCE_SERVER_URL.as_str())
-        .query("ticker", hex::encode(ticker))
-        .query("pkscript", hex::encode(pkscript))
-        .call();
-
-    if response.is_err() {
-        return Err("Failed to get balance".into());
-    }
-
-    let balance = response.unwrap().body_mut().read_to_string();
-
-    if balance.is_err() {
-        return Err("Failed to get balance".into());
-    }
-
-    let balance = balance.unwrap().parse::<u64>();
-
-    if balance.is_err() {
-        return Err("Failed to get balance".into());
+#[cfg(test)]
+mod tests {
+    use alloy_primitives::U256;
+
+    use super::*;
+    use crate::evm::precompiles::Brc20BalanceSnapshot;
+
+    #[test]
+    fn test_brc20_balance_precompile() {
+        let snapshot = Brc20BalanceSnapshot::default();
+        let ticker = Bytes::from("ordi".as_bytes());
+        let pkscript = Bytes::from([0x51, 0x20]);
+        let hash = B256::from([1; 32]);
+        let balance = U256::from(21000000u64) * U256::from(10u64).pow(U256::from(18u64));
+        snapshot.set_balances(
+            10,
+            hash,
+            vec![(ticker.clone(), pkscript.clone(), balance)],
+            None,
+        );
+
+        let data = Bytes::from(balanceOfCall::new((ticker, pkscript)).abi_encode());
+        let result = brc20_balance_precompile(&data, 1000000, 10, Some(hash), &snapshot).unwrap();
+        assert_eq!(result.gas.spent(), 100000);
+        assert_eq!(
+            balanceOfCall::abi_decode_returns(&result.output, false)
+                .unwrap()
+                ._0,
+            balance
+        );
+
+        // Invalid params fail the call, unavailable balances fail the transaction
+        let result =
+            brc20_balance_precompile(&Bytes::from([1, 2]), 1000000, 10, Some(hash), &snapshot)
+                .unwrap();
+        assert!(result.is_error());
+        assert!(brc20_balance_precompile(&data, 1000000, 11, Some(hash), &snapshot).is_err());
     }
-
-    Ok(balance.unwrap())
 }
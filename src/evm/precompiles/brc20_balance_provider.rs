@@ -0,0 +1,395 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+
+use alloy_primitives::U256;
+use revm::primitives::{Bytes, B256};
+use ureq::Agent;
+
+lazy_static::lazy_static! {
+    static ref BRC20_CLIENT: Agent = Agent::new_with_defaults();
+    static ref BRC20_PROG_BALANCE_PROVIDER: String = std::env::var("BRC20_PROG_BALANCE_PROVIDER")
+            .unwrap_or("server".to_string());
+    static ref BRC20_PROG_BALANCE_SERVER_URL: String = std::env::var("BRC20_PROG_BALANCE_SERVER_URL")
+            .unwrap_or("http://localhost:18546".to_string());
+    /// Balances pushed by the indexer with brc20_setBalances, if BRC20_PROG_BALANCE_PROVIDER is snapshot
+    pub static ref BRC20_BALANCE_SNAPSHOT: Option<Arc<Brc20BalanceSnapshot>> =
+        (BRC20_PROG_BALANCE_PROVIDER.as_str() == "snapshot")
+            .then(|| Arc::new(Brc20BalanceSnapshot::default()));
+    /// Provider used by the BRC20 balance precompile, selected by the BRC20_PROG_BALANCE_PROVIDER environment variable
+    pub static ref BRC20_BALANCE_PROVIDER: Arc<dyn Brc20BalanceProvider> =
+        match BRC20_BALANCE_SNAPSHOT.as_ref() {
+            Some(snapshot) => snapshot.clone(),
+            None => Arc::new(Brc20BalanceServerProvider::new(&BRC20_PROG_BALANCE_SERVER_URL)),
+        };
+}
+
+/// Source of the non-module BRC20 balances used by the BRC20 balance precompile
+///
+/// Balances are returned at the given block, block_hash is None if the block is not known yet,
+/// e.g. for calls on top of the latest block. An error means the balance at this block
+/// isn't available, it fails the whole transaction so it can be retried
+pub trait Brc20BalanceProvider: Send + Sync {
+    /// Name of the provider, used in logs
+    fn name(&self) -> &'static str;
+
+    fn get_balance(
+        &self,
+        ticker: &Bytes,
+        pkscript: &Bytes,
+        block_height: u64,
+        block_hash: Option<B256>,
+    ) -> Result<U256, String>;
+
+    /// Returns true if the provider can be reached
+    fn check_status(&self) -> bool;
+}
+
+/// Gets balances with an HTTP request to the balance server of the indexer for every call
+pub struct Brc20BalanceServerProvider {
+    url: String,
+}
+
+impl Brc20BalanceServerProvider {
+    pub fn new(url: &str) -> Self {
+        Self {
+            url: url.to_string(),
+        }
+    }
+}
+
+impl Brc20BalanceProvider for Brc20BalanceServerProvider {
+    fn name(&self) -> &'static str {
+        "server"
+    }
+
+    /// The server should return an error status if it can't return the balance at this block,
+    /// e.g. if it hasn't indexed it yet or has a different hash for it
+    fn get_balance(
+        &self,
+        ticker: &Bytes,
+        pkscript: &Bytes,
+        block_height: u64,
+        block_hash: Option<B256>,
+    ) -> Result<U256, String> {
+        let mut request = BRC20_CLIENT
+            .get(&self.url)
+            .query("ticker", hex::encode(ticker))
+            .query("pkscript", hex::encode(pkscript))
+            .query("block_height", block_height.to_string());
+        if let Some(block_hash) = block_hash {
+            request = request.query("block_hash", hex::encode(block_hash));
+        }
+        let mut response = request
+            .config()
+            .http_status_as_error(false)
+            .build()
+            .call()
+            .map_err(|error| format!("Failed to get balance: {}", error))?;
+
+        let status = response.status();
+        let body = response
+            .body_mut()
+            .read_to_string()
+            .map_err(|error| format!("Failed to read balance: {}", error))?;
+        if status != 200 {
+            return Err(format!(
+                "Failed to get balance at block {} with status {}: {}",
+                block_height, status, body
+            ));
+        }
+
+        parse_balance(body.trim()).ok_or(format!("Invalid balance: {}", body))
+    }
+
+    /// Any response means the server is up, even an error for the empty request
+    fn check_status(&self) -> bool {
+        BRC20_CLIENT
+            .get(&self.url)
+            .config()
+            .http_status_as_error(false)
+            .build()
+            .call()
+            .is_ok()
+    }
+}
+
+#[derive(Default)]
+struct SnapshotState {
+    block_height: Option<u64>,
+    block_hash: Option<B256>,
+    /// Balances by ticker and pkscript, tickers are lowercase
+    balances: HashMap<(Bytes, Bytes), U256>,
+    /// State before the updates of each block that can still be reorged, by block height
+    reverts: BTreeMap<u64, SnapshotRevert>,
+}
+
+/// Block and balances before the first update of a block, None for balances that weren't set
+struct SnapshotRevert {
+    block_height: Option<u64>,
+    block_hash: Option<B256>,
+    balances: HashMap<(Bytes, Bytes), Option<U256>>,
+}
+
+/// Keeps the balances in memory, the indexer pushes the balances that changed for each block
+/// before its transactions run, so precompile calls don't need a request to the indexer
+///
+/// Balances that were never set are zero. Balances are only available from the block of the last
+/// update, calls on older blocks fail, as their balances may have been overwritten. Nothing is
+/// stored in the database, so calls fail after a restart until the indexer sets the balances again
+///
+/// Previous balances are kept for the blocks that can still be reorged, so updates of reorged blocks are reverted
+#[derive(Default)]
+pub struct Brc20BalanceSnapshot {
+    state: Mutex<SnapshotState>,
+}
+
+impl Brc20BalanceSnapshot {
+    /// Sets the balances at the given block, other balances are kept
+    ///
+    /// Can be called again during the block, e.g. between transactions, to update balances.
+    /// Previous balances are kept for max_reorg_depth blocks, or for all blocks if it's None
+    pub fn set_balances(
+        &self,
+        block_height: u64,
+        block_hash: B256,
+        balances: Vec<(Bytes, Bytes, U256)>,
+        max_reorg_depth: Option<u64>,
+    ) {
+        let mut guard = self.state.lock().unwrap();
+        let state = &mut *guard;
+        if let Some(max_reorg_depth) = max_reorg_depth {
+            state.reverts = state
+                .reverts
+                .split_off(&block_height.saturating_sub(max_reorg_depth));
+        }
+        let revert = state
+            .reverts
+            .entry(block_height)
+            .or_insert_with(|| SnapshotRevert {
+                block_height: state.block_height,
+                block_hash: state.block_hash,
+                balances: HashMap::new(),
+            });
+        state.block_height = Some(block_height);
+        state.block_hash = Some(block_hash);
+        for (ticker, pkscript, balance) in balances {
+            let key = (Bytes::from(ticker.to_ascii_lowercase()), pkscript);
+            revert
+                .balances
+                .entry(key.clone())
+                .or_insert_with(|| state.balances.get(&key).copied());
+            if balance.is_zero() {
+                state.balances.remove(&key);
+            } else {
+                state.balances.insert(key, balance);
+            }
+        }
+    }
+
+    /// Reverts the updates of the blocks after the latest valid block
+    pub fn reorg(&self, latest_valid_block_height: u64) {
+        let mut guard = self.state.lock().unwrap();
+        let state = &mut *guard;
+        let reverts = state.reverts.split_off(&(latest_valid_block_height + 1));
+        for revert in reverts.into_values().rev() {
+            state.block_height = revert.block_height;
+            state.block_hash = revert.block_hash;
+            for (key, balance) in revert.balances {
+                match balance {
+                    Some(balance) => state.balances.insert(key, balance),
+                    None => state.balances.remove(&key),
+                };
+            }
+        }
+    }
+}
+
+impl Brc20BalanceProvider for Brc20BalanceSnapshot {
+    fn name(&self) -> &'static str {
+        "snapshot"
+    }
+
+    fn get_balance(
+        &self,
+        ticker: &Bytes,
+        pkscript: &Bytes,
+        block_height: u64,
+        block_hash: Option<B256>,
+    ) -> Result<U256, String> {
+        let state = self.state.lock().unwrap();
+        let Some(snapshot_height) = state.block_height else {
+            return Err("No balances were set".to_string());
+        };
+        // Known blocks need balances set for them, calls on top of the latest block
+        // use the balances of the last update
+        let available = match block_hash {
+            Some(block_hash) => {
+                block_height == snapshot_height && Some(block_hash) == state.block_hash
+            }
+            None => block_height >= snapshot_height,
+        };
+        if !available {
+            return Err(format!(
+                "Balances are not set for block {}, last update was for block {}",
+                block_height, snapshot_height
+            ));
+        }
+        let key = (Bytes::from(ticker.to_ascii_lowercase()), pkscript.clone());
+        Ok(state.balances.get(&key).copied().unwrap_or(U256::ZERO))
+    }
+
+    fn check_status(&self) -> bool {
+        true
+    }
+}
+
+/// Parses a balance as a decimal or 0x prefixed hex string, balances have 18 decimals
+/// so they don't fit in a u64
+fn parse_balance(balance: &str) -> Option<U256> {
+    if balance.is_empty() || balance == "0x" {
+        return None;
+    }
+    match balance.strip_prefix("0x") {
+        Some(hex) => U256::from_str_radix(hex, 16).ok(),
+        None => U256::from_str_radix(balance, 10).ok(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_balance() {
+        assert_eq!(parse_balance("86"), Some(U256::from(86u64)));
+        assert_eq!(
+            parse_balance("21000000000000000000000000"),
+            Some(U256::from(21000000u64) * U256::from(10u64).pow(U256::from(18u64)))
+        );
+        assert_eq!(
+            parse_balance("0x115eec47f6cf7e35000000"),
+            Some(U256::from(21000000u64) * U256::from(10u64).pow(U256::from(18u64)))
+        );
+        assert_eq!(parse_balance(&U256::MAX.to_string()), Some(U256::MAX));
+        assert_eq!(parse_balance("-1"), None);
+        assert_eq!(parse_balance("1.5"), None);
+        assert_eq!(parse_balance("0xzz"), None);
+        assert_eq!(parse_balance(""), None);
+        assert_eq!(parse_balance("0x"), None);
+    }
+
+    #[test]
+    fn test_balance_snapshot() {
+        let snapshot = Brc20BalanceSnapshot::default();
+        let ticker = Bytes::from("ordi".as_bytes());
+        let pkscript = Bytes::from([0x51, 0x20]);
+        let other_pkscript = Bytes::from([0x00, 0x14]);
+        let hash = B256::from([1; 32]);
+        let other_hash = B256::from([2; 32]);
+
+        assert!(snapshot
+            .get_balance(&ticker, &pkscript, 10, Some(hash))
+            .is_err());
+
+        snapshot.set_balances(
+            10,
+            hash,
+            vec![(Bytes::from("ORDI".as_bytes()), pkscript.clone(), U256::MAX)],
+            None,
+        );
+        // Tickers are case insensitive, balances that weren't set are zero
+        assert_eq!(
+            snapshot.get_balance(&ticker, &pkscript, 10, Some(hash)),
+            Ok(U256::MAX)
+        );
+        assert_eq!(
+            snapshot.get_balance(&ticker, &other_pkscript, 10, Some(hash)),
+            Ok(U256::ZERO)
+        );
+
+        // Known blocks need the same block, calls on top of the latest block can be later
+        assert!(snapshot
+            .get_balance(&ticker, &pkscript, 10, Some(other_hash))
+            .is_err());
+        assert!(snapshot
+            .get_balance(&ticker, &pkscript, 11, Some(hash))
+            .is_err());
+        assert!(snapshot.get_balance(&ticker, &pkscript, 9, None).is_err());
+        assert_eq!(
+            snapshot.get_balance(&ticker, &pkscript, 11, None),
+            Ok(U256::MAX)
+        );
+
+        // Updates keep the other balances
+        snapshot.set_balances(
+            11,
+            other_hash,
+            vec![(ticker.clone(), other_pkscript.clone(), U256::from(5u64))],
+            None,
+        );
+        assert_eq!(
+            snapshot.get_balance(&ticker, &pkscript, 11, Some(other_hash)),
+            Ok(U256::MAX)
+        );
+        assert_eq!(
+            snapshot.get_balance(&ticker, &other_pkscript, 11, Some(other_hash)),
+            Ok(U256::from(5u64))
+        );
+        assert!(snapshot
+            .get_balance(&ticker, &pkscript, 10, Some(hash))
+            .is_err());
+
+        snapshot.set_balances(
+            11,
+            other_hash,
+            vec![(ticker.clone(), pkscript.clone(), U256::ZERO)],
+            None,
+        );
+        assert_eq!(
+            snapshot.get_balance(&ticker, &pkscript, 11, Some(other_hash)),
+            Ok(U256::ZERO)
+        );
+    }
+
+    #[test]
+    fn test_balance_snapshot_reorg() {
+        let snapshot = Brc20BalanceSnapshot::default();
+        let ticker = Bytes::from("ordi".as_bytes());
+        let pkscript = Bytes::from([0x51, 0x20]);
+        let other_pkscript = Bytes::from([0x00, 0x14]);
+        let set_balance = |block_height: u64, pkscript: &Bytes, balance: u64| {
+            snapshot.set_balances(
+                block_height,
+                B256::from(U256::from(block_height)),
+                vec![(ticker.clone(), pkscript.clone(), U256::from(balance))],
+                Some(2),
+            );
+        };
+        let get_balance = |block_height: u64, pkscript: &Bytes| {
+            snapshot.get_balance(
+                &ticker,
+                pkscript,
+                block_height,
+                Some(B256::from(U256::from(block_height))),
+            )
+        };
+
+        set_balance(10, &pkscript, 1);
+        set_balance(11, &pkscript, 2);
+        set_balance(12, &other_pkscript, 3);
+        set_balance(12, &pkscript, 4);
+
+        // Updates of the reorged blocks are reverted, balances set in them are removed
+        snapshot.reorg(10);
+        assert_eq!(get_balance(10, &pkscript), Ok(U256::from(1u64)));
+        assert_eq!(get_balance(10, &other_pkscript), Ok(U256::ZERO));
+        assert!(get_balance(11, &pkscript).is_err());
+
+        // Blocks older than the reorg depth can't be reverted
+        for block_height in 11..=15 {
+            set_balance(block_height, &pkscript, block_height);
+        }
+        snapshot.reorg(10);
+        assert_eq!(get_balance(12, &pkscript), Ok(U256::from(12u64)));
+    }
+}
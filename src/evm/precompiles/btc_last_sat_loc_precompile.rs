@@ -4,8 +4,7 @@ use alloy_sol_types::{sol, SolCall};
 use revm::interpreter::{Gas, InstructionResult, InterpreterResult};
 use revm::primitives::Bytes;
 
-use crate::evm::precompiles::btc_utils::get_raw_transaction;
-use crate::evm::precompiles::{precompile_error, precompile_output, use_gas};
+use crate::evm::precompiles::{precompile_error, precompile_output, use_gas, BitcoinData};
 
 static GAS_PER_RPC_CALL: u64 = 100000;
 
@@ -21,7 +20,12 @@ sol! {
     function getLastSatLocation(bytes32 txid, uint256 vout, uint256 sat) returns (bytes32 last_txid, uint256 last_vout, uint256 last_sat, bytes old_pkscript, bytes new_pkscript);
 }
 
-pub fn last_sat_location_precompile(bytes: &Bytes, gas_limit: u64) -> InterpreterResult {
+/// Returns an error only if the Bitcoin data can't be fetched, which should fail the transaction
+pub fn last_sat_location_precompile(
+    bytes: &Bytes,
+    gas_limit: u64,
+    bitcoin_data: &mut BitcoinData,
+) -> Result<InterpreterResult, String> {
     let mut interpreter_result =
         InterpreterResult::new(InstructionResult::Stop, Bytes::new(), Gas::new(gas_limit));
 
@@ -29,7 +33,7 @@ pub fn last_sat_location_precompile(bytes: &Bytes, gas_limit: u64) -> Interprete
 
     if result.is_err() {
         // Invalid params
-        return precompile_error(interpreter_result);
+        return Ok(precompile_error(interpreter_result));
     }
 
     let result = result.unwrap();
@@ -39,57 +43,44 @@ pub fn last_sat_location_precompile(bytes: &Bytes, gas_limit: u64) -> Interprete
     let sat = result.sat.as_limbs()[0];
 
     if !use_gas(&mut interpreter_result, GAS_PER_RPC_CALL) {
-        return interpreter_result;
+        return Ok(interpreter_result);
     }
 
-    let response = get_raw_transaction(&hex::encode(txid));
-
-    if response["error"].is_object() || response["result"].is_null() {
+    let Some(response) = bitcoin_data.get_transaction(&hex::encode(txid))? else {
         // Transaction not found
         tracing::warn!("Transaction not found");
-        return precompile_error(interpreter_result);
-    }
-
-    let response = response["result"].clone();
+        return Ok(precompile_error(interpreter_result));
+    };
 
-    if response["vin"][0]["coinbase"].is_string() {
+    if response.is_coinbase() {
         // Coinbase transactions are not supported
         tracing::warn!("Coinbase transactions are not supported");
-        return precompile_error(interpreter_result);
+        return Ok(precompile_error(interpreter_result));
     }
 
-    if response["vout"].as_array().unwrap().len() <= vout {
+    let Some(output) = response.vout.get(vout) else {
         // Vout index out of bounds
         tracing::warn!(
             "Vout index out of bounds, vout: {}, vout_len: {}",
             vout,
-            response["vout"].as_array().unwrap().len()
+            response.vout.len()
         );
-        return precompile_error(interpreter_result);
-    }
+        return Ok(precompile_error(interpreter_result));
+    };
 
-    if to_sats(response["vout"][vout]["value"].as_f64().unwrap()) < sat {
+    if output.value < sat {
         // Sat value out of bounds
         tracing::warn!("Sat value out of bounds");
-        return precompile_error(interpreter_result);
+        return Ok(precompile_error(interpreter_result));
     }
 
-    let new_pkscript = response["vout"][vout]["scriptPubKey"]["hex"]
-        .as_str()
-        .unwrap();
+    let new_pkscript = &output.script_pubkey;
 
-    let mut total_vout_sat_count = 0;
-    let mut current_vout_index = 0;
-    while current_vout_index < vout {
-        let value = to_sats(
-            response["vout"][current_vout_index]["value"]
-                .as_f64()
-                .unwrap(),
-        );
-        total_vout_sat_count += value;
-        current_vout_index += 1;
-    }
-    total_vout_sat_count += sat;
+    let total_vout_sat_count = response.vout[..vout]
+        .iter()
+        .map(|output| output.value)
+        .sum::<u64>()
+        + sat;
 
     let mut total_vin_sat_count = 0;
     let mut current_vin_index = 0;
@@ -97,32 +88,23 @@ pub fn last_sat_location_precompile(bytes: &Bytes, gas_limit: u64) -> Interprete
     let mut current_vin_vout = 0;
     let mut current_vin_script_pub_key_hex = "".to_string();
     let mut current_vin_value = 0;
-    let vin_count = response["vin"].as_array().unwrap().len();
-    while total_vin_sat_count < total_vout_sat_count && current_vin_index < vin_count {
-        current_vin_txid = response["vin"][current_vin_index]["txid"]
-            .as_str()
-            .unwrap()
-            .to_string();
-        current_vin_vout = response["vin"][current_vin_index]["vout"].as_u64().unwrap() as usize;
+    while total_vin_sat_count < total_vout_sat_count && current_vin_index < response.vin.len() {
+        let vin = &response.vin[current_vin_index];
+        current_vin_txid = vin.txid.clone().unwrap_or_default();
+        current_vin_vout = vin.vout as usize;
         if !use_gas(&mut interpreter_result, GAS_PER_RPC_CALL) {
-            return interpreter_result;
+            return Ok(interpreter_result);
         }
-        let vin_response = get_raw_transaction(&current_vin_txid);
-        if vin_response["error"].is_object() || vin_response["result"].is_null() {
+        let Some(vin_response) = bitcoin_data.get_transaction(&current_vin_txid)? else {
             // Transaction not found
-            return precompile_error(interpreter_result);
-        }
-        let vin_response = vin_response["result"].clone();
-        current_vin_script_pub_key_hex = vin_response["vout"][current_vin_vout]["scriptPubKey"]
-            ["hex"]
-            .as_str()
-            .unwrap()
-            .to_string();
-        current_vin_value = to_sats(
-            vin_response["vout"][current_vin_vout]["value"]
-                .as_f64()
-                .unwrap(),
-        );
+            return Ok(precompile_error(interpreter_result));
+        };
+        let Some(vin_output) = vin_response.vout.get(current_vin_vout) else {
+            // Spent output not found
+            return Ok(precompile_error(interpreter_result));
+        };
+        current_vin_script_pub_key_hex = vin_output.script_pubkey.clone();
+        current_vin_value = vin_output.value;
 
         total_vin_sat_count += current_vin_value;
         current_vin_index += 1;
@@ -130,28 +112,33 @@ pub fn last_sat_location_precompile(bytes: &Bytes, gas_limit: u64) -> Interprete
 
     if total_vin_sat_count < total_vout_sat_count {
         // Insufficient satoshis in vin
-        return precompile_error(interpreter_result);
+        return Ok(precompile_error(interpreter_result));
     }
 
+    let (Ok(current_vin_txid), Ok(old_pkscript), Ok(new_pkscript)) = (
+        FixedBytes::from_hex(current_vin_txid),
+        Bytes::from_hex(current_vin_script_pub_key_hex),
+        Bytes::from_hex(new_pkscript),
+    ) else {
+        return Ok(precompile_error(interpreter_result));
+    };
+
     let bytes = getLastSatLocationCall::abi_encode_returns(&(
-        FixedBytes::from_hex(current_vin_txid).unwrap(),
+        current_vin_txid,
         U256::from(current_vin_vout as u64),
         U256::from(total_vout_sat_count - (total_vin_sat_count - current_vin_value)),
-        Bytes::from_hex(current_vin_script_pub_key_hex).unwrap(),
-        Bytes::from_hex(new_pkscript).unwrap(),
+        old_pkscript,
+        new_pkscript,
     ));
 
-    return precompile_output(interpreter_result, bytes);
-}
-
-fn to_sats(btc_value: f64) -> u64 {
-    (btc_value * 1e8) as u64
+    Ok(precompile_output(interpreter_result, bytes))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::evm::precompiles::btc_utils::skip_btc_tests;
+    use crate::evm::precompiles::get_test_fixture_provider;
 
     #[test]
     fn test_get_last_sat_location_encode_params_single_vin_vout() {
@@ -173,7 +160,9 @@ mod tests {
         );
 
         // Consider mocking the RPC call to bitcoind
-        let result = last_sat_location_precompile(&data.into(), 1000000);
+        let result =
+            last_sat_location_precompile(&data.into(), 1000000, &mut BitcoinData::default())
+                .unwrap();
         let result = result;
         let returns = getLastSatLocationCall::abi_decode_returns(&result.output, false).unwrap();
 
@@ -220,7 +209,9 @@ mod tests {
         let data = getLastSatLocationCall::new((txid, vout, sat)).abi_encode();
 
         // Consider mocking the RPC call to bitcoind
-        let result = last_sat_location_precompile(&data.into(), 10000000);
+        let result =
+            last_sat_location_precompile(&data.into(), 10000000, &mut BitcoinData::default())
+                .unwrap();
         let result = result;
         let returns = getLastSatLocationCall::abi_decode_returns(&result.output, false).unwrap();
 
@@ -270,8 +261,84 @@ mod tests {
         let data = getLastSatLocationCall::new((txid, vout, sat)).abi_encode();
 
         // Consider mocking the RPC call to bitcoind
-        let result = last_sat_location_precompile(&data.into(), 1000000);
+        let result =
+            last_sat_location_precompile(&data.into(), 1000000, &mut BitcoinData::default())
+                .unwrap();
 
         assert!(result.is_error());
     }
+
+    #[test]
+    fn test_get_last_sat_location_fixture() {
+        let mut bitcoin_data = BitcoinData::with_provider(get_test_fixture_provider(), None);
+        let txid = FixedBytes::from_hex(
+            "4b9228370196e80cfc329c73e9d109487cdc67abb1a0cac00662824fe7242cf3",
+        )
+        .unwrap();
+        let data =
+            getLastSatLocationCall::new((txid, U256::from(1u64), U256::from(100u64))).abi_encode();
+
+        let result =
+            last_sat_location_precompile(&data.into(), 1000000, &mut bitcoin_data).unwrap();
+        assert_eq!(result.gas.spent(), 300000);
+
+        let returns = getLastSatLocationCall::abi_decode_returns(&result.output, false).unwrap();
+        assert_eq!(
+            (
+                returns.last_txid,
+                returns.last_vout,
+                returns.last_sat,
+                returns.old_pkscript,
+                returns.new_pkscript,
+            ),
+            (
+                FixedBytes::from_hex(
+                    "514009a91179fc847e1bf4f0c8f8189d458d5ec4d771d5711498efac658a9500"
+                )
+                .unwrap(),
+                U256::from(1u64),
+                U256::from(2000100u64),
+                Bytes::from_hex("0014f477952f33561c1b89a1fe9f28682f623263e159").unwrap(),
+                Bytes::from_hex(format!("5120{}", "bb".repeat(32))).unwrap(),
+            )
+        );
+    }
+
+    #[test]
+    fn test_get_last_sat_location_fixture_errors() {
+        let mut bitcoin_data = BitcoinData::with_provider(get_test_fixture_provider(), None);
+        for (txid, vout, sat) in [
+            // Coinbase transaction
+            (
+                "dea55524ee55c578b7e0650a69d5eb3fb1457d098bd21ee955a6e0b0faff4f73",
+                0u64,
+                100u64,
+            ),
+            // Vout out of bounds
+            (
+                "4b9228370196e80cfc329c73e9d109487cdc67abb1a0cac00662824fe7242cf3",
+                2,
+                100,
+            ),
+            // Sat out of bounds
+            (
+                "4b9228370196e80cfc329c73e9d109487cdc67abb1a0cac00662824fe7242cf3",
+                1,
+                3000000,
+            ),
+            // Spent transaction is missing
+            (
+                "514009a91179fc847e1bf4f0c8f8189d458d5ec4d771d5711498efac658a9500",
+                0,
+                100,
+            ),
+        ] {
+            let txid = FixedBytes::from_hex(txid).unwrap();
+            let data =
+                getLastSatLocationCall::new((txid, U256::from(vout), U256::from(sat))).abi_encode();
+            let result =
+                last_sat_location_precompile(&data.into(), 1000000, &mut bitcoin_data).unwrap();
+            assert!(result.is_error());
+        }
+    }
 }
@@ -4,8 +4,7 @@ use alloy_sol_types::{sol, SolCall};
 use revm::interpreter::{Gas, InstructionResult, InterpreterResult};
 use revm::primitives::Bytes;
 
-use crate::evm::precompiles::btc_utils::{get_block_height, get_raw_transaction};
-use crate::evm::precompiles::{precompile_error, precompile_output, use_gas};
+use crate::evm::precompiles::{precompile_error, precompile_output, use_gas, BitcoinData};
 
 static GAS_PER_RPC_CALL: u64 = 100000;
 
@@ -21,50 +20,48 @@ sol! {
     function getTxDetails(bytes32) returns (uint256 block_height, bytes32[] vin_txids, uint256[] vin_vouts , bytes[] vin_scriptPubKeys, uint256[] vin_values, bytes[] vout_scriptPubKeys, uint256[] vout_values);
 }
 
-pub fn btc_tx_details_precompile(bytes: &Bytes, gas_limit: u64) -> InterpreterResult {
+/// Returns an error only if the Bitcoin data can't be fetched, which should fail the transaction
+pub fn btc_tx_details_precompile(
+    bytes: &Bytes,
+    gas_limit: u64,
+    bitcoin_data: &mut BitcoinData,
+) -> Result<InterpreterResult, String> {
     let mut interpreter_result =
         InterpreterResult::new(InstructionResult::Stop, Bytes::new(), Gas::new(gas_limit));
 
     if !use_gas(&mut interpreter_result, GAS_PER_RPC_CALL) {
-        return interpreter_result;
+        return Ok(interpreter_result);
     }
 
     let result = getTxDetailsCall::abi_decode(&bytes, false);
 
     if result.is_err() {
         // Invalid params
-        return precompile_error(interpreter_result);
+        return Ok(precompile_error(interpreter_result));
     }
 
     let txid = result.unwrap()._0;
 
-    let response = get_raw_transaction(&hex::encode(txid));
+    let Some(response) = bitcoin_data.get_transaction(&hex::encode(txid))? else {
+        // Transaction not found
+        return Ok(precompile_error(interpreter_result));
+    };
 
-    if response["error"].is_object() || !response["result"].is_object() {
-        tracing::error!("Error: {}", response["error"]["message"]);
-        return precompile_error(interpreter_result);
-    }
-
-    let response = response["result"].clone();
-
-    let vin_count = response["vin"].as_array();
     if !use_gas(
         &mut interpreter_result,
-        vin_count.unwrap().len() as u64 * GAS_PER_RPC_CALL,
+        response.vin.len() as u64 * GAS_PER_RPC_CALL,
     ) {
-        return interpreter_result;
-    }
-
-    let block_hash = response["blockhash"].as_str().unwrap_or("").to_string();
-
-    let block_height_result = get_block_height(&block_hash);
-    if block_height_result["error"].is_object() || !block_height_result["result"].is_object() {
-        return precompile_error(interpreter_result);
+        return Ok(interpreter_result);
     }
 
-    let block_height = Some(block_height_result["result"]["height"].as_u64().unwrap());
+    let Some(block_height) =
+        bitcoin_data.get_block_height(response.block_hash.as_deref().unwrap_or(""))?
+    else {
+        // Transaction is not in a block
+        return Ok(precompile_error(interpreter_result));
+    };
 
-    let block_height = U256::from(block_height.unwrap());
+    let block_height = U256::from(block_height);
 
     let mut vin_txids = Vec::new();
     let mut vin_vouts = Vec::new();
@@ -73,52 +70,44 @@ pub fn btc_tx_details_precompile(bytes: &Bytes, gas_limit: u64) -> InterpreterRe
     let mut vout_script_pub_keys = Vec::new();
     let mut vout_values = Vec::new();
 
-    for vin in response["vin"].as_array().unwrap().into_iter() {
-        let vin_txid = vin["txid"].as_str().unwrap_or("").to_string();
-        let vin_vout = vin["vout"].as_u64().unwrap_or(0);
+    for vin in response.vin.iter() {
+        let Some(vin_txid) = &vin.txid else {
+            // Coinbase inputs don't spend an output
+            return Ok(precompile_error(interpreter_result));
+        };
 
         // Get the scriptPubKey from the vin transaction, using the txid and vout
-        let vin_script_pub_key_response = get_raw_transaction(&vin_txid);
-        if vin_script_pub_key_response["error"].is_object()
-            || !vin_script_pub_key_response["result"].is_object()
-        {
-            return precompile_error(interpreter_result);
-        }
-
-        let vin_script_pub_key_response = vin_script_pub_key_response["result"].clone();
-        let vin_script_pub_key_hex = vin_script_pub_key_response["vout"][vin_vout as usize]
-            ["scriptPubKey"]["hex"]
-            .as_str()
-            .unwrap_or("")
-            .to_string();
-        let vin_script_pub_key_bytes = Bytes::from(hex::decode(vin_script_pub_key_hex).unwrap());
-
-        let vin_value = vin_script_pub_key_response["vout"][vin_vout as usize]["value"]
-            .as_f64()
-            .unwrap_or(0.0);
-        let vin_value = (vin_value * 100000000.0) as u64;
-
-        vin_txids.push(FixedBytes::from_hex(vin_txid).unwrap());
-        vin_vouts.push(U256::from(vin_vout));
+        let Some(vin_response) = bitcoin_data.get_transaction(vin_txid)? else {
+            return Ok(precompile_error(interpreter_result));
+        };
+        let Some(vin_output) = vin_response.vout.get(vin.vout as usize) else {
+            return Ok(precompile_error(interpreter_result));
+        };
+
+        let Ok(vin_txid) = FixedBytes::from_hex(vin_txid) else {
+            return Ok(precompile_error(interpreter_result));
+        };
+        let Ok(vin_script_pub_key_bytes) = Bytes::from_hex(&vin_output.script_pubkey) else {
+            return Ok(precompile_error(interpreter_result));
+        };
+
+        vin_txids.push(vin_txid);
+        vin_vouts.push(U256::from(vin.vout));
         vin_script_pub_keys.push(vin_script_pub_key_bytes);
-        vin_values.push(U256::from(vin_value));
+        vin_values.push(U256::from(vin_output.value));
     }
 
-    for vout in response["vout"].as_array().unwrap().into_iter() {
-        let vout_script_pub_key_hex = vout["scriptPubKey"]["hex"]
-            .as_str()
-            .unwrap_or("")
-            .to_string();
-        let vout_script_pub_key_bytes = Bytes::from(hex::decode(vout_script_pub_key_hex).unwrap());
-        let vout_value = vout["value"].as_f64().unwrap_or(0.0);
-        let vout_value = (vout_value * 100000000.0) as u64;
+    for vout in response.vout.iter() {
+        let Ok(vout_script_pub_key_bytes) = Bytes::from_hex(&vout.script_pubkey) else {
+            return Ok(precompile_error(interpreter_result));
+        };
 
         vout_script_pub_keys.push(vout_script_pub_key_bytes);
-        vout_values.push(U256::from(vout_value));
+        vout_values.push(U256::from(vout.value));
     }
 
     let bytes = getTxDetailsCall::abi_encode_returns(&(
-        U256::from(block_height),
+        block_height,
         vin_txids,
         vin_vouts,
         vin_script_pub_keys,
@@ -127,13 +116,14 @@ pub fn btc_tx_details_precompile(bytes: &Bytes, gas_limit: u64) -> InterpreterRe
         vout_values,
     ));
 
-    return precompile_output(interpreter_result, bytes);
+    Ok(precompile_output(interpreter_result, bytes))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::evm::precompiles::btc_utils::skip_btc_tests;
+    use crate::evm::precompiles::get_test_fixture_provider;
 
     #[test]
     fn test_get_tx_details_encode_params_single_vin_vout() {
@@ -173,7 +163,9 @@ mod tests {
         let response = btc_tx_details_precompile(
             &Bytes::from(getTxDetailsCall::new((txid,)).abi_encode()),
             1000000,
-        );
+            &mut BitcoinData::default(),
+        )
+        .unwrap();
 
         let returns = getTxDetailsCall::abi_decode_returns(&response.output, false).unwrap();
 
@@ -204,4 +196,76 @@ mod tests {
         assert_eq!(returns.vout_values.len(), 1);
         assert_eq!(returns.vout_values[0], U256::from(9658000u64));
     }
+
+    #[test]
+    fn test_get_tx_details_fixture() {
+        let mut bitcoin_data = BitcoinData::with_provider(get_test_fixture_provider(), None);
+        let txid = FixedBytes::from_hex(
+            "4b9228370196e80cfc329c73e9d109487cdc67abb1a0cac00662824fe7242cf3",
+        )
+        .unwrap();
+        let response = btc_tx_details_precompile(
+            &Bytes::from(getTxDetailsCall::new((txid,)).abi_encode()),
+            1000000,
+            &mut bitcoin_data,
+        )
+        .unwrap();
+        assert_eq!(response.gas.spent(), 300000);
+
+        let returns = getTxDetailsCall::abi_decode_returns(&response.output, false).unwrap();
+        let vin_txid = FixedBytes::from_hex(
+            "514009a91179fc847e1bf4f0c8f8189d458d5ec4d771d5711498efac658a9500",
+        )
+        .unwrap();
+        assert_eq!(returns.block_height, U256::from(240960u64));
+        assert_eq!(returns.vin_txids, vec![vin_txid, vin_txid]);
+        assert_eq!(returns.vin_vouts, vec![U256::from(0u64), U256::from(1u64)]);
+        assert_eq!(
+            returns.vin_scriptPubKeys,
+            vec![
+                Bytes::from_hex(
+                    "51204a6041f54b8cf8b2d48c6f725cb0514e51e5e7e7ac429c33da62e98765dd62f3"
+                )
+                .unwrap(),
+                Bytes::from_hex("0014f477952f33561c1b89a1fe9f28682f623263e159").unwrap(),
+            ]
+        );
+        assert_eq!(
+            returns.vin_values,
+            vec![U256::from(10000000u64), U256::from(5000000u64)]
+        );
+        assert_eq!(
+            returns.vout_scriptPubKeys,
+            vec![
+                Bytes::from_hex(format!("0014{}", "aa".repeat(20))).unwrap(),
+                Bytes::from_hex(format!("5120{}", "bb".repeat(32))).unwrap(),
+            ]
+        );
+        assert_eq!(
+            returns.vout_values,
+            vec![U256::from(12000000u64), U256::from(2990000u64)]
+        );
+    }
+
+    #[test]
+    fn test_get_tx_details_fixture_errors() {
+        let mut bitcoin_data = BitcoinData::with_provider(get_test_fixture_provider(), None);
+        for txid in [
+            // Not in a block
+            "514009a91179fc847e1bf4f0c8f8189d458d5ec4d771d5711498efac658a9500",
+            // Coinbase transaction
+            "dea55524ee55c578b7e0650a69d5eb3fb1457d098bd21ee955a6e0b0faff4f73",
+            // Missing transaction
+            "6244d9f1d3d30bcdb5e8c7ace6a8d76f9108804da37a7050682cfcc269cc2144",
+        ] {
+            let txid = FixedBytes::from_hex(txid).unwrap();
+            let response = btc_tx_details_precompile(
+                &Bytes::from(getTxDetailsCall::new((txid,)).abi_encode()),
+                1000000,
+                &mut bitcoin_data,
+            )
+            .unwrap();
+            assert!(response.is_error());
+        }
+    }
 }
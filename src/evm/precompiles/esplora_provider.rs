@@ -0,0 +1,125 @@
+use crate::db::types::{BitcoinBlockHeaderED, BitcoinTxED};
+use crate::evm::precompiles::btc_utils::BTC_CLIENT;
+use crate::evm::precompiles::{parse_raw_transaction, BitcoinDataProvider};
+
+/// Number of times a request is retried if the server can't be reached
+const REST_RETRIES: u32 = 5;
+
+/// Gets Bitcoin data from an Esplora compatible REST API, e.g. electrs or mempool.space
+pub struct EsploraProvider {
+    url: String,
+}
+
+impl EsploraProvider {
+    /// url is the base url of the API, e.g. https://mempool.space/signet/api
+    pub fn new(url: &str) -> Self {
+        Self {
+            url: url.trim_end_matches('/').to_string(),
+        }
+    }
+
+    /// Sends a GET request and returns the body, retrying if the server can't be reached
+    ///
+    /// Returns Ok(None) if the requested object doesn't exist
+    fn get(&self, path: &str) -> Result<Option<String>, String> {
+        let url = format!("{}{}", self.url, path);
+        let mut retries_left = REST_RETRIES;
+        let mut response = loop {
+            let response = BTC_CLIENT
+                .get(&url)
+                .config()
+                .http_status_as_error(false)
+                .build()
+                .call();
+            match response {
+                Ok(response) => break response,
+                Err(error) if retries_left == 0 => {
+                    return Err(format!("Failed to get {}: {}", url, error));
+                }
+                Err(_) => {
+                    // wait and retry
+                    retries_left -= 1;
+                    std::thread::sleep(std::time::Duration::from_secs(1));
+                }
+            }
+        };
+
+        let status = response.status();
+        let body = response
+            .body_mut()
+            .read_to_string()
+            .map_err(|error| format!("Failed to read {}: {}", url, error))?;
+        match status.as_u16() {
+            200 => Ok(Some(body)),
+            // Esplora returns 400 for invalid hashes and 404 for missing objects
+            400 | 404 => Ok(None),
+            _ => Err(format!(
+                "Failed to get {} with status {}: {}",
+                url, status, body
+            )),
+        }
+    }
+
+    fn get_json(&self, path: &str) -> Result<Option<serde_json::Value>, String> {
+        self.get(path)?
+            .map(|body| {
+                body.parse()
+                    .map_err(|error| format!("Invalid response for {}: {}", path, error))
+            })
+            .transpose()
+    }
+}
+
+impl BitcoinDataProvider for EsploraProvider {
+    fn name(&self) -> &'static str {
+        "esplora"
+    }
+
+    fn get_transaction(&self, txid: &str) -> Result<Option<BitcoinTxED>, String> {
+        let Some(raw_transaction) = self.get(&format!("/tx/{}/hex", txid))? else {
+            return Ok(None);
+        };
+        let status = self
+            .get_json(&format!("/tx/{}/status", txid))?
+            .ok_or(format!("Missing status for transaction {}", txid))?;
+        let block_hash = status["block_hash"].as_str().map(|x| x.to_lowercase());
+
+        let transaction = parse_raw_transaction(&raw_transaction, block_hash)?;
+        if transaction.txid != txid.to_lowercase() {
+            return Err(format!(
+                "Transaction {} doesn't match the requested txid {}",
+                transaction.txid, txid
+            ));
+        }
+        Ok(Some(transaction))
+    }
+
+    fn get_block_header(&self, block_hash: &str) -> Result<Option<BitcoinBlockHeaderED>, String> {
+        let Some(block) = self.get_json(&format!("/block/{}", block_hash))? else {
+            return Ok(None);
+        };
+        let invalid = || format!("Invalid block: {}", block);
+        Ok(Some(BitcoinBlockHeaderED {
+            hash: block["id"].as_str().ok_or_else(invalid)?.to_lowercase(),
+            height: block["height"].as_u64().ok_or_else(invalid)?,
+            time: block["timestamp"].as_u64().ok_or_else(invalid)?,
+            merkle_root: block["merkle_root"]
+                .as_str()
+                .ok_or_else(invalid)?
+                .to_lowercase(),
+            previous_block_hash: block["previousblockhash"]
+                .as_str()
+                .map(|x| x.to_lowercase()),
+        }))
+    }
+
+    fn is_in_main_chain(&self, block_hash: &str) -> Result<bool, String> {
+        Ok(self
+            .get_json(&format!("/block/{}/status", block_hash))?
+            .is_some_and(|status| status["in_best_chain"].as_bool() == Some(true)))
+    }
+
+    fn check_status(&self) -> bool {
+        self.get("/blocks/tip/height").is_ok_and(|x| x.is_some())
+    }
+}
@@ -0,0 +1,172 @@
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+use crate::db::types::{BitcoinBlockHeaderED, BitcoinTxED};
+use crate::evm::precompiles::{
+    is_verbose_block_header_in_main_chain, parse_raw_transaction, parse_verbose_block_header,
+    parse_verbose_transaction, BitcoinDataProvider,
+};
+
+/// Gets Bitcoin data from files in a directory, so precompiles can run without a Bitcoin node
+///
+/// Transactions are read from tx/<txid>.json, either in the verbose format of bitcoind
+/// getrawtransaction or as {"hex": <raw transaction>, "blockhash": <block hash>},
+/// or from tx/<txid>.hex with the raw transaction, which is not in a block.
+/// Block headers are read from block/<hash>.json in the verbose format of bitcoind getblockheader,
+/// with -1 confirmations if the block is not in the main chain
+pub struct FixtureProvider {
+    dir: PathBuf,
+}
+
+impl FixtureProvider {
+    pub fn new(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+
+    /// Reads a file, returns Ok(None) if it doesn't exist
+    fn read(path: &Path) -> Result<Option<String>, String> {
+        match std::fs::read_to_string(path) {
+            Ok(content) => Ok(Some(content)),
+            Err(error) if error.kind() == ErrorKind::NotFound => Ok(None),
+            Err(error) => Err(format!("Failed to read {}: {}", path.display(), error)),
+        }
+    }
+
+    fn read_json(path: &Path) -> Result<Option<serde_json::Value>, String> {
+        Self::read(path)?
+            .map(|content| {
+                serde_json::from_str(&content)
+                    .map_err(|error| format!("Invalid JSON in {}: {}", path.display(), error))
+            })
+            .transpose()
+    }
+}
+
+/// Hashes are used as file names, so anything else is treated as missing
+fn is_hash(hash: &str) -> bool {
+    !hash.is_empty() && hash.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+impl BitcoinDataProvider for FixtureProvider {
+    fn name(&self) -> &'static str {
+        "fixture"
+    }
+
+    fn get_transaction(&self, txid: &str) -> Result<Option<BitcoinTxED>, String> {
+        if !is_hash(txid) {
+            return Ok(None);
+        }
+        let txid = txid.to_lowercase();
+        let tx_dir = self.dir.join("tx");
+        if let Some(transaction) = Self::read_json(&tx_dir.join(format!("{}.json", txid)))? {
+            if transaction["vin"].is_array() {
+                return parse_verbose_transaction(&transaction).map(Some);
+            }
+            let raw_transaction = transaction["hex"]
+                .as_str()
+                .ok_or(format!("Missing hex for transaction {}", txid))?;
+            let block_hash = transaction["blockhash"].as_str().map(|x| x.to_lowercase());
+            return parse_raw_transaction(raw_transaction, block_hash).map(Some);
+        }
+        Self::read(&tx_dir.join(format!("{}.hex", txid)))?
+            .map(|raw_transaction| parse_raw_transaction(&raw_transaction, None))
+            .transpose()
+    }
+
+    fn get_block_header(&self, block_hash: &str) -> Result<Option<BitcoinBlockHeaderED>, String> {
+        if !is_hash(block_hash) {
+            return Ok(None);
+        }
+        let path = self
+            .dir
+            .join("block")
+            .join(format!("{}.json", block_hash.to_lowercase()));
+        Self::read_json(&path)?
+            .map(|block_header| parse_verbose_block_header(&block_header))
+            .transpose()
+    }
+
+    fn is_in_main_chain(&self, block_hash: &str) -> Result<bool, String> {
+        if !is_hash(block_hash) {
+            return Ok(false);
+        }
+        let path = self
+            .dir
+            .join("block")
+            .join(format!("{}.json", block_hash.to_lowercase()));
+        Ok(Self::read_json(&path)?
+            .is_some_and(|block_header| is_verbose_block_header_in_main_chain(&block_header)))
+    }
+
+    fn check_status(&self) -> bool {
+        self.dir.is_dir()
+    }
+}
+
+/// Provider for the fixtures in the repository, see fixtures/bitcoin/README.md
+#[cfg(test)]
+pub fn get_test_fixture_provider() -> std::sync::Arc<dyn BitcoinDataProvider> {
+    std::sync::Arc::new(FixtureProvider::new(
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures/bitcoin"),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fixture_provider() {
+        let provider = get_test_fixture_provider();
+        assert!(provider.check_status());
+
+        // Verbose transaction
+        let transaction = provider
+            .get_transaction("4B9228370196E80CFC329C73E9D109487CDC67ABB1A0CAC00662824FE7242CF3")
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            transaction.txid,
+            "4b9228370196e80cfc329c73e9d109487cdc67abb1a0cac00662824fe7242cf3"
+        );
+        assert_eq!(transaction.vin.len(), 2);
+        assert_eq!(transaction.vout[1].value, 2990000);
+
+        // Raw transaction with a block hash, parsed from the hex
+        let transaction = provider
+            .get_transaction("dea55524ee55c578b7e0650a69d5eb3fb1457d098bd21ee955a6e0b0faff4f73")
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            transaction.txid,
+            "dea55524ee55c578b7e0650a69d5eb3fb1457d098bd21ee955a6e0b0faff4f73"
+        );
+        assert!(transaction.is_coinbase());
+        assert_eq!(transaction.vout[0].value, 5000000000);
+        assert!(transaction.block_hash.is_some());
+
+        // Raw transaction without a block
+        let transaction = provider
+            .get_transaction("514009a91179fc847e1bf4f0c8f8189d458d5ec4d771d5711498efac658a9500")
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            transaction.vout[0].script_pubkey,
+            "51204a6041f54b8cf8b2d48c6f725cb0514e51e5e7e7ac429c33da62e98765dd62f3"
+        );
+        assert_eq!(transaction.block_hash, None);
+
+        let block_hash = "fb665657d185149a8adefbda5693369ac67ef0ebe48dc258c90610803b358356";
+        let block_header = provider.get_block_header(block_hash).unwrap().unwrap();
+        assert_eq!(block_header.hash, block_hash);
+        assert_eq!(block_header.height, 240960);
+        assert_eq!(provider.get_block_height(block_hash).unwrap(), Some(240960));
+        assert!(provider.is_in_main_chain(block_hash).unwrap());
+
+        // Missing objects are not errors
+        assert_eq!(provider.get_transaction(&"00".repeat(32)).unwrap(), None);
+        assert_eq!(provider.get_block_height(&"00".repeat(32)).unwrap(), None);
+        assert!(!provider.is_in_main_chain(&"00".repeat(32)).unwrap());
+        assert_eq!(provider.get_transaction("../tx").unwrap(), None);
+    }
+}
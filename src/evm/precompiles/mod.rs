@@ -7,6 +7,9 @@ pub use btc_tx_details_precompile::*;
 mod brc20_balance_precompile;
 pub use brc20_balance_precompile::*;
 
+mod brc20_balance_provider;
+pub use brc20_balance_provider::*;
+
 mod btc_last_sat_loc_precompile;
 pub use btc_last_sat_loc_precompile::*;
 
@@ -18,3 +21,18 @@ pub use precompiles::*;
 
 mod btc_utils;
 pub use btc_utils::*;
+
+mod bitcoin_data;
+pub use bitcoin_data::*;
+
+mod bitcoin_data_provider;
+pub use bitcoin_data_provider::*;
+
+mod bitcoind_provider;
+pub use bitcoind_provider::*;
+
+mod esplora_provider;
+pub use esplora_provider::*;
+
+mod fixture_provider;
+pub use fixture_provider::*;
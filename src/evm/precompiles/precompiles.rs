@@ -1,15 +1,17 @@
 use std::collections::{HashMap, HashSet};
 use std::str::FromStr;
+use std::sync::Arc;
 
-use revm::context::{Cfg, ContextTr};
+use revm::context::{Block, Cfg, ContextTr};
 use revm::handler::PrecompileProvider;
 use revm::interpreter::{Gas, InstructionResult, InterpreterResult};
 use revm::precompile::Precompiles;
-use revm::primitives::{Address, Bytes};
+use revm::primitives::{Address, Bytes, B256};
 
 use crate::evm::precompiles::{
     bip322_verify_precompile, brc20_balance_precompile, btc_tx_details_precompile,
-    get_locked_pkscript_precompile, last_sat_location_precompile,
+    get_locked_pkscript_precompile, last_sat_location_precompile, BitcoinData,
+    Brc20BalanceProvider, BRC20_BALANCE_PROVIDER,
 };
 
 lazy_static::lazy_static! {
@@ -20,10 +22,21 @@ lazy_static::lazy_static! {
     static ref GET_LOCKED_PK_SCRIPT_PRECOMPILE_ADDRESS: Address = Address::from_str("0x00000000000000000000000000000000000000fb").unwrap();
 }
 
+/// Precompile that reads Bitcoin data, it returns an error if the data can't be fetched
+type BitcoinPrecompile = fn(&Bytes, u64, &mut BitcoinData) -> Result<InterpreterResult, String>;
+
 pub struct BRC20Precompiles {
     pub eth_precompiles: &'static Precompiles,
     pub custom_precompiles: HashMap<Address, fn(&Bytes, u64) -> InterpreterResult>,
+    pub bitcoin_precompiles: HashMap<Address, BitcoinPrecompile>,
     pub all_addresses: HashSet<Address>,
+    /// Bitcoin data used by the bitcoin precompiles, recorded for each transaction
+    pub bitcoin_data: BitcoinData,
+    /// Hash of the executing block, used by the BRC20 balance precompile,
+    /// None if it's not known yet, e.g. for calls on top of the latest block
+    pub block_hash: Option<B256>,
+    /// Provider of the non-module BRC20 balances used by the BRC20 balance precompile
+    pub balance_provider: Arc<dyn Brc20BalanceProvider>,
 }
 
 impl Default for BRC20Precompiles {
@@ -41,25 +54,30 @@ impl Default for BRC20Precompiles {
 
         let mut custom_precompiles: HashMap<Address, fn(&Bytes, u64) -> InterpreterResult> =
             HashMap::new();
-        custom_precompiles.insert(*BRC20_BALANCE_PRECOMPILE_ADDRESS, brc20_balance_precompile);
         custom_precompiles.insert(*BIP322_PRECOMPILE_ADDRESS, bip322_verify_precompile);
         custom_precompiles.insert(
+            *GET_LOCKED_PK_SCRIPT_PRECOMPILE_ADDRESS,
+            get_locked_pkscript_precompile,
+        );
+
+        let mut bitcoin_precompiles: HashMap<Address, BitcoinPrecompile> = HashMap::new();
+        bitcoin_precompiles.insert(
             *BTC_TX_DETAILS_PRECOMPILE_ADDRESS,
             btc_tx_details_precompile,
         );
-        custom_precompiles.insert(
+        bitcoin_precompiles.insert(
             *LAST_SAT_LOCATION_PRECOMPILE_ADDRESS,
             last_sat_location_precompile,
         );
-        custom_precompiles.insert(
-            *GET_LOCKED_PK_SCRIPT_PRECOMPILE_ADDRESS,
-            get_locked_pkscript_precompile,
-        );
 
         Self {
             eth_precompiles,
             all_addresses,
             custom_precompiles,
+            bitcoin_precompiles,
+            bitcoin_data: BitcoinData::default(),
+            block_hash: None,
+            balance_provider: BRC20_BALANCE_PROVIDER.clone(),
         }
     }
 }
@@ -71,7 +89,7 @@ impl<CTX: ContextTr> PrecompileProvider<CTX> for BRC20Precompiles {
 
     fn run(
         &mut self,
-        _: &mut CTX,
+        context: &mut CTX,
         address: &Address,
         bytes: &Bytes,
         gas_limit: u64,
@@ -103,9 +121,22 @@ impl<CTX: ContextTr> PrecompileProvider<CTX> for BRC20Precompiles {
                 }
                 Err(e) => return Err(e.to_string()),
             }
+        } else if *address == *BRC20_BALANCE_PRECOMPILE_ADDRESS {
+            // Failing to fetch the balance fails the whole transaction, so it can be retried
+            result = brc20_balance_precompile(
+                bytes,
+                gas_limit,
+                context.block().number(),
+                self.block_hash,
+                self.balance_provider.as_ref(),
+            )?;
         } else if self.custom_precompiles.contains_key(address) {
             let function = self.custom_precompiles.get(address).unwrap();
             result = function(bytes, gas_limit);
+        } else if self.bitcoin_precompiles.contains_key(address) {
+            let function = self.bitcoin_precompiles.get(address).unwrap();
+            // Failing to fetch the data fails the whole transaction, so it can be retried
+            result = function(bytes, gas_limit, &mut self.bitcoin_data)?;
         } else {
             return Ok(None);
         }
@@ -0,0 +1,370 @@
+use revm::context::ContextTr;
+use revm::context_interface::CreateScheme;
+use revm::interpreter::interpreter::EthInterpreter;
+use revm::interpreter::{
+    CallInputs, CallOutcome, CallScheme, CreateInputs, CreateOutcome, InstructionResult,
+    Interpreter, InterpreterResult,
+};
+use revm::primitives::{Address, Bytes, Log};
+use revm::Inspector;
+use serde::Serialize;
+use serde_hex::{CompactPfx, SerHex};
+
+use crate::db::types::{AddressED, B256ED, U256ED};
+use crate::evm::get_revert_reason;
+use crate::evm::tracers::bytes_to_hex;
+
+/// Options of the callTracer, same as geth
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CallTracerConfig {
+    /// Only the top call is traced, sub calls are not included
+    pub only_top_call: bool,
+    /// Logs emitted by each call are included
+    pub with_log: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CallLogFrame {
+    pub address: AddressED,
+    pub topics: Vec<B256ED>,
+    #[serde(serialize_with = "bytes_to_hex")]
+    pub data: Bytes,
+    /// Number of sub calls made by the frame before this log was emitted
+    #[serde(with = "SerHex::<CompactPfx>")]
+    pub position: u64,
+}
+
+/// Single call in the output of the callTracer, in geth format
+#[derive(Debug, Clone, Serialize)]
+pub struct CallFrame {
+    #[serde(rename = "type")]
+    pub call_type: String,
+    pub from: AddressED,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub to: Option<AddressED>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub value: Option<U256ED>,
+    #[serde(with = "SerHex::<CompactPfx>")]
+    pub gas: u64,
+    #[serde(rename = "gasUsed", with = "SerHex::<CompactPfx>")]
+    pub gas_used: u64,
+    #[serde(serialize_with = "bytes_to_hex")]
+    pub input: Bytes,
+    #[serde(
+        serialize_with = "bytes_to_hex",
+        skip_serializing_if = "<[u8]>::is_empty"
+    )]
+    pub output: Bytes,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(rename = "revertReason", skip_serializing_if = "Option::is_none")]
+    pub revert_reason: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub calls: Vec<CallFrame>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub logs: Vec<CallLogFrame>,
+}
+
+impl CallFrame {
+    fn new(call_type: &str, from: Address, to: Option<Address>, gas: u64, input: Bytes) -> Self {
+        CallFrame {
+            call_type: call_type.to_string(),
+            from: AddressED(from),
+            to: to.map(AddressED),
+            value: None,
+            gas,
+            gas_used: 0,
+            input,
+            output: Bytes::new(),
+            error: None,
+            revert_reason: None,
+            calls: Vec::new(),
+            logs: Vec::new(),
+        }
+    }
+
+    /// Logs of a failed call are reverted, so they are removed from the call and its sub calls
+    fn clear_logs(&mut self) {
+        self.logs.clear();
+        for call in self.calls.iter_mut() {
+            call.clear_logs();
+        }
+    }
+}
+
+/// Builds the call tree of a transaction, geth callTracer equivalent
+pub struct CallTracer {
+    config: CallTracerConfig,
+    stack: Vec<CallFrame>,
+    root: Option<CallFrame>,
+    /// Number of nested calls that are not recorded due to only_top_call
+    ignored_depth: usize,
+}
+
+impl CallTracer {
+    pub fn new(config: CallTracerConfig) -> Self {
+        CallTracer {
+            config,
+            stack: Vec::new(),
+            root: None,
+            ignored_depth: 0,
+        }
+    }
+
+    /// Returns the top call, gas values are replaced with the ones of the transaction
+    /// so they include the intrinsic gas
+    pub fn into_frame(self, gas_limit: u64, gas_used: u64) -> Option<CallFrame> {
+        let mut root = self.root?;
+        root.gas = gas_limit;
+        root.gas_used = gas_used;
+        Some(root)
+    }
+
+    fn start_frame(&mut self, frame: CallFrame) {
+        if self.config.only_top_call && !self.stack.is_empty() {
+            self.ignored_depth += 1;
+            return;
+        }
+        self.stack.push(frame);
+    }
+
+    fn end_frame(&mut self, result: &InterpreterResult, to: Option<Address>) {
+        if self.ignored_depth > 0 {
+            self.ignored_depth -= 1;
+            return;
+        }
+        let Some(mut frame) = self.stack.pop() else {
+            return;
+        };
+        frame.gas_used = result.gas.spent();
+        if to.is_some() {
+            frame.to = to.map(AddressED);
+        }
+        frame.output = result.output.clone();
+        if !result.result.is_ok() {
+            frame.error = Some(get_error_message(result.result));
+            if result.result.is_revert() {
+                frame.revert_reason = get_revert_reason(&result.output);
+            }
+            frame.clear_logs();
+        }
+
+        match self.stack.last_mut() {
+            Some(parent) => parent.calls.push(frame),
+            None => self.root = Some(frame),
+        }
+    }
+}
+
+/// Error messages of failed calls, same as geth where possible
+fn get_error_message(result: InstructionResult) -> String {
+    match result {
+        InstructionResult::Revert => "execution reverted".to_string(),
+        InstructionResult::OutOfGas
+        | InstructionResult::MemoryOOG
+        | InstructionResult::MemoryLimitOOG
+        | InstructionResult::PrecompileOOG
+        | InstructionResult::InvalidOperandOOG
+        | InstructionResult::ReentrancySentryOOG => "out of gas".to_string(),
+        InstructionResult::OpcodeNotFound | InstructionResult::InvalidFEOpcode => {
+            "invalid opcode".to_string()
+        }
+        InstructionResult::InvalidJump => "invalid jump destination".to_string(),
+        InstructionResult::StackUnderflow => "stack underflow".to_string(),
+        InstructionResult::StackOverflow => "stack limit reached".to_string(),
+        InstructionResult::StateChangeDuringStaticCall => "write protection".to_string(),
+        InstructionResult::CallTooDeep => "max call depth exceeded".to_string(),
+        InstructionResult::OutOfFunds => "insufficient balance for transfer".to_string(),
+        InstructionResult::CreateCollision => "contract address collision".to_string(),
+        InstructionResult::PrecompileError => "precompiled contract failed".to_string(),
+        _ => format!("{:?}", result),
+    }
+}
+
+fn get_call_type(scheme: CallScheme) -> &'static str {
+    match scheme {
+        CallScheme::Call => "CALL",
+        CallScheme::CallCode => "CALLCODE",
+        CallScheme::DelegateCall => "DELEGATECALL",
+        CallScheme::StaticCall => "STATICCALL",
+        CallScheme::ExtCall => "EXTCALL",
+        CallScheme::ExtStaticCall => "EXTSTATICCALL",
+        CallScheme::ExtDelegateCall => "EXTDELEGATECALL",
+    }
+}
+
+impl<CTX: ContextTr> Inspector<CTX, EthInterpreter> for CallTracer {
+    fn log(&mut self, _interp: &mut Interpreter<EthInterpreter>, _context: &mut CTX, log: Log) {
+        if !self.config.with_log || self.ignored_depth > 0 {
+            return;
+        }
+        let Some(frame) = self.stack.last_mut() else {
+            return;
+        };
+        frame.logs.push(CallLogFrame {
+            address: AddressED(log.address),
+            topics: log
+                .topics()
+                .iter()
+                .copied()
+                .map(B256ED::from_b256)
+                .collect(),
+            data: log.data.data.clone(),
+            position: frame.calls.len() as u64,
+        });
+    }
+
+    fn call(&mut self, _context: &mut CTX, inputs: &mut CallInputs) -> Option<CallOutcome> {
+        // Code of the target runs in the context of the caller for these
+        let to = match inputs.scheme {
+            CallScheme::DelegateCall | CallScheme::CallCode | CallScheme::ExtDelegateCall => {
+                inputs.bytecode_address
+            }
+            _ => inputs.target_address,
+        };
+        let mut frame = CallFrame::new(
+            get_call_type(inputs.scheme),
+            inputs.caller,
+            Some(to),
+            inputs.gas_limit,
+            inputs.input.clone(),
+        );
+        if !matches!(
+            inputs.scheme,
+            CallScheme::StaticCall | CallScheme::ExtStaticCall
+        ) {
+            frame.value = Some(U256ED::from_u256(inputs.value.get()));
+        }
+        self.start_frame(frame);
+        None
+    }
+
+    fn call_end(&mut self, _context: &mut CTX, _inputs: &CallInputs, outcome: &mut CallOutcome) {
+        self.end_frame(&outcome.result, None);
+    }
+
+    fn create(&mut self, _context: &mut CTX, inputs: &mut CreateInputs) -> Option<CreateOutcome> {
+        let call_type = match inputs.scheme {
+            CreateScheme::Create => "CREATE",
+            CreateScheme::Create2 { .. } => "CREATE2",
+        };
+        let mut frame = CallFrame::new(
+            call_type,
+            inputs.caller,
+            None,
+            inputs.gas_limit,
+            inputs.init_code.clone(),
+        );
+        frame.value = Some(U256ED::from_u256(inputs.value));
+        self.start_frame(frame);
+        None
+    }
+
+    fn create_end(
+        &mut self,
+        _context: &mut CTX,
+        _inputs: &CreateInputs,
+        outcome: &mut CreateOutcome,
+    ) {
+        self.end_frame(&outcome.result, outcome.address);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloy_sol_types::{Revert, SolError};
+    use revm::interpreter::Gas;
+
+    use super::*;
+
+    fn get_result(result: InstructionResult, output: Bytes) -> InterpreterResult {
+        InterpreterResult {
+            result,
+            output,
+            gas: Gas::new(100),
+        }
+    }
+
+    #[test]
+    fn test_call_tree() {
+        let mut tracer = CallTracer::new(CallTracerConfig {
+            only_top_call: false,
+            with_log: true,
+        });
+        tracer.start_frame(CallFrame::new(
+            "CALL",
+            Address::ZERO,
+            Some(Address::repeat_byte(1)),
+            1000,
+            Bytes::new(),
+        ));
+        tracer.start_frame(CallFrame::new(
+            "STATICCALL",
+            Address::repeat_byte(1),
+            Some(Address::repeat_byte(2)),
+            500,
+            Bytes::new(),
+        ));
+        tracer.end_frame(&get_result(InstructionResult::Return, Bytes::new()), None);
+
+        // Revert(string) with reason "fail"
+        let revert_output = Revert::from("fail").abi_encode();
+        tracer.start_frame(CallFrame::new(
+            "CALL",
+            Address::repeat_byte(1),
+            Some(Address::repeat_byte(3)),
+            500,
+            Bytes::new(),
+        ));
+        tracer.stack.last_mut().unwrap().logs.push(CallLogFrame {
+            address: AddressED(Address::repeat_byte(3)),
+            topics: Vec::new(),
+            data: Bytes::new(),
+            position: 0,
+        });
+        tracer.end_frame(
+            &get_result(InstructionResult::Revert, revert_output.into()),
+            None,
+        );
+        tracer.end_frame(&get_result(InstructionResult::Stop, Bytes::new()), None);
+
+        let root = tracer.into_frame(2000, 1500).unwrap();
+        assert_eq!(root.gas, 2000);
+        assert_eq!(root.gas_used, 1500);
+        assert!(root.error.is_none());
+        assert_eq!(root.calls.len(), 2);
+        assert_eq!(root.calls[0].call_type, "STATICCALL");
+        assert_eq!(root.calls[1].error, Some("execution reverted".to_string()));
+        assert_eq!(root.calls[1].revert_reason, Some("fail".to_string()));
+        // Logs of reverted calls are removed
+        assert!(root.calls[1].logs.is_empty());
+    }
+
+    #[test]
+    fn test_only_top_call() {
+        let mut tracer = CallTracer::new(CallTracerConfig {
+            only_top_call: true,
+            with_log: false,
+        });
+        tracer.start_frame(CallFrame::new(
+            "CALL",
+            Address::ZERO,
+            Some(Address::repeat_byte(1)),
+            1000,
+            Bytes::new(),
+        ));
+        tracer.start_frame(CallFrame::new(
+            "CALL",
+            Address::repeat_byte(1),
+            Some(Address::repeat_byte(2)),
+            500,
+            Bytes::new(),
+        ));
+        tracer.end_frame(&get_result(InstructionResult::Stop, Bytes::new()), None);
+        tracer.end_frame(&get_result(InstructionResult::OutOfGas, Bytes::new()), None);
+
+        let root = tracer.into_frame(1000, 1000).unwrap();
+        assert!(root.calls.is_empty());
+        assert_eq!(root.error, Some("out of gas".to_string()));
+    }
+}
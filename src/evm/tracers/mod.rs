@@ -0,0 +1,329 @@
+use revm::context::{BlockEnv, ContextTr, TxEnv};
+use revm::database::CacheDB;
+use revm::handler::EvmTr;
+use revm::inspector::InspectorEvmTr;
+use revm::interpreter::interpreter::EthInterpreter;
+use revm::interpreter::{CallInputs, CallOutcome, CreateInputs, CreateOutcome, Interpreter};
+use revm::primitives::{hex, Bytes, Log, B256};
+use revm::{DatabaseCommit, DatabaseRef, ExecuteCommitEvm, ExecuteEvm, InspectEvm, Inspector};
+use serde::Serialize;
+
+use crate::db::types::{BitcoinDataED, B256ED};
+use crate::db::BitcoinCache;
+use crate::evm::{get_evm_with_inspector, BitcoinData};
+
+mod call_tracer;
+pub use call_tracer::{CallFrame, CallLogFrame, CallTracer, CallTracerConfig};
+
+mod prestate_tracer;
+pub use prestate_tracer::{get_prestate, PrestateAccount, PrestateResult, PrestateTracerConfig};
+
+mod struct_log_tracer;
+pub use struct_log_tracer::{StructLog, StructLogConfig, StructLogResult, StructLogTracer};
+
+/// Tracers supported by the debug_trace methods, with their options
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TracerType {
+    StructLog(StructLogConfig),
+    Call(CallTracerConfig),
+    Prestate(PrestateTracerConfig),
+}
+
+/// Output of a traced transaction, format depends on the tracer
+#[derive(Debug, Clone, Serialize)]
+#[serde(untagged)]
+pub enum TraceResult {
+    StructLog(StructLogResult),
+    Call(Box<CallFrame>),
+    Prestate(PrestateResult),
+}
+
+/// Trace of a single transaction in the output of debug_traceBlockByNumber
+#[derive(Debug, Clone, Serialize)]
+pub struct BlockTraceResult {
+    #[serde(rename = "txHash")]
+    pub tx_hash: B256ED,
+    pub result: TraceResult,
+}
+
+pub(crate) fn bytes_to_hex<S>(bytes: &Bytes, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+{
+    serializer.serialize_str(&format!("0x{}", hex::encode(bytes)))
+}
+
+/// Inspector that runs the selected tracer, prestate tracer only needs the final state
+#[derive(Default)]
+pub enum TracerInspector {
+    #[default]
+    None,
+    StructLog(StructLogTracer),
+    Call(Box<CallTracer>),
+}
+
+impl TracerInspector {
+    pub fn new(tracer: &TracerType) -> Self {
+        match tracer {
+            TracerType::StructLog(config) => {
+                TracerInspector::StructLog(StructLogTracer::new(config.clone()))
+            }
+            TracerType::Call(config) => {
+                TracerInspector::Call(Box::new(CallTracer::new(config.clone())))
+            }
+            TracerType::Prestate(_) => TracerInspector::None,
+        }
+    }
+}
+
+impl<CTX: ContextTr> Inspector<CTX, EthInterpreter> for TracerInspector {
+    fn step(&mut self, interp: &mut Interpreter<EthInterpreter>, context: &mut CTX) {
+        if let TracerInspector::StructLog(tracer) = self {
+            tracer.step(interp, context);
+        }
+    }
+
+    fn step_end(&mut self, interp: &mut Interpreter<EthInterpreter>, context: &mut CTX) {
+        if let TracerInspector::StructLog(tracer) = self {
+            tracer.step_end(interp, context);
+        }
+    }
+
+    fn log(&mut self, interp: &mut Interpreter<EthInterpreter>, context: &mut CTX, log: Log) {
+        if let TracerInspector::Call(tracer) = self {
+            tracer.log(interp, context, log);
+        }
+    }
+
+    fn call(&mut self, context: &mut CTX, inputs: &mut CallInputs) -> Option<CallOutcome> {
+        match self {
+            TracerInspector::Call(tracer) => tracer.call(context, inputs),
+            _ => None,
+        }
+    }
+
+    fn call_end(&mut self, context: &mut CTX, inputs: &CallInputs, outcome: &mut CallOutcome) {
+        if let TracerInspector::Call(tracer) = self {
+            tracer.call_end(context, inputs, outcome);
+        }
+    }
+
+    fn create(&mut self, context: &mut CTX, inputs: &mut CreateInputs) -> Option<CreateOutcome> {
+        match self {
+            TracerInspector::Call(tracer) => tracer.create(context, inputs),
+            _ => None,
+        }
+    }
+
+    fn create_end(
+        &mut self,
+        context: &mut CTX,
+        inputs: &CreateInputs,
+        outcome: &mut CreateOutcome,
+    ) {
+        if let TracerInspector::Call(tracer) = self {
+            tracer.create_end(context, inputs, outcome);
+        }
+    }
+}
+
+/// Runs the transactions in order on top of the given state and traces the ones
+/// starting from trace_start, earlier transactions are only executed to build the state
+///
+/// Each transaction is run with the Bitcoin data recorded when it was executed,
+/// other data is read from the cache or the Bitcoin data provider.
+/// block_hash is used to get BRC20 balances at the traced block, None if it's not known yet
+pub fn trace_txes<D: DatabaseRef>(
+    state: D,
+    block_info: BlockEnv,
+    block_hash: Option<B256>,
+    txes: Vec<(TxEnv, BitcoinDataED)>,
+    trace_start: usize,
+    tracer: &TracerType,
+    bitcoin_cache: Option<BitcoinCache>,
+) -> Result<Vec<TraceResult>, &'static str> {
+    let mut evm = get_evm_with_inspector(
+        block_info,
+        CacheDB::new(state),
+        None,
+        TracerInspector::default(),
+    );
+    *evm.bitcoin_data() = BitcoinData::new(bitcoin_cache);
+    evm.set_block_hash(block_hash);
+
+    let mut results = Vec::new();
+    for (idx, (tx, bitcoin_data)) in txes.into_iter().enumerate() {
+        evm.bitcoin_data().take_recorded_data();
+        evm.bitcoin_data().set_replay_data(bitcoin_data);
+        if idx < trace_start {
+            evm.transact_commit(tx)
+                .map_err(|_| "Error while executing transaction")?;
+            continue;
+        }
+
+        let gas_limit = tx.gas_limit;
+        evm.set_tx(tx);
+        evm.set_inspector(TracerInspector::new(tracer));
+        let output = evm
+            .inspect_replay()
+            .map_err(|_| "Error while tracing transaction")?;
+        let result = &output.result;
+
+        let trace_result = match (tracer, core::mem::take(evm.inspector())) {
+            (TracerType::StructLog(_), TracerInspector::StructLog(inspector)) => {
+                TraceResult::StructLog(inspector.into_result(
+                    result.gas_used(),
+                    !result.is_success(),
+                    result.output().cloned().unwrap_or_default(),
+                ))
+            }
+            (TracerType::Call(_), TracerInspector::Call(inspector)) => TraceResult::Call(Box::new(
+                inspector
+                    .into_frame(gas_limit, result.gas_used())
+                    .ok_or("Transaction was not executed")?,
+            )),
+            (TracerType::Prestate(config), _) => TraceResult::Prestate(
+                get_prestate(evm.ctx().db(), &output.state, config)
+                    .map_err(|_| "Error while reading state")?,
+            ),
+            _ => return Err("Error while tracing transaction"),
+        };
+        evm.ctx().db().commit(output.state);
+        results.push(trace_result);
+    }
+    Ok(results)
+}
+
+#[cfg(test)]
+mod tests {
+    use revm::context::TransactTo;
+    use revm::database::EmptyDB;
+
+    use crate::db::types::AddressED;
+    use revm::primitives::{hex, Address, B256, U256};
+
+    use super::*;
+
+    // Stores 1 at slot 0, emits an empty log and deploys a contract that returns slot 0
+    const INIT_CODE: &str = "600160005560006000a0600b6016600039600b6000f360005460005260206000f3";
+
+    fn get_txes() -> Vec<TxEnv> {
+        let from = Address::repeat_byte(0x11);
+        let create = TxEnv {
+            chain_id: Some(331337),
+            caller: from,
+            kind: TransactTo::Create,
+            data: hex::decode(INIT_CODE).unwrap().into(),
+            nonce: 0,
+            gas_limit: 1_000_000,
+            gas_price: 0,
+            ..Default::default()
+        };
+        let call = TxEnv {
+            kind: TransactTo::Call(from.create(0)),
+            data: Bytes::new(),
+            nonce: 1,
+            ..create.clone()
+        };
+        vec![create, call]
+    }
+
+    fn trace(trace_start: usize, tracer: TracerType) -> Vec<TraceResult> {
+        trace_txes(
+            CacheDB::new(EmptyDB::default()),
+            BlockEnv::default(),
+            None,
+            get_txes()
+                .into_iter()
+                .map(|tx| (tx, BitcoinDataED::default()))
+                .collect(),
+            trace_start,
+            &tracer,
+            None,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_call_tracer() {
+        let results = trace(
+            0,
+            TracerType::Call(CallTracerConfig {
+                only_top_call: false,
+                with_log: true,
+            }),
+        );
+        assert_eq!(results.len(), 2);
+
+        let TraceResult::Call(create) = &results[0] else {
+            panic!("expected call frame");
+        };
+        assert_eq!(create.call_type, "CREATE");
+        assert_eq!(
+            create.to,
+            Some(AddressED(Address::repeat_byte(0x11).create(0)))
+        );
+        assert_eq!(create.gas, 1_000_000);
+        assert_eq!(create.logs.len(), 1);
+
+        let TraceResult::Call(call) = &results[1] else {
+            panic!("expected call frame");
+        };
+        assert_eq!(call.call_type, "CALL");
+        assert!(call.error.is_none());
+        assert_eq!(call.output, Bytes::from(U256::from(1).to_be_bytes::<32>()));
+    }
+
+    #[test]
+    fn test_struct_log_tracer() {
+        let results = trace(1, TracerType::StructLog(StructLogConfig::default()));
+        assert_eq!(results.len(), 1);
+
+        let TraceResult::StructLog(result) = &results[0] else {
+            panic!("expected struct logs");
+        };
+        assert!(!result.failed);
+        let ops = result
+            .struct_logs
+            .iter()
+            .map(|x| x.op.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            ops,
+            vec!["PUSH1", "SLOAD", "PUSH1", "MSTORE", "PUSH1", "PUSH1", "RETURN"]
+        );
+        let sload = &result.struct_logs[1];
+        assert_eq!(sload.depth, 1);
+        assert!(sload.gas_cost > 0);
+        assert_eq!(
+            sload.storage.as_ref().unwrap()[&hex::encode(B256::ZERO)],
+            hex::encode(B256::from(U256::from(1)))
+        );
+        assert!(result.struct_logs[0].memory.is_none());
+    }
+
+    #[test]
+    fn test_prestate_tracer() {
+        let results = trace(
+            0,
+            TracerType::Prestate(PrestateTracerConfig { diff_mode: true }),
+        );
+        let TraceResult::Prestate(PrestateResult::Diff { pre, post }) = &results[0] else {
+            panic!("expected prestate diff");
+        };
+        let contract = format!("0x{:x}", Address::repeat_byte(0x11).create(0));
+        // Contract doesn't exist before the deployment
+        assert!(!pre.contains_key(&contract));
+        assert!(!post[&contract].code.is_empty());
+        assert_eq!(post[&contract].storage.len(), 1);
+
+        // Second transaction sees the state of the first one
+        let TraceResult::Prestate(PrestateResult::Diff { pre, .. }) = &results[1] else {
+            panic!("expected prestate diff");
+        };
+        assert_eq!(
+            pre[&format!("0x{:x}", Address::repeat_byte(0x11))].nonce,
+            Some(1)
+        );
+    }
+}
@@ -0,0 +1,220 @@
+use std::collections::BTreeMap;
+
+use revm::primitives::{Address, Bytes, B256, KECCAK_EMPTY, U256};
+use revm::Database;
+use revm_state::{AccountInfo, EvmState};
+use serde::Serialize;
+
+use crate::db::types::{B256ED, U256ED};
+use crate::evm::tracers::bytes_to_hex;
+
+/// Options of the prestateTracer, same as geth
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PrestateTracerConfig {
+    /// Returns the state before and after the transaction for modified accounts only
+    pub diff_mode: bool,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct PrestateAccount {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub balance: Option<U256ED>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub nonce: Option<u64>,
+    #[serde(
+        serialize_with = "bytes_to_hex",
+        skip_serializing_if = "<[u8]>::is_empty"
+    )]
+    pub code: Bytes,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub storage: BTreeMap<String, B256ED>,
+}
+
+/// Output of the prestateTracer, accounts are keyed by their hex address
+#[derive(Debug, Clone, Serialize)]
+#[serde(untagged)]
+pub enum PrestateResult {
+    Prestate(BTreeMap<String, PrestateAccount>),
+    Diff {
+        pre: BTreeMap<String, PrestateAccount>,
+        post: BTreeMap<String, PrestateAccount>,
+    },
+}
+
+fn get_account_key(address: &Address) -> String {
+    format!("0x{:x}", address)
+}
+
+fn get_slot_key(slot: &U256) -> String {
+    format!("0x{:x}", B256::from(*slot))
+}
+
+fn get_code<D: Database>(db: &mut D, info: &AccountInfo) -> Result<Bytes, D::Error> {
+    if let Some(code) = &info.code {
+        return Ok(code.original_bytes());
+    }
+    if info.code_hash == KECCAK_EMPTY || info.code_hash == B256::ZERO {
+        return Ok(Bytes::new());
+    }
+    Ok(db.code_by_hash(info.code_hash)?.original_bytes())
+}
+
+fn get_account<D: Database>(db: &mut D, info: &AccountInfo) -> Result<PrestateAccount, D::Error> {
+    Ok(PrestateAccount {
+        balance: Some(U256ED::from_u256(info.balance)),
+        nonce: (info.nonce > 0).then_some(info.nonce),
+        code: get_code(db, info)?,
+        storage: BTreeMap::new(),
+    })
+}
+
+/// Builds the prestateTracer output from the state changes of a transaction
+///
+/// db should still have the state before the transaction, i.e. changes are not committed yet
+pub fn get_prestate<D: Database>(
+    db: &mut D,
+    state: &EvmState,
+    config: &PrestateTracerConfig,
+) -> Result<PrestateResult, D::Error> {
+    let mut pre = BTreeMap::new();
+    let mut post = BTreeMap::new();
+    for (address, account) in state.iter() {
+        let pre_info = db.basic(*address)?;
+        let mut pre_account = match &pre_info {
+            Some(info) => get_account(db, info)?,
+            None => PrestateAccount {
+                balance: Some(U256ED::from_u256(U256::ZERO)),
+                ..Default::default()
+            },
+        };
+
+        if !config.diff_mode {
+            for (slot, value) in account.storage.iter() {
+                pre_account.storage.insert(
+                    get_slot_key(slot),
+                    B256ED::from_b256(value.original_value().into()),
+                );
+            }
+            pre.insert(get_account_key(address), pre_account);
+            continue;
+        }
+
+        let pre_info = pre_info.unwrap_or_default();
+        let mut post_account = PrestateAccount::default();
+        let mut modified = false;
+        if account.info.balance != pre_info.balance {
+            post_account.balance = Some(U256ED::from_u256(account.info.balance));
+            modified = true;
+        }
+        if account.info.nonce != pre_info.nonce {
+            post_account.nonce = Some(account.info.nonce);
+            modified = true;
+        }
+        if account.info.code_hash != pre_info.code_hash {
+            post_account.code = get_code(db, &account.info)?;
+            modified = true;
+        }
+        for (slot, value) in account.storage.iter() {
+            if !value.is_changed() {
+                continue;
+            }
+            modified = true;
+            if !value.original_value().is_zero() {
+                pre_account.storage.insert(
+                    get_slot_key(slot),
+                    B256ED::from_b256(value.original_value().into()),
+                );
+            }
+            if !value.present_value().is_zero() {
+                post_account.storage.insert(
+                    get_slot_key(slot),
+                    B256ED::from_b256(value.present_value().into()),
+                );
+            }
+        }
+        if account.is_selfdestructed() {
+            modified = true;
+        }
+        if !modified {
+            continue;
+        }
+
+        if !pre_info.is_empty() || !pre_account.storage.is_empty() {
+            pre.insert(get_account_key(address), pre_account);
+        }
+        if !account.is_selfdestructed() {
+            post.insert(get_account_key(address), post_account);
+        }
+    }
+
+    if config.diff_mode {
+        Ok(PrestateResult::Diff { pre, post })
+    } else {
+        Ok(PrestateResult::Prestate(pre))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use revm::database::{CacheDB, EmptyDB};
+    use revm_state::{Account, AccountStatus, EvmStorageSlot};
+
+    use super::*;
+
+    #[test]
+    fn test_prestate() {
+        let address = Address::repeat_byte(1);
+        let mut db = CacheDB::new(EmptyDB::default());
+        db.insert_account_info(
+            address,
+            AccountInfo {
+                balance: U256::from(10),
+                nonce: 1,
+                ..Default::default()
+            },
+        );
+        db.insert_account_storage(address, U256::from(1), U256::from(5))
+            .unwrap();
+
+        let mut account = Account::from(AccountInfo {
+            balance: U256::from(10),
+            nonce: 2,
+            ..Default::default()
+        });
+        account.status = AccountStatus::Touched;
+        account.storage.insert(
+            U256::from(1),
+            EvmStorageSlot::new_changed(U256::from(5), U256::from(6)),
+        );
+        account
+            .storage
+            .insert(U256::from(2), EvmStorageSlot::new(U256::from(7)));
+        let mut state = EvmState::default();
+        state.insert(address, account);
+
+        let PrestateResult::Prestate(pre) =
+            get_prestate(&mut db, &state, &PrestateTracerConfig::default()).unwrap()
+        else {
+            panic!("expected prestate");
+        };
+        let pre_account = &pre[&get_account_key(&address)];
+        assert_eq!(pre_account.nonce, Some(1));
+        assert_eq!(pre_account.storage.len(), 2);
+
+        let PrestateResult::Diff { pre, post } =
+            get_prestate(&mut db, &state, &PrestateTracerConfig { diff_mode: true }).unwrap()
+        else {
+            panic!("expected diff");
+        };
+        let pre_account = &pre[&get_account_key(&address)];
+        let post_account = &post[&get_account_key(&address)];
+        // Only the changed slot is included
+        assert_eq!(pre_account.storage.len(), 1);
+        assert_eq!(post_account.nonce, Some(2));
+        assert!(post_account.balance.is_none());
+        assert_eq!(
+            post_account.storage[&get_slot_key(&U256::from(1))],
+            B256ED::from_b256(U256::from(6).into())
+        );
+    }
+}
@@ -0,0 +1,171 @@
+use std::collections::{BTreeMap, HashMap};
+
+use revm::bytecode::opcode::{self, OpCode};
+use revm::context::{ContextTr, JournalTr};
+use revm::interpreter::interpreter::EthInterpreter;
+use revm::interpreter::interpreter_types::{InputsTr, Jumps, LoopControl, MemoryTr, ReturnData};
+use revm::interpreter::Interpreter;
+use revm::primitives::{hex, Address, Bytes, B256};
+use revm::Inspector;
+use serde::Serialize;
+
+use crate::db::types::U256ED;
+use crate::evm::tracers::bytes_to_hex;
+
+/// Options of the default struct log tracer, same as geth
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StructLogConfig {
+    pub disable_stack: bool,
+    pub disable_storage: bool,
+    pub enable_memory: bool,
+    pub enable_return_data: bool,
+}
+
+/// Single opcode execution in the output of the struct log tracer
+#[derive(Debug, Clone, Serialize)]
+pub struct StructLog {
+    pub pc: u64,
+    pub op: String,
+    pub gas: u64,
+    #[serde(rename = "gasCost")]
+    pub gas_cost: u64,
+    pub depth: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stack: Option<Vec<U256ED>>,
+    #[serde(rename = "returnData", skip_serializing_if = "Option::is_none")]
+    pub return_data: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub memory: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub storage: Option<BTreeMap<String, String>>,
+    #[serde(skip_serializing_if = "is_zero")]
+    pub refund: u64,
+}
+
+/// Output of the struct log tracer
+#[derive(Debug, Clone, Serialize)]
+pub struct StructLogResult {
+    pub gas: u64,
+    pub failed: bool,
+    #[serde(rename = "returnValue", serialize_with = "bytes_to_hex")]
+    pub return_value: Bytes,
+    #[serde(rename = "structLogs")]
+    pub struct_logs: Vec<StructLog>,
+}
+
+fn is_zero(value: &u64) -> bool {
+    *value == 0
+}
+
+/// Records every executed opcode, geth default tracer equivalent
+pub struct StructLogTracer {
+    config: StructLogConfig,
+    logs: Vec<StructLog>,
+    /// Storage slots accessed so far by each contract
+    storage: HashMap<Address, BTreeMap<B256, B256>>,
+    /// Remaining gas before the current opcode
+    gas_before: u64,
+    /// Contract and slot of the current opcode if it's an SLOAD
+    pending_sload: Option<(Address, B256)>,
+}
+
+impl StructLogTracer {
+    pub fn new(config: StructLogConfig) -> Self {
+        StructLogTracer {
+            config,
+            logs: Vec::new(),
+            storage: HashMap::new(),
+            gas_before: 0,
+            pending_sload: None,
+        }
+    }
+
+    pub fn into_result(self, gas_used: u64, failed: bool, return_value: Bytes) -> StructLogResult {
+        StructLogResult {
+            gas: gas_used,
+            failed,
+            return_value,
+            struct_logs: self.logs,
+        }
+    }
+
+    fn set_storage(&mut self, address: Address, key: B256, value: B256) {
+        self.storage.entry(address).or_default().insert(key, value);
+        if let Some(log) = self.logs.last_mut() {
+            log.storage = Some(
+                self.storage[&address]
+                    .iter()
+                    .map(|(k, v)| (hex::encode(k), hex::encode(v)))
+                    .collect(),
+            );
+        }
+    }
+}
+
+impl<CTX: ContextTr> Inspector<CTX, EthInterpreter> for StructLogTracer {
+    fn step(&mut self, interp: &mut Interpreter<EthInterpreter>, context: &mut CTX) {
+        let op = interp.bytecode.opcode();
+        let stack = interp.stack.data();
+        self.gas_before = interp.control.gas().remaining();
+
+        let memory = if self.config.enable_memory {
+            let memory = interp.memory.slice(0..interp.memory.size());
+            Some(memory.chunks(32).map(hex::encode).collect())
+        } else {
+            None
+        };
+        let return_data = interp.return_data.buffer();
+        let return_data = (self.config.enable_return_data && !return_data.is_empty())
+            .then(|| format!("0x{}", hex::encode(return_data)));
+
+        self.logs.push(StructLog {
+            pc: interp.bytecode.pc() as u64,
+            op: OpCode::new(op)
+                .map(|x| x.as_str().to_string())
+                .unwrap_or_else(|| format!("opcode 0x{:x} not defined", op)),
+            gas: self.gas_before,
+            gas_cost: 0,
+            depth: context.journal().depth() as u64,
+            error: None,
+            stack: (!self.config.disable_stack)
+                .then(|| stack.iter().copied().map(U256ED::from_u256).collect()),
+            return_data,
+            memory,
+            storage: None,
+            refund: interp.control.gas().refunded().max(0) as u64,
+        });
+
+        self.pending_sload = None;
+        if self.config.disable_storage || stack.is_empty() {
+            return;
+        }
+        let address = interp.input.target_address();
+        let key = B256::from(stack[stack.len() - 1]);
+        if op == opcode::SLOAD {
+            // Loaded value is only known after the opcode is executed
+            self.pending_sload = Some((address, key));
+        } else if op == opcode::SSTORE && stack.len() >= 2 {
+            let value = B256::from(stack[stack.len() - 2]);
+            self.set_storage(address, key, value);
+        }
+    }
+
+    fn step_end(&mut self, interp: &mut Interpreter<EthInterpreter>, _context: &mut CTX) {
+        let result = interp.control.instruction_result();
+        let gas_after = interp.control.gas().remaining();
+        if let Some(log) = self.logs.last_mut() {
+            log.gas_cost = self.gas_before.saturating_sub(gas_after);
+            if result.is_error() {
+                log.error = Some(format!("{:?}", result));
+            }
+        }
+
+        if let Some((address, key)) = self.pending_sload.take() {
+            if let Some(value) = interp.stack.data().last() {
+                self.set_storage(address, key, B256::from(*value));
+            }
+        }
+    }
+}
@@ -1,8 +1,11 @@
 use std::cmp::max;
 
+use alloy_sol_types::{Panic, Revert, SolError};
 use revm::context::result::{ExecutionResult, HaltReason, OutOfGasError, Output, SuccessReason};
 use revm::primitives::{keccak256, Address};
 
+use crate::brc20_controller::decode_brc20_controller_error;
+
 static GAS_PER_BYTE: u64 = 12000; // 12M gas per byte
 static MINIMUM_GAS_LIMIT: u64 = 384000; // GAS_PER_BYTE * 32
 
@@ -13,6 +16,15 @@ pub fn get_gas_limit(inscription_byte_len: u64) -> u64 {
     )
 }
 
+/// Returns the minimum inscription byte length whose gas limit is at least the given gas,
+/// inverse of get_gas_limit
+pub fn get_inscription_byte_len(gas_limit: u64) -> u64 {
+    if gas_limit <= MINIMUM_GAS_LIMIT {
+        return 0;
+    }
+    gas_limit.div_ceil(GAS_PER_BYTE)
+}
+
 pub fn get_evm_address(pkscript: &str) -> Address {
     let mut address = [0u8; 20];
     let pkscript_hash = keccak256(hex::decode(pkscript).unwrap());
@@ -56,8 +68,8 @@ pub fn get_result_reason(result: &ExecutionResult) -> String {
         },
         ExecutionResult::Revert {
             gas_used: _,
-            output: _,
-        } => "".to_string(),
+            output,
+        } => get_revert_reason(output).unwrap_or_default(),
         ExecutionResult::Halt {
             gas_used: _,
             reason,
@@ -98,6 +110,17 @@ pub fn get_result_reason(result: &ExecutionResult) -> String {
     }
 }
 
+/// Decodes the revert output, Error(string), Panic(uint256) and BRC20_Controller errors are supported
+pub fn get_revert_reason(output: &[u8]) -> Option<String> {
+    if let Ok(revert) = Revert::abi_decode(output, true) {
+        return Some(revert.reason);
+    }
+    if let Ok(panic) = Panic::abi_decode(output, true) {
+        return Some(panic.to_string());
+    }
+    decode_brc20_controller_error(output)
+}
+
 pub fn get_contract_address(result: &ExecutionResult) -> Option<Address> {
     match result {
         ExecutionResult::Success {
@@ -125,7 +148,13 @@ pub fn get_contract_address(result: &ExecutionResult) -> Option<Address> {
 mod tests {
     use std::str::FromStr;
 
+    use alloy_sol_types::{PanicKind, SolInterface};
+    use revm::primitives::{Bytes, U256};
+
     use super::*;
+    use crate::brc20_controller::IBRC20_ControllerErrors::{
+        BRC20InsufficientBalance, IBRC20_ControllerErrorsErrors,
+    };
 
     #[test]
     fn test_get_evm_address() {
@@ -136,4 +165,48 @@ mod tests {
             Address::from_str("0x7f217045127859b40ef1a27a5bfe73aa16687467").unwrap(),
         );
     }
+
+    #[test]
+    fn test_get_inscription_byte_len() {
+        assert_eq!(get_inscription_byte_len(0), 0);
+        assert_eq!(get_inscription_byte_len(MINIMUM_GAS_LIMIT), 0);
+        assert_eq!(get_inscription_byte_len(MINIMUM_GAS_LIMIT + 1), 33);
+        assert_eq!(get_inscription_byte_len(GAS_PER_BYTE * 100), 100);
+        assert_eq!(get_inscription_byte_len(GAS_PER_BYTE * 100 + 1), 101);
+        for gas in [1, 21000, 500000, 12345678] {
+            let inscription_byte_len = get_inscription_byte_len(gas);
+            assert!(get_gas_limit(inscription_byte_len) >= gas);
+            assert!(inscription_byte_len == 0 || get_gas_limit(inscription_byte_len - 1) < gas);
+        }
+    }
+
+    #[test]
+    fn test_get_revert_reason() {
+        assert_eq!(
+            get_revert_reason(&Revert::from("not allowed").abi_encode()),
+            Some("not allowed".to_string())
+        );
+        assert_eq!(
+            get_revert_reason(&Panic::from(PanicKind::UnderOverflow).abi_encode()),
+            Some("panic: arithmetic underflow or overflow (0x11)".to_string())
+        );
+
+        let error =
+            IBRC20_ControllerErrorsErrors::BRC20InsufficientBalance(BRC20InsufficientBalance {
+                ticker: Bytes::from("ordi".as_bytes()),
+                sender: Address::repeat_byte(0x11),
+                balance: U256::from(1),
+                needed: U256::from(2),
+            });
+        assert_eq!(
+            get_revert_reason(&error.abi_encode()),
+            Some(format!(
+                "BRC20InsufficientBalance(ticker: ordi, sender: {}, balance: 1, needed: 2)",
+                Address::repeat_byte(0x11)
+            ))
+        );
+
+        assert_eq!(get_revert_reason(&[0x12, 0x34, 0x56, 0x78]), None);
+        assert_eq!(get_revert_reason(&[]), None);
+    }
 }
@@ -5,14 +5,18 @@ mod brc20_controller;
 pub mod evm;
 
 mod db;
-use db::DB;
+use db::{DB, DEFAULT_MAX_REORG_DEPTH};
 
 mod server;
-use evm::check_bitcoin_rpc_status;
+use evm::{check_bitcoin_data_provider_status, BRC20_BALANCE_PROVIDER};
 use server::{start_rpc_server, ServerInstance};
 
 lazy_static::lazy_static! {
     static ref BRC20_PROG_RPC_SERVER_URL: String = std::env::var("BRC20_PROG_RPC_SERVER_URL").unwrap_or("127.0.0.1:18545".to_string());
+    static ref BRC20_PROG_INDEXER_SERVER_URL: Option<String> = std::env::var("BRC20_PROG_INDEXER_SERVER_URL").ok().filter(|x| !x.is_empty());
+    static ref BRC20_PROG_ARCHIVE_MODE: bool = std::env::var("BRC20_PROG_ARCHIVE_MODE").map(|x| x == "true").unwrap_or(false);
+    // 0 keeps the changes of all blocks, so reorgs of any depth are supported
+    static ref BRC20_PROG_MAX_REORG_DEPTH: Option<u64> = std::env::var("BRC20_PROG_MAX_REORG_DEPTH").ok().and_then(|x| x.parse::<u64>().ok()).map_or(Some(DEFAULT_MAX_REORG_DEPTH), |x| (x != 0).then_some(x));
 }
 
 #[tokio::main]
@@ -23,8 +27,29 @@ async fn main() -> Result<(), Box<dyn Error>> {
             .finish(),
     )?;
 
-    let instance = ServerInstance::new(DB::new(&Path::new("target").join("db")).unwrap());
+    let instance = ServerInstance::new(
+        DB::new_with_options(
+            &Path::new("target").join("db"),
+            *BRC20_PROG_ARCHIVE_MODE,
+            *BRC20_PROG_MAX_REORG_DEPTH,
+        )
+        .unwrap(),
+    );
     println!("--- Database ---");
+    println!(
+        "Archive mode: {}",
+        if *BRC20_PROG_ARCHIVE_MODE {
+            "Enabled"
+        } else {
+            "Disabled"
+        }
+    );
+    println!(
+        "Max reorg depth: {}",
+        BRC20_PROG_MAX_REORG_DEPTH
+            .map(|x| x.to_string())
+            .unwrap_or("Unlimited".to_string())
+    );
     println!(
         "Latest block number: {}",
         instance.get_latest_block_height()
@@ -38,9 +63,21 @@ async fn main() -> Result<(), Box<dyn Error>> {
     );
     println!("");
     println!("--- Services ---");
+    let (bitcoin_data_provider_name, bitcoin_data_provider_status) =
+        check_bitcoin_data_provider_status();
     println!(
-        "Bitcoin RPC status: {}",
-        if check_bitcoin_rpc_status() {
+        "Bitcoin data provider ({}): {}",
+        bitcoin_data_provider_name,
+        if bitcoin_data_provider_status {
+            "OK"
+        } else {
+            "Error"
+        }
+    );
+    println!(
+        "BRC20 balance provider ({}): {}",
+        BRC20_BALANCE_PROVIDER.name(),
+        if BRC20_BALANCE_PROVIDER.check_status() {
             "OK"
         } else {
             "Error"
@@ -52,7 +89,21 @@ async fn main() -> Result<(), Box<dyn Error>> {
         "Started JSON-RPC server on {}",
         BRC20_PROG_RPC_SERVER_URL.as_str()
     );
-    let handle = start_rpc_server(BRC20_PROG_RPC_SERVER_URL.to_string(), instance).await?;
-    handle.stopped().await;
+    match BRC20_PROG_INDEXER_SERVER_URL.as_ref() {
+        Some(indexer_url) => println!("Started indexer JSON-RPC server on {}", indexer_url),
+        None => println!("Indexer methods are served on the JSON-RPC server"),
+    }
+    let (handle, indexer_handle) = start_rpc_server(
+        BRC20_PROG_RPC_SERVER_URL.to_string(),
+        BRC20_PROG_INDEXER_SERVER_URL.clone(),
+        instance,
+    )
+    .await?;
+    match indexer_handle {
+        Some(indexer_handle) => {
+            tokio::join!(handle.stopped(), indexer_handle.stopped());
+        }
+        None => handle.stopped().await,
+    }
     Ok(())
 }
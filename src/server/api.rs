@@ -1,21 +1,27 @@
+use std::collections::HashMap;
 use std::str::FromStr;
 
 use alloy_primitives::hex::FromHex;
 use alloy_primitives::{Address, Bytes, FixedBytes, B256, U256};
-use jsonrpsee::core::RpcResult;
+use jsonrpsee::core::{RpcResult, SubscriptionResult};
 use jsonrpsee::proc_macros::rpc;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use serde_hex::{CompactPfx, SerHex};
 
+use super::filters::FilterChanges;
 use super::DEV_ADDRESS;
-use crate::db::types::{BlockResponseED, LogResponseED, TxED, TxReceiptED};
+use crate::db::types::{
+    AccountProofED, BitcoinDataED, BlockResponseED, LogResponseED, TxED, TxReceiptED,
+};
 use crate::db::B256ED;
+use crate::evm::tracers::{BlockTraceResult, TraceResult};
 
 #[rpc(server)]
 pub trait Brc20ProgApi {
     ///
     ///
     /// BRC20 Methods, these methods are intended for the indexers
-    /// TODO: Authentication!
+    /// Methods that change the state are in Brc20ProgIndexerApi
     ///
     ///
 
@@ -25,75 +31,21 @@ pub trait Brc20ProgApi {
         Ok(env!("CARGO_PKG_VERSION").to_string())
     }
 
-    /// Mines blocks for the given block count at the timestamp
-    #[method(name = "brc20_mine")]
-    async fn mine(&self, block_count: u64, timestamp: u64) -> RpcResult<()>;
-
-    #[method(name = "brc20_deploy")]
-    async fn deploy_contract(
-        &self,
-        from_pkscript: String,
-        data: BytesWrapper,
-        timestamp: u64,
-        hash: B256Wrapper,
-        tx_idx: u64,
-        inscription_id: Option<String>,
-        inscription_byte_len: Option<u64>,
-    ) -> RpcResult<TxReceiptED>;
-
-    #[method(name = "brc20_call")]
-    async fn call_contract(
+    /// Simulates a brc20_call, or a brc20_deploy if no contract is given, on the pending state
+    /// and returns the inscription length it needs
+    #[method(name = "brc20_estimateInscriptionSize")]
+    async fn estimate_inscription_size(
         &self,
         from_pkscript: String,
         contract_address: Option<AddressWrapper>,
         contract_inscription_id: Option<String>,
         data: BytesWrapper,
-        timestamp: u64,
-        hash: B256Wrapper,
-        tx_idx: u64,
-        inscription_id: Option<String>,
-        inscription_byte_len: Option<u64>,
-    ) -> RpcResult<TxReceiptED>;
-
-    /// Deposits brc20 tokens to the given address
-    #[method(name = "brc20_deposit")]
-    async fn deposit(
-        &self,
-        to_pkscript: String,
-        ticker: String,
-        amount: U256Wrapper,
-        timestamp: u64,
-        hash: B256Wrapper,
-        tx_idx: u64,
-        inscription_id: Option<String>,
-    ) -> RpcResult<TxReceiptED>;
-
-    /// Withdraws brc20 tokens from the given address
-    #[method(name = "brc20_withdraw")]
-    async fn withdraw(
-        &self,
-        from_pkscript: String,
-        ticker: String,
-        amount: U256Wrapper,
-        timestamp: u64,
-        hash: B256Wrapper,
-        tx_idx: u64,
-        inscription_id: Option<String>,
-    ) -> RpcResult<TxReceiptED>;
+    ) -> RpcResult<InscriptionSizeEstimate>;
 
     /// Checks BRC20 balance for given address
     #[method(name = "brc20_balance")]
     async fn balance(&self, pkscript: String, ticker: String) -> RpcResult<String>;
 
-    /// Initialises the BRC20 prog module with the given genesis hash and timestamp
-    #[method(name = "brc20_initialise")]
-    async fn initialise(
-        &self,
-        genesis_hash: B256Wrapper,
-        genesis_timestamp: u64,
-        genesis_height: u64,
-    ) -> RpcResult<()>;
-
     /// Retrieves transaction receipt for given inscription id
     #[method(name = "brc20_getTxReceiptByInscriptionId")]
     async fn get_transaction_receipt_by_inscription_id(
@@ -101,26 +53,13 @@ pub trait Brc20ProgApi {
         inscription_id: String,
     ) -> RpcResult<Option<TxReceiptED>>;
 
-    /// Finalises the block with the given parameters
-    #[method(name = "brc20_finaliseBlock")]
-    async fn finalise_block(
-        &self,
-        timestamp: u64,
-        hash: B256Wrapper,
-        block_tx_count: u64,
-    ) -> RpcResult<()>;
-
-    /// Reverts the state to the given latest valid block number
-    #[method(name = "brc20_reorg")]
-    async fn reorg(&self, latest_valid_block_number: u64) -> RpcResult<()>;
-
-    /// Commits the state to the database
-    #[method(name = "brc20_commitToDatabase")]
-    async fn commit_to_database(&self) -> RpcResult<()>;
+    /// Returns the state hash of the block, indexers with the same state hash agree on the state
+    #[method(name = "brc20_getBlockStateHash")]
+    async fn get_block_state_hash(&self, height: u64) -> RpcResult<Option<B256ED>>;
 
-    /// Clears the caches, if used before committing to the database, data will be lost
-    #[method(name = "brc20_clearCaches")]
-    async fn clear_caches(&self) -> RpcResult<()>;
+    /// Returns the Bitcoin data read by the precompiles while executing the transaction
+    #[method(name = "brc20_getTxBitcoinData")]
+    async fn get_tx_bitcoin_data(&self, tx_hash: B256Wrapper) -> RpcResult<Option<BitcoinDataED>>;
 
     ///
     ///
@@ -164,29 +103,74 @@ pub trait Brc20ProgApi {
     #[method(name = "eth_getLogs")]
     async fn get_logs(&self, filter: GetLogsFilter) -> RpcResult<Vec<LogResponseED>>;
 
+    /// Creates a log filter, changes can be polled with eth_getFilterChanges
+    #[method(name = "eth_newFilter")]
+    async fn new_filter(&self, filter: GetLogsFilter) -> RpcResult<String>;
+
+    /// Creates a filter that reports new block hashes, changes can be polled with eth_getFilterChanges
+    #[method(name = "eth_newBlockFilter")]
+    async fn new_block_filter(&self) -> RpcResult<String>;
+
+    /// Returns logs or block hashes added since the last poll of the given filter
+    #[method(name = "eth_getFilterChanges")]
+    async fn get_filter_changes(&self, filter_id: U256Wrapper) -> RpcResult<FilterChanges>;
+
+    /// Returns all logs matching the given log filter
+    #[method(name = "eth_getFilterLogs")]
+    async fn get_filter_logs(&self, filter_id: U256Wrapper) -> RpcResult<Vec<LogResponseED>>;
+
+    /// Removes the filter with the given id
+    #[method(name = "eth_uninstallFilter")]
+    async fn uninstall_filter(&self, filter_id: U256Wrapper) -> RpcResult<bool>;
+
+    /// Subscribes to new block headers ("newHeads") or logs ("logs") over WebSocket
+    #[subscription(name = "eth_subscribe" => "eth_subscription", unsubscribe = "eth_unsubscribe", item = serde_json::Value)]
+    async fn subscribe(&self, kind: String, filter: Option<GetLogsFilter>) -> SubscriptionResult;
+
     /// Calls a contract with the given parameters
     #[method(name = "eth_call")]
-    async fn call(&self, eth_call: EthCall, block: Option<String>) -> RpcResult<String>;
+    async fn call(
+        &self,
+        eth_call: EthCall,
+        block: Option<String>,
+        state_overrides: Option<HashMap<AddressWrapper, EthCallAccountOverride>>,
+        block_overrides: Option<EthCallBlockOverrides>,
+    ) -> RpcResult<String>;
 
-    /// Estimates the gas for the given transaction
+    /// Estimates the gas for the given transaction with a binary search for the minimum gas
+    /// limit it succeeds with
     #[method(name = "eth_estimateGas")]
     async fn estimate_gas(&self, eth_call: EthCall, block: Option<String>) -> RpcResult<String>;
 
-    /// Estimates the gas for the given transaction
-    #[method(name = "eth_sendTransaction")]
-    async fn send_transaction(&self, eth_call: EthCall) -> RpcResult<B256ED>;
-
     /// Get storage for the given contract and memory location
     #[method(name = "eth_getStorageAt")]
     async fn get_storage_at(
         &self,
         contract: AddressWrapper,
         location: U256Wrapper,
+        block: Option<String>,
     ) -> RpcResult<String>;
 
     /// Returns the bytecode of the contract at the given address
     #[method(name = "eth_getCode")]
-    async fn get_code(&self, contract: AddressWrapper) -> RpcResult<String>;
+    async fn get_code(&self, contract: AddressWrapper, block: Option<String>) -> RpcResult<String>;
+
+    /// Returns the balance of the account at the given address (always 1 ether in BRC20)
+    #[method(name = "eth_getBalance")]
+    async fn get_balance(
+        &self,
+        address: AddressWrapper,
+        block: Option<String>,
+    ) -> RpcResult<String>;
+
+    /// Returns the account and storage proofs of the account at the given address, in EIP-1186 format
+    #[method(name = "eth_getProof")]
+    async fn get_proof(
+        &self,
+        address: AddressWrapper,
+        storage_keys: Vec<U256Wrapper>,
+        block: Option<String>,
+    ) -> RpcResult<AccountProofED>;
 
     /// Returns the transaction receipt for the given transaction hash
     #[method(name = "eth_getTransactionReceipt")]
@@ -215,6 +199,31 @@ pub trait Brc20ProgApi {
         index: u64,
     ) -> RpcResult<Option<TxED>>;
 
+    /// Re-executes the transaction on the state before it and returns its trace
+    #[method(name = "debug_traceTransaction")]
+    async fn trace_transaction(
+        &self,
+        transaction: B256Wrapper,
+        options: Option<TraceOptions>,
+    ) -> RpcResult<TraceResult>;
+
+    /// Traces a call on top of the state at the given block, same as eth_call
+    #[method(name = "debug_traceCall")]
+    async fn trace_call(
+        &self,
+        eth_call: EthCall,
+        block: Option<String>,
+        options: Option<TraceCallOptions>,
+    ) -> RpcResult<TraceResult>;
+
+    /// Re-executes all transactions in the block and returns their traces
+    #[method(name = "debug_traceBlockByNumber")]
+    async fn trace_block_by_number(
+        &self,
+        number: String,
+        options: Option<TraceOptions>,
+    ) -> RpcResult<Vec<BlockTraceResult>>;
+
     ///
     ///
     /// Eth methods with static values
@@ -239,12 +248,6 @@ pub trait Brc20ProgApi {
         Ok("0x0".to_string())
     }
 
-    /// Returns the balance of the account at the given address (0 in BRC20)
-    #[method(name = "eth_getBalance")]
-    async fn get_balance(&self, _address: AddressWrapper, _block: String) -> RpcResult<String> {
-        Ok("0xDE0B6B3A7640000".to_string())
-    }
-
     /// Returns the uncle count of the block at the given block number (0 in BRC20)
     #[method(name = "eth_getUncleCountByBlockNumber")]
     async fn get_uncle_count_by_block_number(&self, _number: u64) -> RpcResult<String> {
@@ -290,22 +293,293 @@ pub trait Brc20ProgApi {
     }
 }
 
+/// Methods that change the state, these are only served on the indexer server if it is enabled
+///
+/// They are a separate module that the public server only merges without an indexer server,
+/// so a new method here is never served publicly by mistake
+#[rpc(server)]
+pub trait Brc20ProgIndexerApi {
+    /// Mines blocks for the given block count at the timestamp
+    #[method(name = "brc20_mine")]
+    async fn mine(&self, block_count: u64, timestamp: u64) -> RpcResult<()>;
+
+    #[method(name = "brc20_deploy")]
+    async fn deploy_contract(
+        &self,
+        from_pkscript: String,
+        data: BytesWrapper,
+        timestamp: u64,
+        hash: B256Wrapper,
+        tx_idx: u64,
+        inscription_id: Option<String>,
+        inscription_byte_len: Option<u64>,
+    ) -> RpcResult<TxReceiptED>;
+
+    #[method(name = "brc20_call")]
+    async fn call_contract(
+        &self,
+        from_pkscript: String,
+        contract_address: Option<AddressWrapper>,
+        contract_inscription_id: Option<String>,
+        data: BytesWrapper,
+        timestamp: u64,
+        hash: B256Wrapper,
+        tx_idx: u64,
+        inscription_id: Option<String>,
+        inscription_byte_len: Option<u64>,
+    ) -> RpcResult<TxReceiptED>;
+
+    /// Deposits brc20 tokens to the given address
+    #[method(name = "brc20_deposit")]
+    async fn deposit(
+        &self,
+        to_pkscript: String,
+        ticker: String,
+        amount: U256Wrapper,
+        timestamp: u64,
+        hash: B256Wrapper,
+        tx_idx: u64,
+        inscription_id: Option<String>,
+    ) -> RpcResult<TxReceiptED>;
+
+    /// Withdraws brc20 tokens from the given address
+    #[method(name = "brc20_withdraw")]
+    async fn withdraw(
+        &self,
+        from_pkscript: String,
+        ticker: String,
+        amount: U256Wrapper,
+        timestamp: u64,
+        hash: B256Wrapper,
+        tx_idx: u64,
+        inscription_id: Option<String>,
+    ) -> RpcResult<TxReceiptED>;
+
+    /// Sets non-module BRC20 balances for the next block, if the balance snapshot is enabled
+    /// Balances are only kept in memory, all of them should be set again after a restart
+    #[method(name = "brc20_setBalances")]
+    async fn set_balances(
+        &self,
+        block_height: u64,
+        block_hash: B256Wrapper,
+        balances: Vec<Brc20Balance>,
+    ) -> RpcResult<()>;
+
+    /// Initialises the BRC20 prog module with the given genesis hash and timestamp
+    #[method(name = "brc20_initialise")]
+    async fn initialise(
+        &self,
+        genesis_hash: B256Wrapper,
+        genesis_timestamp: u64,
+        genesis_height: u64,
+    ) -> RpcResult<()>;
+
+    /// Finalises the block with the given parameters
+    /// If expected_state_hash is given and the state hash of the block is different, the block is discarded
+    #[method(name = "brc20_finaliseBlock")]
+    async fn finalise_block(
+        &self,
+        timestamp: u64,
+        hash: B256Wrapper,
+        block_tx_count: u64,
+        expected_state_hash: Option<B256Wrapper>,
+    ) -> RpcResult<()>;
+
+    /// Adds the transactions in order and finalises the block, nothing is added if any of them fails
+    /// or if expected_state_hash is given and the state hash of the block is different
+    #[method(name = "brc20_addBlock")]
+    async fn add_block(
+        &self,
+        hash: B256Wrapper,
+        timestamp: u64,
+        txes: Vec<BlockTransaction>,
+        expected_state_hash: Option<B256Wrapper>,
+    ) -> RpcResult<Vec<TxReceiptED>>;
+
+    /// Reverts the state to the given latest valid block number
+    #[method(name = "brc20_reorg")]
+    async fn reorg(&self, latest_valid_block_number: u64) -> RpcResult<()>;
+
+    /// Commits the state to the database
+    #[method(name = "brc20_commitToDatabase")]
+    async fn commit_to_database(&self) -> RpcResult<()>;
+
+    /// Clears the caches, if used before committing to the database, data will be lost
+    #[method(name = "brc20_clearCaches")]
+    async fn clear_caches(&self) -> RpcResult<()>;
+
+    /// Estimates the gas for the given transaction
+    #[method(name = "eth_sendTransaction")]
+    async fn send_transaction(&self, eth_call: EthCall) -> RpcResult<B256ED>;
+}
+
+/// Transaction in a brc20_addBlock call, fields are the same as the single transaction methods
+#[derive(Debug, serde::Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum BlockTransaction {
+    Deploy {
+        from_pkscript: String,
+        data: BytesWrapper,
+        inscription_id: Option<String>,
+        inscription_byte_len: Option<u64>,
+    },
+    Call {
+        from_pkscript: String,
+        contract_address: Option<AddressWrapper>,
+        contract_inscription_id: Option<String>,
+        data: BytesWrapper,
+        inscription_id: Option<String>,
+        inscription_byte_len: Option<u64>,
+    },
+    Deposit {
+        to_pkscript: String,
+        ticker: String,
+        amount: U256Wrapper,
+        inscription_id: Option<String>,
+    },
+    Withdraw {
+        from_pkscript: String,
+        ticker: String,
+        amount: U256Wrapper,
+        inscription_id: Option<String>,
+    },
+}
+
+/// Non-module BRC20 balance of a pkscript, used by brc20_setBalances
+#[derive(Debug, serde::Deserialize)]
+pub struct Brc20Balance {
+    pub ticker: BytesWrapper,
+    pub pkscript: BytesWrapper,
+    pub balance: U256Wrapper,
+}
+
+/// Result of brc20_estimateInscriptionSize
+#[derive(Debug, Clone, Serialize)]
+pub struct InscriptionSizeEstimate {
+    #[serde(rename = "gasUsed", with = "SerHex::<CompactPfx>")]
+    pub gas_used: u64,
+    /// Minimum gas limit the transaction succeeds with
+    #[serde(rename = "gasLimit", with = "SerHex::<CompactPfx>")]
+    pub gas_limit: u64,
+    /// Minimum inscription_byte_len whose gas limit is enough for the transaction
+    #[serde(rename = "inscriptionByteLen")]
+    pub inscription_byte_len: u64,
+    /// Bytes to add to an inscription with only the data to reach inscription_byte_len
+    #[serde(rename = "paddingByteLen")]
+    pub padding_byte_len: u64,
+}
+
 #[derive(Debug, serde::Deserialize)]
 pub struct EthCall {
     pub from: AddressWrapper,
     pub to: Option<AddressWrapper>,
     pub data: Option<BytesWrapper>,
     pub input: Option<BytesWrapper>,
+    pub gas: Option<U256Wrapper>,
+    #[serde(rename = "gasPrice")]
+    pub gas_price: Option<U256Wrapper>,
+    pub value: Option<U256Wrapper>,
+    pub nonce: Option<U256Wrapper>,
+    #[serde(rename = "accessList")]
+    pub access_list: Option<Vec<EthCallAccessListItem>>,
+    #[serde(rename = "type")]
+    pub tx_type: Option<U256Wrapper>,
 }
 
+/// Account and storage slots warmed before an EIP-2930 transaction runs
 #[derive(Debug, serde::Deserialize)]
+pub struct EthCallAccessListItem {
+    pub address: AddressWrapper,
+    #[serde(rename = "storageKeys", default)]
+    pub storage_keys: Vec<B256Wrapper>,
+}
+
+/// State override of an account in eth_call, same as geth
+///
+/// state replaces the whole storage of the account, stateDiff only changes the given slots
+#[derive(Debug, Default, serde::Deserialize)]
+pub struct EthCallAccountOverride {
+    pub balance: Option<U256Wrapper>,
+    pub nonce: Option<U256Wrapper>,
+    pub code: Option<BytesWrapper>,
+    pub state: Option<HashMap<B256Wrapper, B256Wrapper>>,
+    #[serde(rename = "stateDiff")]
+    pub state_diff: Option<HashMap<B256Wrapper, B256Wrapper>>,
+}
+
+/// Block overrides in eth_call, same as geth
+#[derive(Debug, Default, serde::Deserialize)]
+pub struct EthCallBlockOverrides {
+    pub number: Option<U256Wrapper>,
+    pub time: Option<U256Wrapper>,
+}
+
+/// Options of the debug_trace methods, same as geth
+///
+/// Struct log tracer is used if tracer is not set
+#[derive(Debug, Default, serde::Deserialize)]
+pub struct TraceOptions {
+    pub tracer: Option<String>,
+    #[serde(rename = "tracerConfig")]
+    pub tracer_config: Option<TracerConfig>,
+    #[serde(rename = "disableStack", default)]
+    pub disable_stack: bool,
+    #[serde(rename = "disableStorage", default)]
+    pub disable_storage: bool,
+    #[serde(rename = "enableMemory", default)]
+    pub enable_memory: bool,
+    #[serde(rename = "enableReturnData", default)]
+    pub enable_return_data: bool,
+}
+
+/// Options of debug_traceCall, same as geth
+#[derive(Debug, Default, serde::Deserialize)]
+pub struct TraceCallOptions {
+    #[serde(flatten)]
+    pub trace_options: TraceOptions,
+    #[serde(rename = "stateOverrides")]
+    pub state_overrides: Option<HashMap<AddressWrapper, EthCallAccountOverride>>,
+    #[serde(rename = "blockOverrides")]
+    pub block_overrides: Option<EthCallBlockOverrides>,
+}
+
+#[derive(Debug, Default, serde::Deserialize)]
+pub struct TracerConfig {
+    #[serde(rename = "onlyTopCall", default)]
+    pub only_top_call: bool,
+    #[serde(rename = "withLog", default)]
+    pub with_log: bool,
+    #[serde(rename = "diffMode", default)]
+    pub diff_mode: bool,
+}
+
+#[derive(Debug, Default, serde::Deserialize)]
 pub struct GetLogsFilter {
     #[serde(rename = "fromBlock")]
     pub from_block: Option<String>,
     #[serde(rename = "toBlock")]
     pub to_block: Option<String>,
-    pub address: Option<AddressWrapper>,
-    pub topics: Option<Vec<B256Wrapper>>,
+    #[serde(rename = "blockHash")]
+    pub block_hash: Option<B256Wrapper>,
+    pub address: Option<ValueOrArray<AddressWrapper>>,
+    pub topics: Option<Vec<Option<ValueOrArray<B256Wrapper>>>>,
+}
+
+/// A single value or an array of values, used for log filter addresses and topics
+#[derive(Debug, serde::Deserialize)]
+#[serde(untagged)]
+pub enum ValueOrArray<T> {
+    Value(T),
+    Array(Vec<T>),
+}
+
+impl<T> ValueOrArray<T> {
+    pub fn into_vec(self) -> Vec<T> {
+        match self {
+            ValueOrArray::Value(value) => vec![value],
+            ValueOrArray::Array(values) => values,
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -333,7 +607,7 @@ impl<'de> Deserialize<'de> for U256Wrapper {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq, Eq, Hash)]
 pub struct B256Wrapper(B256);
 
 impl B256Wrapper {
@@ -353,7 +627,7 @@ impl<'de> Deserialize<'de> for B256Wrapper {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq, Eq, Hash)]
 pub struct AddressWrapper(Address);
 
 impl AddressWrapper {
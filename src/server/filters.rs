@@ -0,0 +1,235 @@
+use std::collections::HashMap;
+use std::ops::RangeInclusive;
+use std::time::{Duration, Instant};
+
+use revm::primitives::{Address, B256, U256};
+use serde::Serialize;
+
+use crate::db::types::{LogResponseED, B256ED};
+
+/// Filters that are not polled for this long are removed, same as geth
+pub const FILTER_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+
+/// Log filter criteria, block numbers set to None follow the latest block
+///
+/// Empty addresses match any address, and each topic position matches any of its topics,
+/// or any topic if it's empty
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogFilter {
+    pub from_block: Option<u64>,
+    pub to_block: Option<u64>,
+    pub addresses: Vec<Address>,
+    pub topics: Vec<Vec<B256>>,
+}
+
+impl LogFilter {
+    /// Restricts a block range to the blocks covered by this filter
+    ///
+    /// Returns None if there are no blocks left in the range
+    pub fn clamp_range(&self, range: RangeInclusive<u64>) -> Option<RangeInclusive<u64>> {
+        let from = (*range.start()).max(self.from_block.unwrap_or(0));
+        let to = (*range.end()).min(self.to_block.unwrap_or(u64::MAX));
+        if from > to {
+            return None;
+        }
+        Some(from..=to)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FilterType {
+    Logs(LogFilter),
+    Blocks,
+}
+
+/// Result of eth_getFilterChanges, either logs or block hashes depending on the filter type
+#[derive(Debug, Clone, Serialize)]
+#[serde(untagged)]
+pub enum FilterChanges {
+    Logs(Vec<LogResponseED>),
+    Hashes(Vec<B256ED>),
+}
+
+struct Filter {
+    filter_type: FilterType,
+    /// First block that hasn't been returned by eth_getFilterChanges yet
+    next_block: u64,
+    last_poll: Instant,
+}
+
+/// Keeps track of installed filters and their cursors
+///
+/// Filter IDs are random 128-bit numbers like in geth, so clients on the public server
+/// can't guess the filters of other clients
+pub struct FilterManager {
+    filters: HashMap<U256, Filter>,
+}
+
+impl FilterManager {
+    pub fn new() -> Self {
+        FilterManager {
+            filters: HashMap::new(),
+        }
+    }
+
+    /// Installs a new filter, changes are reported starting from the block after latest_block
+    pub fn new_filter(&mut self, filter_type: FilterType, latest_block: u64) -> U256 {
+        self.remove_expired();
+
+        let mut filter_id = U256::from(rand::random::<u128>());
+        while self.filters.contains_key(&filter_id) {
+            filter_id = U256::from(rand::random::<u128>());
+        }
+        self.filters.insert(
+            filter_id,
+            Filter {
+                filter_type,
+                next_block: latest_block + 1,
+                last_poll: Instant::now(),
+            },
+        );
+        filter_id
+    }
+
+    /// Returns the filter type and the block range that wasn't reported yet
+    ///
+    /// Range is empty if there are no new blocks since the last poll,
+    /// advance should be called after the changes in the range are returned
+    pub fn poll(
+        &mut self,
+        filter_id: U256,
+        latest_block: u64,
+    ) -> Option<(FilterType, RangeInclusive<u64>)> {
+        self.remove_expired();
+
+        let filter = self.filters.get_mut(&filter_id)?;
+        filter.last_poll = Instant::now();
+        Some((filter.filter_type.clone(), filter.next_block..=latest_block))
+    }
+
+    /// Moves the cursor past a range returned by poll
+    ///
+    /// Cursor is not moved if it has changed since the poll, e.g. due to a reorg
+    pub fn advance(&mut self, filter_id: U256, range: &RangeInclusive<u64>) {
+        if let Some(filter) = self.filters.get_mut(&filter_id) {
+            if filter.next_block == *range.start() {
+                filter.next_block = filter.next_block.max(range.end() + 1);
+            }
+        }
+    }
+
+    /// Returns the filter type without moving the cursor
+    pub fn get(&mut self, filter_id: U256) -> Option<FilterType> {
+        self.remove_expired();
+
+        let filter = self.filters.get_mut(&filter_id)?;
+        filter.last_poll = Instant::now();
+        Some(filter.filter_type.clone())
+    }
+
+    /// Removes the filter, returns false if it doesn't exist
+    pub fn uninstall(&mut self, filter_id: U256) -> bool {
+        self.remove_expired();
+
+        self.filters.remove(&filter_id).is_some()
+    }
+
+    /// Moves the cursors back so blocks after latest_valid_block_number are reported again
+    pub fn reorg(&mut self, latest_valid_block_number: u64) {
+        for filter in self.filters.values_mut() {
+            filter.next_block = filter.next_block.min(latest_valid_block_number + 1);
+        }
+    }
+
+    fn remove_expired(&mut self) {
+        self.filters
+            .retain(|_, filter| filter.last_poll.elapsed() < FILTER_TIMEOUT);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_block_filter_cursor() {
+        let mut manager = FilterManager::new();
+        let filter_id = manager.new_filter(FilterType::Blocks, 10);
+
+        let (filter_type, range) = manager.poll(filter_id, 10).unwrap();
+        assert_eq!(filter_type, FilterType::Blocks);
+        assert!(range.is_empty());
+        manager.advance(filter_id, &range);
+
+        let (_, range) = manager.poll(filter_id, 12).unwrap();
+        assert_eq!(range, 11..=12);
+
+        // Cursor only moves after advance
+        let (_, range) = manager.poll(filter_id, 12).unwrap();
+        assert_eq!(range, 11..=12);
+        manager.advance(filter_id, &range);
+
+        let (_, range) = manager.poll(filter_id, 12).unwrap();
+        assert!(range.is_empty());
+    }
+
+    #[test]
+    fn test_reorg_rewinds_cursor() {
+        let mut manager = FilterManager::new();
+        let filter_id = manager.new_filter(FilterType::Blocks, 10);
+        let (_, range) = manager.poll(filter_id, 15).unwrap();
+        manager.advance(filter_id, &range);
+
+        manager.reorg(12);
+        let (_, range) = manager.poll(filter_id, 14).unwrap();
+        assert_eq!(range, 13..=14);
+        manager.advance(filter_id, &range);
+
+        // Reorg to a block after the cursor doesn't change anything
+        manager.reorg(20);
+        let (_, range) = manager.poll(filter_id, 14).unwrap();
+        assert!(range.is_empty());
+
+        // Reorg between poll and advance keeps the rewound cursor
+        let (_, range) = manager.poll(filter_id, 16).unwrap();
+        manager.reorg(13);
+        manager.advance(filter_id, &range);
+        let (_, range) = manager.poll(filter_id, 16).unwrap();
+        assert_eq!(range, 14..=16);
+    }
+
+    #[test]
+    fn test_uninstall() {
+        let mut manager = FilterManager::new();
+        let filter_id = manager.new_filter(FilterType::Blocks, 0);
+        let other_filter_id = manager.new_filter(FilterType::Blocks, 0);
+        assert_ne!(filter_id, other_filter_id);
+
+        assert!(manager.uninstall(filter_id));
+        assert!(!manager.uninstall(filter_id));
+        assert!(manager.poll(filter_id, 1).is_none());
+        assert!(manager.get(other_filter_id).is_some());
+    }
+
+    #[test]
+    fn test_log_filter_clamp_range() {
+        let filter = LogFilter {
+            from_block: Some(5),
+            to_block: Some(8),
+            addresses: Vec::new(),
+            topics: Vec::new(),
+        };
+        assert_eq!(filter.clamp_range(1..=10), Some(5..=8));
+        assert_eq!(filter.clamp_range(6..=7), Some(6..=7));
+        assert_eq!(filter.clamp_range(9..=10), None);
+        assert_eq!(filter.clamp_range(1..=4), None);
+
+        let filter = LogFilter {
+            from_block: None,
+            to_block: None,
+            addresses: Vec::new(),
+            topics: Vec::new(),
+        };
+        assert_eq!(filter.clamp_range(3..=4), Some(3..=4));
+    }
+}
@@ -1,29 +1,54 @@
+use std::collections::HashMap;
 use std::error::Error;
 use std::net::SocketAddr;
+use std::sync::Arc;
 
-use alloy_primitives::{Address, Bytes};
+use alloy_primitives::{Address, Bytes, U256};
 use hyper::Method;
-use jsonrpsee::core::{async_trait, RpcResult};
-use jsonrpsee::server::{RpcServiceBuilder, Server, ServerHandle};
+use jsonrpsee::core::{async_trait, RpcResult, SubscriptionResult};
+use jsonrpsee::server::{
+    PendingSubscriptionSink, RpcModule, RpcServiceBuilder, Server, ServerHandle,
+    SubscriptionMessage,
+};
 use jsonrpsee::types::{ErrorObject, ErrorObjectOwned};
+use revm::context_interface::transaction::{AccessList, AccessListItem};
 use revm::primitives::B256;
+use tokio::sync::broadcast::error::RecvError;
 use tower_http::cors::{Any, CorsLayer};
 use tracing::{event, instrument, Level};
 
-use super::api::{AddressWrapper, B256Wrapper, BytesWrapper, EthCall, U256Wrapper};
+use super::api::{
+    AddressWrapper, B256Wrapper, BlockTransaction, Brc20Balance, BytesWrapper, EthCall,
+    EthCallAccountOverride, EthCallBlockOverrides, InscriptionSizeEstimate, TraceCallOptions,
+    TraceOptions, U256Wrapper,
+};
 use crate::brc20_controller::{
     decode_brc20_balance_result, load_brc20_balance_tx, load_brc20_burn_tx, load_brc20_mint_tx,
 };
-use crate::db::types::{BlockResponseED, LogResponseED, TxED, TxReceiptED};
+use crate::db::types::{
+    AccountProofED, BitcoinDataED, BlockResponseED, LogResponseED, TxED, TxReceiptED,
+};
 use crate::db::B256ED;
 use crate::evm::get_evm_address;
+use crate::evm::tracers::{
+    BlockTraceResult, CallTracerConfig, PrestateTracerConfig, StructLogConfig, TraceResult,
+    TracerType,
+};
 use crate::server::api::GetLogsFilter;
+use crate::server::filters::{FilterChanges, FilterType, LogFilter};
 use crate::server::server_instance::ServerInstance;
-use crate::server::types::TxInfo;
-use crate::server::Brc20ProgApiServer;
+use crate::server::subscriptions::{ChainEvent, SentLogs};
+use crate::server::types::{
+    AccountOverride, BlockOverrides, BlockTxInfo, CallOverrides, CallParams, TxInfo,
+};
+use crate::server::{Brc20ProgApiServer, Brc20ProgIndexerApiServer};
+
+/// Highest inscription length tried by brc20_estimateInscriptionSize, around the size limit
+/// of a standard Bitcoin transaction, the gas limit is also capped at RPC_GAS_CAP
+const MAX_INSCRIPTION_BYTE_LEN: u64 = 400_000;
 
 pub struct RpcServer {
-    server_instance: ServerInstance,
+    server_instance: Arc<ServerInstance>,
 }
 
 impl RpcServer {
@@ -39,15 +64,810 @@ impl RpcServer {
                 .map_err(|_| wrap_error_message("Invalid block number"))
         }
     }
-}
 
-fn wrap_error_message(message: &'static str) -> ErrorObject<'static> {
-    event!(Level::ERROR, "Error: {:?}", message);
-    RpcServerError::new(message).into()
+    /// Parses a log filter, "latest" or missing block numbers are kept as None
+    ///
+    /// If blockHash is set, the range only contains that block
+    fn parse_log_filter(&self, filter: GetLogsFilter) -> Result<LogFilter, ErrorObject<'static>> {
+        let (from_block, to_block) = if let Some(block_hash) = &filter.block_hash {
+            if filter.from_block.is_some() || filter.to_block.is_some() {
+                return Err(wrap_error_message(
+                    "blockHash cannot be used together with fromBlock or toBlock",
+                ));
+            }
+            let block_number = self
+                .server_instance
+                .get_block_number_by_hash(block_hash.value())
+                .ok_or(wrap_error_message("Block not found"))?;
+            (Some(block_number), Some(block_number))
+        } else {
+            (
+                self.parse_filter_block_number(filter.from_block)?,
+                self.parse_filter_block_number(filter.to_block)?,
+            )
+        };
+        Ok(LogFilter {
+            from_block,
+            to_block,
+            addresses: filter
+                .address
+                .map(|address| address.into_vec().iter().map(|x| x.value()).collect())
+                .unwrap_or_default(),
+            topics: filter
+                .topics
+                .unwrap_or_default()
+                .into_iter()
+                .map(|topic| {
+                    topic
+                        .map(|topic| topic.into_vec().iter().map(|x| x.value()).collect())
+                        .unwrap_or_default()
+                })
+                .collect(),
+        })
+    }
+
+    /// Parses a filter block number, "latest" or missing block numbers are kept as None
+    fn parse_filter_block_number(
+        &self,
+        number: Option<String>,
+    ) -> Result<Option<u64>, ErrorObject<'static>> {
+        match number {
+            None => Ok(None),
+            Some(number) if number == "latest" => Ok(None),
+            Some(number) => self.parse_block_number(&number).map(Some),
+        }
+    }
+
+    /// Parses the block tag of a state query, "pending" is kept as None for the pending state,
+    /// which includes the transactions of the block that's being built
+    ///
+    /// "latest" or missing tags are the last finalised block, or the pending state if there are
+    /// no blocks yet. Block hashes are also accepted, older blocks require archive mode
+    fn parse_state_block_number(
+        &self,
+        block: Option<String>,
+    ) -> Result<Option<u64>, ErrorObject<'static>> {
+        let block = block.unwrap_or("latest".to_string());
+        match block.as_str() {
+            "latest" => Ok(self.server_instance.get_next_block_height().checked_sub(1)),
+            "pending" => Ok(None),
+            "earliest" => Ok(Some(0)),
+            _ if block.len() == 66 => {
+                let block_hash = block[2..]
+                    .parse::<B256>()
+                    .map_err(|_| wrap_error_message("Invalid block hash"))?;
+                self.server_instance
+                    .get_block_number_by_hash(block_hash)
+                    .map(Some)
+                    .ok_or_else(|| wrap_error_message("Block not found"))
+            }
+            _ => self.parse_block_number(&block).map(Some),
+        }
+    }
+
+    /// Returns the contract of a brc20_call, given by its address or the inscription that deployed it
+    fn get_call_contract_address(
+        &self,
+        contract_address: Option<AddressWrapper>,
+        contract_inscription_id: Option<String>,
+    ) -> Result<Address, ErrorObject<'static>> {
+        match (contract_address, contract_inscription_id) {
+            (Some(contract_address), _) if contract_address.value() == Address::ZERO => {
+                Err(RpcServerError::new("Contract address cannot be zero").into())
+            }
+            (Some(contract_address), _) => Ok(contract_address.value()),
+            (None, Some(contract_inscription_id)) => self
+                .server_instance
+                .get_contract_address_by_inscription_id(contract_inscription_id)
+                .map_err(wrap_error_message),
+            (None, None) => {
+                Err(RpcServerError::new("Contract address or inscription ID is required").into())
+            }
+        }
+    }
+}
+
+/// Selects the tracer from the debug_trace options, default is the struct log tracer
+fn parse_trace_options(options: Option<TraceOptions>) -> Result<TracerType, ErrorObject<'static>> {
+    let options = options.unwrap_or_default();
+    let config = options.tracer_config.unwrap_or_default();
+    match options.tracer.as_deref() {
+        None | Some("") => Ok(TracerType::StructLog(StructLogConfig {
+            disable_stack: options.disable_stack,
+            disable_storage: options.disable_storage,
+            enable_memory: options.enable_memory,
+            enable_return_data: options.enable_return_data,
+        })),
+        Some("callTracer") => Ok(TracerType::Call(CallTracerConfig {
+            only_top_call: config.only_top_call,
+            with_log: config.with_log,
+        })),
+        Some("prestateTracer") => Ok(TracerType::Prestate(PrestateTracerConfig {
+            diff_mode: config.diff_mode,
+        })),
+        Some(_) => Err(wrap_error_message("Unsupported tracer")),
+    }
+}
+
+/// Converts a brc20_addBlock transaction to the transaction it runs, same as the single transaction methods
+fn get_block_tx_info(tx: BlockTransaction) -> Result<BlockTxInfo, ErrorObject<'static>> {
+    Ok(match tx {
+        BlockTransaction::Deploy {
+            from_pkscript,
+            data,
+            inscription_id,
+            inscription_byte_len,
+        } => BlockTxInfo {
+            tx_info: TxInfo {
+                from: get_evm_address(&from_pkscript),
+                to: None,
+                data: data.value().clone(),
+            },
+            contract_inscription_id: None,
+            inscription_id,
+            inscription_byte_len,
+        },
+        BlockTransaction::Call {
+            from_pkscript,
+            contract_address,
+            contract_inscription_id,
+            data,
+            inscription_id,
+            inscription_byte_len,
+        } => {
+            let contract_address = contract_address.map(|x| x.value());
+            if contract_address.is_none() && contract_inscription_id.is_none() {
+                return Err(
+                    RpcServerError::new("Contract address or inscription ID is required").into(),
+                );
+            }
+            if contract_address == Some(Address::ZERO) {
+                return Err(RpcServerError::new("Contract address cannot be zero").into());
+            }
+            BlockTxInfo {
+                tx_info: TxInfo {
+                    from: get_evm_address(&from_pkscript),
+                    to: contract_address,
+                    data: data.value().clone(),
+                },
+                contract_inscription_id: contract_inscription_id
+                    .filter(|_| contract_address.is_none()),
+                inscription_id,
+                inscription_byte_len,
+            }
+        }
+        BlockTransaction::Deposit {
+            to_pkscript,
+            ticker,
+            amount,
+            inscription_id,
+        } => BlockTxInfo {
+            tx_info: load_brc20_mint_tx(ticker, get_evm_address(&to_pkscript), amount.value()),
+            contract_inscription_id: None,
+            inscription_id,
+            inscription_byte_len: Some(u64::MAX),
+        },
+        BlockTransaction::Withdraw {
+            from_pkscript,
+            ticker,
+            amount,
+            inscription_id,
+        } => BlockTxInfo {
+            tx_info: load_brc20_burn_tx(ticker, get_evm_address(&from_pkscript), amount.value()),
+            contract_inscription_id: None,
+            inscription_id,
+            inscription_byte_len: Some(u64::MAX),
+        },
+    })
+}
+
+/// Returns the transaction of eth_call and eth_estimateGas and its other fields
+fn parse_call(call: EthCall) -> Result<(TxInfo, CallParams), ErrorObject<'static>> {
+    let to_u64 = |value: U256Wrapper| {
+        u64::try_from(value.value()).map_err(|_| wrap_error_message("Call value is too large"))
+    };
+    let tx_info = TxInfo {
+        from: call.from.value(),
+        to: call.to.map(|x| x.value()),
+        data: call
+            .data
+            .or(call.input)
+            .map(|x| x.value().clone())
+            .unwrap_or_default(),
+    };
+    let params = CallParams {
+        gas_limit: call.gas.map(to_u64).transpose()?,
+        gas_price: call
+            .gas_price
+            .map(|x| u128::try_from(x.value()))
+            .transpose()
+            .map_err(|_| wrap_error_message("Call value is too large"))?
+            .unwrap_or(0),
+        value: call.value.map(|x| x.value()).unwrap_or_default(),
+        nonce: call.nonce.map(to_u64).transpose()?,
+        access_list: AccessList(
+            call.access_list
+                .unwrap_or_default()
+                .into_iter()
+                .map(|item| AccessListItem {
+                    address: item.address.value(),
+                    storage_keys: item.storage_keys.iter().map(|x| x.value()).collect(),
+                })
+                .collect(),
+        ),
+        tx_type: call
+            .tx_type
+            .map(|x| u8::try_from(x.value()))
+            .transpose()
+            .map_err(|_| wrap_error_message("Invalid transaction type"))?,
+    };
+    Ok((tx_info, params))
+}
+
+/// Converts the eth_call state and block overrides, values that don't fit are rejected
+fn parse_call_overrides(
+    state_overrides: Option<HashMap<AddressWrapper, EthCallAccountOverride>>,
+    block_overrides: Option<EthCallBlockOverrides>,
+) -> Result<CallOverrides, ErrorObject<'static>> {
+    let to_u64 = |value: U256Wrapper| {
+        u64::try_from(value.value()).map_err(|_| wrap_error_message("Override value is too large"))
+    };
+    let to_slots = |slots: HashMap<B256Wrapper, B256Wrapper>| {
+        slots
+            .into_iter()
+            .map(|(slot, value)| (slot.value().into(), value.value().into()))
+            .collect::<HashMap<U256, U256>>()
+    };
+
+    let mut overrides = CallOverrides::default();
+    for (address, account) in state_overrides.unwrap_or_default() {
+        if account.state.is_some() && account.state_diff.is_some() {
+            return Err(wrap_error_message(
+                "state and stateDiff can't be used together for the same account",
+            ));
+        }
+        overrides.accounts.insert(
+            address.value(),
+            AccountOverride {
+                balance: account.balance.map(|x| x.value()),
+                nonce: account.nonce.map(to_u64).transpose()?,
+                code: account.code.map(|x| x.value().clone()),
+                state: account.state.map(to_slots),
+                state_diff: account.state_diff.map(to_slots).unwrap_or_default(),
+            },
+        );
+    }
+    if let Some(block) = block_overrides {
+        overrides.block = BlockOverrides {
+            number: block.number.map(to_u64).transpose()?,
+            time: block.time.map(to_u64).transpose()?,
+        };
+    }
+    Ok(overrides)
+}
+
+/// Returns the geth compatible error for reverted calls, with code 3 and the revert data
+fn get_revert_error(receipt: &TxReceiptED) -> Option<ErrorObject<'static>> {
+    if receipt.transaction_result != "Revert" {
+        return None;
+    }
+    let message = if receipt.reason.is_empty() {
+        "execution reverted".to_string()
+    } else {
+        format!("execution reverted: {}", receipt.reason)
+    };
+    let data = receipt.result_bytes.clone().unwrap_or_default().to_string();
+    Some(ErrorObjectOwned::owned(3, message, Some(data)))
+}
+
+fn wrap_error_message(message: &'static str) -> ErrorObject<'static> {
+    event!(Level::ERROR, "Error: {:?}", message);
+    RpcServerError::new(message).into()
+}
+
+#[async_trait]
+impl Brc20ProgApiServer for RpcServer {
+    #[instrument(skip(self))]
+    async fn balance(&self, pkscript: String, ticker: String) -> RpcResult<String> {
+        event!(Level::INFO, "Checking balance");
+
+        self.server_instance
+            .view_contract(
+                &load_brc20_balance_tx(ticker, get_evm_address(&pkscript)),
+                &CallParams::default(),
+                None,
+                &CallOverrides::default(),
+            )
+            .map(|receipt| {
+                format!(
+                    "0x{:x}",
+                    decode_brc20_balance_result(receipt.result_bytes.as_ref())
+                )
+            })
+            .map_err(wrap_error_message)
+    }
+
+    #[instrument(skip(self))]
+    async fn get_transaction_receipt_by_inscription_id(
+        &self,
+        inscription_id: String,
+    ) -> RpcResult<Option<TxReceiptED>> {
+        event!(Level::INFO, "Getting transaction receipt by inscription id");
+        let receipt = self
+            .server_instance
+            .get_transaction_receipt_by_inscription_id(inscription_id);
+        Ok(receipt)
+    }
+
+    #[instrument(skip(self, data))]
+    async fn estimate_inscription_size(
+        &self,
+        from_pkscript: String,
+        contract_address: Option<AddressWrapper>,
+        contract_inscription_id: Option<String>,
+        data: BytesWrapper,
+    ) -> RpcResult<InscriptionSizeEstimate> {
+        event!(Level::INFO, "Estimating inscription size");
+        let to = if contract_address.is_none() && contract_inscription_id.is_none() {
+            None
+        } else {
+            Some(self.get_call_contract_address(contract_address, contract_inscription_id)?)
+        };
+        let tx_info = TxInfo {
+            from: get_evm_address(&from_pkscript),
+            to,
+            data: data.value().clone(),
+        };
+        let estimate = self
+            .server_instance
+            .estimate_inscription_size(&tx_info, MAX_INSCRIPTION_BYTE_LEN)
+            .map_err(wrap_error_message)?;
+        if let Some(error) = get_revert_error(&estimate.gas.receipt) {
+            return Err(error);
+        }
+        if estimate.gas.receipt.status == 0 {
+            return Err(wrap_error_message(
+                "Transaction fails even with the maximum inscription size or RPC gas cap",
+            ));
+        }
+        Ok(InscriptionSizeEstimate {
+            gas_used: estimate.gas.receipt.gas_used,
+            gas_limit: estimate.gas.gas_limit,
+            inscription_byte_len: estimate.inscription_byte_len,
+            padding_byte_len: estimate.padding_byte_len,
+        })
+    }
+
+    #[instrument(skip(self))]
+    async fn get_block_state_hash(&self, height: u64) -> RpcResult<Option<B256ED>> {
+        event!(Level::INFO, "Getting block state hash");
+        Ok(self
+            .server_instance
+            .get_block_state_hash(height)
+            .map(B256ED::from_b256))
+    }
+
+    #[instrument(skip(self))]
+    async fn get_tx_bitcoin_data(&self, tx_hash: B256Wrapper) -> RpcResult<Option<BitcoinDataED>> {
+        event!(Level::INFO, "Getting tx bitcoin data");
+        Ok(self.server_instance.get_tx_bitcoin_data(tx_hash.value()))
+    }
+
+    async fn block_number(&self) -> RpcResult<String> {
+        let height = self.server_instance.get_latest_block_height();
+        Ok(format!("0x{:x}", height))
+    }
+
+    #[instrument(skip(self))]
+    async fn get_block_by_number(
+        &self,
+        block: String,
+        is_full: Option<bool>,
+    ) -> RpcResult<BlockResponseED> {
+        let number = self.parse_block_number(&block)?;
+        event!(Level::INFO, "Getting block by number: {}", number);
+        let block = self
+            .server_instance
+            .get_block_by_number(number, is_full.unwrap_or(false));
+        if let Some(block) = block {
+            Ok(block)
+        } else {
+            Err(RpcServerError::new("Block not found").into())
+        }
+    }
+
+    #[instrument(skip(self))]
+    async fn get_block_by_hash(
+        &self,
+        block: B256Wrapper,
+        is_full: Option<bool>,
+    ) -> RpcResult<BlockResponseED> {
+        event!(Level::INFO, "Getting block by number");
+        let block = self
+            .server_instance
+            .get_block_by_hash(block.value(), is_full.unwrap_or(false));
+        if let Some(block) = block {
+            Ok(block)
+        } else {
+            Err(RpcServerError::new("Block not found").into())
+        }
+    }
+
+    #[instrument(skip(self))]
+    async fn get_transaction_count(&self, account: String, block: String) -> RpcResult<String> {
+        event!(Level::INFO, "Getting transaction count");
+        let account = account.parse().unwrap();
+        let block = self.parse_block_number(&block)?;
+        let count = self.server_instance.get_transaction_count(account, block);
+        if count.is_err() {
+            return Err(RpcServerError::new("Couldn't get transaction count").into());
+        }
+        Ok(format!("0x{:x}", count.unwrap()))
+    }
+
+    #[instrument(skip(self))]
+    async fn get_block_transaction_count_by_number(&self, block: String) -> RpcResult<String> {
+        event!(Level::INFO, "Getting block transaction count");
+        let block = self.parse_block_number(&block)?;
+        let count = self
+            .server_instance
+            .get_block_transaction_count_by_number(block);
+        if count.is_err() {
+            return Err(RpcServerError::new("Couldn't get block transaction count").into());
+        }
+        Ok(format!("0x{:x}", count.unwrap()))
+    }
+
+    #[instrument(skip(self))]
+    async fn get_block_transaction_count_by_hash(&self, block: B256Wrapper) -> RpcResult<String> {
+        event!(Level::INFO, "Getting block transaction count");
+        let count = self
+            .server_instance
+            .get_block_transaction_count_by_hash(block.value());
+        if count.is_err() {
+            return Err(RpcServerError::new("Couldn't get block transaction count").into());
+        }
+        Ok(format!("0x{:x}", count.unwrap()))
+    }
+
+    #[instrument(skip(self))]
+    async fn get_logs(&self, filter: GetLogsFilter) -> RpcResult<Vec<LogResponseED>> {
+        event!(Level::INFO, "Getting logs");
+        let log_filter = self.parse_log_filter(filter)?;
+        let latest_block = self.server_instance.get_latest_block_height();
+        self.server_instance
+            .get_logs(
+                Some(log_filter.from_block.unwrap_or(latest_block)),
+                Some(log_filter.to_block.unwrap_or(latest_block)),
+                log_filter.addresses,
+                log_filter.topics,
+            )
+            .map_err(wrap_error_message)
+    }
+
+    #[instrument(skip(self))]
+    async fn new_filter(&self, filter: GetLogsFilter) -> RpcResult<String> {
+        event!(Level::INFO, "Creating new filter");
+        let filter_type = FilterType::Logs(self.parse_log_filter(filter)?);
+        let filter_id = self.server_instance.new_filter(filter_type);
+        Ok(format!("0x{:x}", filter_id))
+    }
+
+    #[instrument(skip(self))]
+    async fn new_block_filter(&self) -> RpcResult<String> {
+        event!(Level::INFO, "Creating new block filter");
+        let filter_id = self.server_instance.new_filter(FilterType::Blocks);
+        Ok(format!("0x{:x}", filter_id))
+    }
+
+    #[instrument(skip(self))]
+    async fn get_filter_changes(&self, filter_id: U256Wrapper) -> RpcResult<FilterChanges> {
+        event!(Level::INFO, "Getting filter changes");
+        self.server_instance
+            .get_filter_changes(filter_id.value())
+            .map_err(wrap_error_message)
+    }
+
+    #[instrument(skip(self))]
+    async fn get_filter_logs(&self, filter_id: U256Wrapper) -> RpcResult<Vec<LogResponseED>> {
+        event!(Level::INFO, "Getting filter logs");
+        self.server_instance
+            .get_filter_logs(filter_id.value())
+            .map_err(wrap_error_message)
+    }
+
+    #[instrument(skip(self))]
+    async fn uninstall_filter(&self, filter_id: U256Wrapper) -> RpcResult<bool> {
+        event!(Level::INFO, "Uninstalling filter");
+        Ok(self.server_instance.uninstall_filter(filter_id.value()))
+    }
+
+    #[instrument(skip(self, pending))]
+    async fn subscribe(
+        &self,
+        pending: PendingSubscriptionSink,
+        kind: String,
+        filter: Option<GetLogsFilter>,
+    ) -> SubscriptionResult {
+        event!(Level::INFO, "Subscribing to {}", kind);
+        let log_filter = match kind.as_str() {
+            "newHeads" => None,
+            "logs" => match self.parse_log_filter(filter.unwrap_or_default()) {
+                Ok(log_filter) => Some(LogFilter {
+                    from_block: None,
+                    to_block: None,
+                    ..log_filter
+                }),
+                Err(error) => {
+                    pending.reject(error).await;
+                    return Ok(());
+                }
+            },
+            _ => {
+                pending
+                    .reject(wrap_error_message("Unsupported subscription type"))
+                    .await;
+                return Ok(());
+            }
+        };
+
+        // Subscribe before accepting, so no blocks are missed in between
+        let mut chain_events = self.server_instance.subscribe_chain_events();
+        let sink = pending.accept().await?;
+        let mut sent_logs = SentLogs::new(self.server_instance.get_max_reorg_depth());
+
+        loop {
+            let chain_event = tokio::select! {
+                _ = sink.closed() => break,
+                chain_event = chain_events.recv() => chain_event,
+            };
+            let chain_event = match chain_event {
+                Ok(chain_event) => chain_event,
+                Err(RecvError::Lagged(skipped)) => {
+                    event!(Level::WARN, "Subscription skipped {} events", skipped);
+                    continue;
+                }
+                Err(RecvError::Closed) => break,
+            };
+
+            let messages = match (chain_event, &log_filter) {
+                (ChainEvent::NewBlock(block_number), None) => self
+                    .server_instance
+                    .get_block_by_number(block_number, false)
+                    .map(|mut block| {
+                        block.transactions = None;
+                        vec![SubscriptionMessage::from_json(&block)]
+                    })
+                    .unwrap_or_default(),
+                (ChainEvent::NewBlock(block_number), Some(log_filter)) => {
+                    let logs = self
+                        .server_instance
+                        .get_logs(
+                            Some(block_number),
+                            Some(block_number),
+                            log_filter.addresses.clone(),
+                            log_filter.topics.clone(),
+                        )
+                        .unwrap_or_else(|error| {
+                            event!(Level::ERROR, "Error while getting logs: {}", error);
+                            Vec::new()
+                        });
+                    let messages = logs.iter().map(SubscriptionMessage::from_json).collect();
+                    sent_logs.add(block_number, logs);
+                    messages
+                }
+                (ChainEvent::Reorg(_), None) => Vec::new(),
+                (ChainEvent::Reorg(latest_valid_block_number), Some(_)) => sent_logs
+                    .reorg(latest_valid_block_number)
+                    .iter()
+                    .map(SubscriptionMessage::from_json)
+                    .collect(),
+            };
+
+            for message in messages {
+                if sink.send(message?).await.is_err() {
+                    return Ok(());
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    #[instrument(skip(self))]
+    async fn call(
+        &self,
+        call: EthCall,
+        block: Option<String>,
+        state_overrides: Option<HashMap<AddressWrapper, EthCallAccountOverride>>,
+        block_overrides: Option<EthCallBlockOverrides>,
+    ) -> RpcResult<String> {
+        event!(Level::INFO, "Calling contract");
+        let overrides = parse_call_overrides(state_overrides, block_overrides)?;
+        let (tx_info, params) = parse_call(call)?;
+        let block_number = self.parse_state_block_number(block)?;
+        let receipt = self
+            .server_instance
+            .view_contract(&tx_info, &params, block_number, &overrides)
+            .map_err(wrap_error_message)?;
+        if let Some(error) = get_revert_error(&receipt) {
+            return Err(error);
+        }
+        Ok(receipt.result_bytes.unwrap_or(Bytes::new()).to_string())
+    }
+
+    #[instrument(skip(self))]
+    async fn estimate_gas(&self, call: EthCall, block: Option<String>) -> RpcResult<String> {
+        event!(Level::INFO, "Estimating gas");
+        let (tx_info, params) = parse_call(call)?;
+        let block_number = self.parse_state_block_number(block)?;
+        let estimate = self
+            .server_instance
+            .estimate_gas(&tx_info, &params, block_number, &CallOverrides::default())
+            .map_err(wrap_error_message)?;
+        if let Some(error) = get_revert_error(&estimate.receipt) {
+            return Err(error);
+        }
+        if estimate.receipt.status == 0 {
+            return Err(wrap_error_message(
+                "Call fails even with the maximum gas limit",
+            ));
+        }
+        Ok(format!("0x{:x}", estimate.gas_limit))
+    }
+
+    #[instrument(skip(self))]
+    async fn get_storage_at(
+        &self,
+        contract: AddressWrapper,
+        location: U256Wrapper,
+        block: Option<String>,
+    ) -> RpcResult<String> {
+        event!(Level::INFO, "Getting storage value");
+        let block_number = self.parse_state_block_number(block)?;
+        self.server_instance
+            .get_storage_at(contract.value(), location.value(), block_number)
+            .map(|value| format!("0x{:x}", value))
+            .map_err(wrap_error_message)
+    }
+
+    #[instrument(skip(self))]
+    async fn get_code(&self, contract: AddressWrapper, block: Option<String>) -> RpcResult<String> {
+        event!(Level::INFO, "Getting contract code");
+        let block_number = self.parse_state_block_number(block)?;
+        let result = self
+            .server_instance
+            .get_contract_bytecode(contract.value(), block_number)
+            .map_err(wrap_error_message)?;
+        if let Some(bytecode) = result {
+            Ok(bytecode.to_string())
+        } else {
+            Err(RpcServerError::new("Contract bytecode not found").into())
+        }
+    }
+
+    #[instrument(skip(self))]
+    async fn get_balance(
+        &self,
+        _address: AddressWrapper,
+        block: Option<String>,
+    ) -> RpcResult<String> {
+        event!(Level::INFO, "Getting balance");
+        // There is no native balance in BRC2.0, the block tag is only validated
+        self.parse_state_block_number(block)?;
+        Ok("0xDE0B6B3A7640000".to_string())
+    }
+
+    #[instrument(skip(self))]
+    async fn get_proof(
+        &self,
+        address: AddressWrapper,
+        storage_keys: Vec<U256Wrapper>,
+        block: Option<String>,
+    ) -> RpcResult<AccountProofED> {
+        event!(Level::INFO, "Getting proof");
+        let block_number = self.parse_state_block_number(block)?;
+        let storage_keys = storage_keys
+            .iter()
+            .map(|key| key.value())
+            .collect::<Vec<_>>();
+        self.server_instance
+            .get_proof(address.value(), &storage_keys, block_number)
+            .map_err(wrap_error_message)
+    }
+
+    #[instrument(skip(self))]
+    async fn get_transaction_receipt(
+        &self,
+        transaction: B256Wrapper,
+    ) -> RpcResult<Option<TxReceiptED>> {
+        event!(Level::INFO, "Getting transaction receipt");
+        Ok(self
+            .server_instance
+            .get_transaction_receipt(transaction.value()))
+    }
+
+    #[instrument(skip(self))]
+    async fn get_transaction_by_hash(&self, transaction: B256Wrapper) -> RpcResult<Option<TxED>> {
+        event!(Level::INFO, "Getting transaction by hash");
+        Ok(self
+            .server_instance
+            .get_transaction_by_hash(transaction.value()))
+    }
+
+    #[instrument(skip(self))]
+    async fn get_transaction_by_block_number_and_index(
+        &self,
+        block_number: u64,
+        tx_idx: u64,
+    ) -> RpcResult<Option<TxED>> {
+        event!(Level::INFO, "Getting transaction by block number and index");
+        let tx = self
+            .server_instance
+            .get_transaction_by_block_number_and_index(block_number, tx_idx);
+        Ok(tx)
+    }
+
+    #[instrument(skip(self))]
+    async fn get_transaction_by_block_hash_and_index(
+        &self,
+        block_hash: B256Wrapper,
+        tx_idx: u64,
+    ) -> RpcResult<Option<TxED>> {
+        event!(Level::INFO, "Getting transaction by block hash and index");
+        Ok(self
+            .server_instance
+            .get_transaction_by_block_hash_and_index(block_hash.value(), tx_idx))
+    }
+
+    #[instrument(skip(self))]
+    async fn trace_transaction(
+        &self,
+        transaction: B256Wrapper,
+        options: Option<TraceOptions>,
+    ) -> RpcResult<TraceResult> {
+        event!(Level::INFO, "Tracing transaction");
+        let tracer = parse_trace_options(options)?;
+        self.server_instance
+            .trace_transaction(transaction.value(), &tracer)
+            .map_err(wrap_error_message)
+    }
+
+    #[instrument(skip(self))]
+    async fn trace_call(
+        &self,
+        call: EthCall,
+        block: Option<String>,
+        options: Option<TraceCallOptions>,
+    ) -> RpcResult<TraceResult> {
+        event!(Level::INFO, "Tracing call");
+        let options = options.unwrap_or_default();
+        let overrides = parse_call_overrides(options.state_overrides, options.block_overrides)?;
+        let tracer = parse_trace_options(Some(options.trace_options))?;
+        let (tx_info, params) = parse_call(call)?;
+        let block_number = self.parse_state_block_number(block)?;
+        self.server_instance
+            .trace_call(&tx_info, &params, block_number, &overrides, &tracer)
+            .map_err(wrap_error_message)
+    }
+
+    #[instrument(skip(self))]
+    async fn trace_block_by_number(
+        &self,
+        number: String,
+        options: Option<TraceOptions>,
+    ) -> RpcResult<Vec<BlockTraceResult>> {
+        event!(Level::INFO, "Tracing block");
+        let tracer = parse_trace_options(options)?;
+        let block_number = self.parse_block_number(&number)?;
+        self.server_instance
+            .trace_block(block_number, &tracer)
+            .map_err(wrap_error_message)
+    }
 }
 
 #[async_trait]
-impl Brc20ProgApiServer for RpcServer {
+impl Brc20ProgIndexerApiServer for RpcServer {
     #[instrument(skip(self))]
     async fn mine(&self, block_count: u64, timestamp: u64) -> RpcResult<()> {
         event!(Level::INFO, "Mining empty blocks");
@@ -108,18 +928,29 @@ impl Brc20ProgApiServer for RpcServer {
             .map_err(wrap_error_message)
     }
 
-    #[instrument(skip(self))]
-    async fn balance(&self, pkscript: String, ticker: String) -> RpcResult<String> {
-        event!(Level::INFO, "Checking balance");
-
+    #[instrument(skip(self, balances))]
+    async fn set_balances(
+        &self,
+        block_height: u64,
+        block_hash: B256Wrapper,
+        balances: Vec<Brc20Balance>,
+    ) -> RpcResult<()> {
+        event!(Level::INFO, "Setting {} BRC20 balances", balances.len());
         self.server_instance
-            .view_contract(&load_brc20_balance_tx(ticker, get_evm_address(&pkscript)))
-            .map(|receipt| {
-                format!(
-                    "0x{:x}",
-                    decode_brc20_balance_result(receipt.result_bytes.as_ref())
-                )
-            })
+            .set_brc20_balances(
+                block_height,
+                block_hash.value(),
+                balances
+                    .into_iter()
+                    .map(|balance| {
+                        (
+                            balance.ticker.value().clone(),
+                            balance.pkscript.value().clone(),
+                            balance.balance.value(),
+                        )
+                    })
+                    .collect(),
+            )
             .map_err(wrap_error_message)
     }
 
@@ -136,18 +967,6 @@ impl Brc20ProgApiServer for RpcServer {
             .map_err(wrap_error_message)
     }
 
-    #[instrument(skip(self))]
-    async fn get_transaction_receipt_by_inscription_id(
-        &self,
-        inscription_id: String,
-    ) -> RpcResult<Option<TxReceiptED>> {
-        event!(Level::INFO, "Getting transaction receipt by inscription id");
-        let receipt = self
-            .server_instance
-            .get_transaction_receipt_by_inscription_id(inscription_id);
-        Ok(receipt)
-    }
-
     #[instrument(skip(self, data))]
     async fn deploy_contract(
         &self,
@@ -191,31 +1010,14 @@ impl Brc20ProgApiServer for RpcServer {
         inscription_byte_len: Option<u64>,
     ) -> RpcResult<TxReceiptED> {
         event!(Level::INFO, "Calling contract");
-        let derived_contract_address;
-        if contract_address.is_none() {
-            if contract_inscription_id.is_none() {
-                return Err(
-                    RpcServerError::new("Contract address or inscription ID is required").into(),
-                );
-            }
-            let inscription_contract_address = self
-                .server_instance
-                .get_contract_address_by_inscription_id(contract_inscription_id.unwrap())
-                .map_err(wrap_error_message)?;
-            derived_contract_address = Some(inscription_contract_address);
-        } else {
-            let contract_address = contract_address.unwrap();
-            if contract_address.value() == Address::ZERO {
-                return Err(RpcServerError::new("Contract address cannot be zero").into());
-            }
-            derived_contract_address = Some(contract_address.value());
-        }
+        let contract_address =
+            self.get_call_contract_address(contract_address, contract_inscription_id)?;
         self.server_instance
             .add_tx_to_block(
                 timestamp,
                 &TxInfo {
                     from: get_evm_address(&from_pkscript),
-                    to: derived_contract_address,
+                    to: Some(contract_address),
                     data: data.value().clone(),
                 },
                 tx_idx,
@@ -233,11 +1035,41 @@ impl Brc20ProgApiServer for RpcServer {
         timestamp: u64,
         hash: B256Wrapper,
         block_tx_count: u64,
+        expected_state_hash: Option<B256Wrapper>,
     ) -> RpcResult<()> {
         let block_height = self.server_instance.get_next_block_height();
         event!(Level::INFO, "Finalising block {}", block_height);
         self.server_instance
-            .finalise_block(timestamp, block_height, hash.value(), block_tx_count)
+            .finalise_block(
+                timestamp,
+                block_height,
+                hash.value(),
+                block_tx_count,
+                expected_state_hash.map(|x| x.value()),
+            )
+            .map_err(wrap_error_message)
+    }
+
+    #[instrument(skip(self, txes))]
+    async fn add_block(
+        &self,
+        hash: B256Wrapper,
+        timestamp: u64,
+        txes: Vec<BlockTransaction>,
+        expected_state_hash: Option<B256Wrapper>,
+    ) -> RpcResult<Vec<TxReceiptED>> {
+        event!(Level::INFO, "Adding block with {} txes", txes.len());
+        let txes = txes
+            .into_iter()
+            .map(get_block_tx_info)
+            .collect::<Result<Vec<_>, _>>()?;
+        self.server_instance
+            .add_block(
+                timestamp,
+                hash.value(),
+                txes,
+                expected_state_hash.map(|x| x.value()),
+            )
             .map_err(wrap_error_message)
     }
 
@@ -264,132 +1096,6 @@ impl Brc20ProgApiServer for RpcServer {
         Ok(())
     }
 
-    async fn block_number(&self) -> RpcResult<String> {
-        let height = self.server_instance.get_latest_block_height();
-        Ok(format!("0x{:x}", height))
-    }
-
-    #[instrument(skip(self))]
-    async fn get_block_by_number(
-        &self,
-        block: String,
-        is_full: Option<bool>,
-    ) -> RpcResult<BlockResponseED> {
-        let number = self.parse_block_number(&block)?;
-        event!(Level::INFO, "Getting block by number: {}", number);
-        let block = self
-            .server_instance
-            .get_block_by_number(number, is_full.unwrap_or(false));
-        if let Some(block) = block {
-            Ok(block)
-        } else {
-            Err(RpcServerError::new("Block not found").into())
-        }
-    }
-
-    #[instrument(skip(self))]
-    async fn get_block_by_hash(
-        &self,
-        block: B256Wrapper,
-        is_full: Option<bool>,
-    ) -> RpcResult<BlockResponseED> {
-        event!(Level::INFO, "Getting block by number");
-        let block = self
-            .server_instance
-            .get_block_by_hash(block.value(), is_full.unwrap_or(false));
-        if let Some(block) = block {
-            Ok(block)
-        } else {
-            Err(RpcServerError::new("Block not found").into())
-        }
-    }
-
-    #[instrument(skip(self))]
-    async fn get_transaction_count(&self, account: String, block: String) -> RpcResult<String> {
-        event!(Level::INFO, "Getting transaction count");
-        let account = account.parse().unwrap();
-        let block = self.parse_block_number(&block)?;
-        let count = self.server_instance.get_transaction_count(account, block);
-        if count.is_err() {
-            return Err(RpcServerError::new("Couldn't get transaction count").into());
-        }
-        Ok(format!("0x{:x}", count.unwrap()))
-    }
-
-    #[instrument(skip(self))]
-    async fn get_block_transaction_count_by_number(&self, block: String) -> RpcResult<String> {
-        event!(Level::INFO, "Getting block transaction count");
-        let block = self.parse_block_number(&block)?;
-        let count = self
-            .server_instance
-            .get_block_transaction_count_by_number(block);
-        if count.is_err() {
-            return Err(RpcServerError::new("Couldn't get block transaction count").into());
-        }
-        Ok(format!("0x{:x}", count.unwrap()))
-    }
-
-    #[instrument(skip(self))]
-    async fn get_block_transaction_count_by_hash(&self, block: B256Wrapper) -> RpcResult<String> {
-        event!(Level::INFO, "Getting block transaction count");
-        let count = self
-            .server_instance
-            .get_block_transaction_count_by_hash(block.value());
-        if count.is_err() {
-            return Err(RpcServerError::new("Couldn't get block transaction count").into());
-        }
-        Ok(format!("0x{:x}", count.unwrap()))
-    }
-
-    #[instrument(skip(self))]
-    async fn get_logs(&self, filter: GetLogsFilter) -> RpcResult<Vec<LogResponseED>> {
-        event!(Level::INFO, "Getting logs");
-        Ok(self.server_instance.get_logs(
-            Some(self.parse_block_number(&filter.from_block.unwrap_or("latest".to_string()))?),
-            Some(self.parse_block_number(&filter.to_block.unwrap_or("latest".to_string()))?),
-            filter.address.map(|x| x.value()),
-            filter
-                .topics
-                .map(|vec| vec.into_iter().map(|topic| topic.value()).collect()),
-        ))
-    }
-
-    #[instrument(skip(self))]
-    async fn call(&self, call: EthCall, _: Option<String>) -> RpcResult<String> {
-        event!(Level::INFO, "Calling contract");
-        let data = call.data.map(|x| x.value().clone()).unwrap_or(
-            call.input
-                .map(|x| x.value().clone())
-                .unwrap_or(Bytes::new()),
-        );
-        self.server_instance
-            .view_contract(&TxInfo {
-                from: call.from.value(),
-                to: call.to.map(|x| x.value()),
-                data: data,
-            })
-            .map(|result| result.result_bytes.unwrap_or(Bytes::new()).to_string())
-            .map_err(wrap_error_message)
-    }
-
-    #[instrument(skip(self))]
-    async fn estimate_gas(&self, call: EthCall, _: Option<String>) -> RpcResult<String> {
-        event!(Level::INFO, "Estimating gas");
-        let data = call.data.map(|x| x.value().clone()).unwrap_or(
-            call.input
-                .map(|x| x.value().clone())
-                .unwrap_or(Bytes::new()),
-        );
-        self.server_instance
-            .view_contract(&TxInfo {
-                from: call.from.value(),
-                to: call.to.map(|x| x.value()),
-                data: data,
-            })
-            .map(|receipt| format!("0x{:x}", receipt.gas_used))
-            .map_err(wrap_error_message)
-    }
-
     #[instrument(skip(self))]
     async fn send_transaction(&self, call: EthCall) -> RpcResult<B256ED> {
         event!(Level::INFO, "Sending transaction");
@@ -410,75 +1116,6 @@ impl Brc20ProgApiServer for RpcServer {
             .map(|receipt| receipt.transaction_hash)
             .map_err(wrap_error_message)
     }
-
-    #[instrument(skip(self))]
-    async fn get_storage_at(
-        &self,
-        contract: AddressWrapper,
-        location: U256Wrapper,
-    ) -> RpcResult<String> {
-        event!(Level::INFO, "Getting storage value");
-        Ok(format!(
-            "0x{:x}",
-            self.server_instance
-                .get_storage_at(contract.value(), location.value())
-        ))
-    }
-
-    #[instrument(skip(self))]
-    async fn get_code(&self, contract: AddressWrapper) -> RpcResult<String> {
-        event!(Level::INFO, "Getting contract code");
-        let result = self.server_instance.get_contract_bytecode(contract.value());
-        if let Some(bytecode) = result {
-            Ok(bytecode.to_string())
-        } else {
-            Err(RpcServerError::new("Contract bytecode not found").into())
-        }
-    }
-
-    #[instrument(skip(self))]
-    async fn get_transaction_receipt(
-        &self,
-        transaction: B256Wrapper,
-    ) -> RpcResult<Option<TxReceiptED>> {
-        event!(Level::INFO, "Getting transaction receipt");
-        Ok(self
-            .server_instance
-            .get_transaction_receipt(transaction.value()))
-    }
-
-    #[instrument(skip(self))]
-    async fn get_transaction_by_hash(&self, transaction: B256Wrapper) -> RpcResult<Option<TxED>> {
-        event!(Level::INFO, "Getting transaction by hash");
-        Ok(self
-            .server_instance
-            .get_transaction_by_hash(transaction.value()))
-    }
-
-    #[instrument(skip(self))]
-    async fn get_transaction_by_block_number_and_index(
-        &self,
-        block_number: u64,
-        tx_idx: u64,
-    ) -> RpcResult<Option<TxED>> {
-        event!(Level::INFO, "Getting transaction by block number and index");
-        let tx = self
-            .server_instance
-            .get_transaction_by_block_number_and_index(block_number, tx_idx);
-        Ok(tx)
-    }
-
-    #[instrument(skip(self))]
-    async fn get_transaction_by_block_hash_and_index(
-        &self,
-        block_hash: B256Wrapper,
-        tx_idx: u64,
-    ) -> RpcResult<Option<TxED>> {
-        event!(Level::INFO, "Getting transaction by block hash and index");
-        Ok(self
-            .server_instance
-            .get_transaction_by_block_hash_and_index(block_hash.value(), tx_idx))
-    }
 }
 
 struct RpcServerError {
@@ -497,10 +1134,44 @@ impl Into<ErrorObject<'static>> for RpcServerError {
     }
 }
 
+/// Module of the public server and module of the indexer server
+type RpcModules = (RpcModule<RpcServer>, Option<RpcModule<RpcServer>>);
+
+/// Returns the module of the public server, and the module of the indexer server if it's enabled
+///
+/// Methods that change the state are only merged into the public module when the indexer server
+/// is not enabled, the indexer module has all methods
+fn get_rpc_modules(
+    server_instance: Arc<ServerInstance>,
+    indexer_server: bool,
+) -> Result<RpcModules, Box<dyn Error>> {
+    let get_public_module = || {
+        Brc20ProgApiServer::into_rpc(RpcServer {
+            server_instance: server_instance.clone(),
+        })
+    };
+    let get_all_methods_module = || -> Result<RpcModule<RpcServer>, Box<dyn Error>> {
+        let mut module = get_public_module();
+        module.merge(Brc20ProgIndexerApiServer::into_rpc(RpcServer {
+            server_instance: server_instance.clone(),
+        }))?;
+        Ok(module)
+    };
+    if !indexer_server {
+        return Ok((get_all_methods_module()?, None));
+    }
+    Ok((get_public_module(), Some(get_all_methods_module()?)))
+}
+
+/// Starts the JSON-RPC server, and the indexer server if indexer_addr is set
+///
+/// When the indexer server is enabled, methods that change the state are not served on the
+/// public server, and the indexer server serves all methods without CORS
 pub async fn start_rpc_server(
     addr: String,
+    indexer_addr: Option<String>,
     server_instance: ServerInstance,
-) -> Result<ServerHandle, Box<dyn Error>> {
+) -> Result<(ServerHandle, Option<ServerHandle>), Box<dyn Error>> {
     let cors = CorsLayer::new()
         // Allow `POST` when accessing the resource
         .allow_methods([Method::POST])
@@ -514,8 +1185,73 @@ pub async fn start_rpc_server(
         .set_rpc_middleware(RpcServiceBuilder::new().rpc_logger(1024))
         .build(addr.parse::<SocketAddr>()?)
         .await?;
-    let module = RpcServer { server_instance }.into_rpc();
+    let (module, indexer_module) =
+        get_rpc_modules(Arc::new(server_instance), indexer_addr.is_some())?;
     let handle = server.start(module);
 
-    Ok(handle)
+    let (Some(indexer_addr), Some(indexer_module)) = (indexer_addr, indexer_module) else {
+        return Ok((handle, None));
+    };
+    let indexer_server = Server::builder()
+        .set_rpc_middleware(RpcServiceBuilder::new().rpc_logger(1024))
+        .build(indexer_addr.parse::<SocketAddr>()?)
+        .await?;
+    let indexer_handle = indexer_server.start(indexer_module);
+
+    Ok((handle, Some(indexer_handle)))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::db::DB;
+
+    #[test]
+    fn test_indexer_methods_are_not_public() {
+        let path = TempDir::new().unwrap().into_path();
+        let server_instance = Arc::new(ServerInstance::new(DB::new(&path).unwrap()));
+
+        let (public_module, indexer_module) =
+            get_rpc_modules(server_instance.clone(), true).unwrap();
+        let public_methods = public_module.method_names().collect::<HashSet<_>>();
+        let all_methods = indexer_module
+            .unwrap()
+            .method_names()
+            .collect::<HashSet<_>>();
+        let missing_methods = all_methods
+            .difference(&public_methods)
+            .copied()
+            .collect::<HashSet<_>>();
+        assert!(public_methods.is_subset(&all_methods));
+        assert_eq!(
+            missing_methods,
+            HashSet::from([
+                "brc20_mine",
+                "brc20_deploy",
+                "brc20_call",
+                "brc20_deposit",
+                "brc20_withdraw",
+                "brc20_initialise",
+                "brc20_setBalances",
+                "brc20_finaliseBlock",
+                "brc20_addBlock",
+                "brc20_reorg",
+                "brc20_commitToDatabase",
+                "brc20_clearCaches",
+                "eth_sendTransaction",
+            ])
+        );
+
+        // Without the indexer server, the public server has all methods
+        let (public_module, indexer_module) = get_rpc_modules(server_instance, false).unwrap();
+        assert!(indexer_module.is_none());
+        assert_eq!(
+            public_module.method_names().collect::<HashSet<_>>(),
+            all_methods
+        );
+    }
 }
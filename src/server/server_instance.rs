@@ -1,23 +1,45 @@
-use std::sync::Mutex;
+use std::ops::RangeInclusive;
+use std::sync::{Mutex, RwLock};
 use std::time::Instant;
 
-use revm::context::result::ExecutionResult;
-use revm::context::{BlockEnv, ContextTr, TransactTo};
-use revm::handler::{EvmTr, ExecuteCommitEvm};
+use revm::context::result::{EVMError, ExecutionResult};
+use revm::context::{BlockEnv, ContextTr, TransactTo, TxEnv};
+use revm::database::CacheDB;
+use revm::handler::EvmTr;
+use revm::inspector::NoOpInspector;
 use revm::primitives::alloy_primitives::logs_bloom;
 use revm::primitives::{Address, Bytes, B256, U256};
-use revm::{Database, ExecuteEvm};
+use revm::{Database, DatabaseCommit, DatabaseRef, ExecuteEvm};
+use tokio::sync::broadcast;
 
 use crate::brc20_controller::{load_brc20_deploy_tx, verify_brc20_contract_address};
 use crate::db::types::{
-    AddressED, BlockResponseED, Decode, LogED, LogResponseED, TxED, TxReceiptED, B2048ED, B256ED,
+    AccountProofED, AddressED, BitcoinDataED, BlockResponseED, Decode, LogED, LogResponseED, TxED,
+    TxReceiptED, B2048ED, B256ED,
 };
-use crate::db::{DB, MAX_HISTORY_SIZE};
+use crate::db::{BitcoinCache, StateView, DB};
+use crate::evm::tracers::{trace_txes, BlockTraceResult, TraceResult, TracerType};
 use crate::evm::{
-    get_brc20_balance, get_contract_address, get_evm, get_gas_limit, get_result_reason,
-    get_result_type,
+    get_contract_address, get_evm, get_evm_with_inspector, get_gas_limit, get_inscription_byte_len,
+    get_result_reason, get_result_type, BitcoinData, BRC20_BALANCE_PROVIDER,
+    BRC20_BALANCE_SNAPSHOT,
 };
-use crate::server::types::{get_tx_hash, TxInfo};
+use crate::server::filters::{FilterChanges, FilterManager, FilterType, LogFilter};
+use crate::server::subscriptions::{ChainEvent, CHAIN_EVENT_CAPACITY};
+use crate::server::types::{
+    get_tx_hash, BlockTxInfo, CallOverrides, CallParams, GasEstimate, InscriptionEstimate, TxInfo,
+};
+
+lazy_static::lazy_static! {
+    static ref MAX_LOG_RESULTS: usize = std::env::var("BRC20_PROG_MAX_LOG_RESULTS")
+            .ok()
+            .and_then(|x| x.parse().ok())
+            .unwrap_or(10000);
+}
+
+/// Block environment, transaction hashes and transactions of a block,
+/// with the Bitcoin data recorded when they were executed
+type BlockTxes = (BlockEnv, Vec<B256>, Vec<(TxEnv, BitcoinDataED)>);
 
 pub struct LastBlockInfo {
     pub waiting_tx_count: u64,
@@ -42,8 +64,19 @@ impl LastBlockInfo {
 }
 
 pub struct ServerInstance {
-    pub db_mutex: Mutex<DB>,
+    pub db: RwLock<DB>,
+    /// Locked before db when both are needed
     pub last_block_info: Mutex<LastBlockInfo>,
+    pub filters: Mutex<FilterManager>,
+    pub chain_events: broadcast::Sender<ChainEvent>,
+}
+
+/// State and block a call runs on
+struct CallEnv<'a> {
+    state: StateView<'a>,
+    block_info: BlockEnv,
+    block_hash: Option<B256>,
+    bitcoin_cache: Option<BitcoinCache>,
 }
 
 impl ServerInstance {
@@ -52,8 +85,10 @@ impl ServerInstance {
         println!("Creating new server instance");
 
         let instance = ServerInstance {
-            db_mutex: Mutex::new(db),
+            db: RwLock::new(db),
             last_block_info: Mutex::new(LastBlockInfo::new()),
+            filters: Mutex::new(FilterManager::new()),
+            chain_events: broadcast::channel(CHAIN_EVENT_CAPACITY).0,
         };
 
         instance
@@ -97,17 +132,45 @@ impl ServerInstance {
         let brc20_controller_contract = result.contract_address.unwrap().0;
         verify_brc20_contract_address(&brc20_controller_contract.to_string());
 
-        self.finalise_block(genesis_timestamp, genesis_height, genesis_hash, 1)?;
+        self.finalise_block(genesis_timestamp, genesis_height, genesis_hash, 1, None)?;
 
         // Check status of BRC20 Balance Server before proceeding
-        get_brc20_balance(&Bytes::from([10]), &Bytes::from([10]))
-            .map_err(|_| "BRC20 Balance Server is down. This error can be ignored in tests that doesn't involve the BRC20 indexer.")?;
+        if !BRC20_BALANCE_PROVIDER.check_status() {
+            return Err("BRC20 Balance Server is down. This error can be ignored in tests that doesn't involve the BRC20 indexer.");
+        }
+
+        Ok(())
+    }
 
+    /// Sets non-module BRC20 balances for the next block, before its transactions run,
+    /// balances that are not given keep their values
+    pub fn set_brc20_balances(
+        &self,
+        block_height: u64,
+        block_hash: B256,
+        balances: Vec<(Bytes, Bytes, U256)>,
+    ) -> Result<(), &'static str> {
+        let snapshot = BRC20_BALANCE_SNAPSHOT.as_ref().ok_or(
+            "BRC20 balance snapshot is not enabled, set BRC20_PROG_BALANCE_PROVIDER to snapshot",
+        )?;
+        if block_height != self.get_next_block_height() {
+            return Err("Balances can only be set for the next block");
+        }
+        let last_block_info = self.last_block_info.lock().unwrap();
+        if last_block_info.waiting_tx_count != 0 && block_hash != last_block_info.last_block_hash {
+            return Err("Block hash is different from other txes in block");
+        }
+        snapshot.set_balances(
+            block_height,
+            block_hash,
+            balances,
+            self.get_max_reorg_depth(),
+        );
         Ok(())
     }
 
     pub fn get_next_block_height(&self) -> u64 {
-        let mut db = self.db_mutex.lock().unwrap();
+        let db = self.db.read().unwrap();
         let latest_block_height = db.get_latest_block_height();
 
         let block_height = latest_block_height.unwrap_or(0);
@@ -123,8 +186,13 @@ impl ServerInstance {
         block_height + 1
     }
 
+    /// Number of blocks that can be reorged, None if there is no limit
+    pub fn get_max_reorg_depth(&self) -> Option<u64> {
+        self.db.read().unwrap().get_max_reorg_depth()
+    }
+
     pub fn get_latest_block_height(&self) -> u64 {
-        let db = self.db_mutex.lock().unwrap();
+        let db = self.db.read().unwrap();
         let latest_block_height = db.get_latest_block_height();
 
         let block_height = latest_block_height.unwrap_or(0);
@@ -137,7 +205,7 @@ impl ServerInstance {
         timestamp: u64,
         hash: B256,
     ) -> Result<(), &'static str> {
-        self.require_no_waiting_txes()?;
+        Self::require_no_waiting_txes(&self.last_block_info.lock().unwrap())?;
 
         let mut number = self.get_next_block_height();
 
@@ -149,7 +217,7 @@ impl ServerInstance {
             let genesis_timestamp = timestamp;
             let genesis_height = 0;
 
-            self.finalise_block(genesis_timestamp, genesis_height, genesis_hash, 0)?;
+            self.finalise_block(genesis_timestamp, genesis_height, genesis_hash, 0, None)?;
             block_count -= 1;
             number += 1;
         }
@@ -164,7 +232,7 @@ impl ServerInstance {
         );
 
         for _ in 0..block_count {
-            self.finalise_block(timestamp, number, hash, 0)?;
+            self.finalise_block(timestamp, number, hash, 0, None)?;
             number += 1;
         }
 
@@ -181,7 +249,7 @@ impl ServerInstance {
             inscription_id
         );
 
-        let mut db = self.db_mutex.lock().unwrap();
+        let db = self.db.read().unwrap();
         let tx_hash = db
             .get_tx_hash_by_inscription_id(inscription_id)
             .unwrap_or(None);
@@ -244,8 +312,10 @@ impl ServerInstance {
         };
 
         let output: Option<ExecutionResult>;
+        let bitcoin_data;
         let nonce = self.get_nonce(tx_info.from);
         let txhash = get_tx_hash(&tx_info, &nonce);
+        let gas_limit = get_gas_limit(inscription_byte_len.unwrap_or(tx_info.data.len() as u64));
 
         {
             #[cfg(debug_assertions)]
@@ -254,9 +324,10 @@ impl ServerInstance {
                 tx_idx, tx_idx, block_number, block_number, block_hash
             );
 
-            let mut db = self.db_mutex.lock().unwrap();
-            let db_moved = core::mem::take(&mut *db);
-            let mut evm = get_evm(block_info, db_moved, None);
+            // Transaction runs on a read lock, so RPC reads aren't blocked while it executes
+            let db = self.db.read().unwrap();
+            let mut evm = get_evm(block_info, &db, None);
+            evm.set_block_hash(Some(block_hash));
             #[cfg(debug_assertions)]
             println!(
                 "Adding tx 0x{:x} ({}) from: {:?} to: {:?} with data: {:?}",
@@ -274,11 +345,11 @@ impl ServerInstance {
                     .unwrap_or(TransactTo::Create);
                 tx.data = tx_info.data.clone();
                 tx.nonce = nonce;
-                tx.gas_limit = get_gas_limit(inscription_byte_len.unwrap_or(tx.data.len() as u64));
+                tx.gas_limit = gas_limit;
             });
 
             let tx = evm.ctx().tx().clone();
-            output = Some(evm.transact_commit(tx).unwrap());
+            let result = evm.transact(tx);
 
             println!(
                 "Tx 0x{:x} ({}) took {}ms",
@@ -286,7 +357,16 @@ impl ServerInstance {
                 tx_idx,
                 start_time.elapsed().as_millis()
             );
-            core::mem::swap(&mut *db, &mut evm.ctx().db());
+            bitcoin_data = evm.bitcoin_data().take_recorded_data();
+            let result = result.map_err(|_| "Error while executing transaction")?;
+            drop(evm);
+            drop(db);
+
+            // Other writers need the last block info lock, so the state can't change before the commit
+            let mut db = self.db.write().unwrap();
+            db.set_pending_block_number(block_number);
+            db.commit(result.state);
+            output = Some(result.result);
         }
 
         let output = output.unwrap();
@@ -309,7 +389,7 @@ impl ServerInstance {
             .checked_add(output.gas_used())
             .unwrap_or(last_block_info.last_block_gas_used);
 
-        let mut db = self.db_mutex.lock().unwrap();
+        let mut db = self.db.write().unwrap();
         db.set_tx_receipt(
             &get_result_type(&output),
             &get_result_reason(&output),
@@ -321,6 +401,7 @@ impl ServerInstance {
             tx_info.from,
             tx_info.to,
             &tx_info.data,
+            gas_limit,
             txhash,
             tx_idx,
             &output.clone(),
@@ -330,12 +411,95 @@ impl ServerInstance {
             inscription_id,
         )
         .unwrap();
+        db.set_tx_bitcoin_data(block_number, txhash, bitcoin_data)
+            .map_err(|_| "Error while saving bitcoin data")?;
 
         last_block_info.last_block_log_index += output.logs().len() as u64;
 
         Ok(db.get_tx_receipt(txhash).unwrap().unwrap())
     }
 
+    /// Adds the transactions of a block in order and finalises it
+    ///
+    /// If any transaction fails, or the state hash is not expected_state_hash when it is given,
+    /// the whole block is reverted and nothing is added
+    pub fn add_block(
+        &self,
+        timestamp: u64,
+        block_hash: B256,
+        txes: Vec<BlockTxInfo>,
+        expected_state_hash: Option<B256>,
+    ) -> Result<Vec<TxReceiptED>, &'static str> {
+        Self::require_no_waiting_txes(&self.last_block_info.lock().unwrap())?;
+
+        let block_number = self.get_next_block_height();
+        let result = self.add_block_txes(
+            timestamp,
+            block_number,
+            block_hash,
+            txes,
+            expected_state_hash,
+        );
+        if result.is_err() {
+            let mut last_block_info = self.last_block_info.lock().unwrap();
+            let mut db = self.db.write().unwrap();
+            Self::discard_pending_block(&mut db, &mut last_block_info, block_number);
+        }
+        result
+    }
+
+    fn add_block_txes(
+        &self,
+        timestamp: u64,
+        block_number: u64,
+        block_hash: B256,
+        txes: Vec<BlockTxInfo>,
+        expected_state_hash: Option<B256>,
+    ) -> Result<Vec<TxReceiptED>, &'static str> {
+        let mut receipts = Vec::with_capacity(txes.len());
+        for (tx_idx, tx) in txes.into_iter().enumerate() {
+            let mut tx_info = tx.tx_info;
+            // Contract might be deployed earlier in the same block, so it's resolved in order
+            if let Some(contract_inscription_id) = tx.contract_inscription_id {
+                tx_info.to =
+                    Some(self.get_contract_address_by_inscription_id(contract_inscription_id)?);
+            }
+            receipts.push(self.add_tx_to_block(
+                timestamp,
+                &tx_info,
+                tx_idx as u64,
+                block_number,
+                block_hash,
+                tx.inscription_id,
+                tx.inscription_byte_len,
+            )?);
+        }
+        self.finalise_block(
+            timestamp,
+            block_number,
+            block_hash,
+            receipts.len() as u64,
+            expected_state_hash,
+        )?;
+        Ok(receipts)
+    }
+
+    /// Removes the changes of the block that is being built
+    ///
+    /// Callers hold both locks, so no transaction can be added while the block is discarded
+    fn discard_pending_block(db: &mut DB, last_block_info: &mut LastBlockInfo, block_number: u64) {
+        #[cfg(debug_assertions)]
+        println!("Discarding block 0x{:x} ({})", block_number, block_number);
+
+        if block_number == 0 {
+            // Nothing exists before the genesis block
+            db.clear_caches();
+        } else {
+            db.discard_changes_after(block_number - 1);
+        }
+        *last_block_info = LastBlockInfo::new();
+    }
+
     pub fn get_transaction_count(
         &self,
         account: Address,
@@ -347,7 +511,7 @@ impl ServerInstance {
             account, block_number, block_number
         );
 
-        let mut db = self.db_mutex.lock().unwrap();
+        let db = self.db.read().unwrap();
         let tx_count = db.get_tx_count(Some(account), block_number).unwrap_or(0);
         Ok(tx_count)
     }
@@ -362,7 +526,7 @@ impl ServerInstance {
             block_number, block_number
         );
 
-        let mut db = self.db_mutex.lock().unwrap();
+        let db = self.db.read().unwrap();
         let tx_count = db.get_tx_count(None, block_number).unwrap_or(0);
         Ok(tx_count)
     }
@@ -374,7 +538,7 @@ impl ServerInstance {
         #[cfg(debug_assertions)]
         println!("Getting block tx count for block hash {:?}", block_hash);
 
-        let mut db = self.db_mutex.lock().unwrap();
+        let db = self.db.read().unwrap();
         let block_number = db.get_block_number(block_hash);
         if block_number.is_err() {
             return Err("block hash not found");
@@ -395,7 +559,7 @@ impl ServerInstance {
             block_hash, tx_idx, tx_idx
         );
 
-        let mut db = self.db_mutex.lock().unwrap();
+        let db = self.db.read().unwrap();
         let tx_hash = db
             .get_tx_hash_by_block_hash_and_index(block_hash, tx_idx)
             .unwrap();
@@ -415,7 +579,7 @@ impl ServerInstance {
             block_number, block_number, tx_idx, tx_idx
         );
 
-        let mut db = self.db_mutex.lock().unwrap();
+        let db = self.db.read().unwrap();
         let tx_hash = db
             .get_tx_hash_by_block_number_and_index(block_number, tx_idx)
             .unwrap();
@@ -428,10 +592,30 @@ impl ServerInstance {
         #[cfg(debug_assertions)]
         println!("Getting tx by hash {:?}", tx_hash);
 
-        let mut db = self.db_mutex.lock().unwrap();
+        let db = self.db.read().unwrap();
         db.get_tx_by_hash(tx_hash).unwrap_or(None)
     }
 
+    /// Returns the state hash of the block, None if the block doesn't exist or it was added
+    /// before state hashes were computed
+    pub fn get_block_state_hash(&self, block_number: u64) -> Option<B256> {
+        #[cfg(debug_assertions)]
+        println!("Getting block state hash for block {}", block_number);
+
+        let db = self.db.read().unwrap();
+        db.get_block_state_hash(block_number).unwrap_or(None)
+    }
+
+    /// Returns the Bitcoin data read by the precompiles while executing the transaction,
+    /// None if the transaction doesn't exist or it didn't read any
+    pub fn get_tx_bitcoin_data(&self, tx_hash: B256) -> Option<BitcoinDataED> {
+        #[cfg(debug_assertions)]
+        println!("Getting bitcoin data for tx {:?}", tx_hash);
+
+        let db = self.db.read().unwrap();
+        db.get_tx_bitcoin_data(tx_hash).unwrap_or(None)
+    }
+
     pub fn get_transaction_receipt_by_inscription_id(
         &self,
         inscription_id: String,
@@ -439,7 +623,7 @@ impl ServerInstance {
         #[cfg(debug_assertions)]
         println!("Getting tx receipt by inscription id {:?}", inscription_id);
 
-        let mut db = self.db_mutex.lock().unwrap();
+        let db = self.db.read().unwrap();
         let tx_hash = db
             .get_tx_hash_by_inscription_id(inscription_id)
             .unwrap_or(None);
@@ -453,7 +637,7 @@ impl ServerInstance {
         #[cfg(debug_assertions)]
         println!("Getting tx receipt for {:?}", tx_hash);
 
-        let mut db = self.db_mutex.lock().unwrap();
+        let db = self.db.read().unwrap();
         db.get_tx_receipt(tx_hash).unwrap_or(None)
     }
 
@@ -461,28 +645,136 @@ impl ServerInstance {
         &self,
         block_number_from: Option<u64>,
         block_number_to: Option<u64>,
-        address: Option<Address>,
-        topics: Option<Vec<B256>>,
-    ) -> Vec<LogResponseED> {
+        addresses: Vec<Address>,
+        topics: Vec<Vec<B256>>,
+    ) -> Result<Vec<LogResponseED>, &'static str> {
         #[cfg(debug_assertions)]
         println!("Getting logs");
 
-        let mut db = self.db_mutex.lock().unwrap();
-        db.get_logs(
-            block_number_from,
-            block_number_to,
-            address,
-            topics.unwrap_or(Vec::new()),
+        let db = self.db.read().unwrap();
+        let logs = db
+            .get_logs(
+                block_number_from,
+                block_number_to,
+                addresses,
+                topics,
+                *MAX_LOG_RESULTS,
+            )
+            .map_err(|_| "Error while getting logs")?;
+        if logs.len() > *MAX_LOG_RESULTS {
+            return Err(
+                "Query returned too many logs, use a smaller block range or a more specific filter",
+            );
+        }
+        Ok(logs)
+    }
+
+    pub fn get_block_number_by_hash(&self, block_hash: B256) -> Option<u64> {
+        let db = self.db.read().unwrap();
+        db.get_block_number(block_hash)
+            .unwrap_or(None)
+            .map(|x| x.to_u64())
+    }
+
+    pub fn new_filter(&self, filter_type: FilterType) -> U256 {
+        #[cfg(debug_assertions)]
+        println!("Creating new filter {:?}", filter_type);
+
+        let latest_block_height = self.get_latest_block_height();
+        let mut filters = self.filters.lock().unwrap();
+        filters.new_filter(filter_type, latest_block_height)
+    }
+
+    pub fn get_filter_changes(&self, filter_id: U256) -> Result<FilterChanges, &'static str> {
+        #[cfg(debug_assertions)]
+        println!("Getting filter changes for filter {:?}", filter_id);
+
+        let latest_block_height = self.get_latest_block_height();
+        let (filter_type, range) = self
+            .filters
+            .lock()
+            .unwrap()
+            .poll(filter_id, latest_block_height)
+            .ok_or("filter not found")?;
+
+        let changes = match filter_type {
+            FilterType::Blocks => {
+                let db = self.db.read().unwrap();
+                let mut hashes = Vec::new();
+                for block_number in range.clone() {
+                    if let Some(hash) = db.get_block_hash(block_number).unwrap_or(None) {
+                        hashes.push(B256ED::from_b256(hash));
+                    }
+                }
+                FilterChanges::Hashes(hashes)
+            }
+            FilterType::Logs(log_filter) => {
+                let logs = match log_filter.clamp_range(range.clone()) {
+                    Some(range) => self.get_filter_range_logs(&log_filter, range)?,
+                    None => Vec::new(),
+                };
+                FilterChanges::Logs(logs)
+            }
+        };
+
+        self.filters.lock().unwrap().advance(filter_id, &range);
+        Ok(changes)
+    }
+
+    pub fn get_filter_logs(&self, filter_id: U256) -> Result<Vec<LogResponseED>, &'static str> {
+        #[cfg(debug_assertions)]
+        println!("Getting filter logs for filter {:?}", filter_id);
+
+        let filter_type = self
+            .filters
+            .lock()
+            .unwrap()
+            .get(filter_id)
+            .ok_or("filter not found")?;
+        let FilterType::Logs(log_filter) = filter_type else {
+            return Err("filter is not a log filter");
+        };
+
+        let latest_block_height = self.get_latest_block_height();
+        match log_filter.clamp_range(0..=latest_block_height) {
+            Some(range) => self.get_filter_range_logs(&log_filter, range),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    pub fn uninstall_filter(&self, filter_id: U256) -> bool {
+        #[cfg(debug_assertions)]
+        println!("Uninstalling filter {:?}", filter_id);
+
+        self.filters.lock().unwrap().uninstall(filter_id)
+    }
+
+    fn get_filter_range_logs(
+        &self,
+        log_filter: &LogFilter,
+        range: RangeInclusive<u64>,
+    ) -> Result<Vec<LogResponseED>, &'static str> {
+        self.get_logs(
+            Some(*range.start()),
+            Some(*range.end()),
+            log_filter.addresses.clone(),
+            log_filter.topics.clone(),
         )
-        .unwrap_or(Vec::new())
     }
 
+    pub fn subscribe_chain_events(&self) -> broadcast::Receiver<ChainEvent> {
+        self.chain_events.subscribe()
+    }
+
+    /// Finalises the block, if expected_state_hash is given and the state hash of the block is different,
+    /// the block is discarded and an error is returned
     pub fn finalise_block(
         &self,
         timestamp: u64,
         block_number: u64,
         block_hash: B256,
         block_tx_count: u64,
+        expected_state_hash: Option<B256>,
     ) -> Result<(), &'static str> {
         let mut last_block_info = self.last_block_info.lock().unwrap();
 
@@ -503,7 +795,7 @@ impl ServerInstance {
             return Err("Block tx count is different from waiting tx count for block");
         }
 
-        let mut db = self.db_mutex.lock().unwrap();
+        let mut db = self.db.write().unwrap();
 
         #[cfg(debug_assertions)]
         println!(
@@ -524,14 +816,111 @@ impl ServerInstance {
             .unwrap();
         db.set_block_timestamp(block_number, timestamp).unwrap();
         db.set_block_hash(block_number, block_hash).unwrap();
+        let state_hash = db
+            .set_block_roots(block_number)
+            .map_err(|_| "Failed to compute the state root")?;
+
+        if expected_state_hash.is_some_and(|expected_state_hash| expected_state_hash != state_hash)
+        {
+            Self::discard_pending_block(&mut db, &mut last_block_info, block_number);
+            return Err("Block state hash is different from the expected state hash");
+        }
 
         *last_block_info = LastBlockInfo::new();
 
+        // Sending fails only if there are no subscribers
+        let _ = self.chain_events.send(ChainEvent::NewBlock(block_number));
+
         Ok(())
     }
 
-    pub fn view_contract(&self, tx_info: &TxInfo) -> Result<TxReceiptED, &'static str> {
-        self.call_contract(tx_info, false)
+    /// Calls a contract without saving the changes, on the pending state if block_number is None
+    ///
+    /// Pending state includes the transactions of the block that's being built
+    ///
+    /// Overrides are applied on top of the state and the block of the call
+    pub fn view_contract(
+        &self,
+        tx_info: &TxInfo,
+        params: &CallParams,
+        block_number: Option<u64>,
+        overrides: &CallOverrides,
+    ) -> Result<TxReceiptED, &'static str> {
+        #[cfg(debug_assertions)]
+        println!(
+            "Calling contract from: {:?} to: {:?} in block {:?}",
+            tx_info.from, tx_info.to, block_number
+        );
+        self.with_call_env(block_number, |env| {
+            Self::run_call(env, tx_info, params, overrides)?.ok_or("Error while calling contract")
+        })
+    }
+
+    /// Finds the minimum gas limit the call succeeds with, same as view_contract otherwise
+    ///
+    /// Runs the call with the gas limit of params first, then does a binary search between
+    /// the gas used and this limit, as the gas used can be lower than the gas needed because of
+    /// refunds and the 63/64 rule for subcalls. All runs use the same state, the database
+    /// stays locked for reading until the search ends.
+    pub fn estimate_gas(
+        &self,
+        tx_info: &TxInfo,
+        params: &CallParams,
+        block_number: Option<u64>,
+        overrides: &CallOverrides,
+    ) -> Result<GasEstimate, &'static str> {
+        self.with_call_env(block_number, |env| {
+            let receipt = Self::run_call(env, tx_info, params, overrides)?
+                .ok_or("Error while calling contract")?;
+            let mut high = params.get_gas_limit(&tx_info.data);
+            if receipt.status == 1 {
+                let mut low = receipt.gas_used.saturating_sub(1);
+                let mut params = CallParams {
+                    access_list: params.access_list.clone(),
+                    ..*params
+                };
+                while low + 1 < high {
+                    let gas_limit = low + (high - low) / 2;
+                    params.gas_limit = Some(gas_limit);
+                    // Failures with a lower gas limit mean it's too low, even if they aren't
+                    // out of gas, same for transactions rejected for a limit below the intrinsic
+                    // gas. Database errors are returned.
+                    let succeeded = Self::run_call(env, tx_info, &params, overrides)?
+                        .is_some_and(|receipt| receipt.status == 1);
+                    if succeeded {
+                        high = gas_limit;
+                    } else {
+                        low = gas_limit;
+                    }
+                }
+            }
+            Ok(GasEstimate {
+                receipt,
+                gas_limit: high,
+            })
+        })
+    }
+
+    /// Finds the minimum inscription byte length a brc20_deploy or brc20_call succeeds with on
+    /// the pending state, the gas limit is at most the gas limit of max_inscription_byte_len
+    /// bytes and RPC_GAS_CAP. Same as estimate_gas otherwise, the receipt status is 0 if the
+    /// transaction needs more.
+    pub fn estimate_inscription_size(
+        &self,
+        tx_info: &TxInfo,
+        max_inscription_byte_len: u64,
+    ) -> Result<InscriptionEstimate, &'static str> {
+        let params = CallParams {
+            gas_limit: Some(get_gas_limit(max_inscription_byte_len)),
+            ..Default::default()
+        };
+        let gas = self.estimate_gas(tx_info, &params, None, &CallOverrides::default())?;
+        let inscription_byte_len = get_inscription_byte_len(gas.gas_limit);
+        Ok(InscriptionEstimate {
+            gas,
+            inscription_byte_len,
+            padding_byte_len: inscription_byte_len.saturating_sub(tx_info.data.len() as u64),
+        })
     }
 
     pub fn call_contract(
@@ -544,76 +933,190 @@ impl ServerInstance {
             "Calling contract from: {:?} to: {:?}",
             tx_info.from, tx_info.to
         );
-        self.require_no_waiting_txes()?;
-
-        let number = self.get_next_block_height();
-
-        if commit {
-            let hash = {
-                // just hash the number
-                let bytes = number.to_be_bytes();
-                let full_bytes = [0u8; 24]
-                    .iter()
-                    .chain(bytes.iter())
-                    .copied()
-                    .collect::<Vec<u8>>();
-                B256::from_slice(&full_bytes)
-            };
-            let result = self.add_tx_to_block(
-                0,
+        if !commit {
+            return self.view_contract(
                 tx_info,
-                0,
-                number,
-                hash,
+                &CallParams::default(),
                 None,
-                Some(tx_info.data.len() as u64),
+                &CallOverrides::default(),
             );
-            self.finalise_block(0, number, hash, 1)?;
-            return result;
         }
+        Self::require_no_waiting_txes(&self.last_block_info.lock().unwrap())?;
 
-        let timestamp = std::time::UNIX_EPOCH.elapsed().unwrap().as_secs();
-        let block_info: BlockEnv = BlockEnv {
-            number,
-            timestamp,
-            ..Default::default()
+        let number = self.get_next_block_height();
+        let hash = {
+            // just hash the number
+            let bytes = number.to_be_bytes();
+            let full_bytes = [0u8; 24]
+                .iter()
+                .chain(bytes.iter())
+                .copied()
+                .collect::<Vec<u8>>();
+            B256::from_slice(&full_bytes)
         };
+        let result = self.add_tx_to_block(
+            0,
+            tx_info,
+            0,
+            number,
+            hash,
+            None,
+            Some(tx_info.data.len() as u64),
+        );
+        self.finalise_block(0, number, hash, 1, None)?;
+        result
+    }
 
-        let output;
-        let nonce = self.get_nonce(tx_info.from);
-        let txhash = get_tx_hash(&tx_info, &nonce);
-
-        {
-            let mut db = self.db_mutex.lock().unwrap();
-            let db_moved = core::mem::take(&mut *db);
-            let mut evm = get_evm(block_info, db_moved, None);
-
-            evm.ctx().modify_tx(|tx| {
-                tx.chain_id = Some(331337);
-                tx.caller = tx_info.from;
-                tx.kind = tx_info
-                    .to
-                    .map(|x| TransactTo::Call(x))
-                    .unwrap_or(TransactTo::Create);
-                tx.data = tx_info.data.clone();
-                tx.nonce = nonce;
-                // Use 10x gas limit for call
-                tx.gas_limit = get_gas_limit(tx_info.data.len() as u64) * 10;
-            });
-
-            let tx = evm.ctx().tx().clone();
+    /// Runs f on the state and the block a call runs on, on the pending state if block_number is None
+    ///
+    /// Calls, traces and gas estimates all use this, so they run on the same block.
+    /// The database stays read-locked while f runs, so repeated runs see the same state
+    fn with_call_env<T>(
+        &self,
+        block_number: Option<u64>,
+        f: impl FnOnce(&CallEnv) -> Result<T, &'static str>,
+    ) -> Result<T, &'static str> {
+        let db;
+        let env = match block_number {
+            Some(block_number) => {
+                db = self.db.read().unwrap();
+                Self::require_block_exists(&db, block_number)?;
+                let timestamp = db
+                    .get_block_timestamp(block_number)
+                    .map_err(|_| "Error while getting block")?
+                    .map_or(0, |x| x.to::<u64>());
+                let block_hash = db
+                    .get_block_hash(block_number)
+                    .map_err(|_| "Error while getting block")?;
+                CallEnv {
+                    state: db
+                        .get_state_view(Some(block_number))
+                        .map_err(|_| "State is not available for this block")?,
+                    block_info: BlockEnv {
+                        number: block_number,
+                        timestamp,
+                        ..Default::default()
+                    },
+                    block_hash,
+                    bitcoin_cache: db.get_bitcoin_cache(),
+                }
+            }
+            None => {
+                // Call runs in the block that's being built if there are waiting txes, otherwise in a new block
+                let (timestamp, block_hash) = {
+                    let last_block_info = self.last_block_info.lock().unwrap();
+                    if last_block_info.waiting_tx_count != 0 {
+                        (
+                            last_block_info.last_ts,
+                            Some(last_block_info.last_block_hash),
+                        )
+                    } else {
+                        (std::time::UNIX_EPOCH.elapsed().unwrap().as_secs(), None)
+                    }
+                };
+                let number = self.get_next_block_height();
+                db = self.db.read().unwrap();
+                CallEnv {
+                    state: StateView::latest(&db),
+                    block_info: BlockEnv {
+                        number,
+                        timestamp,
+                        ..Default::default()
+                    },
+                    block_hash,
+                    bitcoin_cache: db.get_bitcoin_cache(),
+                }
+            }
+        };
+        f(&env)
+    }
 
-            output = evm.transact(tx).map(|x| x.result);
-            core::mem::swap(&mut *db, &mut evm.ctx().db());
-        }
+    /// Runs a call in the given environment, changes are not saved
+    ///
+    /// Returns None if the EVM rejects the transaction, e.g. if the gas limit is below the
+    /// intrinsic gas, other errors are returned as errors
+    /// Returns the block, its hash and the transaction of a call, with the overrides applied
+    /// to them and to the state
+    fn get_call_tx<D: DatabaseRef>(
+        env: &CallEnv,
+        tx_info: &TxInfo,
+        params: &CallParams,
+        overrides: &CallOverrides,
+        state: &mut CacheDB<D>,
+    ) -> Result<(BlockEnv, Option<B256>, TxEnv), &'static str> {
+        let mut block_info = env.block_info.clone();
+        overrides.apply_to_block(&mut block_info);
+        // Hash of an overridden block is unknown
+        let block_hash = env.block_hash.filter(|_| overrides.block.number.is_none());
+
+        overrides
+            .apply_to_state(state)
+            .map_err(|_| "Error while applying state overrides")?;
+        let nonce = match params.nonce {
+            Some(nonce) => nonce,
+            None => state
+                .basic(tx_info.from)
+                .map_err(|_| "Error while getting nonce")?
+                .map_or(0, |x| x.nonce),
+        };
+        let mut tx = TxEnv {
+            chain_id: Some(331337),
+            caller: tx_info.from,
+            kind: tx_info
+                .to
+                .map(TransactTo::Call)
+                .unwrap_or(TransactTo::Create),
+            data: tx_info.data.clone(),
+            nonce,
+            ..Default::default()
+        };
+        params.apply_to_tx(&mut tx);
+        Ok((block_info, block_hash, tx))
+    }
 
-        if output.is_err() {
-            return Err("Error while calling contract");
+    fn run_call(
+        env: &CallEnv,
+        tx_info: &TxInfo,
+        params: &CallParams,
+        overrides: &CallOverrides,
+    ) -> Result<Option<TxReceiptED>, &'static str> {
+        let mut state = CacheDB::new(&env.state);
+        let (block_info, block_hash, tx) =
+            Self::get_call_tx(env, tx_info, params, overrides, &mut state)?;
+        let nonce = tx.nonce;
+        let txhash = get_tx_hash(tx_info, &nonce);
+
+        let mut evm = get_evm_with_inspector(block_info.clone(), state, None, NoOpInspector);
+        if params.nonce.is_some() {
+            evm.ctx().modify_cfg(|cfg| cfg.disable_nonce_check = true);
         }
+        *evm.bitcoin_data() = BitcoinData::new(env.bitcoin_cache.clone());
+        evm.set_block_hash(block_hash);
+        let output = match evm.transact(tx) {
+            Ok(output) => output.result,
+            Err(EVMError::Transaction(_)) => return Ok(None),
+            Err(_) => return Err("Error while calling contract"),
+        };
 
-        let output = output.as_ref().unwrap();
+        Ok(Some(Self::get_call_receipt(
+            &output,
+            tx_info,
+            txhash,
+            nonce,
+            block_info.number,
+            block_info.timestamp,
+        )))
+    }
 
-        Ok(TxReceiptED {
+    fn get_call_receipt(
+        output: &ExecutionResult,
+        tx_info: &TxInfo,
+        txhash: B256,
+        nonce: u64,
+        number: u64,
+        timestamp: u64,
+    ) -> TxReceiptED {
+        TxReceiptED {
             status: output.is_success() as u8,
             transaction_result: get_result_type(output),
             reason: get_result_reason(output),
@@ -636,20 +1139,172 @@ impl ServerInstance {
             nonce,
             effective_gas_price: 0,
             transaction_type: 0,
+        }
+    }
+
+    /// Re-executes a transaction on the state before it and traces it
+    pub fn trace_transaction(
+        &self,
+        tx_hash: B256,
+        tracer: &TracerType,
+    ) -> Result<TraceResult, &'static str> {
+        #[cfg(debug_assertions)]
+        println!("Tracing tx {:?}", tx_hash);
+
+        let db = self.db.read().unwrap();
+        let receipt = db
+            .get_tx_receipt(tx_hash)
+            .map_err(|_| "Error while getting transaction")?
+            .ok_or("Transaction not found")?;
+        let (block_info, _, txes) = Self::get_block_txes(&db, receipt.block_number)?;
+        let tx_idx = receipt.transaction_index as usize;
+        if tx_idx >= txes.len() {
+            return Err("Transaction not found");
+        }
+        let txes = txes.into_iter().take(tx_idx + 1).collect();
+
+        let block_hash = db
+            .get_block_hash(receipt.block_number)
+            .map_err(|_| "Error while getting block")?;
+        let bitcoin_cache = db.get_bitcoin_cache();
+        let state = db
+            .get_state_view(receipt.block_number.checked_sub(1))
+            .map_err(|_| "State is not available for this block")?;
+        trace_txes(
+            state,
+            block_info,
+            block_hash,
+            txes,
+            tx_idx,
+            tracer,
+            bitcoin_cache,
+        )?
+        .pop()
+        .ok_or("Error while tracing transaction")
+    }
+
+    /// Re-executes all transactions of a block on the state before it and traces them
+    pub fn trace_block(
+        &self,
+        block_number: u64,
+        tracer: &TracerType,
+    ) -> Result<Vec<BlockTraceResult>, &'static str> {
+        #[cfg(debug_assertions)]
+        println!("Tracing block 0x{:x} ({})", block_number, block_number);
+
+        let db = self.db.read().unwrap();
+        let Some(block_hash) = db.get_block_hash(block_number).unwrap_or(None) else {
+            return Err("Block not found");
+        };
+        let (block_info, tx_hashes, txes) = Self::get_block_txes(&db, block_number)?;
+
+        let bitcoin_cache = db.get_bitcoin_cache();
+        let state = db
+            .get_state_view(block_number.checked_sub(1))
+            .map_err(|_| "State is not available for this block")?;
+        let results = trace_txes(
+            state,
+            block_info,
+            Some(block_hash),
+            txes,
+            0,
+            tracer,
+            bitcoin_cache,
+        )?;
+        Ok(tx_hashes
+            .into_iter()
+            .zip(results)
+            .map(|(tx_hash, result)| BlockTraceResult {
+                tx_hash: B256ED::from_b256(tx_hash),
+                result,
+            })
+            .collect())
+    }
+
+    /// Traces a call on the state and the block eth_call runs on, the pending state if block_number is None
+    /// Traces a call, same as view_contract
+    pub fn trace_call(
+        &self,
+        tx_info: &TxInfo,
+        params: &CallParams,
+        block_number: Option<u64>,
+        overrides: &CallOverrides,
+        tracer: &TracerType,
+    ) -> Result<TraceResult, &'static str> {
+        #[cfg(debug_assertions)]
+        println!("Tracing call from: {:?} to: {:?}", tx_info.from, tx_info.to);
+
+        self.with_call_env(block_number, |env| {
+            let mut state = CacheDB::new(&env.state);
+            let (block_info, block_hash, mut tx) =
+                Self::get_call_tx(env, tx_info, params, overrides, &mut state)?;
+            // Nonce isn't checked in calls, the nonce of the account runs the same and passes
+            // the check of the tracer
+            if params.nonce.is_some() {
+                tx.nonce = state
+                    .basic(tx_info.from)
+                    .map_err(|_| "Error while getting nonce")?
+                    .map_or(0, |x| x.nonce);
+            }
+            trace_txes(
+                &state,
+                block_info,
+                block_hash,
+                vec![(tx, BitcoinDataED::default())],
+                0,
+                tracer,
+                env.bitcoin_cache.clone(),
+            )?
+            .pop()
+            .ok_or("Error while tracing call")
         })
     }
 
-    pub fn get_storage_at(&self, contract: Address, location: U256) -> U256 {
+    /// Returns the storage value, at the end of the given block or the current one if block_number is None
+    pub fn get_storage_at(
+        &self,
+        contract: Address,
+        location: U256,
+        block_number: Option<u64>,
+    ) -> Result<U256, &'static str> {
         #[cfg(debug_assertions)]
         println!(
             "Getting storage at {:?} for contract {:?}",
             location, contract
         );
 
-        let mut db = self.db_mutex.lock().unwrap();
-        let storage = db.storage(contract, location);
+        let db = self.db.read().unwrap();
+        let Some(block_number) = block_number else {
+            return Ok(db.storage_ref(contract, location).unwrap_or(U256::ZERO));
+        };
+        Self::require_block_exists(&db, block_number)?;
+        db.get_state_view(Some(block_number))
+            .map_err(|_| "State is not available for this block")?
+            .storage_ref(contract, location)
+            .map_err(|_| "Error while getting storage")
+    }
 
-        storage.unwrap_or(U256::ZERO)
+    /// Returns the account and storage proofs, at the end of the given block or the latest one if block_number is None
+    pub fn get_proof(
+        &self,
+        account: Address,
+        storage_keys: &[U256],
+        block_number: Option<u64>,
+    ) -> Result<AccountProofED, &'static str> {
+        #[cfg(debug_assertions)]
+        println!("Getting proof for {:?}", account);
+
+        let db = self.db.read().unwrap();
+        let block_number = match block_number {
+            Some(block_number) => block_number,
+            None => db
+                .get_latest_block_height()
+                .map_err(|_| "Error while getting latest block")?,
+        };
+        Self::require_block_exists(&db, block_number)?;
+        db.get_proof(account, storage_keys, block_number)
+            .map_err(|_| "Error while getting proof")?
+            .ok_or("State root is not available for this block")
     }
 
     pub fn get_block_by_number(&self, block_number: u64, is_full: bool) -> Option<BlockResponseED> {
@@ -659,7 +1314,7 @@ impl ServerInstance {
             block_number, block_number
         );
 
-        let mut db = self.db_mutex.lock().unwrap();
+        let db = self.db.read().unwrap();
         let block = db.get_block(block_number).unwrap();
         if block.is_none() || !is_full {
             return block;
@@ -688,7 +1343,7 @@ impl ServerInstance {
         #[cfg(debug_assertions)]
         println!("Getting block by hash {:?}", block_hash);
 
-        let mut db = self.db_mutex.lock().unwrap();
+        let db = self.db.read().unwrap();
         let block_number = db.get_block_number(block_hash).unwrap();
 
         if block_number.is_none() {
@@ -701,37 +1356,60 @@ impl ServerInstance {
         self.get_block_by_number(block_number.unwrap().to_u64(), is_full)
     }
 
-    pub fn get_contract_bytecode(&self, addr: Address) -> Option<Bytes> {
+    /// Returns the contract code, at the end of the given block or the current one if block_number is None
+    pub fn get_contract_bytecode(
+        &self,
+        addr: Address,
+        block_number: Option<u64>,
+    ) -> Result<Option<Bytes>, &'static str> {
         #[cfg(debug_assertions)]
         println!("Getting contract bytecode for {:?}", addr);
 
-        let mut db = self.db_mutex.lock().unwrap();
-        let acct = db.basic(addr).unwrap();
+        let db = self.db.read().unwrap();
+        let acct = match block_number {
+            Some(block_number) => {
+                Self::require_block_exists(&db, block_number)?;
+                db.get_state_view(Some(block_number))
+                    .map_err(|_| "State is not available for this block")?
+                    .basic_ref(addr)
+                    .map_err(|_| "Error while getting account")?
+            }
+            None => db.basic_ref(addr).unwrap(),
+        };
         if acct.is_none() {
-            return None;
+            return Ok(None);
         }
         let bytecode = db.get_code(acct.unwrap().code_hash).unwrap();
 
-        bytecode.map(|x| x.0.bytes())
+        Ok(bytecode.map(|x| x.0.bytes()))
     }
 
     pub fn clear_caches(&self) {
         #[cfg(debug_assertions)]
         println!("Clearing caches");
 
-        let mut db = self.db_mutex.lock().unwrap();
         let mut last_block_info = self.last_block_info.lock().unwrap();
+        let mut db = self.db.write().unwrap();
 
         db.clear_caches();
         *last_block_info = LastBlockInfo::new();
+
+        // Blocks that weren't committed are gone, treat this as a reorg for filters and subscriptions
+        let latest_block_height = db.get_latest_block_height().unwrap_or(0);
+        self.filters.lock().unwrap().reorg(latest_block_height);
+        let _ = self
+            .chain_events
+            .send(ChainEvent::Reorg(latest_block_height));
     }
 
     pub fn commit_to_db(&self) -> Result<(), &'static str> {
         #[cfg(debug_assertions)]
         println!("Committing to db");
-        self.require_no_waiting_txes()?;
+        // Held until the commit ends, so no transaction can be added in between
+        let last_block_info = self.last_block_info.lock().unwrap();
+        Self::require_no_waiting_txes(&last_block_info)?;
 
-        let mut db = self.db_mutex.lock().unwrap();
+        let mut db = self.db.write().unwrap();
         db.commit_changes().unwrap();
         Ok(())
     }
@@ -743,34 +1421,423 @@ impl ServerInstance {
             latest_valid_block_number, latest_valid_block_number
         );
 
-        self.require_no_waiting_txes()?;
+        // Held until the reorg ends, so transactions that are being added can't commit state
+        // of the reverted blocks
+        let last_block_info = self.last_block_info.lock().unwrap();
+        Self::require_no_waiting_txes(&last_block_info)?;
 
-        let current_block_height = self.get_latest_block_height();
+        let mut db = self.db.write().unwrap();
+        let current_block_height = db.get_latest_block_height().unwrap_or(0);
         if latest_valid_block_number > current_block_height {
             return Err("Latest valid block number is greater than current block height");
         }
-        if current_block_height - latest_valid_block_number > MAX_HISTORY_SIZE {
+        if db.get_max_reorg_depth().is_some_and(|max_reorg_depth| {
+            current_block_height - latest_valid_block_number > max_reorg_depth
+        }) {
             return Err("Latest valid block number is too far behind current block height");
         }
+        // Balances might already be set for the next block, they are reverted even if no block is
+        // reorged
+        if let Some(snapshot) = BRC20_BALANCE_SNAPSHOT.as_ref() {
+            snapshot.reorg(latest_valid_block_number);
+        }
         if latest_valid_block_number == current_block_height {
             return Ok(());
         }
 
-        let mut db = self.db_mutex.lock().unwrap();
         db.reorg(latest_valid_block_number).unwrap();
+
+        self.filters
+            .lock()
+            .unwrap()
+            .reorg(latest_valid_block_number);
+        let _ = self
+            .chain_events
+            .send(ChainEvent::Reorg(latest_valid_block_number));
         Ok(())
     }
 
-    fn require_no_waiting_txes(&self) -> Result<(), &'static str> {
-        let last_block_info = self.last_block_info.lock().unwrap();
+    fn require_block_exists(db: &DB, block_number: u64) -> Result<(), &'static str> {
+        if db.get_block_hash(block_number).unwrap_or(None).is_none() {
+            return Err("Block not found");
+        }
+        Ok(())
+    }
+
+    fn require_no_waiting_txes(last_block_info: &LastBlockInfo) -> Result<(), &'static str> {
         if last_block_info.waiting_tx_count != 0 {
             return Err("There are waiting txes, either finalise the block or clear caches");
         }
         Ok(())
     }
 
+    /// Returns the block environment and the transactions of a block in order, with their hashes
+    fn get_block_txes(db: &DB, block_number: u64) -> Result<BlockTxes, &'static str> {
+        let mut block_info = BlockEnv {
+            number: block_number,
+            ..Default::default()
+        };
+        let mut tx_hashes = Vec::new();
+        let mut txes = Vec::new();
+        loop {
+            let tx_hash = db
+                .get_tx_hash_by_block_number_and_index(block_number, txes.len() as u64)
+                .map_err(|_| "Error while getting transaction")?;
+            let Some(tx_hash) = tx_hash else {
+                break;
+            };
+            let tx = db
+                .get_tx_by_hash(tx_hash.0)
+                .map_err(|_| "Error while getting transaction")?
+                .ok_or("Transaction not found")?;
+            let receipt = db
+                .get_tx_receipt(tx_hash.0)
+                .map_err(|_| "Error while getting transaction")?
+                .ok_or("Transaction not found")?;
+            // Transactions stored before gas limits were recorded have 0 gas, their limit
+            // depends on the inscription length which isn't known, so they can't be replayed
+            if tx.gas == 0 {
+                return Err("Transaction was stored without its gas limit, it can't be traced");
+            }
+            let bitcoin_data = db
+                .get_tx_bitcoin_data(tx_hash.0)
+                .map_err(|_| "Error while getting transaction")?
+                .unwrap_or_default();
+            // Timestamp is the same for all transactions in a block
+            block_info.timestamp = receipt.block_timestamp;
+            tx_hashes.push(tx_hash.0);
+            txes.push((
+                TxEnv {
+                    chain_id: Some(331337),
+                    caller: tx.from.0,
+                    kind: tx
+                        .to
+                        .map(|x| TransactTo::Call(x.0))
+                        .unwrap_or(TransactTo::Create),
+                    nonce: tx.nonce,
+                    gas_limit: tx.gas,
+                    data: tx.input,
+                    gas_price: 0,
+                    ..Default::default()
+                },
+                bitcoin_data,
+            ));
+        }
+        Ok((block_info, tx_hashes, txes))
+    }
+
     fn get_nonce(&self, addr: Address) -> u64 {
-        let mut db = self.db_mutex.lock().unwrap();
-        db.basic(addr).unwrap().map_or(0, |x| x.nonce)
+        let db = self.db.read().unwrap();
+        db.basic_ref(addr).unwrap().map_or(0, |x| x.nonce)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::{Arc, Barrier};
+    use std::thread;
+
+    use revm::primitives::hex;
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::evm::tracers::CallTracerConfig;
+    use crate::server::types::AccountOverride;
+
+    /// Stores 42 in slot 0, the deployed code returns slot 0
+    const STORAGE_CONTRACT: &str = "602a600055600b6011600039600b6000f360005460005260206000f3";
+    /// The deployed code sets slots 0 to 29 to 1, which needs more than the minimum gas limit
+    const LOOP_CONTRACT: &str = "6012600c60003960126000f360005b6001815560010180601e1160025700";
+    /// The deployed code increments slot 0 and returns the new value
+    const COUNTER_CONTRACT: &str = "6012600c60003960126000f36000546001018060005560005260206000f3";
+
+    fn new_server() -> ServerInstance {
+        let path = TempDir::new().unwrap().into_path();
+        let server = ServerInstance::new(DB::new(&path).unwrap());
+        server
+            .finalise_block(0, 0, B256::from([1; 32]), 0, None)
+            .unwrap();
+        server
+    }
+
+    fn deploy_tx(code: &str) -> TxInfo {
+        TxInfo {
+            from: Address::from([1; 20]),
+            to: None,
+            data: Bytes::from(hex::decode(code).unwrap()),
+        }
+    }
+
+    fn call_tx(contract: Address) -> TxInfo {
+        TxInfo {
+            from: Address::from([1; 20]),
+            to: Some(contract),
+            data: Bytes::new(),
+        }
+    }
+
+    fn result_of(receipt: TxReceiptED) -> U256 {
+        let result_bytes = receipt.result_bytes.unwrap_or_default();
+        if result_bytes.is_empty() {
+            return U256::ZERO;
+        }
+        U256::from_be_slice(&result_bytes)
+    }
+
+    #[test]
+    fn test_pending_and_latest_calls() {
+        let server = new_server();
+        let block_hash = B256::from([2; 32]);
+        let contract = server
+            .add_tx_to_block(
+                0,
+                &deploy_tx(STORAGE_CONTRACT),
+                0,
+                1,
+                block_hash,
+                None,
+                None,
+            )
+            .unwrap()
+            .contract_address
+            .unwrap()
+            .0;
+        let params = CallParams::default();
+        let overrides = CallOverrides::default();
+
+        // Pending state has the transaction that isn't finalised yet, latest block doesn't
+        let latest_block_number = server.get_next_block_height().checked_sub(1);
+        assert_eq!(latest_block_number, Some(0));
+        let pending = server
+            .view_contract(&call_tx(contract), &params, None, &overrides)
+            .unwrap();
+        assert_eq!(result_of(pending), U256::from(42));
+        let latest = server
+            .view_contract(&call_tx(contract), &params, latest_block_number, &overrides)
+            .unwrap();
+        assert_eq!(result_of(latest), U256::ZERO);
+
+        // Gas estimates and traces don't wait for the block either
+        assert!(server
+            .estimate_gas(&call_tx(contract), &params, None, &overrides)
+            .is_ok());
+        assert!(server
+            .estimate_gas(&call_tx(contract), &params, latest_block_number, &overrides)
+            .is_ok());
+        assert!(server
+            .trace_call(
+                &call_tx(contract),
+                &params,
+                None,
+                &overrides,
+                &TracerType::Call(CallTracerConfig::default())
+            )
+            .is_ok());
+
+        // Finalised transaction is in the latest block
+        server.finalise_block(0, 1, block_hash, 1, None).unwrap();
+        let latest = server
+            .view_contract(&call_tx(contract), &params, Some(1), &overrides)
+            .unwrap();
+        assert_eq!(result_of(latest), U256::from(42));
+    }
+
+    #[test]
+    fn test_trace_call_params_and_overrides() {
+        let server = new_server();
+        let block_hash = B256::from([2; 32]);
+        let contract = server
+            .add_tx_to_block(
+                0,
+                &deploy_tx(STORAGE_CONTRACT),
+                0,
+                1,
+                block_hash,
+                None,
+                None,
+            )
+            .unwrap()
+            .contract_address
+            .unwrap()
+            .0;
+        server.finalise_block(0, 1, block_hash, 1, None).unwrap();
+
+        let params = CallParams {
+            gas_limit: Some(50000),
+            nonce: Some(100),
+            ..Default::default()
+        };
+        let mut overrides = CallOverrides::default();
+        overrides.accounts.insert(
+            contract,
+            AccountOverride {
+                state_diff: HashMap::from([(U256::ZERO, U256::from(7))]),
+                ..Default::default()
+            },
+        );
+
+        // Trace runs the same call as view_contract, with the gas limit and the overridden slot
+        let receipt = server
+            .view_contract(&call_tx(contract), &params, None, &overrides)
+            .unwrap();
+        let TraceResult::Call(frame) = server
+            .trace_call(
+                &call_tx(contract),
+                &params,
+                None,
+                &overrides,
+                &TracerType::Call(CallTracerConfig::default()),
+            )
+            .unwrap()
+        else {
+            panic!("Expected a call trace");
+        };
+        assert_eq!(frame.gas, 50000);
+        assert_eq!(frame.gas_used, receipt.gas_used);
+        assert_eq!(U256::from_be_slice(&frame.output), U256::from(7));
+        assert_eq!(result_of(receipt), U256::from(7));
+    }
+
+    #[test]
+    fn test_estimate_inscription_size() {
+        let server = new_server();
+        let block_hash = B256::from([2; 32]);
+
+        // Deploy needs less than the minimum gas limit, so no padding
+        let deploy = server
+            .estimate_inscription_size(&deploy_tx(LOOP_CONTRACT), 1000)
+            .unwrap();
+        assert_eq!(deploy.gas.receipt.status, 1);
+        assert!(deploy.gas.gas_limit <= get_gas_limit(0));
+        assert_eq!(
+            (deploy.inscription_byte_len, deploy.padding_byte_len),
+            (0, 0)
+        );
+        let contract = server
+            .add_tx_to_block(
+                0,
+                &deploy_tx(LOOP_CONTRACT),
+                0,
+                1,
+                block_hash,
+                None,
+                Some(0),
+            )
+            .unwrap()
+            .contract_address
+            .unwrap()
+            .0;
+
+        let call = server
+            .estimate_inscription_size(&call_tx(contract), 1000)
+            .unwrap();
+        assert_eq!(call.gas.receipt.status, 1);
+        assert!(call.gas.gas_limit >= call.gas.receipt.gas_used);
+        assert!(call.gas.gas_limit > get_gas_limit(0));
+        assert_eq!(
+            call.inscription_byte_len,
+            get_inscription_byte_len(call.gas.gas_limit)
+        );
+        // Call has no data, so the whole inscription is padding
+        assert_eq!(call.padding_byte_len, call.inscription_byte_len);
+
+        // Transactions that need more than the maximum length fail
+        let estimate = server
+            .estimate_inscription_size(&call_tx(contract), call.inscription_byte_len - 1)
+            .unwrap();
+        assert_eq!(estimate.gas.receipt.status, 0);
+
+        // Estimated length is the minimum one the call succeeds with
+        let receipt = server
+            .add_tx_to_block(
+                0,
+                &call_tx(contract),
+                1,
+                1,
+                block_hash,
+                None,
+                Some(call.inscription_byte_len - 1),
+            )
+            .unwrap();
+        assert_eq!(receipt.status, 0);
+        let receipt = server
+            .add_tx_to_block(
+                0,
+                &call_tx(contract),
+                2,
+                1,
+                block_hash,
+                None,
+                Some(call.inscription_byte_len),
+            )
+            .unwrap();
+        assert_eq!(receipt.status, 1);
+    }
+
+    #[test]
+    fn test_reorg_while_adding_tx() {
+        for _ in 0..20 {
+            let server = Arc::new(new_server());
+            let contract = server
+                .add_tx_to_block(
+                    0,
+                    &deploy_tx(COUNTER_CONTRACT),
+                    0,
+                    1,
+                    B256::from([2; 32]),
+                    None,
+                    None,
+                )
+                .unwrap()
+                .contract_address
+                .unwrap()
+                .0;
+            server
+                .finalise_block(0, 1, B256::from([2; 32]), 1, None)
+                .unwrap();
+            server
+                .add_tx_to_block(0, &call_tx(contract), 0, 2, B256::from([3; 32]), None, None)
+                .unwrap();
+            server
+                .finalise_block(0, 2, B256::from([3; 32]), 1, None)
+                .unwrap();
+
+            let barrier = Arc::new(Barrier::new(2));
+            let add_tx = {
+                let (server, barrier) = (server.clone(), barrier.clone());
+                thread::spawn(move || {
+                    barrier.wait();
+                    server
+                        .add_tx_to_block(
+                            0,
+                            &call_tx(contract),
+                            0,
+                            3,
+                            B256::from([4; 32]),
+                            None,
+                            None,
+                        )
+                        .unwrap();
+                })
+            };
+            barrier.wait();
+            let reorged = server.reorg(1).is_ok();
+            add_tx.join().unwrap();
+
+            // Either the reorg runs after the transaction and fails as it's waiting, or the
+            // transaction runs on the reorged state, state of reverted blocks is never committed
+            let next_count = result_of(
+                server
+                    .view_contract(
+                        &call_tx(contract),
+                        &CallParams::default(),
+                        None,
+                        &CallOverrides::default(),
+                    )
+                    .unwrap(),
+            );
+            assert_eq!(next_count, U256::from(if reorged { 2 } else { 3 }));
+        }
     }
 }
@@ -0,0 +1,118 @@
+use std::collections::BTreeMap;
+
+use crate::db::types::LogResponseED;
+
+/// Capacity of the chain event channel, slow subscribers skip events after this many
+pub const CHAIN_EVENT_CAPACITY: usize = 1024;
+
+/// Events sent to eth_subscribe subscriptions
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChainEvent {
+    /// Block with the given number is finalised
+    NewBlock(u64),
+    /// Blocks after the given block number are reverted
+    Reorg(u64),
+}
+
+/// Keeps the logs sent to a logs subscription for the blocks that can still be reorged,
+/// so they can be sent again with removed: true if a reorg happens
+pub struct SentLogs {
+    logs: BTreeMap<u64, Vec<LogResponseED>>,
+    /// Number of blocks that can be reorged, None if there is no limit
+    max_reorg_depth: Option<u64>,
+}
+
+impl SentLogs {
+    pub fn new(max_reorg_depth: Option<u64>) -> Self {
+        SentLogs {
+            logs: BTreeMap::new(),
+            max_reorg_depth,
+        }
+    }
+
+    /// Stores the logs sent for a block, and forgets blocks that are too old for a reorg
+    pub fn add(&mut self, block_number: u64, logs: Vec<LogResponseED>) {
+        if !logs.is_empty() {
+            self.logs.insert(block_number, logs);
+        }
+        if let Some(max_reorg_depth) = self.max_reorg_depth {
+            self.logs = self
+                .logs
+                .split_off(&block_number.saturating_sub(max_reorg_depth));
+        }
+    }
+
+    /// Removes the logs of the reverted blocks, and returns them with removed set to true,
+    /// latest logs first
+    pub fn reorg(&mut self, latest_valid_block_number: u64) -> Vec<LogResponseED> {
+        let removed_blocks = self.logs.split_off(&(latest_valid_block_number + 1));
+        removed_blocks
+            .into_values()
+            .rev()
+            .flat_map(|logs| logs.into_iter().rev())
+            .map(|mut log| {
+                log.removed = true;
+                log
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use revm::primitives::{Address, Bytes, B256};
+
+    use super::*;
+    use crate::db::types::{AddressED, B256ED, U64ED};
+    use crate::db::DEFAULT_MAX_REORG_DEPTH;
+
+    fn get_log(block_number: u64, log_index: u64) -> LogResponseED {
+        LogResponseED {
+            address: AddressED(Address::ZERO),
+            topics: Vec::new(),
+            data: Bytes::new(),
+            transaction_index: U64ED::from_u64(0),
+            transaction_hash: B256ED::from_b256(B256::ZERO),
+            block_hash: B256ED::from_b256(B256::ZERO),
+            block_number: U64ED::from_u64(block_number),
+            log_index: U64ED::from_u64(log_index),
+            removed: false,
+        }
+    }
+
+    #[test]
+    fn test_reorg_removes_logs() {
+        let mut sent_logs = SentLogs::new(Some(DEFAULT_MAX_REORG_DEPTH));
+        sent_logs.add(1, vec![get_log(1, 0)]);
+        sent_logs.add(2, vec![get_log(2, 0), get_log(2, 1)]);
+        sent_logs.add(3, vec![get_log(3, 0)]);
+
+        let removed = sent_logs.reorg(1);
+        assert_eq!(removed.len(), 3);
+        assert!(removed.iter().all(|log| log.removed));
+        assert_eq!(removed[0].block_number, U64ED::from_u64(3));
+        assert_eq!(removed[1].log_index, U64ED::from_u64(1));
+        assert_eq!(removed[2].log_index, U64ED::from_u64(0));
+
+        // Removed logs are not sent again
+        assert!(sent_logs.reorg(1).is_empty());
+        assert_eq!(sent_logs.reorg(0).len(), 1);
+    }
+
+    #[test]
+    fn test_old_logs_are_forgotten() {
+        let mut sent_logs = SentLogs::new(Some(DEFAULT_MAX_REORG_DEPTH));
+        sent_logs.add(1, vec![get_log(1, 0)]);
+        sent_logs.add(
+            DEFAULT_MAX_REORG_DEPTH + 2,
+            vec![get_log(DEFAULT_MAX_REORG_DEPTH + 2, 0)],
+        );
+
+        let removed = sent_logs.reorg(0);
+        assert_eq!(removed.len(), 1);
+        assert_eq!(
+            removed[0].block_number,
+            U64ED::from_u64(DEFAULT_MAX_REORG_DEPTH + 2)
+        );
+    }
+}
@@ -1,5 +1,23 @@
+use std::collections::HashMap;
+
+use revm::context::{BlockEnv, TxEnv};
+use revm::context_interface::transaction::{AccessList, TransactionType};
+use revm::database::{AccountState, CacheDB};
 use revm::primitives::alloy_primitives::Bytes;
-use revm::primitives::{keccak256, Address, B256};
+use revm::primitives::{keccak256, Address, B256, U256};
+use revm::DatabaseRef;
+use revm_state::Bytecode;
+
+use crate::db::types::TxReceiptED;
+use crate::evm::get_gas_limit;
+
+lazy_static::lazy_static! {
+    /// Highest gas limit of eth_call and eth_estimateGas, same as the default RPC gas cap of geth
+    pub static ref RPC_GAS_CAP: u64 = std::env::var("BRC20_PROG_RPC_GAS_CAP")
+            .ok()
+            .and_then(|x| x.parse().ok())
+            .unwrap_or(50_000_000);
+}
 
 #[derive(Clone)]
 pub struct TxInfo {
@@ -8,6 +26,15 @@ pub struct TxInfo {
     pub data: Bytes,
 }
 
+/// Transaction of a block added in a single call
+pub struct BlockTxInfo {
+    pub tx_info: TxInfo,
+    /// Contract to call if tx_info.to is not set, resolved when the transaction is executed
+    pub contract_inscription_id: Option<String>,
+    pub inscription_id: Option<String>,
+    pub inscription_byte_len: Option<u64>,
+}
+
 pub fn get_tx_hash(txinfo: &TxInfo, nonce: &u64) -> B256 {
     let mut data = Vec::new();
     data.extend_from_slice(txinfo.from.as_slice());
@@ -20,3 +47,258 @@ pub fn get_tx_hash(txinfo: &TxInfo, nonce: &u64) -> B256 {
     data.extend_from_slice(&txinfo.data);
     keccak256(data)
 }
+
+/// Transaction fields of eth_call and eth_estimateGas that aren't in TxInfo
+#[derive(Default)]
+pub struct CallParams {
+    /// Defaults to 10x the gas limit of an inscription with the call data, capped at RPC_GAS_CAP
+    pub gas_limit: Option<u64>,
+    pub gas_price: u128,
+    pub value: U256,
+    /// Defaults to the nonce of the sender, the nonce isn't checked if it's set
+    pub nonce: Option<u64>,
+    pub access_list: AccessList,
+    /// Derived from the other fields if not set
+    pub tx_type: Option<u8>,
+}
+
+impl CallParams {
+    /// Returns the gas limit of the call with the given data, at most RPC_GAS_CAP
+    pub fn get_gas_limit(&self, data: &Bytes) -> u64 {
+        self.gas_limit
+            .unwrap_or(get_gas_limit(data.len() as u64) * 10)
+            .min(*RPC_GAS_CAP)
+    }
+
+    /// Sets the fields of the call on the transaction, nonce is set by the caller
+    pub fn apply_to_tx(&self, tx: &mut TxEnv) {
+        tx.gas_limit = self.get_gas_limit(&tx.data);
+        tx.gas_price = self.gas_price;
+        tx.value = self.value;
+        tx.access_list = self.access_list.clone();
+        tx.tx_type = self.tx_type.unwrap_or(if self.access_list.is_empty() {
+            TransactionType::Legacy as u8
+        } else {
+            TransactionType::Eip2930 as u8
+        });
+    }
+}
+
+/// Result of eth_estimateGas
+pub struct GasEstimate {
+    /// Receipt of the call with the highest gas limit, if it failed there is no estimate
+    /// and the gas limit is this limit
+    pub receipt: TxReceiptED,
+    /// Minimum gas limit the call succeeds with
+    pub gas_limit: u64,
+}
+
+/// Result of brc20_estimateInscriptionSize
+pub struct InscriptionEstimate {
+    pub gas: GasEstimate,
+    /// Minimum inscription byte length whose gas limit is enough for the transaction
+    pub inscription_byte_len: u64,
+    /// Bytes to add to an inscription with only the data to reach inscription_byte_len
+    pub padding_byte_len: u64,
+}
+
+/// Changes to an account for the duration of a call, same as the geth state overrides
+#[derive(Default)]
+pub struct AccountOverride {
+    pub balance: Option<U256>,
+    pub nonce: Option<u64>,
+    pub code: Option<Bytes>,
+    /// Replaces the whole storage of the account
+    pub state: Option<HashMap<U256, U256>>,
+    /// Changes the given slots, other slots are kept
+    pub state_diff: HashMap<U256, U256>,
+}
+
+/// Changes to the block of a call, same as the geth block overrides
+#[derive(Default)]
+pub struct BlockOverrides {
+    pub number: Option<u64>,
+    pub time: Option<u64>,
+}
+
+/// State and block overrides of eth_call, they are only applied to the state of the call
+/// and never saved
+#[derive(Default)]
+pub struct CallOverrides {
+    pub accounts: HashMap<Address, AccountOverride>,
+    pub block: BlockOverrides,
+}
+
+impl CallOverrides {
+    pub fn apply_to_block(&self, block_info: &mut BlockEnv) {
+        if let Some(number) = self.block.number {
+            block_info.number = number;
+        }
+        if let Some(time) = self.block.time {
+            block_info.timestamp = time;
+        }
+    }
+
+    /// Applies the account overrides to the cache of the call, the database below isn't changed
+    pub fn apply_to_state<D: DatabaseRef>(&self, db: &mut CacheDB<D>) -> Result<(), D::Error> {
+        for (address, account) in &self.accounts {
+            let mut info = db.load_account(*address)?.info.clone();
+            if let Some(balance) = account.balance {
+                info.balance = balance;
+            }
+            if let Some(nonce) = account.nonce {
+                info.nonce = nonce;
+            }
+            if let Some(code) = &account.code {
+                let bytecode = Bytecode::new_raw(code.clone());
+                info.code_hash = bytecode.hash_slow();
+                info.code = Some(bytecode);
+            }
+            db.insert_contract(&mut info);
+            let db_account = db.load_account(*address)?;
+            db_account.info = info;
+            // Overridden accounts exist even if they weren't in the state
+            if db_account.account_state == AccountState::NotExisting {
+                db_account.account_state = AccountState::Touched;
+            }
+
+            if let Some(state) = &account.state {
+                db.replace_account_storage(
+                    *address,
+                    state.iter().map(|(k, v)| (*k, *v)).collect(),
+                )?;
+            }
+            for (slot, value) in &account.state_diff {
+                db.insert_account_storage(*address, *slot, *value)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use revm::context_interface::transaction::AccessListItem;
+    use revm::database::EmptyDB;
+    use revm::Database;
+
+    use super::*;
+
+    #[test]
+    fn test_apply_state_overrides() {
+        let address = Address::from([1u8; 20]);
+        let other_address = Address::from([2u8; 20]);
+        let mut base = CacheDB::new(EmptyDB::default());
+        for state_address in [address, other_address] {
+            base.insert_account_storage(state_address, U256::from(1), U256::from(10))
+                .unwrap();
+            base.insert_account_storage(state_address, U256::from(2), U256::from(20))
+                .unwrap();
+        }
+
+        let mut overrides = CallOverrides::default();
+        overrides.accounts.insert(
+            address,
+            AccountOverride {
+                balance: Some(U256::from(100)),
+                nonce: Some(5),
+                code: Some(Bytes::from(vec![0x60, 0x00])),
+                state_diff: HashMap::from([(U256::from(2), U256::from(30))]),
+                ..Default::default()
+            },
+        );
+        overrides.accounts.insert(
+            other_address,
+            AccountOverride {
+                state: Some(HashMap::from([(U256::from(2), U256::from(30))])),
+                ..Default::default()
+            },
+        );
+
+        let mut db = CacheDB::new(&base);
+        overrides.apply_to_state(&mut db).unwrap();
+
+        let info = db.basic(address).unwrap().unwrap();
+        assert_eq!(info.balance, U256::from(100));
+        assert_eq!(info.nonce, 5);
+        assert_eq!(info.code_hash, keccak256([0x60, 0x00]));
+        assert_eq!(
+            db.code_by_hash(info.code_hash).unwrap().original_bytes(),
+            Bytes::from(vec![0x60, 0x00])
+        );
+
+        // stateDiff keeps the other slots, state replaces the whole storage
+        assert_eq!(db.storage(address, U256::from(1)).unwrap(), U256::from(10));
+        assert_eq!(db.storage(address, U256::from(2)).unwrap(), U256::from(30));
+        assert_eq!(
+            db.storage(other_address, U256::from(1)).unwrap(),
+            U256::ZERO
+        );
+        assert_eq!(
+            db.storage(other_address, U256::from(2)).unwrap(),
+            U256::from(30)
+        );
+
+        // Database below is not changed
+        assert_eq!(
+            base.storage_ref(address, U256::from(2)).unwrap(),
+            U256::from(20)
+        );
+    }
+
+    #[test]
+    fn test_apply_block_overrides() {
+        let mut block_info = BlockEnv {
+            number: 10,
+            timestamp: 100,
+            ..Default::default()
+        };
+        let mut overrides = CallOverrides::default();
+        overrides.apply_to_block(&mut block_info);
+        assert_eq!((block_info.number, block_info.timestamp), (10, 100));
+
+        overrides.block.time = Some(200);
+        overrides.apply_to_block(&mut block_info);
+        assert_eq!((block_info.number, block_info.timestamp), (10, 200));
+    }
+
+    #[test]
+    fn test_apply_call_params() {
+        let mut tx = TxEnv {
+            data: Bytes::from(vec![0; 100]),
+            ..Default::default()
+        };
+        CallParams::default().apply_to_tx(&mut tx);
+        assert_eq!(tx.gas_limit, get_gas_limit(100) * 10);
+        assert_eq!(tx.tx_type, 0);
+
+        CallParams {
+            gas_limit: Some(u64::MAX),
+            ..Default::default()
+        }
+        .apply_to_tx(&mut tx);
+        assert_eq!(tx.gas_limit, *RPC_GAS_CAP);
+
+        let params = CallParams {
+            gas_limit: Some(50000),
+            value: U256::from(5),
+            access_list: AccessList(vec![AccessListItem {
+                address: Address::from([1u8; 20]),
+                storage_keys: vec![B256::ZERO],
+            }]),
+            ..Default::default()
+        };
+        params.apply_to_tx(&mut tx);
+        assert_eq!(tx.gas_limit, 50000);
+        assert_eq!(tx.value, U256::from(5));
+        // Access list makes it an EIP-2930 transaction unless the type is set
+        assert_eq!(tx.tx_type, 1);
+
+        CallParams {
+            tx_type: Some(2),
+            ..params
+        }
+        .apply_to_tx(&mut tx);
+        assert_eq!(tx.tx_type, 2);
+    }
+}